@@ -0,0 +1,157 @@
+// GPU port of `discover_collisions` + `handle_collisions` + `handle_movement`, one invocation per particle.
+// The CPU systems in main.rs remain the reference implementation.
+
+struct Params {
+  // Cells per second squared, velocities are in cells per second
+  gravity: vec2<f32>;
+  // Seconds per tick
+  dt: f32;
+  count: u32;
+  // left, bottom, right, top
+  bounds: vec4<f32>;
+  grid_min: vec2<i32>;
+  grid_size: vec2<u32>;
+  // Velocities are rounded to multiples of this, 0 leaves them exact
+  quantum: f32;
+  sleep_speed: f32;
+  // `CombineRule` of collisions between particles: average, min, max, multiply
+  restitution: u32;
+  padding: f32;
+};
+
+struct Particle {
+  position: vec2<f32>;
+  velocity: vec2<f32>;
+  mass: f32;
+  elasticity: f32;
+  flags: u32;
+  padding: u32;
+};
+
+struct Particles {
+  data: array<Particle>;
+};
+
+struct Grid {
+  // Index + 1 of the particle occupying each cell, 0 when empty
+  cells: array<atomic<u32>>;
+};
+
+let STATIC: u32 = 1u;
+
+[[group(0), binding(0)]]
+var<uniform> params: Params;
+[[group(0), binding(1)]]
+var<storage, read> particles_in: Particles;
+[[group(0), binding(2)]]
+var<storage, read_write> particles_out: Particles;
+[[group(0), binding(3)]]
+var<storage, read_write> grid: Grid;
+
+fn cell_index(point: vec2<i32>) -> i32 {
+  let local = point - params.grid_min;
+  if (local.x < 0 || local.y < 0 || local.x >= i32(params.grid_size.x) || local.y >= i32(params.grid_size.y)) {
+    return -1;
+  }
+  return local.y * i32(params.grid_size.x) + local.x;
+}
+
+fn outside(point: vec2<f32>) -> vec2<f32> {
+  var normal = vec2<f32>(0.0, 0.0);
+  if (point.x < params.bounds.x) {
+    normal.x = 1.0;
+  } else if (point.x > params.bounds.z) {
+    normal.x = -1.0;
+  }
+  if (point.y < params.bounds.y) {
+    normal.y = 1.0;
+  } else if (point.y > params.bounds.w) {
+    normal.y = -1.0;
+  }
+  return normal;
+}
+
+fn combine_restitution(a: f32, b: f32) -> f32 {
+  if (params.restitution == 1u) {
+    return min(a, b);
+  }
+  if (params.restitution == 2u) {
+    return max(a, b);
+  }
+  if (params.restitution == 3u) {
+    return a * b;
+  }
+  return (a + b) * 0.5;
+}
+
+fn reflect_velocity(velocity: vec2<f32>, normal: vec2<f32>, elasticity: f32) -> vec2<f32> {
+  return velocity - (1.0 + elasticity) * (velocity * normal) * normalize(normal);
+}
+
+[[stage(compute), workgroup_size(64)]]
+fn clear_grid([[builtin(global_invocation_id)]] id: vec3<u32>) {
+  if (id.x < params.grid_size.x * params.grid_size.y) {
+    atomicStore(&grid.cells[id.x], 0u);
+  }
+}
+
+[[stage(compute), workgroup_size(64)]]
+fn scatter([[builtin(global_invocation_id)]] id: vec3<u32>) {
+  if (id.x >= params.count) {
+    return;
+  }
+  let index = cell_index(vec2<i32>(floor(particles_in.data[id.x].position)));
+  if (index >= 0) {
+    atomicStore(&grid.cells[index], id.x + 1u);
+  }
+}
+
+[[stage(compute), workgroup_size(64)]]
+fn integrate([[builtin(global_invocation_id)]] id: vec3<u32>) {
+  if (id.x >= params.count) {
+    return;
+  }
+
+  var particle = particles_in.data[id.x];
+  if ((particle.flags & STATIC) != 0u) {
+    particles_out.data[id.x] = particle;
+    return;
+  }
+
+  var velocity = particle.velocity + params.gravity * params.dt;
+  let current = vec2<i32>(floor(particle.position));
+  let potential_position = particle.position + velocity * params.dt;
+  let potential = vec2<i32>(floor(potential_position));
+
+  if (any(potential != current)) {
+    let wall_normal = outside(potential_position);
+    if (any(wall_normal != vec2<f32>(0.0, 0.0))) {
+      velocity = reflect_velocity(velocity, wall_normal, particle.elasticity);
+    } else {
+      let index = cell_index(potential);
+      if (index >= 0) {
+        let occupant = atomicLoad(&grid.cells[index]);
+        if (occupant != 0u && occupant - 1u != id.x) {
+          let other = particles_in.data[occupant - 1u];
+          if ((other.flags & STATIC) != 0u) {
+            velocity = reflect_velocity(velocity, sign(vec2<f32>(current - potential)), particle.elasticity);
+          } else {
+            let restitution = combine_restitution(particle.elasticity, other.elasticity);
+            velocity = (restitution * other.mass * (other.velocity - velocity)
+              + particle.mass * velocity + other.mass * other.velocity) / (particle.mass + other.mass);
+          }
+        }
+      }
+    }
+    if (params.quantum > 0.0) {
+      velocity = round(velocity / params.quantum) * params.quantum;
+    }
+    if (length(velocity) * params.dt < params.sleep_speed) {
+      velocity = vec2<f32>(0.0, 0.0);
+    }
+  }
+
+  particle.velocity = velocity;
+  particle.position = particle.position + velocity * params.dt;
+  particles_out.data[id.x] = particle;
+}
@@ -0,0 +1,149 @@
+use bevy::prelude::*;
+
+use crate::material::{MaterialDef, MaterialId};
+
+// Material colors to draw the world with, the alternates tell the built in materials apart with the
+// hues the named color vision deficiency still sees. Materials from packs keep their own colors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorPalette {
+  #[default]
+  Standard,
+  // Red and green look alike, so the alternates lean on blue against orange and on lightness
+  Deuteranopia,
+  // Like deuteranopia, but reds also look darker
+  Protanopia,
+  // Blue and yellow look alike, so the alternates lean on red against teal
+  Tritanopia,
+}
+
+// In `MaterialId` order
+const DEUTERANOPIA: [(f32, f32, f32); 21] = [
+  (0.95, 0.9, 0.25),
+  (0., 0.45, 0.7),
+  (0.45, 0.3, 0.1),
+  (0.9, 0.6, 0.),
+  (0.35, 0.7, 0.9),
+  (0.5, 0.5, 0.5),
+  (0.9, 0.9, 0.95),
+  (0.25, 0.25, 0.25),
+  (1., 0.95, 0.6),
+  (1., 1., 1.),
+  (0.8, 0.6, 0.7),
+  (0.35, 0.35, 0.45),
+  (0.75, 0.65, 0.35),
+  (0.1, 0.1, 0.1),
+  (0.6, 0.4, 0.1),
+  (0.6, 0.2, 0.4),
+  (0.7, 0.7, 0.7),
+  (0.2, 0.25, 0.5),
+  (0., 0.6, 0.5),
+  (0.7, 0.85, 1.),
+  (1., 0.45, 0.),
+];
+
+const PROTANOPIA: [(f32, f32, f32); 21] = [
+  (0.95, 0.9, 0.25),
+  (0., 0.45, 0.7),
+  (0.45, 0.3, 0.1),
+  (0.9, 0.6, 0.),
+  (0.35, 0.7, 0.9),
+  (0.5, 0.5, 0.5),
+  (0.9, 0.9, 0.95),
+  (0.25, 0.25, 0.25),
+  (1., 0.95, 0.6),
+  (1., 1., 1.),
+  (0.75, 0.55, 0.8),
+  (0.35, 0.35, 0.45),
+  (0.75, 0.65, 0.35),
+  (0.1, 0.1, 0.1),
+  (0.6, 0.4, 0.1),
+  (0.45, 0.2, 0.55),
+  (0.7, 0.7, 0.7),
+  (0.2, 0.25, 0.5),
+  (0., 0.6, 0.5),
+  (0.7, 0.85, 1.),
+  (1., 0.6, 0.1),
+];
+
+const TRITANOPIA: [(f32, f32, f32); 21] = [
+  (0.9, 0.75, 0.7),
+  (0., 0.6, 0.65),
+  (0.45, 0.2, 0.2),
+  (0.85, 0.4, 0.4),
+  (0., 0.45, 0.35),
+  (0.5, 0.5, 0.5),
+  (0.92, 0.92, 0.92),
+  (0.25, 0.25, 0.25),
+  (1., 0.85, 0.85),
+  (1., 1., 1.),
+  (0.6, 0.1, 0.2),
+  (0.4, 0.4, 0.4),
+  (0.7, 0.55, 0.55),
+  (0.1, 0.1, 0.1),
+  (0.55, 0.3, 0.25),
+  (0.9, 0.1, 0.3),
+  (0.7, 0.7, 0.7),
+  (0.2, 0.35, 0.35),
+  (0.3, 0.7, 0.7),
+  (0.75, 0.95, 0.95),
+  (1., 0.25, 0.2),
+];
+
+// Darkening of the cells a pattern marks
+const PATTERN_SHADE: f32 = 0.7;
+const PATTERNS: usize = 7;
+
+impl ColorPalette {
+  pub fn next(self) -> Self {
+    match self {
+      ColorPalette::Standard => ColorPalette::Deuteranopia,
+      ColorPalette::Deuteranopia => ColorPalette::Protanopia,
+      ColorPalette::Protanopia => ColorPalette::Tritanopia,
+      ColorPalette::Tritanopia => ColorPalette::Standard,
+    }
+  }
+
+  fn color(self, material: MaterialId) -> Option<Color> {
+    let colors = match self {
+      ColorPalette::Standard => return None,
+      ColorPalette::Deuteranopia => &DEUTERANOPIA,
+      ColorPalette::Protanopia => &PROTANOPIA,
+      ColorPalette::Tritanopia => &TRITANOPIA,
+    };
+    colors.get(material.0).map(|(r, g, b)| Color::rgb(*r, *g, *b))
+  }
+}
+
+// What a particle is drawn as. `color` is its sprite's, which fading and glowing change from the
+// material's own, so the palette's color is brightened and faded by as much.
+pub fn display_color(palette: ColorPalette, patterns: bool, material: MaterialId, def: &MaterialDef, cell: IVec2, color: Color) -> Color {
+  let mut color = match palette.color(material) {
+    Some(replacement) => {
+      let [r, g, b, a] = color.as_rgba_f32();
+      let [base_r, base_g, base_b, _] = def.color.as_rgba_f32();
+      let brightness = if base_r + base_g + base_b > 0. { (r + g + b) / (base_r + base_g + base_b) } else { 1. };
+      *(replacement * brightness).set_a(a)
+    },
+    None => color,
+  };
+  if patterns && in_pattern(material, cell) {
+    color *= PATTERN_SHADE;
+  }
+  color
+}
+
+// Every material gets one of a few patterns of darker cells, so neighbouring materials stay apart
+// even where their colors do not
+fn in_pattern(material: MaterialId, cell: IVec2) -> bool {
+  let (x, y) = (cell.x.rem_euclid(4), cell.y.rem_euclid(4));
+  match material.0 % PATTERNS {
+    // Solid
+    0 => false,
+    1 => (x + y) % 2 == 0,
+    2 => y % 2 == 0,
+    3 => x % 2 == 0,
+    4 => (x + y) % 4 == 0,
+    5 => x % 2 == 0 && y % 2 == 0,
+    _ => x == 0 || y == 0,
+  }
+}
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Config, console::Console};
+
+// Stick deflection ignored, so a worn stick resting slightly off center does not drift
+const STICK_DEADZONE: f32 = 0.15;
+
+pub struct ActionsPlugin;
+
+impl Plugin for ActionsPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<Input<Action>>()
+      .init_resource::<Sticks>()
+      .add_system_to_stage(CoreStage::PreUpdate, update_actions.label("actions"));
+  }
+}
+
+// Everything the player can trigger, systems read `Input<Action>` instead of raw devices
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+  // Paints with the brush or drags out a selection
+  Primary,
+  // Erases with the brush, cuts with the beam or empties the vacuum
+  Secondary,
+  BrushTool,
+  SelectTool,
+  InspectTool,
+  // Aims and fires projectiles with `Action::Primary`
+  LauncherTool,
+  // Heats with `Action::Primary` and cuts with `Action::Secondary` along a ray
+  BeamTool,
+  // Sucks particles in with `Action::Primary` and puts them back with `Action::Secondary`
+  VacuumTool,
+  // Drag out lines and rectangles with `Action::Primary` and erase them with `Action::Secondary`
+  LineTool,
+  RectangleTool,
+  // Fills the enclosed area clicked with `Action::Primary`
+  FillTool,
+  // Drags out a rope with `Action::Primary`, pinned where it starts
+  RopeTool,
+  Paste,
+  // Makes the material under the cursor the brush's
+  PickMaterial,
+  // Also has the brush copy the velocity and latent heat of the particle under the cursor
+  PickStamp,
+  // Steps the brush through its symmetry modes
+  CycleSymmetry,
+  // Moves the point brush strokes are mirrored around to the cursor
+  SetSymmetryCenter,
+  Pause,
+  // Prints the latest world checksum, for comparing runs
+  PrintChecksum,
+  // Steps through the heat map visualizations
+  CycleVisualization,
+  // Saves a PNG of the world
+  Screenshot,
+  // Starts or stops recording a GIF of the world
+  Record,
+  // Steps the world back a tick per frame while held and paused
+  Rewind,
+  // Saves the world as a Bevy scene, or replaces it with the saved one
+  ExportScene,
+  ImportScene,
+  // Shows or hides the trails behind moving particles
+  ToggleTrails,
+  // Steps through showing the wind as streamlines, as tracers or not at all
+  CycleWindView,
+  // Shows or hides the simulation stats panel
+  ToggleStats,
+  // Replaces the world with a stress test, see `bench`
+  StressTest,
+  // Opens or closes the command console
+  ToggleConsole,
+  // Step the brush through the materials of the registry
+  NextMaterial,
+  PreviousMaterial,
+  // Move the camera while held
+  PanLeft,
+  PanRight,
+  PanUp,
+  PanDown,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Binding {
+  Key(KeyCode),
+  Mouse(MouseButton),
+}
+
+pub fn default_bindings() -> HashMap<Action, Binding> {
+  HashMap::from_iter([
+    (Action::Primary, Binding::Mouse(MouseButton::Left)),
+    (Action::Secondary, Binding::Mouse(MouseButton::Right)),
+    (Action::BrushTool, Binding::Key(KeyCode::B)),
+    (Action::SelectTool, Binding::Key(KeyCode::S)),
+    (Action::InspectTool, Binding::Key(KeyCode::I)),
+    (Action::LauncherTool, Binding::Key(KeyCode::L)),
+    (Action::BeamTool, Binding::Key(KeyCode::E)),
+    (Action::VacuumTool, Binding::Key(KeyCode::U)),
+    (Action::LineTool, Binding::Key(KeyCode::K)),
+    (Action::RectangleTool, Binding::Key(KeyCode::T)),
+    (Action::FillTool, Binding::Key(KeyCode::F)),
+    (Action::RopeTool, Binding::Key(KeyCode::O)),
+    (Action::Paste, Binding::Key(KeyCode::V)),
+    (Action::PickMaterial, Binding::Key(KeyCode::Q)),
+    (Action::PickStamp, Binding::Key(KeyCode::Y)),
+    (Action::CycleSymmetry, Binding::Key(KeyCode::M)),
+    (Action::SetSymmetryCenter, Binding::Key(KeyCode::N)),
+    (Action::Pause, Binding::Key(KeyCode::Escape)),
+    (Action::PrintChecksum, Binding::Key(KeyCode::F9)),
+    (Action::CycleVisualization, Binding::Key(KeyCode::F3)),
+    (Action::Screenshot, Binding::Key(KeyCode::F12)),
+    (Action::Record, Binding::Key(KeyCode::F10)),
+    (Action::Rewind, Binding::Key(KeyCode::R)),
+    (Action::ExportScene, Binding::Key(KeyCode::F6)),
+    (Action::ImportScene, Binding::Key(KeyCode::F7)),
+    (Action::ToggleStats, Binding::Key(KeyCode::F2)),
+    (Action::ToggleTrails, Binding::Key(KeyCode::F4)),
+    (Action::CycleWindView, Binding::Key(KeyCode::F5)),
+    (Action::StressTest, Binding::Key(KeyCode::F8)),
+    (Action::ToggleConsole, Binding::Key(KeyCode::Grave)),
+    (Action::PanLeft, Binding::Key(KeyCode::Left)),
+    (Action::PanRight, Binding::Key(KeyCode::Right)),
+    (Action::PanUp, Binding::Key(KeyCode::Up)),
+    (Action::PanDown, Binding::Key(KeyCode::Down)),
+    (Action::NextMaterial, Binding::Key(KeyCode::RBracket)),
+    (Action::PreviousMaterial, Binding::Key(KeyCode::LBracket)),
+  ])
+}
+
+// Buttons of any connected gamepad, on top of the key bindings
+pub fn default_gamepad_bindings() -> HashMap<Action, GamepadButtonType> {
+  HashMap::from_iter([
+    (Action::Primary, GamepadButtonType::RightTrigger2),
+    (Action::Secondary, GamepadButtonType::LeftTrigger2),
+    (Action::NextMaterial, GamepadButtonType::RightTrigger),
+    (Action::PreviousMaterial, GamepadButtonType::LeftTrigger),
+    (Action::Pause, GamepadButtonType::Start),
+    (Action::BrushTool, GamepadButtonType::South),
+    (Action::SelectTool, GamepadButtonType::West),
+    (Action::InspectTool, GamepadButtonType::North),
+    (Action::LauncherTool, GamepadButtonType::East),
+  ])
+}
+
+// Gamepad sticks from -1 to 1 on each axis, zero inside the deadzone or without a gamepad. The left
+// one moves the cursor and the right one pans the camera.
+#[derive(Default)]
+pub struct Sticks {
+  pub left: Vec2,
+  pub right: Vec2,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_actions(
+  config: Res<Config>,
+  console: Option<Res<Console>>,
+  keys: Res<Input<KeyCode>>,
+  mouse: Res<Input<MouseButton>>,
+  gamepads: Res<Gamepads>,
+  buttons: Res<Input<GamepadButton>>,
+  axes: Res<Axis<GamepadAxis>>,
+  mut actions: ResMut<Input<Action>>,
+  mut sticks: ResMut<Sticks>,
+) {
+  actions.clear();
+  // Keys typed into the console only close it
+  let typing = console.is_some_and(|console| console.open);
+  for (action, binding) in config.keybindings.iter() {
+    let (just_pressed, just_released) = match *binding {
+      Binding::Key(key) if typing && *action != Action::ToggleConsole => (false, keys.just_released(key)),
+      Binding::Key(key) => (keys.just_pressed(key), keys.just_released(key)),
+      Binding::Mouse(button) => (mouse.just_pressed(button), mouse.just_released(button)),
+    };
+    if just_pressed {
+      actions.press(*action);
+    } else if just_released {
+      actions.release(*action);
+    }
+  }
+
+  for (action, button) in config.gamepad_bindings.iter() {
+    let bound = |gamepad: &Gamepad| GamepadButton(*gamepad, *button);
+    if !typing && gamepads.iter().any(|gamepad| buttons.just_pressed(bound(gamepad))) {
+      actions.press(*action);
+    } else if gamepads.iter().any(|gamepad| buttons.just_released(bound(gamepad))) {
+      actions.release(*action);
+    }
+  }
+
+  let stick = |x, y| {
+    let value = gamepads.iter().fold(Vec2::ZERO, |value, gamepad| {
+      let axis = |axis| axes.get(GamepadAxis(*gamepad, axis)).unwrap_or(0.);
+      value + Vec2::new(axis(x), axis(y))
+    });
+    if value.length() < STICK_DEADZONE { Vec2::ZERO } else { value.clamp_length_max(1.) }
+  };
+  sticks.left = stick(GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY);
+  sticks.right = stick(GamepadAxisType::RightStickX, GamepadAxisType::RightStickY);
+}
@@ -0,0 +1,134 @@
+use std::{net::SocketAddr, path::PathBuf};
+
+use bevy::log::Level;
+
+use crate::{bench::StressTest, net::NetRole, simulation::GravityPreset};
+
+// Command line flags, anything not understood is reported and ignored. Parsing happens before the
+// logger is set up, so problems go straight to stderr.
+#[derive(Clone, Debug, Default)]
+pub struct Args {
+  pub net: NetRole,
+  // Exchange inputs instead of world state, see `net::lockstep`
+  pub lockstep: bool,
+  // Per tick metrics outputs, see `metrics`
+  pub metrics_csv: Option<PathBuf>,
+  pub metrics_address: Option<SocketAddr>,
+  // Logs the `Diagnostics`, see `profiling`
+  pub diagnostics: bool,
+  pub level: Option<Level>,
+  // See `SimulationSettings::debug_log`
+  pub sim_debug: bool,
+  // Starts with an unbounded world, see `SpatialIndex::infinite`
+  pub infinite: bool,
+  // Spawned on startup, see `bench`
+  pub stress: Option<StressTest>,
+  // Milliseconds per tick to benchmark against
+  pub benchmark: Option<f32>,
+  // Gravity to start with instead of Earth's
+  pub gravity: Option<GravityPreset>,
+}
+
+impl Args {
+  const USAGE: &'static str = "usage: arrakoids [--host <addr> | --server <addr> | --connect <addr>] [--lockstep] \
+    [--metrics-csv <path>] [--metrics <addr>] [--diagnostics] [--log-level <level>] [--sim-debug] [--infinite] \
+    [--stress <rain|block|streams> <count>] [--benchmark <budget_ms>] [--gravity <earth|moon|jupiter|zero-g|planet>]";
+
+  pub fn parse() -> Self {
+    Self::parse_from(std::env::args().skip(1))
+  }
+
+  pub fn parse_from(args: impl IntoIterator<Item = String>) -> Self {
+    let mut parsed = Self::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+      if arg == "--lockstep" {
+        parsed.lockstep = true;
+        continue;
+      }
+      if arg == "--diagnostics" {
+        parsed.diagnostics = true;
+        continue;
+      }
+      if arg == "--sim-debug" {
+        parsed.sim_debug = true;
+        continue;
+      }
+      if arg == "--infinite" {
+        parsed.infinite = true;
+        continue;
+      }
+      if arg == "--log-level" {
+        match args.next().map(|value| value.parse::<Level>()) {
+          Some(Ok(level)) => parsed.level = Some(level),
+          Some(Err(error)) => eprintln!("Invalid level for {}: {}", arg, error),
+          None => eprintln!("Missing level for {}\n{}", arg, Self::USAGE),
+        }
+        continue;
+      }
+      if arg == "--stress" {
+        let pattern = args.next().map(|value| value.parse());
+        let count = args.next().map(|value| value.parse::<u32>());
+        match (pattern, count) {
+          (Some(Ok(pattern)), Some(Ok(count))) => parsed.stress = Some(StressTest { pattern, count }),
+          (Some(Err(error)), _) => eprintln!("Invalid pattern for {}: {}", arg, error),
+          (_, Some(Err(error))) => eprintln!("Invalid count for {}: {}", arg, error),
+          _ => eprintln!("Missing pattern or count for {}\n{}", arg, Self::USAGE),
+        }
+        continue;
+      }
+      if arg == "--benchmark" {
+        match args.next().map(|value| value.parse::<f32>()) {
+          Some(Ok(budget)) => parsed.benchmark = Some(budget),
+          Some(Err(error)) => eprintln!("Invalid budget for {}: {}", arg, error),
+          None => eprintln!("Missing budget for {}\n{}", arg, Self::USAGE),
+        }
+        continue;
+      }
+      if arg == "--gravity" {
+        match args.next().map(|value| value.parse::<GravityPreset>()) {
+          Some(Ok(gravity)) => parsed.gravity = Some(gravity),
+          Some(Err(error)) => eprintln!("Invalid gravity for {}: {}", arg, error),
+          None => eprintln!("Missing gravity for {}\n{}", arg, Self::USAGE),
+        }
+        continue;
+      }
+      if arg == "--metrics-csv" {
+        match args.next() {
+          Some(path) => parsed.metrics_csv = Some(PathBuf::from(path)),
+          None => eprintln!("Missing path for {}\n{}", arg, Self::USAGE),
+        }
+        continue;
+      }
+      if arg == "--metrics" {
+        match args.next().map(|value| value.parse::<SocketAddr>()) {
+          Some(Ok(address)) => parsed.metrics_address = Some(address),
+          Some(Err(error)) => eprintln!("Invalid address for {}: {}", arg, error),
+          None => eprintln!("Missing address for {}\n{}", arg, Self::USAGE),
+        }
+        continue;
+      }
+
+      let role: fn(SocketAddr) -> NetRole = match arg.as_str() {
+        "--host" => NetRole::Host,
+        "--server" => NetRole::DedicatedServer,
+        "--connect" => NetRole::Client,
+        _ => {
+          eprintln!("Unknown argument {}\n{}", arg, Self::USAGE);
+          continue;
+        }
+      };
+
+      match args.next().map(|value| value.parse::<SocketAddr>()) {
+        Some(Ok(address)) => parsed.net = role(address),
+        Some(Err(error)) => eprintln!("Invalid address for {}: {}", arg, error),
+        None => eprintln!("Missing address for {}\n{}", arg, Self::USAGE),
+      }
+    }
+    parsed
+  }
+  // The simulation's debug output is only useful with debug logs enabled, so it lowers the default
+  pub fn log_level(&self) -> Level {
+    self.level.unwrap_or(if self.sim_debug { Level::DEBUG } else { Level::INFO })
+  }
+}
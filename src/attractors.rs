@@ -0,0 +1,230 @@
+use bevy::prelude::*;
+
+use crate::{
+  boids::Boid,
+  console::{ConsoleApp, ConsoleCommand, ConsoleLog},
+  simulation::{SimulationClock, SimulationSettings, SimulationStep},
+  Particle, SpatialIndex, Static,
+};
+
+// Particles that pull every other moving particle towards them with inverse-square gravity, for
+// orbits and accretion disks. A fixed particle makes a sun that stays put, a loose one is pulled on
+// by the other attractors in turn. With `SelfGravity` on every moving particle pulls on all the
+// others as well, so dust clouds clump together.
+pub struct AttractorsPlugin;
+
+impl Plugin for AttractorsPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<SelfGravity>()
+      .add_console_command("selfgravity", "selfgravity <on [theta]|off>, every moving particle pulls on the others")
+      .add_system(run_self_gravity_command)
+      .add_system_set(SystemSet::new()
+        .with_run_criteria("fixed_tick")
+        .with_system(attract.label(SimulationStep::Integrate))
+        .with_system(attract_each_other.label(SimulationStep::Integrate))
+      );
+  }
+}
+
+// Gravitational constant, a body of mass 1 pulls a cell away with 1 cell per second squared
+const G: f32 = 1.;
+
+#[derive(Component, Clone, Copy, Debug)]
+pub struct AttractorBody {
+  pub mass: f32,
+  // Cells the pull is smoothed out over, so it stays finite when something gets right on top of it
+  pub softening: f32,
+  // Most force it puts on a particle, keeps particles skimming past it from being flung off
+  pub max_force: f32,
+}
+
+impl AttractorBody {
+  pub const SOFTENING: f32 = 1.;
+  pub const MAX_FORCE: f32 = 64.;
+
+  pub fn new(mass: f32) -> Self {
+    Self { mass, softening: Self::SOFTENING, max_force: Self::MAX_FORCE }
+  }
+
+  // Force on a particle of `mass` at `offset` from the body
+  fn pull(&self, offset: Vec2, mass: f32) -> Vec2 {
+    let distance_squared = offset.length_squared() + self.softening * self.softening;
+    let force = (G * self.mass * mass / distance_squared).min(self.max_force);
+    -offset.normalize_or_zero() * force
+  }
+}
+
+// Boids fly against the pull on their own
+fn attract(
+  clock: Res<SimulationClock>,
+  settings: Res<SimulationSettings>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  attractors: Query<(Entity, &AttractorBody)>,
+  mut particles: Query<(Entity, &mut Particle, Option<&Static>, Option<&Boid>)>,
+) {
+  if !clock.ticked || attractors.is_empty() { return }
+  let bodies: Vec<(Entity, Vec2, AttractorBody)> = attractors
+    .iter()
+    .filter_map(|(entity, body)| Some((entity, particles.get(entity).ok()?.1.position, *body)))
+    .collect();
+  let delta = settings.tick_delta();
+
+  for (entity, mut particle, fixed, boid) in particles.iter_mut() {
+    if fixed.is_some() || boid.is_some() { continue }
+    let mass = particle.mass.max(f32::EPSILON);
+    let force = bodies
+      .iter()
+      // Nothing pulls on itself
+      .filter(|(body_entity, _, _)| *body_entity != entity)
+      .fold(Vec2::ZERO, |force, (_, position, body)| force + body.pull(particle.position - *position, mass));
+    if force == Vec2::ZERO { continue }
+    particle.velocity += force / mass * delta;
+    spatial_index.wake(particle.position.floor().as_ivec2());
+  }
+}
+
+// Every moving particle attracting every other by its own mass, softened and clamped like an
+// `AttractorBody`. Far away groups of particles pull as one body at their center of mass, see
+// `BarnesHut`.
+pub struct SelfGravity {
+  pub enabled: bool,
+  // How small a group has to look from a particle to pull as one, its size over its distance.
+  // 0 sums up every particle on its own, larger is faster and rougher.
+  pub theta: f32,
+}
+
+impl Default for SelfGravity {
+  fn default() -> Self {
+    Self { enabled: false, theta: 0.5 }
+  }
+}
+
+fn run_self_gravity_command(
+  mut self_gravity: ResMut<SelfGravity>,
+  mut entered: EventReader<ConsoleCommand>,
+  mut log: Option<ResMut<ConsoleLog>>,
+) {
+  for command in entered.iter().filter(|command| command.name == "selfgravity") {
+    let answer = match command.args.first().map(String::as_str) {
+      Some("on") => {
+        self_gravity.enabled = true;
+        if let Some(theta) = command.arg::<f32>(1).filter(|theta| theta.is_finite() && *theta >= 0.) {
+          self_gravity.theta = theta;
+        }
+        format!("Self gravity is on, theta {}", self_gravity.theta)
+      },
+      Some("off") => {
+        self_gravity.enabled = false;
+        "Self gravity is off".to_string()
+      },
+      _ => "selfgravity <on [theta]|off>".to_string(),
+    };
+    if let Some(log) = log.as_mut() {
+      log.print(answer);
+    }
+  }
+}
+
+// A node of the quadtree, a square of the world with the mass of the particles in it
+struct Node {
+  size: f32,
+  mass: f32,
+  center_of_mass: Vec2,
+  // Index of the first of the four quarters in `BarnesHut::nodes`, none for a node of one particle
+  // or too small to split further
+  children: Option<usize>,
+}
+
+// Quadtree over the particles' masses that gives the pull on a particle in O(log n) rather than
+// going through every other particle. Nodes far enough away for their `size / distance` to be under
+// theta count as a single body.
+struct BarnesHut {
+  nodes: Vec<Node>,
+}
+
+impl BarnesHut {
+  // Nodes smaller than a cell are not split, particles never share a cell for long
+  const MIN_SIZE: f32 = 1.;
+
+  fn new(bodies: &mut [(Vec2, f32)]) -> Self {
+    let mut tree = Self { nodes: Vec::new() };
+    if bodies.is_empty() { return tree }
+    let (min, max) = bodies.iter().fold((Vec2::splat(f32::MAX), Vec2::splat(f32::MIN)), |(min, max), (position, _)| {
+      (min.min(*position), max.max(*position))
+    });
+    tree.nodes.push(Node { size: 0., mass: 0., center_of_mass: Vec2::ZERO, children: None });
+    tree.build(0, bodies, min, (max - min).max_element().max(Self::MIN_SIZE));
+    tree
+  }
+
+  fn build(&mut self, index: usize, bodies: &mut [(Vec2, f32)], min: Vec2, size: f32) {
+    let mass: f32 = bodies.iter().map(|(_, mass)| mass).sum();
+    let weighted = bodies.iter().fold(Vec2::ZERO, |sum, (position, mass)| sum + *position * *mass);
+    let center_of_mass = if mass > 0. { weighted / mass } else { min + Vec2::splat(size / 2.) };
+    self.nodes[index] = Node { size, mass, center_of_mass, children: None };
+    if bodies.len() <= 1 || size <= Self::MIN_SIZE { return }
+
+    // Sorted into the quarters left to right and bottom to top
+    let middle = min + Vec2::splat(size / 2.);
+    let quarter = |position: Vec2| (position.x >= middle.x) as usize + 2 * (position.y >= middle.y) as usize;
+    bodies.sort_unstable_by_key(|(position, _)| quarter(*position));
+    let first = self.nodes.len();
+    for _ in 0..4 {
+      self.nodes.push(Node { size: 0., mass: 0., center_of_mass: Vec2::ZERO, children: None });
+    }
+    self.nodes[index].children = Some(first);
+
+    let mut start = 0;
+    for child in 0..4 {
+      let end = start + bodies[start..].iter().take_while(|(position, _)| quarter(*position) == child).count();
+      let offset = Vec2::new((child % 2) as f32, (child / 2) as f32) * size / 2.;
+      self.build(first + child, &mut bodies[start..end], min + offset, size / 2.);
+      start = end;
+    }
+  }
+
+  // Force on a particle of `mass` at `position`. Its own node is at no distance and pulls on it
+  // with no force.
+  fn pull(&self, position: Vec2, mass: f32, theta: f32, body: &AttractorBody) -> Vec2 {
+    let mut force = Vec2::ZERO;
+    let mut stack = if self.nodes.is_empty() { Vec::new() } else { vec![0] };
+    while let Some(index) = stack.pop() {
+      let node = &self.nodes[index];
+      if node.mass <= 0. { continue }
+      let offset = position - node.center_of_mass;
+      match node.children {
+        Some(first) if node.size >= theta * offset.length() => stack.extend(first..first + 4),
+        _ => force += AttractorBody { mass: node.mass, ..*body }.pull(offset, mass),
+      }
+    }
+    force
+  }
+}
+
+fn attract_each_other(
+  clock: Res<SimulationClock>,
+  settings: Res<SimulationSettings>,
+  self_gravity: Res<SelfGravity>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut particles: Query<&mut Particle, (Without<Static>, Without<Boid>)>,
+) {
+  if !self_gravity.enabled || !clock.ticked { return }
+  let mut bodies: Vec<(Vec2, f32)> = spatial_index
+    .values()
+    .filter_map(|entity| particles.get(*entity).ok())
+    .map(|particle| (particle.position, particle.mass))
+    .collect();
+  let tree = BarnesHut::new(&mut bodies);
+  // Softened and clamped like any attractor, only the mass differs from node to node
+  let body = AttractorBody::new(0.);
+  let delta = settings.tick_delta();
+
+  for mut particle in particles.iter_mut() {
+    let mass = particle.mass.max(f32::EPSILON);
+    let force = tree.pull(particle.position, mass, self_gravity.theta, &body);
+    if force == Vec2::ZERO { continue }
+    particle.velocity += force / mass * delta;
+    spatial_index.wake(particle.position.floor().as_ivec2());
+  }
+}
@@ -0,0 +1,384 @@
+use std::{collections::VecDeque, fs, io, path::{Path, PathBuf}};
+
+use bevy::{prelude::*, app::AppExit, tasks::{AsyncComputeTaskPool, Task}, utils::HashMap};
+use futures_lite::future;
+
+use crate::{
+  config::Config,
+  locale::Locale,
+  material::{MaterialId, MaterialRegistry},
+  scenario::{LoadScenario, Scenario, TimelineEntry, TimelineEvent},
+  simulation::SimulationSettings,
+  snapshot::{self, SavedParticle, SavedSettings, Snapshot, SnapshotReader},
+  spawn_particle_with_velocity, AppState, Particle, SpatialIndex,
+};
+
+// Writes the world to disk every so often while it runs and once more on the way out, so a crash
+// loses little of a long session. The main menu offers to bring the latest save back on the next
+// launch. Saves are encoded and written in the background, and a restored save is read in the
+// background and put back a few chunks a frame, so large worlds do not stall the game.
+pub struct AutosavePlugin;
+
+impl Plugin for AutosavePlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<Autosave>()
+      .add_event::<RestoreAutosave>()
+      .add_startup_system(find_autosave)
+      // Spawns into the world the restored save's `LoadScenario` reset the frame before
+      .add_system(restore_autosave.after("load_scenario"))
+      .add_system(show_progress)
+      .add_system_set(SystemSet::on_update(AppState::Running).with_system(autosave))
+      // Both the menu and closing the window ask to exit during the update
+      .add_system_to_stage(CoreStage::Last, save_on_exit);
+  }
+}
+
+const FILE_NAME: &str = "autosave.bin";
+// What the save was called while it was gzipped, it is renamed once found. Snapshots say how they are
+// compressed themselves, so the old file reads as it is.
+const LEGACY_FILE_NAME: &str = "autosave.bin.gz";
+// Particles of a restored save spawned each frame
+const RESTORED_PER_FRAME: usize = 20_000;
+const BAR_WIDTH: f32 = 320.;
+
+// Sent by the main menu to go on with the world of the last session
+pub struct RestoreAutosave;
+
+#[derive(Default)]
+pub struct Autosave {
+  // Particles in the save left from the last session, until it is restored or the new one saves
+  // over it
+  recovered: Option<usize>,
+  // Only a world that was played gets saved, the menu alone does not overwrite the last session's
+  started: bool,
+  since_save: f32,
+  saving: Option<Task<io::Result<()>>>,
+  reading: Option<Task<io::Result<Restored>>>,
+  restoring: Option<Restored>,
+}
+
+impl Autosave {
+  // Particles in the save left from the last session, if there is one
+  pub fn recovered(&self) -> Option<usize> {
+    self.recovered
+  }
+}
+
+// A save read back, with its particles still to be spawned
+struct Restored {
+  // An empty world of the save's size and settings, to load before the particles go in
+  scenario: Scenario,
+  // Names of the save's materials, then what they are in this release once it was read
+  names: Vec<String>,
+  materials: Vec<Option<MaterialId>>,
+  chunks: VecDeque<Vec<SavedParticle>>,
+  particles: usize,
+  spawned: usize,
+}
+
+// Takes the result of a task once it is done
+fn poll<T>(task: &mut Option<Task<T>>) -> Option<T> {
+  let result = future::block_on(future::poll_once(task.as_mut()?))?;
+  *task = None;
+  Some(result)
+}
+
+fn directory(config: &Config) -> PathBuf {
+  match &config.autosave.directory {
+    Some(directory) => PathBuf::from(directory),
+    None => dirs::data_dir().map_or_else(|| PathBuf::from("saves"), |data| data.join("arrakoids")),
+  }
+}
+
+fn write_snapshot(directory: PathBuf, snapshot: &Snapshot) -> io::Result<()> {
+  fs::create_dir_all(&directory)?;
+  // Written next to the last save and moved over it, so a crash while writing keeps the old one
+  let temporary = directory.join(format!("{}.tmp", FILE_NAME));
+  snapshot::write(io::BufWriter::new(fs::File::create(&temporary)?), snapshot, true)?;
+  fs::rename(temporary, directory.join(FILE_NAME))
+}
+
+fn migrate_file_name(directory: &Path) -> io::Result<()> {
+  let (legacy, path) = (directory.join(LEGACY_FILE_NAME), directory.join(FILE_NAME));
+  if path.exists() || !legacy.exists() { return Ok(()) }
+  fs::rename(legacy, path)
+}
+
+fn open_snapshot(directory: &Path) -> io::Result<SnapshotReader<'static>> {
+  SnapshotReader::new(io::BufReader::new(fs::File::open(directory.join(FILE_NAME))?))
+}
+
+// Restored like a scenario, particles come back at the corner of their cell. Only particles and
+// settings are kept, chunks streamed out of an infinite world are not.
+fn read_snapshot(directory: PathBuf) -> io::Result<Restored> {
+  let mut reader = open_snapshot(&directory)?;
+  let particles = reader.particles();
+  let mut chunks = VecDeque::new();
+  while let Some(chunk) = reader.next_chunk()? {
+    chunks.push_back(chunk);
+  }
+  let mut scenario = Scenario { width: reader.width, height: reader.height, infinite: reader.infinite, ..Default::default() };
+  if let Some(settings) = reader.settings {
+    scenario.boundary = settings.boundary;
+    scenario.timeline.push(TimelineEntry { tick: 0, event: TimelineEvent::Gravity(settings.gravity) });
+  }
+  Ok(Restored { scenario, names: reader.materials, materials: Vec::new(), chunks, particles, spawned: 0 })
+}
+
+// Copies what a save needs out of the world, the encoding is left for later
+fn snapshot(
+  spatial_index: &SpatialIndex,
+  settings: &SimulationSettings,
+  materials: &MaterialRegistry,
+  particles: &Query<(&Particle, &MaterialId)>,
+) -> Snapshot {
+  let bounds = spatial_index.bounds();
+  let mut names = Vec::new();
+  let mut indices = HashMap::default();
+  let particles = particles
+    .iter()
+    .map(|(particle, material)| SavedParticle {
+      material: *indices.entry(*material).or_insert_with(|| {
+        names.push(materials.get(*material).name.clone());
+        names.len() as u16 - 1
+      }),
+      position: particle.position.into(),
+      velocity: particle.velocity.into(),
+    })
+    .collect();
+  Snapshot {
+    width: (bounds.right - bounds.left) as i32,
+    height: (bounds.top - bounds.bottom) as i32,
+    infinite: spatial_index.infinite,
+    materials: names,
+    particles,
+    settings: Some(SavedSettings { gravity: settings.gravity.into(), boundary: settings.boundary }),
+  }
+}
+
+// Only reads as far as the particle count, the particles are read once the player restores them
+fn find_autosave(config: Res<Config>, mut autosave: ResMut<Autosave>) {
+  if !config.autosave.enabled { return }
+  let directory = directory(&config);
+  if let Err(error) = migrate_file_name(&directory) {
+    warn!("Could not rename the autosave from {}: {}", LEGACY_FILE_NAME, error);
+  }
+  match open_snapshot(&directory) {
+    Ok(reader) => autosave.recovered = Some(reader.particles()),
+    Err(error) if error.kind() == io::ErrorKind::NotFound => {},
+    Err(error) => warn!("Could not read the autosave: {}", error),
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn restore_autosave(
+  mut commands: Commands,
+  config: Res<Config>,
+  pool: Res<AsyncComputeTaskPool>,
+  mut events: EventReader<RestoreAutosave>,
+  mut state: ResMut<State<AppState>>,
+  mut autosave: ResMut<Autosave>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  materials: Res<MaterialRegistry>,
+  mut load_events: EventWriter<LoadScenario>,
+) {
+  if events.iter().last().is_some() && autosave.recovered.take().is_some() {
+    let directory = directory(&config);
+    autosave.reading = Some(pool.spawn(async move { read_snapshot(directory) }));
+  }
+  match poll(&mut autosave.reading) {
+    Some(Ok(mut restored)) => {
+      info!("Restoring {} particles from the autosave", restored.particles);
+      restored.materials = restored
+        .names
+        .iter()
+        .map(|name| {
+          let material = materials.find(name);
+          if material.is_none() {
+            warn!("Unknown material {} in the autosave", name);
+          }
+          material
+        })
+        .collect();
+      load_events.send(LoadScenario(std::mem::take(&mut restored.scenario)));
+      autosave.restoring = Some(restored);
+      return;
+    },
+    Some(Err(error)) => {
+      error!("Could not read the autosave: {}", error);
+      if let Err(error) = state.replace(AppState::MainMenu) {
+        error!("Could not change state: {:?}", error);
+      }
+      return;
+    },
+    None => {},
+  }
+
+  let restored = match &mut autosave.restoring {
+    Some(restored) => restored,
+    None => return,
+  };
+  let mut budget = RESTORED_PER_FRAME;
+  while budget > 0 {
+    let chunk = match restored.chunks.front_mut() {
+      Some(chunk) => chunk,
+      None => break,
+    };
+    // Saves from before chunks come as one, those are split up here
+    let count = chunk.len().min(budget);
+    for particle in chunk.drain(..count) {
+      let material = match restored.materials.get(particle.material as usize) {
+        Some(Some(material)) => *material,
+        _ => continue,
+      };
+      let point = IVec2::new(particle.position.0.floor() as i32, particle.position.1.floor() as i32);
+      if !spatial_index.is_free(point) { continue }
+      let velocity = Vec2::new(particle.velocity.0, particle.velocity.1);
+      spawn_particle_with_velocity(&mut commands, &mut spatial_index, &materials, point, material, velocity);
+    }
+    if chunk.is_empty() {
+      restored.chunks.pop_front();
+    }
+    restored.spawned += count;
+    budget -= count;
+  }
+  if !restored.chunks.is_empty() { return }
+
+  autosave.restoring = None;
+  if let Err(error) = state.replace(AppState::Running) {
+    error!("Could not change state: {:?}", error);
+  }
+}
+
+#[derive(Component)]
+struct ProgressBar;
+
+#[derive(Component)]
+struct ProgressFill;
+
+#[derive(Component)]
+struct ProgressText;
+
+fn show_progress(
+  mut commands: Commands,
+  asset_server: Res<AssetServer>,
+  autosave: Res<Autosave>,
+  locale: Res<Locale>,
+  bars: Query<Entity, With<ProgressBar>>,
+  mut fills: Query<&mut Style, With<ProgressFill>>,
+  mut texts: Query<&mut Text, With<ProgressText>>,
+) {
+  let (text, progress) = match (&autosave.reading, &autosave.restoring) {
+    (_, Some(restored)) => (
+      locale.format("autosave.restoring", &[("spawned", &restored.spawned), ("particles", &restored.particles)]),
+      restored.spawned as f32 / restored.particles.max(1) as f32,
+    ),
+    (Some(_), None) => (locale.get("autosave.reading").to_string(), 0.),
+    (None, None) => {
+      for entity in bars.iter() {
+        commands.entity(entity).despawn_recursive();
+      }
+      return;
+    },
+  };
+  if bars.is_empty() {
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+    commands
+      .spawn_bundle(NodeBundle {
+        style: Style {
+          size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+          flex_direction: FlexDirection::ColumnReverse,
+          justify_content: JustifyContent::Center,
+          align_items: AlignItems::Center,
+          ..Default::default()
+        },
+        color: Color::rgba(0., 0., 0., 0.6).into(),
+        ..Default::default()
+      })
+      .insert(ProgressBar)
+      .with_children(|parent| {
+        parent
+          .spawn_bundle(TextBundle {
+            style: Style { margin: Rect::all(Val::Px(8.)), ..Default::default() },
+            text: Text::with_section("", TextStyle { font, font_size: 24., color: Color::WHITE }, Default::default()),
+            ..Default::default()
+          })
+          .insert(ProgressText);
+        parent
+          .spawn_bundle(NodeBundle {
+            style: Style { size: Size::new(Val::Px(BAR_WIDTH), Val::Px(12.)), ..Default::default() },
+            color: Color::rgb(0.15, 0.15, 0.15).into(),
+            ..Default::default()
+          })
+          .with_children(|parent| {
+            parent
+              .spawn_bundle(NodeBundle {
+                style: Style { size: Size::new(Val::Px(0.), Val::Percent(100.)), ..Default::default() },
+                color: Color::rgb(0.35, 0.55, 0.35).into(),
+                ..Default::default()
+              })
+              .insert(ProgressFill);
+          });
+      });
+    return;
+  }
+
+  for mut style in fills.iter_mut() {
+    style.size.width = Val::Px(BAR_WIDTH * progress);
+  }
+  for mut section in texts.iter_mut() {
+    section.sections[0].value = text.clone();
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn autosave(
+  time: Res<Time>,
+  config: Res<Config>,
+  pool: Res<AsyncComputeTaskPool>,
+  settings: Res<SimulationSettings>,
+  spatial_index: Res<SpatialIndex>,
+  materials: Res<MaterialRegistry>,
+  mut autosave: ResMut<Autosave>,
+  particles: Query<(&Particle, &MaterialId)>,
+) {
+  if !config.autosave.enabled { return }
+  autosave.started = true;
+  autosave.since_save += time.delta_seconds();
+  if let Some(Err(error)) = poll(&mut autosave.saving) {
+    error!("Could not autosave: {}", error);
+  }
+  // A save that takes longer than the interval holds back the next one
+  if autosave.since_save < config.autosave.interval || autosave.saving.is_some() { return }
+  autosave.since_save = 0.;
+  // Past this point the last session's save is gone
+  autosave.recovered = None;
+  let directory = directory(&config);
+  let snapshot = snapshot(&spatial_index, &settings, &materials, &particles);
+  autosave.saving = Some(pool.spawn(async move { write_snapshot(directory, &snapshot) }));
+}
+
+// Saves right away, the game is about to close
+fn save_on_exit(
+  mut exit_events: EventReader<AppExit>,
+  config: Res<Config>,
+  settings: Res<SimulationSettings>,
+  spatial_index: Res<SpatialIndex>,
+  materials: Res<MaterialRegistry>,
+  mut autosave: ResMut<Autosave>,
+  particles: Query<(&Particle, &MaterialId)>,
+) {
+  if exit_events.iter().last().is_none() || !autosave.started { return }
+  // Both would write the same file
+  if let Some(saving) = autosave.saving.take() {
+    if let Err(error) = future::block_on(saving) {
+      error!("Could not autosave: {}", error);
+    }
+  }
+  let directory = directory(&config);
+  match write_snapshot(directory.clone(), &snapshot(&spatial_index, &settings, &materials, &particles)) {
+    Ok(()) => info!("Saved the world to {}", directory.display()),
+    Err(error) => error!("Could not save the world on exit: {}", error),
+  }
+}
@@ -0,0 +1,318 @@
+use std::{
+  alloc::{GlobalAlloc, Layout, System},
+  str::FromStr,
+  sync::atomic::{AtomicUsize, Ordering},
+  time::Instant,
+};
+
+use bevy::{app::AppExit, prelude::*};
+
+use crate::{
+  actions::Action,
+  clear_world,
+  material::{MaterialId, MaterialRegistry},
+  simulation::{SimulationClock, SimulationSettings, SimulationStep, TickProgress},
+  spawn_particle_with_velocity, SpatialIndex,
+};
+
+// Ticks after a respawn that are not timed, while the new particles are still being sorted into chunks
+const WARMUP_TICKS: usize = 5;
+// Ticks averaged for every particle count
+const SAMPLE_TICKS: usize = 20;
+// Particle count the benchmark starts from and multiplies by after every count that fit the budget
+const START_COUNT: u32 = 1000;
+const GROWTH: f32 = 1.5;
+// Counts past this are never tried, so a machine fast enough does not run out of memory instead
+const MAX_COUNT: u32 = 2_000_000;
+
+// Counts allocations for the benchmark to report, see `ParticlePool` for what keeps respawns and
+// churn from allocating
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    System.alloc(layout)
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    System.dealloc(ptr, layout)
+  }
+
+  unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    System.realloc(ptr, layout, new_size)
+  }
+}
+
+fn allocations() -> usize {
+  ALLOCATIONS.load(Ordering::Relaxed)
+}
+
+// Spawns large amounts of particles for testing performance. `Action::StressTest` or `--stress`
+// replaces the world with a `StressTest`, `--benchmark` keeps growing one until a tick takes longer
+// than the budget and reports the largest particle count that did not.
+pub struct BenchPlugin {
+  pub stress: Option<StressTest>,
+  // Milliseconds a tick may take
+  pub benchmark: Option<f32>,
+}
+
+impl Plugin for BenchPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .insert_resource(self.stress.unwrap_or_default())
+      .add_event::<StressTest>()
+      .add_system(trigger_stress_test)
+      .add_system(spawn_stress_test.after(SimulationStep::Commit));
+
+    // A benchmark spawns its own, growing the pattern of the one given
+    if let (Some(stress), None) = (self.stress, self.benchmark) {
+      app.add_startup_system(move |mut tests: EventWriter<StressTest>| tests.send(stress));
+    }
+
+    if let Some(budget) = self.benchmark {
+      app
+        .insert_resource(Benchmark::new(budget))
+        .add_startup_system(start_benchmark)
+        .add_system_set(SystemSet::new()
+          .with_run_criteria("fixed_tick")
+          .with_system(start_bench_tick.before(SimulationStep::Integrate))
+          .with_system(record_bench_tick.after(SimulationStep::Commit))
+        );
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StressPattern {
+  // Drops spread over the top half of the world
+  Rain,
+  // A solid square falling from the top
+  BlockDrop,
+  // Two blocks thrown at each other from the sides
+  Streams,
+}
+
+impl FromStr for StressPattern {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "rain" => Ok(Self::Rain),
+      "block" | "block-drop" => Ok(Self::BlockDrop),
+      "streams" => Ok(Self::Streams),
+      _ => Err(format!("unknown pattern {}, expected rain, block or streams", value)),
+    }
+  }
+}
+
+// Replaces the world with one just large enough for `count` particles spawned in `pattern`. As a
+// resource it is the test `Action::StressTest` spawns.
+#[derive(Clone, Copy, Debug)]
+pub struct StressTest {
+  pub pattern: StressPattern,
+  pub count: u32,
+}
+
+impl Default for StressTest {
+  fn default() -> Self {
+    Self { pattern: StressPattern::Rain, count: 2000 }
+  }
+}
+
+impl StressTest {
+  // Eight cells per particle, twice as wide as high, leaves room for the particles to fall and pile up
+  fn world_size(&self) -> (i32, i32) {
+    let width = ((self.count as f32 * 16.).sqrt().ceil() as i32).max(40);
+    (width, (width / 2).max(20))
+  }
+
+  // Cells and velocities of the particles, from the top down in cell order
+  fn particles(&self) -> Vec<(IVec2, MaterialId, Vec2)> {
+    let (width, height) = self.world_size();
+    let (left, right, top) = (-width / 2, width / 2, height / 2);
+    let count = self.count as i32;
+    let mut particles = Vec::with_capacity(self.count as usize);
+    match self.pattern {
+      StressPattern::Rain => {
+        // Every other cell of every other row, shifted by one on alternate rows
+        let per_row = (width / 2).max(1);
+        for index in 0..count {
+          let row = index / per_row;
+          let x = left + (index % per_row) * 2 + row % 2;
+          particles.push((IVec2::new(x, top - row * 2), MaterialId::WATER, Vec2::new(0., -4.)));
+        }
+      },
+      StressPattern::BlockDrop => {
+        let side = (count as f32).sqrt().ceil() as i32;
+        for index in 0..count {
+          let x = -side / 2 + index % side;
+          particles.push((IVec2::new(x, top - index / side), MaterialId::SAND, Vec2::ZERO));
+        }
+      },
+      StressPattern::Streams => {
+        let half = count / 2;
+        let side = (half as f32).sqrt().ceil() as i32;
+        for index in 0..count {
+          let (stream, index) = if index < half { (0, index) } else { (1, index - half) };
+          let (x, velocity, material) = if stream == 0 {
+            (left + index % side, Vec2::new(16., 0.), MaterialId::SAND)
+          } else {
+            (right - index % side, Vec2::new(-16., 0.), MaterialId::WATER)
+          };
+          particles.push((IVec2::new(x, top - index / side), material, velocity));
+        }
+      },
+    }
+    particles
+  }
+}
+
+fn trigger_stress_test(actions: Option<Res<Input<Action>>>, test: Res<StressTest>, mut tests: EventWriter<StressTest>) {
+  if actions.is_some_and(|actions| actions.just_pressed(Action::StressTest)) {
+    tests.send(*test);
+  }
+}
+
+fn spawn_stress_test(
+  mut commands: Commands,
+  materials: Res<MaterialRegistry>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut tests: EventReader<StressTest>,
+  benchmark: Option<ResMut<Benchmark>>,
+  // Cleared worlds are filled once the particles that were in them can be reused from the pool
+  mut pending: Local<Option<StressTest>>,
+) {
+  if let Some(test) = pending.filter(|_| spatial_index.pool.is_ready()) {
+    *pending = None;
+    let (width, height) = test.world_size();
+    let reused = spatial_index.pool.reused;
+    for (cell, material, velocity) in test.particles() {
+      if !spatial_index.is_free(cell) { continue }
+      spawn_particle_with_velocity(&mut commands, &mut spatial_index, &materials, cell, material, velocity);
+    }
+    info!(
+      "Spawned {} particles as {:?} in a {}x{} world, {} of them reusing pooled entities",
+      spatial_index.len(), test.pattern, width, height, spatial_index.pool.reused - reused,
+    );
+    if let Some(mut benchmark) = benchmark {
+      benchmark.spawned = true;
+    }
+  }
+  if let Some(test) = tests.iter().last() {
+    let (width, height) = test.world_size();
+    clear_world(&mut commands, &mut spatial_index, width, height);
+    *pending = Some(*test);
+  }
+}
+
+struct Benchmark {
+  budget: f32,
+  count: u32,
+  // Largest particle count whose ticks fit the budget so far
+  sustainable: Option<usize>,
+  // When the tick started, and the allocations made up to then
+  started: Option<(Instant, usize)>,
+  // Milliseconds and allocations of the ticks since the last respawn
+  ticks: Vec<(f32, usize)>,
+  // Allocations made up to asking for the current respawn, until the particles are in
+  respawned: Option<usize>,
+  // Whether the particles of the current respawn were spawned, they are in by the next tick
+  spawned: bool,
+  // Allocations it took to clear the world and spawn the current particles
+  respawn_allocations: usize,
+}
+
+impl Benchmark {
+  fn new(budget: f32) -> Self {
+    Self {
+      budget,
+      count: START_COUNT,
+      sustainable: None,
+      started: None,
+      ticks: Vec::new(),
+      respawned: None,
+      spawned: false,
+      respawn_allocations: 0,
+    }
+  }
+
+  fn respawn(&mut self, test: StressTest, tests: &mut EventWriter<StressTest>) {
+    self.respawned = Some(allocations());
+    tests.send(StressTest { count: self.count, ..test });
+  }
+}
+
+// Every frame runs exactly one tick, so how long a tick takes is not hidden by the timestep
+fn start_benchmark(
+  mut benchmark: ResMut<Benchmark>,
+  test: Res<StressTest>,
+  mut settings: ResMut<SimulationSettings>,
+  mut tests: EventWriter<StressTest>,
+) {
+  settings.deterministic = true;
+  settings.timestep = 1. / 60.;
+  info!("Benchmarking {:?} against {} ms per tick", test.pattern, benchmark.budget);
+  benchmark.respawn(*test, &mut tests);
+}
+
+fn start_bench_tick(clock: Res<SimulationClock>, mut benchmark: ResMut<Benchmark>) {
+  if !clock.ticked { return }
+  if benchmark.spawned {
+    benchmark.spawned = false;
+    if let Some(respawned) = benchmark.respawned.take() {
+      benchmark.respawn_allocations = allocations() - respawned;
+    }
+  }
+  benchmark.started = Some((Instant::now(), allocations()));
+}
+
+fn record_bench_tick(
+  progress: Res<TickProgress>,
+  spatial_index: Res<SpatialIndex>,
+  test: Res<StressTest>,
+  mut benchmark: ResMut<Benchmark>,
+  mut tests: EventWriter<StressTest>,
+  mut exit: EventWriter<AppExit>,
+) {
+  if !progress.is_complete() { return }
+  let started = match benchmark.started.take() {
+    Some(started) => started,
+    None => return,
+  };
+  // Ticks before the new particles are in are not timed
+  if benchmark.respawned.is_some() { return }
+  let (started, allocated) = started;
+  benchmark.ticks.push((started.elapsed().as_secs_f32() * 1000., allocations() - allocated));
+  if benchmark.ticks.len() < WARMUP_TICKS + SAMPLE_TICKS { return }
+
+  let samples = &benchmark.ticks[WARMUP_TICKS..];
+  let average = samples.iter().map(|(milliseconds, _)| milliseconds).sum::<f32>() / SAMPLE_TICKS as f32;
+  let allocated = samples.iter().map(|(_, allocations)| allocations).sum::<usize>() / SAMPLE_TICKS;
+  let particles = spatial_index.len();
+  info!(
+    "{} particles: {:.2} ms and {} allocations per tick, {} allocations to respawn",
+    particles, average, allocated, benchmark.respawn_allocations,
+  );
+  benchmark.ticks.clear();
+  if average <= benchmark.budget {
+    benchmark.sustainable = Some(particles);
+    if benchmark.count < MAX_COUNT {
+      benchmark.count = ((benchmark.count as f32 * GROWTH) as u32).min(MAX_COUNT);
+      benchmark.respawn(*test, &mut tests);
+      return;
+    }
+  }
+
+  match benchmark.sustainable {
+    Some(sustainable) => info!("Sustainable particle count within {} ms per tick: {}", benchmark.budget, sustainable),
+    None => info!("Not even {} particles fit within {} ms per tick", particles, benchmark.budget),
+  }
+  exit.send(AppExit);
+}
@@ -0,0 +1,372 @@
+use bevy::{prelude::*, utils::{HashMap, HashSet}};
+
+use crate::{
+  console::{ConsoleApp, ConsoleCommand, ConsoleLog},
+  despawn_particle,
+  groups::Tag,
+  simulation::{Boundary, SimulationClock, SimulationSettings, SimulationStep},
+  BoundsExt, Particle, SpatialIndex,
+};
+
+pub struct BoidsPlugin;
+
+impl Plugin for BoidsPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .add_event::<CaughtEvent>()
+      .add_console_command("steer", "steer <group> <seek x y [weight]|arrive x y [weight]|path x y [x y ...]|off>, directs the group's boids")
+      .add_system(run_steer_command)
+      .add_system_set(SystemSet::new()
+        .with_run_criteria("fixed_tick")
+        // Steering replaces the velocity the collision response left, so it runs before the tick
+        // moves anything
+        .with_system(steer_boids.label("boids").label(SimulationStep::Integrate))
+        .with_system(catch_prey.after(SimulationStep::Commit))
+      );
+  }
+}
+
+// Set on materials whose particles steer themselves instead of falling
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BoidRole {
+  Predator,
+  Prey,
+}
+
+// A particle that flies, flocking with the boids of its own role. Gravity does not apply to it.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Boid {
+  // Cells other boids are noticed within
+  pub perception: f32,
+  // Cells per second
+  pub max_speed: f32,
+  // Cells ahead of its heading checked for walls and static cells to steer around
+  pub look_ahead: f32,
+  // Cells per second it turns away at from an obstacle right in front of it
+  pub avoidance: f32,
+}
+
+impl Default for Boid {
+  fn default() -> Self {
+    Self { perception: 5., max_speed: 4., look_ahead: 3., avoidance: 8. }
+  }
+}
+
+impl Boid {
+  // Pushes away from the first obstacle along the heading, harder the closer it is, so boids turn
+  // before they reach it instead of bouncing off
+  fn avoid_obstacles(&self, position: Vec2, velocity: Vec2, spatial_index: &SpatialIndex, boundary: Boundary) -> Vec2 {
+    let heading = velocity.normalize_or_zero();
+    if heading == Vec2::ZERO { return Vec2::ZERO }
+
+    let samples = self.look_ahead.ceil().max(1.) as i32;
+    for step in 1..=samples {
+      let distance = (step as f32).min(self.look_ahead);
+      let ahead = position + heading * distance;
+      // Edges are only in the way when they do not wrap around
+      let away = match spatial_index.outside(ahead) {
+        Some(normal) if boundary != Boundary::Wrap => normal,
+        _ if spatial_index.is_collider(spatial_index.wrap(ahead).floor().as_ivec2()) => {
+          (position - ahead).normalize_or_zero()
+        },
+        _ => continue,
+      };
+      return away * self.avoidance * (1. - (distance - 1.) / self.look_ahead);
+    }
+    Vec2::ZERO
+  }
+}
+
+// Steers toward the nearest prey it can see
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct Predator;
+
+// Flees the nearest predator it can see, faster than it normally flies
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct Prey;
+
+// Where a boid is headed
+#[derive(Clone, Debug)]
+pub enum Goal {
+  // Flies at the point at full speed
+  Seek(Vec2),
+  // Flies at the point and slows down within `slowing_radius` cells of it to stop there
+  Arrive { point: Vec2, slowing_radius: f32 },
+  // Arrives at each cell in turn, moving on once within `radius` cells of it, and stays at the last
+  Path { waypoints: Vec<IVec2>, radius: f32, next: usize },
+}
+
+// Directs a boid somewhere, on top of its flocking. Predators chasing prey and prey fleeing
+// predators ignore it until the chase is over.
+#[derive(Component, Clone, Debug)]
+pub struct SteeringTarget {
+  pub goal: Goal,
+  // How much the goal counts against the flocking, 1 weighs them equally
+  pub weight: f32,
+}
+
+impl SteeringTarget {
+  pub fn seek(point: Vec2) -> Self {
+    Self { goal: Goal::Seek(point), weight: 1. }
+  }
+
+  pub fn arrive(point: Vec2) -> Self {
+    Self { goal: Goal::Arrive { point, slowing_radius: 3. }, weight: 1. }
+  }
+
+  pub fn path(waypoints: Vec<IVec2>) -> Self {
+    Self { goal: Goal::Path { waypoints, radius: 1., next: 0 }, weight: 1. }
+  }
+
+  pub fn with_weight(mut self, weight: f32) -> Self {
+    self.weight = weight;
+    self
+  }
+
+  // Velocity that heads for the goal from `position`, advancing along a path as waypoints are reached
+  fn desired_velocity(&mut self, position: Vec2, max_speed: f32) -> Vec2 {
+    let (point, slowing_radius) = match &mut self.goal {
+      Goal::Seek(point) => return (*point - position).normalize_or_zero() * max_speed,
+      Goal::Arrive { point, slowing_radius } => (*point, *slowing_radius),
+      Goal::Path { waypoints, radius, next } => {
+        while *next + 1 < waypoints.len() && waypoints[*next].as_vec2().distance(position) <= *radius {
+          *next += 1;
+        }
+        match waypoints.get(*next) {
+          Some(point) => (point.as_vec2(), *radius),
+          None => return Vec2::ZERO,
+        }
+      },
+    };
+    let offset = point - position;
+    let distance = offset.length();
+    let speed = if distance < slowing_radius { max_speed * distance / slowing_radius } else { max_speed };
+    offset.normalize_or_zero() * speed
+  }
+}
+
+// A predator got within a cell of a prey, which has been despawned. Read by embedders through
+// `ArrakoidsHooks::on_caught`, which is how the console's `watch caught` prints it.
+#[derive(Clone, Copy, Debug)]
+pub struct CaughtEvent {
+  pub predator: Entity,
+  pub prey: Entity,
+  pub cell: IVec2,
+}
+
+// How much faster than `Boid::max_speed` fleeing prey fly
+const FLEE_BURST: f32 = 1.5;
+// Boids closer than this push each other apart
+const SEPARATION: f32 = 1.5;
+// Cells per second a boid steers at for every cell it is closer than `SEPARATION` or away from its
+// flock's center
+const CORRECTION: f32 = 4.;
+// Fraction of the steering applied each tick, lower turns more gradually
+const STEERING: f32 = 0.3;
+
+#[derive(Clone, Copy)]
+struct Snapshot {
+  position: Vec2,
+  velocity: Vec2,
+  predator: bool,
+}
+
+// Every boid steers from the same snapshot of the others, so the order they are updated in does
+// not matter. The boids it sees are looked up in the cells around it rather than among all of them.
+type Boids<'w, 's> = Query<'w, 's, (Entity, &'static mut Particle, &'static Boid, Option<&'static Predator>, Option<&'static Prey>, Option<&'static mut SteeringTarget>)>;
+
+fn steer_boids(
+  clock: Res<SimulationClock>,
+  settings: Res<SimulationSettings>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut boids: Boids,
+) {
+  // A budgeted tick runs its systems over several frames but should only steer once
+  if !clock.ticked { return }
+  let snapshot: HashMap<Entity, Snapshot> = boids
+    .iter()
+    .map(|(entity, particle, _, predator, _, _)| (entity, Snapshot {
+      position: particle.position,
+      velocity: particle.velocity,
+      predator: predator.is_some(),
+    }))
+    .collect();
+
+  // In a wrapping world the nearest copy of a boid may be across an edge
+  let (min, max) = (spatial_index.bounds().min(), spatial_index.bounds().max());
+  let size = max - min;
+  let wrap = settings.boundary == Boundary::Wrap;
+  let offset_between = |from: Vec2, to: Vec2| {
+    let offset = to - from;
+    if wrap { offset - size * (offset / size).round() } else { offset }
+  };
+  // Points to look around for what a boid at `position` sees within `radius`: its own, and where
+  // that reaches past an edge of a wrapping world, the same point moved to the other side
+  let lookouts = |position: Vec2, radius: f32| {
+    let shifts = |at: f32, min: f32, max: f32, size: f32| {
+      [(true, 0.), (wrap && at - radius < min, size), (wrap && at + radius > max, -size)]
+        .into_iter()
+        .filter_map(|(sees, shift)| sees.then_some(shift))
+    };
+    shifts(position.y, min.y, max.y, size.y)
+      .flat_map(move |y| shifts(position.x, min.x, max.x, size.x).map(move |x| position + Vec2::new(x, y)))
+      .collect::<Vec<_>>()
+  };
+
+  for (entity, mut particle, boid, predator, prey, target) in boids.iter_mut() {
+    let lookouts = lookouts(particle.position, boid.perception);
+    let neighbors = lookouts
+      .iter()
+      .flat_map(|lookout| {
+        let reach = Vec2::splat(boid.perception);
+        spatial_index.query((*lookout - reach).floor().as_ivec2(), (*lookout + reach).floor().as_ivec2())
+      })
+      .filter(|(_, other)| *other != entity)
+      .filter_map(|(_, other)| snapshot.get(&other))
+      .map(|other| (other, offset_between(particle.position, other.position)))
+      .filter(|(_, offset)| offset.length_squared() <= boid.perception * boid.perception);
+
+    let mut separation = Vec2::ZERO;
+    let mut heading = Vec2::ZERO;
+    // Relative to this boid
+    let mut center = Vec2::ZERO;
+    let mut flock = 0;
+    for (other, offset) in neighbors {
+      let distance = offset.length();
+      if distance < SEPARATION {
+        separation -= offset.normalize_or_zero() * (SEPARATION - distance) * CORRECTION;
+      }
+      if other.predator == predator.is_some() {
+        heading += other.velocity;
+        center += offset;
+        flock += 1;
+      }
+    }
+
+    // The nearest boid of the other role it can see, the prey a predator chases or the predator a
+    // prey flees
+    let hunted = |other: Entity| snapshot.get(&other).is_some_and(|other| other.predator != predator.is_some());
+    let nearest = lookouts
+      .iter()
+      .flat_map(|lookout| spatial_index.k_nearest(*lookout, 1, boid.perception, |_, other| hunted(other)))
+      .filter_map(|(_, other)| snapshot.get(&other))
+      .map(|other| offset_between(particle.position, other.position))
+      .filter(|offset| offset.length_squared() <= boid.perception * boid.perception)
+      .min_by(|a, b| a.length_squared().total_cmp(&b.length_squared()));
+
+    let mut desired = particle.velocity + separation;
+    if flock > 0 {
+      desired += heading / flock as f32 - particle.velocity;
+      desired += center / flock as f32 * 0.1 * CORRECTION;
+    }
+    let mut max_speed = boid.max_speed;
+    match nearest {
+      Some(offset) if predator.is_some() => desired = offset.normalize_or_zero() * max_speed,
+      Some(offset) if prey.is_some() => {
+        max_speed *= FLEE_BURST;
+        desired = -offset.normalize_or_zero() * max_speed;
+      },
+      _ => match target {
+        // Weighted average of the two, so the goal can slow a boid down to arrive
+        Some(mut target) => {
+          let goal = target.desired_velocity(particle.position, max_speed);
+          desired = (desired.clamp_length_max(max_speed) + goal * target.weight) / (1. + target.weight);
+        },
+        // Boids cruise at full speed, one that has come to a stop sets off to the right
+        None => desired = if desired == Vec2::ZERO { Vec2::X } else { desired.normalize() } * max_speed,
+      },
+    }
+
+    desired += boid.avoid_obstacles(particle.position, particle.velocity, &spatial_index, settings.boundary);
+
+    let velocity = particle.velocity + (desired - particle.velocity) * STEERING;
+    particle.velocity = velocity.clamp_length_max(max_speed);
+    // Boids never settle, so their chunks have to stay awake
+    spatial_index.wake(particle.position.floor().as_ivec2());
+  }
+}
+
+fn catch_prey(
+  mut commands: Commands,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut caught_events: EventWriter<CaughtEvent>,
+  predators: Query<(Entity, &Particle), With<Predator>>,
+  prey: Query<&Particle, With<Prey>>,
+) {
+  let mut caught = HashSet::default();
+  let mut hunters: Vec<(Entity, IVec2)> = predators
+    .iter()
+    .map(|(entity, particle)| (entity, particle.position.floor().as_ivec2()))
+    .collect();
+  // Cell order decides which predator gets a prey two of them reach on the same tick
+  hunters.sort_unstable_by_key(|(_, cell)| (cell.y, cell.x));
+
+  for (predator, cell) in hunters {
+    let target = (-1..=1)
+      .flat_map(|y| (-1..=1).map(move |x| cell + IVec2::new(x, y)))
+      .filter_map(|neighbor| spatial_index.get(&neighbor).copied())
+      .find(|entity| !caught.contains(entity) && prey.get(*entity).is_ok());
+    let target = match target {
+      Some(target) => target,
+      None => continue,
+    };
+
+    if let Ok(particle) = prey.get(target) {
+      let prey_cell = particle.position.floor().as_ivec2();
+      despawn_particle(&mut commands, &mut spatial_index, target, particle);
+      caught.insert(target);
+      caught_events.send(CaughtEvent { predator, prey: target, cell: prey_cell });
+    }
+  }
+}
+
+// Gives the boids of a group a `SteeringTarget`, or takes it away
+fn run_steer_command(
+  mut commands: Commands,
+  mut entered: EventReader<ConsoleCommand>,
+  mut log: Option<ResMut<ConsoleLog>>,
+  boids: Query<(Entity, &Tag), With<Boid>>,
+) {
+  for command in entered.iter().filter(|command| command.name == "steer") {
+    let numbers: Option<Vec<f32>> = command.args.get(2..).unwrap_or_default().iter()
+      .map(|word| word.parse::<f32>().ok().filter(|value| value.is_finite()))
+      .collect();
+    // `Some(None)` takes the target away
+    let target = match (command.args.get(1).map(|goal| goal.to_lowercase()).as_deref(), numbers.as_deref()) {
+      (Some("seek"), Some(&[x, y, ref weight @ ..])) if weight.len() < 2 => {
+        Some(Some(SteeringTarget::seek(Vec2::new(x, y)).with_weight(weight.first().copied().unwrap_or(1.))))
+      },
+      (Some("arrive"), Some(&[x, y, ref weight @ ..])) if weight.len() < 2 => {
+        Some(Some(SteeringTarget::arrive(Vec2::new(x, y)).with_weight(weight.first().copied().unwrap_or(1.))))
+      },
+      (Some("path"), Some(points)) if !points.is_empty() && points.len() % 2 == 0 => {
+        let waypoints = points.chunks(2).map(|point| Vec2::new(point[0], point[1]).round().as_ivec2()).collect();
+        Some(Some(SteeringTarget::path(waypoints)))
+      },
+      (Some("off"), _) => Some(None),
+      _ => None,
+    };
+
+    let (group, target) = match (command.args.first(), target) {
+      (Some(group), Some(target)) => (group, target),
+      _ => {
+        if let Some(log) = log.as_mut() {
+          log.print("steer <group> <seek x y [weight]|arrive x y [weight]|path x y [x y ...]|off>");
+        }
+        continue;
+      },
+    };
+    let mut steered = 0;
+    for (entity, _) in boids.iter().filter(|(_, tag)| tag.0 == *group) {
+      match &target {
+        Some(target) => { commands.entity(entity).insert(target.clone()); },
+        None => { commands.entity(entity).remove::<SteeringTarget>(); },
+      }
+      steered += 1;
+    }
+    if let Some(log) = log.as_mut() {
+      let verb = if target.is_some() { "Steering" } else { "Stopped steering" };
+      log.print(format!("{} {} boids in {}", verb, steered, group));
+    }
+  }
+}
@@ -0,0 +1,57 @@
+use bevy::prelude::*;
+
+use crate::{actions::{Action, Sticks}, cursor::MainCamera, simulation::SimulationFocus, AppState, BoundsExt, Particle, SpatialIndex};
+
+// Pans the main camera with the pan actions or the right stick, within the world bounds unless the
+// world is infinite. Where it looks is the `SimulationFocus`.
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .add_system_set(SystemSet::on_update(AppState::Running).with_system(pan_camera))
+      .add_system(update_focus);
+  }
+}
+
+// Cells per second
+const PAN_SPEED: f32 = 24.;
+
+fn pan_camera(
+  time: Res<Time>,
+  actions: Res<Input<Action>>,
+  sticks: Res<Sticks>,
+  spatial_index: Res<SpatialIndex>,
+  mut cameras: Query<&mut Transform, With<MainCamera>>,
+) {
+  let mut direction = sticks.right;
+  for (action, offset) in [
+    (Action::PanLeft, -Vec2::X),
+    (Action::PanRight, Vec2::X),
+    (Action::PanUp, Vec2::Y),
+    (Action::PanDown, -Vec2::Y),
+  ] {
+    if actions.pressed(action) {
+      direction += offset;
+    }
+  }
+  if direction == Vec2::ZERO { return }
+
+  for mut transform in cameras.iter_mut() {
+    let position = transform.translation.truncate() + direction * PAN_SPEED * Particle::SPRITE_SIZE * time.delta_seconds();
+    transform.translation = clamp_to_world(position, &spatial_index).extend(transform.translation.z);
+  }
+}
+
+// Keeps a camera position over the world, unless the world is infinite
+pub fn clamp_to_world(position: Vec2, spatial_index: &SpatialIndex) -> Vec2 {
+  if spatial_index.infinite { return position }
+  let bounds = spatial_index.bounds();
+  position.clamp(bounds.min() * Particle::SPRITE_SIZE, bounds.max() * Particle::SPRITE_SIZE)
+}
+
+fn update_focus(cameras: Query<&Transform, (With<MainCamera>, Changed<Transform>)>, mut focus: ResMut<SimulationFocus>) {
+  if let Ok(transform) = cameras.get_single() {
+    focus.0 = Some((transform.translation.truncate() / Particle::SPRITE_SIZE).floor().as_ivec2());
+  }
+}
@@ -0,0 +1,146 @@
+use std::{fs, path::PathBuf, thread, time::{SystemTime, UNIX_EPOCH}};
+
+use bevy::prelude::*;
+use image::{codecs::gif::{GifEncoder, Repeat}, Delay, Frame, Rgba, RgbaImage};
+
+use crate::{actions::Action, config::Config, simulation::SimulationStep, BoundsExt, Particle, SpatialIndex};
+
+pub struct CapturePlugin;
+
+impl Plugin for CapturePlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<Recording>()
+      .add_system(take_screenshot.after(SimulationStep::Commit))
+      .add_system(record_frames.after(SimulationStep::Commit));
+  }
+}
+
+// Frames are drawn from the world's cells rather than read back from the GPU, so captures show
+// the simulation at `CaptureConfig::scale` pixels per cell without any UI
+fn render_frame(
+  spatial_index: &SpatialIndex,
+  particles: &Query<(&Particle, &Sprite)>,
+  background: Color,
+  scale: u32,
+) -> RgbaImage {
+  let min = spatial_index.bounds().min().floor().as_ivec2();
+  let max = spatial_index.bounds().max().floor().as_ivec2();
+  let size = (max - min + IVec2::ONE).as_uvec2();
+  let scale = scale.max(1);
+  let mut frame = RgbaImage::from_pixel(size.x * scale, size.y * scale, pixel(background));
+
+  for (particle, sprite) in particles.iter() {
+    let cell = particle.position.floor().as_ivec2() - min;
+    if cell.x < 0 || cell.y < 0 || cell.x as u32 >= size.x || cell.y as u32 >= size.y { continue }
+
+    // Image rows run top to bottom while the world's y axis points up
+    let (x, y) = (cell.x as u32 * scale, (size.y - 1 - cell.y as u32) * scale);
+    let color = blend(sprite.color, background);
+    for offset in 0..scale * scale {
+      frame.put_pixel(x + offset % scale, y + offset / scale, color);
+    }
+  }
+  frame
+}
+
+fn pixel(color: Color) -> Rgba<u8> {
+  let [r, g, b, a] = color.as_rgba_f32();
+  Rgba([(r * 255.) as u8, (g * 255.) as u8, (b * 255.) as u8, (a * 255.) as u8])
+}
+
+// Faded particles are drawn over the background, captures themselves are opaque
+fn blend(color: Color, background: Color) -> Rgba<u8> {
+  let [r, g, b, a] = color.as_rgba_f32();
+  let [br, bg, bb, _] = background.as_rgba_f32();
+  pixel(Color::rgb(r * a + br * (1. - a), g * a + bg * (1. - a), b * a + bb * (1. - a)))
+}
+
+fn capture_path(config: &Config, name: &str, extension: &str) -> PathBuf {
+  let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+  PathBuf::from(&config.capture.directory).join(format!("{}-{}.{}", name, timestamp, extension))
+}
+
+fn take_screenshot(
+  actions: Res<Input<Action>>,
+  config: Res<Config>,
+  clear_color: Res<ClearColor>,
+  spatial_index: Res<SpatialIndex>,
+  particles: Query<(&Particle, &Sprite)>,
+) {
+  if !actions.just_pressed(Action::Screenshot) { return }
+  let frame = render_frame(&spatial_index, &particles, clear_color.0, config.capture.scale);
+  let path = capture_path(&config, "screenshot", "png");
+
+  // Encoding is slow enough to drop frames, so it happens off the main thread
+  thread::spawn(move || {
+    let result = path.parent().map_or(Ok(()), fs::create_dir_all).map_err(image::ImageError::IoError)
+      .and_then(|_| frame.save(&path));
+    match result {
+      Ok(()) => info!("Saved screenshot to {}", path.display()),
+      Err(error) => error!("Could not save screenshot to {}: {}", path.display(), error),
+    }
+  });
+}
+
+#[derive(Default)]
+struct Recording {
+  frames: Vec<RgbaImage>,
+  // Seconds since startup the recording started at and its latest frame was taken at, `None`
+  // while not recording
+  started: Option<f64>,
+  last_frame: f64,
+}
+
+fn record_frames(
+  actions: Res<Input<Action>>,
+  config: Res<Config>,
+  time: Res<Time>,
+  clear_color: Res<ClearColor>,
+  spatial_index: Res<SpatialIndex>,
+  particles: Query<(&Particle, &Sprite)>,
+  mut recording: ResMut<Recording>,
+) {
+  let now = time.seconds_since_startup();
+  let toggled = actions.just_pressed(Action::Record);
+  let started = match recording.started {
+    Some(started) => started,
+    None => {
+      if toggled {
+        info!("Recording {} seconds", config.capture.seconds);
+        recording.started = Some(now);
+        recording.last_frame = f64::NEG_INFINITY;
+      }
+      return;
+    },
+  };
+
+  let frame_interval = 1. / config.capture.fps.max(1.) as f64;
+  if now - recording.last_frame >= frame_interval {
+    let frame = render_frame(&spatial_index, &particles, clear_color.0, config.capture.scale);
+    recording.frames.push(frame);
+    recording.last_frame = now;
+  }
+  // Pressing record again stops early
+  if !toggled && now - started < config.capture.seconds as f64 { return }
+
+  recording.started = None;
+  let frames = std::mem::take(&mut recording.frames);
+  let delay = Delay::from_numer_denom_ms(1000, config.capture.fps.max(1.) as u32);
+  let path = capture_path(&config, "recording", "gif");
+  thread::spawn(move || {
+    let count = frames.len();
+    let result = path.parent().map_or(Ok(()), fs::create_dir_all)
+      .and_then(|_| fs::File::create(&path))
+      .map_err(image::ImageError::IoError)
+      .and_then(|file| {
+        let mut encoder = GifEncoder::new(file);
+        encoder.set_repeat(Repeat::Infinite)?;
+        encoder.encode_frames(frames.into_iter().map(|frame| Frame::from_parts(frame, 0, 0, delay)))
+      });
+    match result {
+      Ok(()) => info!("Saved {} frames to {}", count, path.display()),
+      Err(error) => error!("Could not save recording to {}: {}", path.display(), error),
+    }
+  });
+}
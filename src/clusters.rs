@@ -0,0 +1,354 @@
+use std::f32::consts::{PI, TAU};
+
+use bevy::{math::Mat2, prelude::*, utils::HashMap};
+
+use crate::{
+  layers::CollisionLayers,
+  material::{MaterialId, MaterialRegistry},
+  simulation::{cpu_backend, SimulationSettings, SimulationStep},
+  spawn_particle_with_velocity, Collision, Contacts, Particle, Resting, SpatialIndex, Static,
+};
+
+// Blocks of particles that move as one rigid body, turning as well as moving. The members go
+// through collisions like any particle, but what the contact solver did to each of them is then
+// applied to the cluster as a whole at that member, with the mass of the whole cluster behind it. A
+// block landing on a corner is stopped at the corner and tips over around it rather than stopping
+// as a whole, and one lying on the ground is held up along its whole side. The members are then
+// given the velocities of the moving and turning block at their place in it.
+pub struct ClustersPlugin;
+
+impl Plugin for ClustersPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .add_system_set(SystemSet::new()
+        .with_run_criteria(RunCriteria::pipe("fixed_tick", cpu_backend))
+        .with_system(brace_clusters.after(SimulationStep::Collide).before(SimulationStep::Resolve))
+        .with_system(tumble_clusters.after(SimulationStep::Resolve).before(SimulationStep::Commit))
+      )
+      .add_system(draw_clusters.after(SimulationStep::Commit));
+  }
+}
+
+// Share of a contact's push that it can hold back sliding along it with
+const FRICTION: f32 = 0.6;
+
+// Share of the way back into shape a member that strayed from it is moved in a tick
+const SHAPE_STIFFNESS: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug)]
+struct Member {
+  entity: Entity,
+  // From the center of mass, as the cluster sits unturned
+  offset: Vec2,
+  // What the cluster set its velocity to last tick, anything that changed it since pushed on the
+  // cluster
+  velocity: Vec2,
+  // Its velocity once the tick's collisions were found, before they were solved
+  before_contacts: Option<Vec2>,
+}
+
+#[derive(Component, Clone, Debug)]
+pub struct RigidCluster {
+  members: Vec<Member>,
+  pub velocity: Vec2,
+  // Counter-clockwise from how it was spawned, in radians and radians per second
+  pub angle: f32,
+  pub angular_velocity: f32,
+}
+
+impl RigidCluster {
+  // Members at their positions, moving at `velocity` and turning at `angular_velocity`
+  fn new(members: &[(Entity, Vec2)], velocity: Vec2, angular_velocity: f32) -> Self {
+    let center = members.iter().fold(Vec2::ZERO, |sum, (_, position)| sum + *position) / members.len() as f32;
+    Self {
+      members: members
+        .iter()
+        .map(|(entity, position)| Member { entity: *entity, offset: *position - center, velocity, before_contacts: None })
+        .collect(),
+      velocity,
+      angle: 0.,
+      angular_velocity,
+    }
+  }
+
+  // Moves the offsets onto the center of mass of `masses`, one for each member, after some left
+  fn recenter(&mut self, masses: &[f32]) {
+    let mass: f32 = masses.iter().sum();
+    let center = self.members.iter().zip(masses).fold(Vec2::ZERO, |sum, (member, mass)| sum + member.offset * *mass) / mass;
+    for member in self.members.iter_mut() {
+      member.offset -= center;
+    }
+  }
+}
+
+// Marks the particles of a cluster, they are drawn as sprites whatever the renderer so they can turn
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct ClusterMember;
+
+// Spawns `material` on the free `cells` as a rigid cluster moving at `velocity` and turning at
+// `angular_velocity` radians per second. Fixed materials and anything under two particles are
+// spawned loose. Returns the cluster's entity.
+pub fn spawn_cluster(
+  commands: &mut Commands,
+  spatial_index: &mut SpatialIndex,
+  materials: &MaterialRegistry,
+  cells: impl IntoIterator<Item = IVec2>,
+  material: MaterialId,
+  velocity: Vec2,
+  angular_velocity: f32,
+) -> Option<Entity> {
+  let mut members: Vec<(Entity, Vec2)> = Vec::new();
+  for cell in cells {
+    if !spatial_index.is_free(cell) { continue }
+    let entity = spawn_particle_with_velocity(commands, spatial_index, materials, cell, material, velocity);
+    members.push((entity, cell.as_vec2()));
+  }
+  if materials.get(material).fixed || members.len() < 2 { return None }
+
+  let cluster = commands.spawn().insert(RigidCluster::new(&members, velocity, angular_velocity)).id();
+  for (entity, _) in members {
+    commands.entity(entity).insert(ClusterMember);
+  }
+  Some(cluster)
+}
+
+// Keeps the members' velocities from before the contact solver gets to them
+fn brace_clusters(
+  contacts: Res<Contacts>,
+  mut clusters: Query<&mut RigidCluster>,
+  particles: Query<&Particle>,
+) {
+  if !contacts.awaits_solver() { return }
+  for mut cluster in clusters.iter_mut() {
+    for member in cluster.members.iter_mut() {
+      member.before_contacts = particles.get(member.entity).ok().map(|particle| particle.velocity);
+    }
+  }
+}
+
+// A collision of a member, at its offset from the cluster's center, with `normal` pointing away
+// from what it ran into
+struct Push {
+  offset: Vec2,
+  normal: Vec2,
+  other_velocity: Vec2,
+  // Zero for the world and anything else that doesn't give way
+  other_inverse_mass: f32,
+  restitution: f32,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn tumble_clusters(
+  mut commands: Commands,
+  contacts: Res<Contacts>,
+  resting: Res<Resting>,
+  settings: Res<SimulationSettings>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut clusters: Query<(Entity, &mut RigidCluster)>,
+  mut particles: Query<&mut Particle, Without<Static>>,
+  mut layers: Query<&mut CollisionLayers>,
+  tagged: Query<(), With<ClusterMember>>,
+) {
+  if !contacts.is_solved() { return }
+  let delta = settings.tick_delta();
+
+  for (entity, mut cluster) in clusters.iter_mut() {
+    // Contacts stay solved over the remaining frames of a budgeted tick, the cluster moves once
+    if cluster.members.iter().all(|member| member.before_contacts.is_none()) { continue }
+
+    // Members that were removed or froze in place leave the cluster
+    let count = cluster.members.len();
+    let mut leave = |member: Entity| {
+      if tagged.get(member).is_err() { return }
+      commands.entity(member).remove::<ClusterMember>();
+      if let Ok(mut layers) = layers.get_mut(member) {
+        layers.group = None;
+      }
+    };
+    for member in cluster.members.iter().filter(|member| particles.get(member.entity).is_err()) {
+      leave(member.entity);
+    }
+    cluster.members.retain(|member| particles.get(member.entity).is_ok());
+    if cluster.members.len() < 2 {
+      for member in cluster.members.iter() {
+        leave(member.entity);
+      }
+      commands.entity(entity).despawn();
+      continue;
+    }
+    // Members pass through each other, they only ever move along with the rest, see `CollisionLayers`
+    for member in cluster.members.iter() {
+      if let Ok(mut layers) = layers.get_mut(member.entity) {
+        if layers.group != Some(entity) {
+          layers.group = Some(entity);
+        }
+      }
+    }
+
+    let states: Vec<(Vec2, f32, f32)> = cluster.members
+      .iter()
+      .filter_map(|member| particles.get(member.entity).ok())
+      .map(|particle| (particle.position, particle.mass.max(f32::EPSILON), particle.elasticity))
+      .collect();
+    if cluster.members.len() != count {
+      let masses: Vec<f32> = states.iter().map(|(_, mass, _)| *mass).collect();
+      cluster.recenter(&masses);
+    }
+    let mass: f32 = states.iter().map(|(_, mass, _)| mass).sum();
+    let center = states.iter().fold(Vec2::ZERO, |sum, (position, mass, _)| sum + *position * *mass) / mass;
+    // Every cell is a square of its own as well, so a cluster of two still resists turning
+    let inertia: f32 = cluster.members
+      .iter()
+      .zip(states.iter())
+      .map(|(member, (_, mass, _))| mass * (member.offset.length_squared() + 1. / 6.))
+      .sum();
+
+    // Gravity and forces since the last tick changed the members' velocities, and push the cluster
+    let (mut velocity, mut angular_velocity) = (cluster.velocity, cluster.angular_velocity);
+    let mut holds = Vec::new();
+    let mut index = HashMap::default();
+    for (i, (member, (position, member_mass, _))) in cluster.members.iter().zip(states.iter()).enumerate() {
+      index.insert(member.entity, i);
+      // A member at rest was stopped where it lies rather than given gravity, and holds the cluster there
+      if resting.contains(member.entity) {
+        holds.push(*position - center);
+        continue;
+      }
+      if let Some(before) = member.before_contacts {
+        let change = (before - member.velocity) * *member_mass;
+        velocity += change / mass;
+        angular_velocity += (*position - center).perp_dot(change) / inertia;
+      }
+    }
+
+    // The members' collisions are solved again for the cluster as a whole, overriding how the
+    // solver bounced each of them on its own
+    let mut pushes = Vec::new();
+    for collision in contacts.iter() {
+      let (a, b) = collision.entities();
+      for (entity, other) in [(a, b), (b.unwrap_or(a), Some(a))] {
+        let i = match index.get(&entity) {
+          Some(i) => *i,
+          None => continue,
+        };
+        let (position, _, elasticity) = states[i];
+        let push = match (collision, other) {
+          (Collision::World(collision), _) => Push {
+            offset: position - center,
+            normal: collision.normal.normalize_or_zero(),
+            other_velocity: Vec2::ZERO,
+            other_inverse_mass: 0.,
+            restitution: elasticity,
+          },
+          (Collision::Particle(_), Some(other)) if other != entity => {
+            let (other_position, other_velocity, other_inverse_mass, other_elasticity) = match particles.get(other) {
+              Ok(particle) => (particle.position, particle.velocity, 1. / particle.mass.max(f32::EPSILON), particle.elasticity),
+              Err(_) => continue,
+            };
+            Push {
+              offset: position - center,
+              normal: (position.floor() - other_position.floor()).signum().normalize_or_zero(),
+              other_velocity,
+              other_inverse_mass,
+              restitution: settings.restitution.combine(elasticity, other_elasticity),
+            }
+          },
+          _ => continue,
+        };
+        if push.normal != Vec2::ZERO {
+          pushes.push(push);
+        }
+      }
+    }
+
+    // Sequential impulses, each contact pushes the cluster at its member through the mass the
+    // cluster has at that point along the contact's normal, and holds back sliding along it as far as
+    // friction goes. Members at rest stop the cluster where they are, whichever way it moves there.
+    // Bounces that wouldn't lift the cluster by a cell are left out like for resting particles, gravity
+    // would only bring it straight back.
+    let least_bounce = (2. * settings.gravity.length()).sqrt();
+    let bounces: Vec<f32> = pushes
+      .iter()
+      .map(|push| {
+        let approach = (velocity + push.offset.perp() * angular_velocity - push.other_velocity).dot(push.normal).min(0.);
+        let bounce = -approach * push.restitution;
+        if bounce < least_bounce { 0. } else { bounce }
+      })
+      .collect();
+    let mut impulses = vec![(0., 0.); pushes.len()];
+    for _ in 0..settings.solver_iterations.max(1) {
+      for ((push, bounce), (total, sliding)) in pushes.iter().zip(bounces.iter()).zip(impulses.iter_mut()) {
+        let relative = velocity + push.offset.perp() * angular_velocity - push.other_velocity;
+        let arm = push.offset.perp_dot(push.normal);
+        let effective_mass = 1. / (1. / mass + arm * arm / inertia + push.other_inverse_mass);
+        let impulse = ((bounce - relative.dot(push.normal)) * effective_mass).max(-*total);
+        *total += impulse;
+        velocity += push.normal * impulse / mass;
+        angular_velocity += arm * impulse / inertia;
+
+        let tangent = push.normal.perp();
+        let relative = velocity + push.offset.perp() * angular_velocity - push.other_velocity;
+        let arm = push.offset.perp_dot(tangent);
+        let effective_mass = 1. / (1. / mass + arm * arm / inertia + push.other_inverse_mass);
+        let limit = FRICTION * *total;
+        let held = (*sliding - relative.dot(tangent) * effective_mass).clamp(-limit, limit);
+        let impulse = held - *sliding;
+        *sliding = held;
+        velocity += tangent * impulse / mass;
+        angular_velocity += arm * impulse / inertia;
+      }
+      for offset in holds.iter() {
+        let point_velocity = velocity + offset.perp() * angular_velocity;
+        let normal = -point_velocity.normalize_or_zero();
+        let arm = offset.perp_dot(normal);
+        let impulse = point_velocity.length() / (1. / mass + arm * arm / inertia);
+        velocity += normal * impulse / mass;
+        angular_velocity += arm * impulse / inertia;
+      }
+    }
+    cluster.velocity = velocity;
+    cluster.angular_velocity = angular_velocity;
+
+    // Turned by its angular velocity rather than measured from the members, which sit on the grid
+    cluster.angle = (cluster.angle + angular_velocity * delta + PI).rem_euclid(TAU) - PI;
+
+    // Members move as the whole cluster does, and are pulled back into its shape where they strayed
+    let rotation = Mat2::from_angle(cluster.angle);
+    for (member, (position, _, _)) in cluster.members.iter_mut().zip(states.iter()) {
+      let goal = center + rotation * member.offset;
+      let point_velocity = velocity + (*position - center).perp() * angular_velocity;
+      let velocity = settings.settle_velocity(point_velocity + (goal - *position) * SHAPE_STIFFNESS / delta);
+      let velocity = velocity.clamp_length_max(settings.max_velocity());
+      member.velocity = velocity;
+      member.before_contacts = None;
+      if let Ok(mut particle) = particles.get_mut(member.entity) {
+        if particle.velocity == velocity { continue }
+        particle.velocity = velocity;
+        spatial_index.wake(particle.position.floor().as_ivec2());
+      }
+    }
+  }
+}
+
+// Members are drawn where the turned cluster has them and turned along with it, rather than
+// snapped to their cells
+fn draw_clusters(
+  clusters: Query<&RigidCluster>,
+  mut particles: Query<(&Particle, &mut Transform), With<ClusterMember>>,
+) {
+  for cluster in clusters.iter() {
+    let (mass, weighted) = cluster.members
+      .iter()
+      .filter_map(|member| particles.get(member.entity).ok())
+      .fold((0., Vec2::ZERO), |(mass, weighted), (particle, _)| (mass + particle.mass, weighted + particle.position * particle.mass));
+    if mass <= 0. { continue }
+    let center = weighted / mass;
+    let rotation = Mat2::from_angle(cluster.angle);
+    for member in cluster.members.iter() {
+      if let Ok((_, mut transform)) = particles.get_mut(member.entity) {
+        transform.translation = ((center + rotation * member.offset) * Particle::SPRITE_SIZE).extend(0.);
+        transform.rotation = Quat::from_rotation_z(cluster.angle);
+      }
+    }
+  }
+}
@@ -0,0 +1,275 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use bevy::{prelude::*, window::PresentMode};
+use serde::{de::{value::StrDeserializer, IntoDeserializer}, Deserialize, Deserializer};
+
+use crate::{
+  actions::{default_bindings, default_gamepad_bindings, Action, Binding},
+  erosion::ErosionConfig,
+  material::{MaterialId, MaterialRegistry},
+  phases::PhaseTransitions,
+  simulation::{CombineRule, Integrator, SimulationSettings, UpdateOrder},
+};
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct WindowConfig {
+  pub width: f32,
+  pub height: f32,
+  pub vsync: bool,
+}
+
+impl Default for WindowConfig {
+  fn default() -> Self {
+    Self { width: 1280., height: 720., vsync: true }
+  }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct CaptureConfig {
+  // Screenshots and recordings are written here, relative to the working directory
+  pub directory: String,
+  // Pixels per cell
+  pub scale: u32,
+  // Length of a recording, unless it is stopped early
+  pub seconds: f32,
+  pub fps: f32,
+}
+
+impl Default for CaptureConfig {
+  fn default() -> Self {
+    Self { directory: "captures".to_string(), scale: 4, seconds: 5., fps: 15. }
+  }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct AutosaveConfig {
+  pub enabled: bool,
+  // Seconds between saves while the world is running
+  pub interval: f32,
+  // Defaults to an `arrakoids` directory in the platform's data directory
+  pub directory: Option<String>,
+}
+
+impl Default for AutosaveConfig {
+  fn default() -> Self {
+    Self { enabled: true, interval: 60., directory: None }
+  }
+}
+
+// Settings a further world has other than the main world's, any left out are the same
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SettingsOverrides {
+  pub timestep: Option<f64>,
+  pub integrator: Option<Integrator>,
+  pub update_order: Option<UpdateOrder>,
+  pub restitution: Option<CombineRule>,
+  pub solver_iterations: Option<u32>,
+  pub substeps: Option<u32>,
+}
+
+impl SettingsOverrides {
+  pub fn apply(&self, settings: &mut SimulationSettings) {
+    if let Some(timestep) = self.timestep {
+      settings.timestep = timestep;
+    }
+    if let Some(integrator) = self.integrator {
+      settings.integrator = integrator;
+    }
+    if let Some(update_order) = self.update_order {
+      settings.update_order = update_order;
+    }
+    if let Some(restitution) = self.restitution {
+      settings.restitution = restitution;
+    }
+    if let Some(solver_iterations) = self.solver_iterations {
+      settings.solver_iterations = solver_iterations;
+    }
+    if let Some(substeps) = self.substeps {
+      settings.substeps = substeps;
+    }
+  }
+
+  // The overridden settings, to tell the worlds apart by
+  pub fn describe(&self) -> String {
+    let mut settings = Vec::new();
+    if let Some(timestep) = self.timestep {
+      settings.push(format!("timestep = {}", timestep));
+    }
+    if let Some(integrator) = self.integrator {
+      settings.push(format!("integrator = {:?}", integrator));
+    }
+    if let Some(update_order) = self.update_order {
+      settings.push(format!("update_order = {:?}", update_order));
+    }
+    if let Some(restitution) = self.restitution {
+      settings.push(format!("restitution = {:?}", restitution));
+    }
+    if let Some(solver_iterations) = self.solver_iterations {
+      settings.push(format!("solver_iterations = {}", solver_iterations));
+    }
+    if let Some(substeps) = self.substeps {
+      settings.push(format!("substeps = {}", substeps));
+    }
+    settings.join(", ")
+  }
+}
+
+// A further world simulated next to the main one, see `worlds`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct WorldConfig {
+  // Shown over the world
+  pub name: Option<String>,
+  // Loaded on startup, the world starts out empty without one
+  pub scenario: Option<String>,
+  // Part of the window the world is shown in, as fractions of it: left, bottom, width and height
+  pub viewport: [f32; 4],
+  // Of the world's `SimulationRng`, a random one without it
+  pub seed: Option<u64>,
+  #[serde(flatten)]
+  pub settings: SettingsOverrides,
+}
+
+impl Default for WorldConfig {
+  fn default() -> Self {
+    Self { name: None, scenario: None, viewport: [0.7, 0.7, 0.3, 0.3], seed: None, settings: SettingsOverrides::default() }
+  }
+}
+
+// Two worlds side by side that start out the same and only differ in their settings, so whatever
+// they end up with comes from the settings
+#[derive(Clone, Debug, Deserialize)]
+pub struct ComparisonConfig {
+  pub scenario: String,
+  #[serde(default)]
+  pub seed: u64,
+  #[serde(default)]
+  pub a: SettingsOverrides,
+  #[serde(default)]
+  pub b: SettingsOverrides,
+}
+
+impl ComparisonConfig {
+  // The left and the right world
+  pub fn worlds(&self) -> [WorldConfig; 2] {
+    let world = |name: &str, viewport: [f32; 4], settings: &SettingsOverrides| WorldConfig {
+      name: Some(format!("{}: {}", name, settings.describe())),
+      scenario: Some(self.scenario.clone()),
+      viewport,
+      seed: Some(self.seed),
+      settings: settings.clone(),
+    };
+    [world("A", [0., 0., 0.5, 1.], &self.a), world("B", [0.5, 0., 0.5, 1.], &self.b)]
+  }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+  pub window: WindowConfig,
+  pub capture: CaptureConfig,
+  pub autosave: AutosaveConfig,
+  pub worlds: Vec<WorldConfig>,
+  // Shows two worlds to compare instead of `worlds` when given
+  pub compare: Option<ComparisonConfig>,
+  pub default_material: String,
+  pub brush_size: i32,
+  // Code of the UI language, see `locale`
+  pub language: String,
+  // Keys that select the first materials of the registry in order
+  pub material_keys: Vec<KeyCode>,
+  #[serde(deserialize_with = "deserialize_action_map")]
+  pub keybindings: HashMap<Action, Binding>,
+  #[serde(deserialize_with = "deserialize_action_map")]
+  pub gamepad_bindings: HashMap<Action, GamepadButtonType>,
+  // Replaces the built in `ErosionRules` when given
+  pub erosion: Option<Vec<ErosionConfig>>,
+  // Replaces the default `PhaseTransitions` when given
+  pub phases: Option<PhaseTransitions>,
+  // Replaces the default `SimulationSettings::restitution` when given
+  pub restitution: Option<CombineRule>,
+}
+
+// TOML table keys are always strings, so parse each key back into an `Action`
+fn deserialize_action_map<'de, D: Deserializer<'de>, T: Deserialize<'de>>(deserializer: D) -> Result<HashMap<Action, T>, D::Error> {
+  HashMap::<String, T>::deserialize(deserializer)?
+    .into_iter()
+    .map(|(name, binding)| {
+      let key: StrDeserializer<D::Error> = name.as_str().into_deserializer();
+      Ok((Action::deserialize(key)?, binding))
+    })
+    .collect()
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Self {
+      window: WindowConfig::default(),
+      capture: CaptureConfig::default(),
+      autosave: AutosaveConfig::default(),
+      worlds: Vec::new(),
+      compare: None,
+      default_material: "Sand".to_string(),
+      brush_size: 1,
+      language: "en".to_string(),
+      material_keys: vec![
+        KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4, KeyCode::Key5,
+        KeyCode::Key6, KeyCode::Key7, KeyCode::Key8, KeyCode::Key9, KeyCode::Key0,
+      ],
+      keybindings: default_bindings(),
+      gamepad_bindings: default_gamepad_bindings(),
+      erosion: None,
+      phases: None,
+      restitution: None,
+    }
+  }
+}
+
+impl Config {
+  pub const PATH: &'static str = "config.toml";
+
+  // Falls back to the defaults when the file is missing or invalid
+  pub fn load(path: impl AsRef<Path>) -> Self {
+    let path = path.as_ref();
+    let contents = match fs::read_to_string(path) {
+      Ok(contents) => contents,
+      Err(_) => return Self::default(),
+    };
+
+    match toml::from_str::<Self>(&contents) {
+      Ok(mut config) => {
+        // Any action left out of the file keeps its default binding
+        for (action, binding) in default_bindings() {
+          config.keybindings.entry(action).or_insert(binding);
+        }
+        for (action, button) in default_gamepad_bindings() {
+          config.gamepad_bindings.entry(action).or_insert(button);
+        }
+        config
+      },
+      Err(error) => {
+        // Loaded before the logger is set up
+        eprintln!("Failed to parse {}: {}", path.display(), error);
+        Self::default()
+      }
+    }
+  }
+
+  pub fn window_descriptor(&self) -> WindowDescriptor {
+    WindowDescriptor {
+      title: "Arrakoids".to_string(),
+      width: self.window.width,
+      height: self.window.height,
+      present_mode: if self.window.vsync { PresentMode::Fifo } else { PresentMode::Immediate },
+      ..Default::default()
+    }
+  }
+
+  pub fn default_material(&self, materials: &MaterialRegistry) -> MaterialId {
+    materials.find(&self.default_material).unwrap_or(MaterialId::SAND)
+  }
+}
@@ -0,0 +1,357 @@
+use std::{collections::{BTreeMap, VecDeque}, str::FromStr};
+
+use bevy::{ecs::event::Events, math::const_ivec2, prelude::*, reflect::TypeRegistry, utils::HashSet};
+
+use crate::{
+  actions::Action,
+  clear_world,
+  groups::{GroupCommand, GroupOperation, Tag},
+  material::{MaterialId, MaterialRegistry},
+  profiling::ChunkStats,
+  scenes::save_world_scene,
+  simulation::{SimulationClock, SimulationDiagnostics, SimulationSettings, SimulationStep},
+  spawn_particle, BoundsExt, Particle, SpatialIndex, Static,
+};
+
+const NEIGHBORS: [IVec2; 4] = [const_ivec2!([-1, 0]), const_ivec2!([1, 0]), const_ivec2!([0, -1]), const_ivec2!([0, 1])];
+
+// Lines of output kept, the oldest are dropped
+const LOG_LINES: usize = 12;
+
+// A drop-down console toggled with `Action::ToggleConsole`. Typed lines are looked up in the
+// `ConsoleCommands` and sent as a `ConsoleCommand`, which whoever registered the command handles in
+// a system of its own, answering through the `ConsoleLog`.
+pub struct ConsolePlugin;
+
+impl Plugin for ConsolePlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<Console>()
+      .init_resource::<ConsoleLog>()
+      .add_console_command("help", "help, lists the commands")
+      .add_console_command("spawn", "spawn <material> <count> [at <x> <y>]")
+      .add_console_command("gravity", "gravity <x> <y>, in cells per second squared")
+      .add_console_command("clear", "clear, removes every particle")
+      .add_console_command("save", "save <path>, writes the world as a scene")
+      .add_console_command("stats", "stats, prints the simulation counters")
+      .add_console_command("chunks", "chunks [count], prints the chunks the last tick spent longest on")
+      .add_console_command("tag", "tag <group> <x0> <y0> <x1> <y1>, adds the particles in the box to the group")
+      .add_console_command("group", "group <group> <freeze|delete|recolor r g b|impulse x y>")
+      .add_system(toggle_console)
+      .add_system(type_into_console.label("console_input").after(toggle_console))
+      .add_system(run_builtin_commands.after("console_input").after(SimulationStep::Commit))
+      .add_system(update_console.after(run_builtin_commands));
+  }
+}
+
+// Commands by name along with how to use them
+#[derive(Default)]
+pub struct ConsoleCommands(BTreeMap<String, String>);
+
+impl ConsoleCommands {
+  pub fn register(&mut self, name: &str, usage: &str) {
+    self.0.insert(name.to_lowercase(), usage.to_string());
+  }
+
+  pub fn contains(&self, name: &str) -> bool {
+    self.0.contains_key(name)
+  }
+
+  pub fn usage(&self, name: &str) -> &str {
+    self.0.get(name).map_or("", String::as_str)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+    self.0.iter()
+  }
+}
+
+// Plugins can take commands whether or not the console plugin was added before them, or at all
+pub trait ConsoleApp {
+  // Sets up the registry and the `ConsoleCommand` event, for systems reading commands
+  fn init_console(&mut self) -> &mut Self;
+  fn add_console_command(&mut self, name: &str, usage: &str) -> &mut Self;
+}
+
+impl ConsoleApp for App {
+  fn init_console(&mut self) -> &mut Self {
+    if !self.world.contains_resource::<Events<ConsoleCommand>>() {
+      self.add_event::<ConsoleCommand>().init_resource::<ConsoleCommands>();
+    }
+    self
+  }
+
+  fn add_console_command(&mut self, name: &str, usage: &str) -> &mut Self {
+    self.init_console();
+    self.world.resource_mut::<ConsoleCommands>().register(name, usage);
+    self
+  }
+}
+
+// A registered command that was entered, split on whitespace
+#[derive(Clone, Debug)]
+pub struct ConsoleCommand {
+  pub name: String,
+  pub args: Vec<String>,
+}
+
+impl ConsoleCommand {
+  pub fn parse(line: &str) -> Option<Self> {
+    let mut words = line.split_whitespace();
+    let name = words.next()?.to_lowercase();
+    Some(Self { name, args: words.map(str::to_string).collect() })
+  }
+
+  pub fn arg<T: FromStr>(&self, index: usize) -> Option<T> {
+    self.args.get(index)?.parse().ok()
+  }
+}
+
+// What commands answered, shown under the input line and logged
+#[derive(Default)]
+pub struct ConsoleLog(Vec<String>);
+
+impl ConsoleLog {
+  pub fn print(&mut self, line: impl Into<String>) {
+    let line = line.into();
+    info!("{}", line);
+    self.0.push(line);
+    if self.0.len() > LOG_LINES {
+      self.0.remove(0);
+    }
+  }
+}
+
+// Whether the console is open and what has been typed so far, key bindings are ignored while it is
+// open
+#[derive(Default)]
+pub struct Console {
+  pub open: bool,
+  input: String,
+}
+
+#[derive(Component)]
+struct ConsolePanel;
+
+#[derive(Component)]
+struct ConsoleText;
+
+fn toggle_console(
+  mut commands: Commands,
+  asset_server: Res<AssetServer>,
+  actions: Res<Input<Action>>,
+  mut console: ResMut<Console>,
+  panels: Query<Entity, With<ConsolePanel>>,
+) {
+  if !actions.just_pressed(Action::ToggleConsole) { return }
+  console.open = !console.open;
+  if !console.open {
+    for entity in panels.iter() {
+      commands.entity(entity).despawn_recursive();
+    }
+    return;
+  }
+
+  let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+  commands
+    .spawn_bundle(NodeBundle {
+      style: Style {
+        position_type: PositionType::Absolute,
+        position: Rect { left: Val::Px(0.), top: Val::Px(0.), ..Default::default() },
+        size: Size::new(Val::Percent(100.), Val::Auto),
+        padding: Rect::all(Val::Px(6.)),
+        ..Default::default()
+      },
+      color: Color::rgba(0.05, 0.05, 0.08, 0.85).into(),
+      ..Default::default()
+    })
+    .insert(ConsolePanel)
+    .with_children(|parent| {
+      parent
+        .spawn_bundle(TextBundle {
+          text: Text::with_section("", TextStyle { font, font_size: 16., color: Color::WHITE }, Default::default()),
+          ..Default::default()
+        })
+        .insert(ConsoleText);
+    });
+}
+
+fn type_into_console(
+  keys: Res<Input<KeyCode>>,
+  registry: Res<ConsoleCommands>,
+  mut characters: EventReader<ReceivedCharacter>,
+  mut console: ResMut<Console>,
+  mut log: ResMut<ConsoleLog>,
+  mut entered: EventWriter<ConsoleCommand>,
+) {
+  if !console.open { return }
+  // The key that opens the console also types its character
+  for character in characters.iter().map(|event| event.char).filter(|char| !char.is_control() && !"`~".contains(*char)) {
+    console.input.push(character);
+  }
+  if keys.just_pressed(KeyCode::Back) {
+    console.input.pop();
+  }
+  if !keys.just_pressed(KeyCode::Return) { return }
+
+  let line = std::mem::take(&mut console.input);
+  let command = match ConsoleCommand::parse(&line) {
+    Some(command) => command,
+    None => return,
+  };
+  log.print(format!("> {}", line.trim()));
+  if registry.contains(&command.name) {
+    entered.send(command);
+  } else {
+    log.print(format!("Unknown command {}, try help", command.name));
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_builtin_commands(
+  mut commands: Commands,
+  registry: Res<ConsoleCommands>,
+  type_registry: Res<TypeRegistry>,
+  materials: Res<MaterialRegistry>,
+  clock: Res<SimulationClock>,
+  diagnostics: Res<SimulationDiagnostics>,
+  chunk_stats: Res<ChunkStats>,
+  mut settings: ResMut<SimulationSettings>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut entered: EventReader<ConsoleCommand>,
+  mut log: ResMut<ConsoleLog>,
+  mut groups: EventWriter<GroupCommand>,
+  particles: Query<(&Particle, &MaterialId, &Transform, Option<&Static>)>,
+) {
+  for command in entered.iter() {
+    match command.name.as_str() {
+      "help" => {
+        for (_, usage) in registry.iter() {
+          log.print(usage.clone());
+        }
+      },
+      "spawn" => {
+        let material = command.args.first().and_then(|name| materials.find(name));
+        let at = match command.args.get(2).map(String::as_str) {
+          Some("at") => command.arg(3).zip(command.arg(4)).map(|(x, y)| IVec2::new(x, y)),
+          Some(_) => None,
+          None => Some(IVec2::ZERO),
+        };
+        match (material, command.arg::<usize>(1), at) {
+          (Some(material), Some(count), Some(at)) => {
+            let spawned = spawn_around(&mut commands, &mut spatial_index, &materials, material, count, at);
+            log.print(format!("Spawned {} {}", spawned, materials.get(material).name));
+          },
+          (None, _, _) => log.print("Unknown material"),
+          _ => log.print(registry.usage("spawn")),
+        }
+      },
+      "gravity" => match (command.arg::<f32>(0), command.arg::<f32>(1)) {
+        (Some(x), Some(y)) if x.is_finite() && y.is_finite() => {
+          settings.gravity = Vec2::new(x, y);
+          log.print(format!("Gravity is now {}", settings.gravity));
+        },
+        _ => log.print(registry.usage("gravity")),
+      },
+      "clear" => {
+        let size = (spatial_index.bounds().max() - spatial_index.bounds().min()).as_ivec2();
+        let count = spatial_index.len();
+        clear_world(&mut commands, &mut spatial_index, size.x, size.y);
+        log.print(format!("Removed {} particles", count));
+      },
+      "save" => match command.args.first() {
+        Some(path) => {
+          let particles = particles.iter().map(|(particle, material, transform, fixed)| (particle, material, transform, fixed.is_some()));
+          match save_world_scene(path, particles, &type_registry) {
+            Ok(()) => log.print(format!("Saved the world to {}", path)),
+            Err(error) => log.print(format!("Could not save to {}: {}", path, error)),
+          }
+        },
+        None => log.print(registry.usage("save")),
+      },
+      "tag" => match (command.args.first(), command.arg(1), command.arg(2), command.arg(3), command.arg(4)) {
+        (Some(name), Some(x0), Some(y0), Some(x1), Some(y1)) => {
+          let (corner, other) = (IVec2::new(x0, y0), IVec2::new(x1, y1));
+          let members = spatial_index.query(corner.min(other), corner.max(other));
+          for (_, entity) in members.iter() {
+            commands.entity(*entity).insert(Tag(name.clone()));
+          }
+          log.print(format!("Added {} particles to {}", members.len(), name));
+        },
+        _ => log.print(registry.usage("tag")),
+      },
+      "group" => match (command.args.first(), GroupOperation::parse(command.args.get(1..).unwrap_or_default())) {
+        (Some(name), Some(operation)) => {
+          groups.send(GroupCommand { tag: name.clone(), operation });
+          log.print(format!("{:?} applied to {}", operation, name));
+        },
+        _ => log.print(registry.usage("group")),
+      },
+      "stats" => {
+        log.print(format!(
+          "Tick {}, {} particles, {} active chunks, {} escaped, {} sanitized velocities, {} separated",
+          clock.tick,
+          spatial_index.len(),
+          spatial_index.active_chunks().len(),
+          diagnostics.escaped_particles,
+          diagnostics.sanitized_velocities,
+          diagnostics.separated_particles,
+        ));
+      },
+      "chunks" => {
+        let count = command.args.first().and_then(|count| count.parse().ok()).unwrap_or(5);
+        for (chunk, stat) in chunk_stats.hottest(count) {
+          log.print(format!(
+            "Chunk {} {}: {:.3} ms, {} particles, awake {:.0}% of the time",
+            chunk.x,
+            chunk.y,
+            stat.cost.as_secs_f64() * 1000.,
+            stat.particles,
+            stat.awake * 100.,
+          ));
+        }
+      },
+      _ => {},
+    }
+  }
+}
+
+// Fills the free cells connected to `at`, nearest first, with up to `count` particles
+fn spawn_around(
+  commands: &mut Commands,
+  spatial_index: &mut SpatialIndex,
+  materials: &MaterialRegistry,
+  material: MaterialId,
+  count: usize,
+  at: IVec2,
+) -> usize {
+  if !spatial_index.is_free(at) { return 0 }
+  let mut queue = VecDeque::from([at]);
+  let mut seen = HashSet::from_iter([at]);
+  let mut spawned = 0;
+  while let Some(cell) = queue.pop_front() {
+    if spawned == count { break }
+    spawn_particle(commands, spatial_index, materials, cell, material);
+    spawned += 1;
+    for offset in NEIGHBORS {
+      let next = cell + offset;
+      if spatial_index.is_free(next) && seen.insert(next) {
+        queue.push_back(next);
+      }
+    }
+  }
+  spawned
+}
+
+fn update_console(console: Res<Console>, log: Res<ConsoleLog>, mut texts: Query<&mut Text, With<ConsoleText>>) {
+  if !console.open { return }
+  for mut text in texts.iter_mut() {
+    let mut value = log.0.join("\n");
+    if !value.is_empty() {
+      value.push('\n');
+    }
+    value.push_str("> ");
+    value.push_str(&console.input);
+    text.sections[0].value = value;
+  }
+}
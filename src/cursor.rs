@@ -0,0 +1,111 @@
+use bevy::{prelude::*, ui::UiSystem};
+
+use crate::{actions::Sticks, touch::TouchGesture, Particle};
+
+// Cells per second the left stick moves the cursor at when pushed all the way
+const STICK_SPEED: f32 = 40.;
+
+pub struct CursorPlugin;
+
+impl Plugin for CursorPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<Cursor>()
+      .add_startup_system(spawn_stick_marker)
+      .add_system_to_stage(CoreStage::PreUpdate, update_cursor.after("actions").after("touch"))
+      .add_system_to_stage(CoreStage::PreUpdate, update_over_ui.after(UiSystem::Focus));
+  }
+}
+
+#[derive(Component)]
+pub struct MainCamera;
+
+// The cursor position in world space and the grid cell underneath it
+#[derive(Default)]
+pub struct Cursor {
+  pub world: Option<Vec2>,
+  pub cell: Option<IVec2>,
+  // Set while hovering interactive UI so world tools ignore the click
+  pub over_ui: bool,
+  // Where the left stick moved the cursor to, until the mouse moves again
+  stick: Option<Vec2>,
+}
+
+// Shows where the cursor is while the stick drives it, the system cursor stays behind
+#[derive(Component)]
+struct StickMarker;
+
+fn spawn_stick_marker(mut commands: Commands) {
+  commands
+    .spawn_bundle(SpriteBundle {
+      sprite: Sprite {
+        color: Color::rgba(1., 1., 1., 0.6),
+        custom_size: Some(Vec2::splat(Particle::SPRITE_SIZE)),
+        ..Default::default()
+      },
+      visibility: Visibility { is_visible: false },
+      ..Default::default()
+    })
+    .insert(StickMarker);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_cursor(
+  time: Res<Time>,
+  windows: Res<Windows>,
+  sticks: Res<Sticks>,
+  touch: Option<Res<TouchGesture>>,
+  mut moved: EventReader<CursorMoved>,
+  cameras: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+  mut cursor: ResMut<Cursor>,
+  mut markers: Query<(&mut Transform, &mut Visibility), With<StickMarker>>,
+) {
+  let previous = cursor.world;
+  cursor.world = None;
+  cursor.cell = None;
+  if moved.iter().count() > 0 {
+    cursor.stick = None;
+  }
+
+  let (camera, camera_transform) = match cameras.get_single() {
+    Ok(camera) => camera,
+    Err(_) => return,
+  };
+  let window = match windows.get_primary() {
+    Some(window) => window,
+    None => return,
+  };
+
+  if sticks.left != Vec2::ZERO {
+    // Picks up from wherever the mouse left the cursor, or the middle of the screen
+    let start = cursor.stick.or(previous).unwrap_or_else(|| camera_transform.translation.truncate());
+    cursor.stick = Some(start + sticks.left * STICK_SPEED * Particle::SPRITE_SIZE * time.delta_seconds());
+  }
+
+  // A painting finger goes before the stick and the mouse
+  let touched = touch.and_then(|touch| touch.painting);
+  let world = cursor.stick.filter(|_| touched.is_none()).or_else(|| {
+    let screen_position = touched.or_else(|| window.cursor_position())?;
+    let window_size = Vec2::new(window.width(), window.height());
+    let ndc = (screen_position / window_size) * 2. - Vec2::ONE;
+    let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix.inverse();
+    Some(ndc_to_world.project_point3(ndc.extend(-1.)).truncate())
+  });
+
+  for (mut transform, mut visibility) in markers.iter_mut() {
+    visibility.is_visible = cursor.stick.is_some();
+    if let Some(world) = cursor.stick {
+      transform.translation = ((world / Particle::SPRITE_SIZE).round() * Particle::SPRITE_SIZE).extend(1.);
+    }
+  }
+
+  if let Some(world) = world {
+    cursor.world = Some(world);
+    // Sprites are centered on their cell so round rather than floor
+    cursor.cell = Some((world / Particle::SPRITE_SIZE).round().as_ivec2());
+  }
+}
+
+fn update_over_ui(mut cursor: ResMut<Cursor>, interactions: Query<&Interaction>) {
+  cursor.over_ui = interactions.iter().any(|interaction| *interaction != Interaction::None);
+}
@@ -0,0 +1,124 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+  boids::Boid,
+  simulation::{SimulationClock, SimulationSettings, SimulationStep},
+  Particle, SpatialIndex, Static,
+};
+
+// Default for `SimulationSettings::temperature`, in degrees
+pub const ROOM_TEMPERATURE: f32 = 20.;
+
+// Global conditions animated over time by the `EnvironmentTrack`: the strength of gravity, wind
+// pushing loose particles around and the ambient temperature, see `phases`
+pub struct EnvironmentPlugin;
+
+impl Plugin for EnvironmentPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<EnvironmentTrack>()
+      .add_system_set(SystemSet::new()
+        .with_run_criteria("fixed_tick")
+        .with_system(animate_environment.label("environment").label(SimulationStep::Integrate))
+        .with_system(blow_wind.after("environment").label(SimulationStep::Integrate))
+      );
+  }
+}
+
+fn default_strength() -> f32 {
+  1.
+}
+
+fn default_temperature() -> f32 {
+  ROOM_TEMPERATURE
+}
+
+// The environment at one point of a track
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct Keyframe {
+  // Ticks since the track started
+  pub tick: u64,
+  // Multiple of the default gravity, pulling in the direction gravity already does
+  #[serde(default = "default_strength")]
+  pub gravity: f32,
+  // Cells per second squared for a particle of mass 1, lighter ones are blown harder
+  #[serde(default)]
+  pub wind: (f32, f32),
+  #[serde(default = "default_temperature")]
+  pub temperature: f32,
+}
+
+// Keyframes the environment is interpolated between, in tick order. A looping track starts over
+// after its last keyframe, which should have the same values as the first to loop smoothly.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct EnvironmentTrack {
+  pub keyframes: Vec<Keyframe>,
+  #[serde(default)]
+  pub looping: bool,
+  // Tick the track was started at
+  #[serde(skip)]
+  pub start: u64,
+}
+
+impl EnvironmentTrack {
+  // Starts the track over at `tick`
+  pub fn play(mut self, tick: u64) -> Self {
+    self.keyframes.sort_by_key(|keyframe| keyframe.tick);
+    self.start = tick;
+    self
+  }
+
+  // The environment `tick` ticks into the track, before the first keyframe it is the first one's
+  // and after the last one of a track that does not loop it stays at the last one's
+  pub fn sample(&self, tick: u64) -> Option<Keyframe> {
+    let (first, last) = (self.keyframes.first()?, self.keyframes.last()?);
+    let tick = if self.looping && last.tick > 0 { tick % last.tick } else { tick };
+    let next = match self.keyframes.iter().position(|keyframe| keyframe.tick > tick) {
+      Some(0) => return Some(Keyframe { tick, ..*first }),
+      Some(next) => next,
+      None => return Some(Keyframe { tick, ..*last }),
+    };
+
+    let (from, to) = (self.keyframes[next - 1], self.keyframes[next]);
+    let t = (tick - from.tick) as f32 / (to.tick - from.tick) as f32;
+    let lerp = |a: f32, b: f32| a + (b - a) * t;
+    Some(Keyframe {
+      tick,
+      gravity: lerp(from.gravity, to.gravity),
+      wind: (lerp(from.wind.0, to.wind.0), lerp(from.wind.1, to.wind.1)),
+      temperature: lerp(from.temperature, to.temperature),
+    })
+  }
+}
+
+fn animate_environment(clock: Res<SimulationClock>, track: Res<EnvironmentTrack>, mut settings: ResMut<SimulationSettings>) {
+  if !clock.ticked { return }
+  let keyframe = match track.sample(clock.tick.saturating_sub(track.start)) {
+    Some(keyframe) => keyframe,
+    None => return,
+  };
+
+  // Once gravity has faded out completely it comes back pulling down
+  let direction = settings.gravity.normalize_or_zero();
+  let direction = if direction == Vec2::ZERO { Particle::GRAVITY.normalize() } else { direction };
+  settings.gravity = direction * Particle::GRAVITY.length() * keyframe.gravity;
+  settings.wind = Vec2::new(keyframe.wind.0, keyframe.wind.1);
+  settings.temperature = keyframe.temperature;
+}
+
+// Boids fly against the wind on their own
+fn blow_wind(
+  clock: Res<SimulationClock>,
+  settings: Res<SimulationSettings>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut particles: Query<&mut Particle, (Without<Static>, Without<Boid>)>,
+) {
+  if !clock.ticked || settings.wind == Vec2::ZERO { return }
+  let delta = settings.tick_delta();
+  for mut particle in particles.iter_mut() {
+    let mass = particle.mass.max(f32::EPSILON);
+    particle.velocity += settings.wind / mass * delta;
+    spatial_index.wake(particle.position.floor().as_ivec2());
+  }
+}
@@ -0,0 +1,262 @@
+use bevy::{prelude::*, math::const_ivec2};
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::{
+  config::Config,
+  material::{MaterialId, MaterialRegistry},
+  simulation::{SimulationClock, SimulationRng, SimulationSettings, SimulationStep},
+  Particle, SpatialIndex, ReactionEvent, Static,
+};
+
+const NEIGHBORS: [IVec2; 4] = [const_ivec2!([-1, 0]), const_ivec2!([1, 0]), const_ivec2!([0, -1]), const_ivec2!([0, 1])];
+
+// Fraction of the difference to the surrounding liquid's velocity sediment makes up each tick
+const CARRY: f32 = 0.5;
+
+// Fast liquids pick up granular particles next to them as sediment, which flows along and settles
+// back into the original material once the liquid slows down
+pub struct ErosionPlugin;
+
+impl Plugin for ErosionPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<ErosionRules>()
+      .add_startup_system(configure_erosion)
+      .add_system_set(SystemSet::new()
+        .with_run_criteria("fixed_tick")
+        .with_system(carry_sediment.label(SimulationStep::Integrate))
+        .with_system(erode.label("erode").after(SimulationStep::Commit))
+        .with_system(deposit_sediment.after("erode"))
+      );
+  }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ErosionRule {
+  pub liquid: MaterialId,
+  pub solid: MaterialId,
+  pub sediment: MaterialId,
+  // Cells per second the liquid needs to pick the solid up, sediment slower than this can settle
+  pub speed: f32,
+  // Chance per tick that a solid cell next to fast enough liquid turns into sediment
+  pub erosion: f64,
+  // Chance per tick that slow sediment settles
+  pub deposition: f64,
+}
+
+// The material pairs that erode, replaced by `[[erosion]]` entries in the config
+pub struct ErosionRules(pub Vec<ErosionRule>);
+
+impl Default for ErosionRules {
+  fn default() -> Self {
+    Self(vec![
+      ErosionRule {
+        liquid: MaterialId::WATER,
+        solid: MaterialId::SAND,
+        sediment: MaterialId::SEDIMENT,
+        speed: 2.,
+        erosion: 0.05,
+        deposition: 0.2,
+      },
+      ErosionRule {
+        liquid: MaterialId::WATER,
+        solid: MaterialId::SOIL,
+        sediment: MaterialId::SEDIMENT,
+        speed: 3.2,
+        erosion: 0.02,
+        deposition: 0.2,
+      },
+    ])
+  }
+}
+
+impl ErosionRules {
+  fn find(&self, liquid: MaterialId, solid: MaterialId) -> Option<&ErosionRule> {
+    self.0.iter().find(|rule| rule.liquid == liquid && rule.solid == solid)
+  }
+}
+
+// An erosion rule by material names, as written in the config
+#[derive(Clone, Debug, Deserialize)]
+pub struct ErosionConfig {
+  pub liquid: String,
+  pub solid: String,
+  #[serde(default = "default_sediment")]
+  pub sediment: String,
+  pub speed: f32,
+  pub erosion: f64,
+  pub deposition: f64,
+}
+
+fn default_sediment() -> String {
+  "Sediment".to_string()
+}
+
+// Sediment picked up from `solid`, which it turns back into when it settles
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Suspended {
+  pub solid: MaterialId,
+  // Where it was a tick ago. Sediment pressed against something by the flow keeps its velocity
+  // without getting anywhere, so how far it actually went is what counts as its speed.
+  last_position: Vec2,
+}
+
+fn configure_erosion(config: Option<Res<Config>>, materials: Res<MaterialRegistry>, mut rules: ResMut<ErosionRules>) {
+  let entries = match config.as_ref().and_then(|config| config.erosion.as_ref()) {
+    Some(entries) => entries,
+    None => return,
+  };
+
+  rules.0.clear();
+  for entry in entries {
+    // Both are chances, anything else would panic when rolled
+    if !(0. ..=1.).contains(&entry.erosion) || !(0. ..=1.).contains(&entry.deposition) {
+      warn!("Skipping erosion rule for {} and {}, erosion and deposition have to be between 0 and 1", entry.liquid, entry.solid);
+      continue;
+    }
+    let find = |name: &str| {
+      let material = materials.find(name);
+      if material.is_none() {
+        warn!("Unknown material {} in erosion rule", name);
+      }
+      material
+    };
+    if let (Some(liquid), Some(solid), Some(sediment)) = (find(&entry.liquid), find(&entry.solid), find(&entry.sediment)) {
+      rules.0.push(ErosionRule {
+        liquid,
+        solid,
+        sediment,
+        speed: entry.speed,
+        erosion: entry.erosion,
+        deposition: entry.deposition,
+      });
+    }
+  }
+}
+
+// Swaps the material of a particle in place, keeping its velocity
+pub fn convert(particle: &mut Particle, material: &mut MaterialId, sprite: &mut Sprite, to: MaterialId, materials: &MaterialRegistry) {
+  let def = materials.get(to);
+  *material = to;
+  particle.mass = def.mass;
+  particle.elasticity = def.elasticity;
+  sprite.color = def.color;
+}
+
+// Suspended sediment drifts with the liquid around it rather than sinking on its own
+fn carry_sediment(
+  clock: Res<SimulationClock>,
+  rules: Res<ErosionRules>,
+  spatial_index: Res<SpatialIndex>,
+  mut sediment: Query<(&mut Particle, &MaterialId, &Suspended)>,
+  liquids: Query<(&Particle, &MaterialId), Without<Suspended>>,
+) {
+  if !clock.ticked { return }
+
+  for (mut particle, material, suspended) in sediment.iter_mut() {
+    let liquid = match rules.0.iter().find(|rule| rule.sediment == *material && rule.solid == suspended.solid) {
+      Some(rule) => rule.liquid,
+      None => continue,
+    };
+    let point = particle.position.floor().as_ivec2();
+    let velocities: Vec<Vec2> = NEIGHBORS
+      .iter()
+      .filter_map(|offset| liquids.get(*spatial_index.get(&(point + *offset))?).ok())
+      .filter(|(_, neighbor)| **neighbor == liquid)
+      .map(|(neighbor, _)| neighbor.velocity)
+      .collect();
+    if velocities.is_empty() { continue }
+
+    let flow = velocities.iter().sum::<Vec2>() / velocities.len() as f32;
+    particle.velocity = particle.velocity.lerp(flow, CARRY);
+  }
+}
+
+type Erodible<'w, 's> = Query<'w, 's, (Entity, &'static mut Particle, &'static mut MaterialId, &'static mut Sprite), (Without<Static>, Without<Suspended>)>;
+
+fn erode(
+  mut commands: Commands,
+  rules: Res<ErosionRules>,
+  materials: Res<MaterialRegistry>,
+  mut rng: ResMut<SimulationRng>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut particles: Erodible,
+  mut reactions: EventWriter<ReactionEvent>,
+) {
+  if rules.0.is_empty() { return }
+
+  // Cell order keeps the random draws identical between deterministic runs
+  let mut solids: Vec<(IVec2, Entity, MaterialId)> = particles
+    .iter()
+    .filter(|(_, _, material, _)| rules.0.iter().any(|rule| rule.solid == **material))
+    .map(|(entity, particle, material, _)| (particle.position.floor().as_ivec2(), entity, *material))
+    .collect();
+  solids.sort_unstable_by_key(|(point, _, _)| (point.y, point.x));
+
+  let rng = &mut rng.0;
+  let mut eroded = Vec::new();
+  for (point, entity, solid) in solids {
+    for offset in NEIGHBORS {
+      let liquid = match spatial_index.get(&(point + offset)).and_then(|entity| particles.get(*entity).ok()) {
+        Some((_, liquid, material, _)) => (liquid.velocity, *material),
+        None => continue,
+      };
+      let rule = match rules.find(liquid.1, solid) {
+        Some(rule) => rule,
+        None => continue,
+      };
+      if liquid.0.length() >= rule.speed && rng.gen_bool(rule.erosion) {
+        eroded.push((point, entity, *rule, liquid.0));
+        break;
+      }
+    }
+  }
+
+  for (point, entity, rule, velocity) in eroded {
+    if let Ok((_, mut particle, mut material, mut sprite)) = particles.get_mut(entity) {
+      convert(&mut particle, &mut material, &mut sprite, rule.sediment, &materials);
+      particle.velocity = velocity;
+      reactions.send(ReactionEvent { entity, cell: point, from: rule.solid, to: rule.sediment });
+      commands.entity(entity).insert(Suspended { solid: rule.solid, last_position: particle.position });
+      spatial_index.wake(point);
+    }
+  }
+}
+
+fn deposit_sediment(
+  mut commands: Commands,
+  rules: Res<ErosionRules>,
+  settings: Res<SimulationSettings>,
+  materials: Res<MaterialRegistry>,
+  mut rng: ResMut<SimulationRng>,
+  mut sediment: Query<(Entity, &mut Particle, &mut MaterialId, &mut Sprite, &mut Suspended)>,
+  mut reactions: EventWriter<ReactionEvent>,
+) {
+  let mut order: Vec<(IVec2, Entity)> = sediment
+    .iter()
+    .map(|(entity, particle, _, _, _)| (particle.position.floor().as_ivec2(), entity))
+    .collect();
+  order.sort_unstable_by_key(|(point, _)| (point.y, point.x));
+
+  let rng = &mut rng.0;
+  for (point, entity) in order {
+    let (_, mut particle, mut material, mut sprite, mut suspended) = match sediment.get_mut(entity) {
+      Ok(sediment) => sediment,
+      Err(_) => continue,
+    };
+    let solid = suspended.solid;
+    let moved = particle.position.distance(suspended.last_position) / settings.tick_delta();
+    suspended.last_position = particle.position;
+    let (speed, deposition) = match rules.0.iter().find(|rule| rule.sediment == *material && rule.solid == solid) {
+      Some(rule) => (rule.speed, rule.deposition),
+      // Without its rule nothing holds it up any more
+      None => (f32::INFINITY, 1.),
+    };
+    if moved >= speed || !rng.gen_bool(deposition) { continue }
+
+    reactions.send(ReactionEvent { entity, cell: point, from: *material, to: solid });
+    convert(&mut particle, &mut material, &mut sprite, solid, &materials);
+    commands.entity(entity).remove::<Suspended>();
+  }
+}
@@ -0,0 +1,130 @@
+use bevy::{
+  prelude::*,
+  render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+
+use crate::{
+  material::{MaterialId, MaterialRegistry},
+  simulation::SimulationStep,
+  Particle,
+};
+
+// Size of the halo texture in pixels
+const HALO_RESOLUTION: u32 = 32;
+
+// Makes particles of emissive materials glow. The 2D pipeline renders into a plain 8 bit target
+// without HDR, so rather than a bloom pass over bright pixels every emissive particle gets a soft
+// halo drawn behind it, which looks the same on every backend.
+pub struct GlowPlugin;
+
+impl Plugin for GlowPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<GlowSettings>()
+      .init_resource::<HaloPool>()
+      .add_startup_system(setup_halo_texture)
+      .add_system(draw_halos.after(SimulationStep::Commit));
+  }
+}
+
+pub struct GlowSettings {
+  pub enabled: bool,
+  // Cells from an emissive particle to the edge of its halo
+  pub radius: f32,
+}
+
+impl Default for GlowSettings {
+  fn default() -> Self {
+    Self { enabled: true, radius: 3. }
+  }
+}
+
+struct HaloTexture(Handle<Image>);
+
+#[derive(Component)]
+struct Halo;
+
+// Halo sprites are reused between frames, the ones not needed are hidden
+#[derive(Default)]
+struct HaloPool(Vec<Entity>);
+
+// White fading out from the center, tinted per particle
+fn setup_halo_texture(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+  let center = (HALO_RESOLUTION as f32 - 1.) / 2.;
+  let mut data = Vec::with_capacity((HALO_RESOLUTION * HALO_RESOLUTION * 4) as usize);
+  for y in 0..HALO_RESOLUTION {
+    for x in 0..HALO_RESOLUTION {
+      let distance = Vec2::new(x as f32 - center, y as f32 - center).length() / center;
+      let falloff = (1. - distance).clamp(0., 1.).powi(2);
+      data.extend_from_slice(&[255, 255, 255, (falloff * 255.) as u8]);
+    }
+  }
+  let image = images.add(Image::new(
+    Extent3d { width: HALO_RESOLUTION, height: HALO_RESOLUTION, depth_or_array_layers: 1 },
+    TextureDimension::D2,
+    data,
+    TextureFormat::Rgba8UnormSrgb,
+  ));
+  commands.insert_resource(HaloTexture(image));
+}
+
+fn draw_halos(
+  mut commands: Commands,
+  settings: Res<GlowSettings>,
+  materials: Res<MaterialRegistry>,
+  texture: Option<Res<HaloTexture>>,
+  mut pool: ResMut<HaloPool>,
+  particles: Query<(&Particle, &MaterialId)>,
+  mut halos: Query<(&mut Sprite, &mut Transform, &mut Visibility), With<Halo>>,
+) {
+  let texture = match texture {
+    Some(texture) => texture,
+    None => return,
+  };
+  let mut used = 0;
+  if settings.enabled {
+    for (particle, material) in particles.iter() {
+      let def = materials.get(*material);
+      if def.emissive <= 0. { continue }
+      let color = *def.color.clone().set_a(def.emissive.min(1.));
+      // Centered on the particle's cell, behind the particles but over the grid texture
+      let center = particle.position.floor();
+      let translation = (center * Particle::SPRITE_SIZE).extend(-0.25);
+      match pool.0.get(used).and_then(|entity| halos.get_mut(*entity).ok()) {
+        Some((mut sprite, mut transform, mut visibility)) => {
+          sprite.color = color;
+          sprite.custom_size = Some(Vec2::splat(settings.radius * 2. * Particle::SPRITE_SIZE));
+          transform.translation = translation;
+          visibility.is_visible = true;
+        },
+        None => {
+          let entity = commands
+            .spawn_bundle(SpriteBundle {
+              texture: texture.0.clone(),
+              sprite: Sprite {
+                color,
+                custom_size: Some(Vec2::splat(settings.radius * 2. * Particle::SPRITE_SIZE)),
+                ..Default::default()
+              },
+              transform: Transform::from_translation(translation),
+              ..Default::default()
+            })
+            .insert(Halo)
+            .id();
+          if used < pool.0.len() {
+            pool.0[used] = entity;
+          } else {
+            pool.0.push(entity);
+          }
+        },
+      }
+      used += 1;
+    }
+  }
+
+  for entity in pool.0.iter().skip(used) {
+    if let Ok((_, _, mut visibility)) = halos.get_mut(*entity) {
+      visibility.is_visible = false;
+    }
+  }
+}
@@ -0,0 +1,413 @@
+use std::{borrow::Cow, sync::{Arc, Mutex}};
+
+use bevy::{
+  core_pipeline::node::MAIN_PASS_DEPENDENCIES,
+  ecs::schedule::ShouldRun,
+  prelude::*,
+  render::{
+    render_graph::{self, RenderGraph},
+    render_resource::*,
+    renderer::{RenderContext, RenderDevice},
+    RenderApp, RenderStage,
+  },
+};
+
+use crate::{
+  simulation::{CombineRule, SimulationSettings, SimulationStep, TickProgress},
+  BoundsExt, Particle, SpatialIndex, Static,
+};
+
+const WORKGROUP_SIZE: u32 = 64;
+// Matches the layout of `Particle` in simulation.wgsl
+const PARTICLE_SIZE: usize = 32;
+// Matches the layout of `Params` in simulation.wgsl
+const PARAMS_SIZE: usize = 64;
+const STATIC_FLAG: u32 = 1;
+
+pub struct GpuSimulationPlugin;
+
+impl Plugin for GpuSimulationPlugin {
+  fn build(&self, app: &mut App) {
+    let results = GpuResults::default();
+    app
+      .init_resource::<GpuBatch>()
+      .insert_resource(results.clone())
+      .add_system_set(SystemSet::new()
+        .with_run_criteria(RunCriteria::pipe("fixed_tick", gpu_backend))
+        .with_system(apply_results.label("gpu_apply"))
+        .with_system(upload_particles.label(SimulationStep::Commit).after("gpu_apply"))
+      );
+
+    let render_app = app.sub_app_mut(RenderApp);
+    render_app
+      .insert_resource(results)
+      .init_resource::<SimulationPipeline>()
+      .add_system_to_stage(RenderStage::Extract, extract_batch)
+      .add_system_to_stage(RenderStage::Prepare, prepare_buffers)
+      .add_system_to_stage(RenderStage::Cleanup, read_back);
+
+    let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+    render_graph.add_node("particle_simulation", SimulationNode);
+    render_graph.add_node_edge("particle_simulation", MAIN_PASS_DEPENDENCIES).unwrap();
+  }
+}
+
+pub fn gpu_backend(In(should_run): In<ShouldRun>, settings: Res<SimulationSettings>) -> ShouldRun {
+  if settings.uses_gpu() { should_run } else { ShouldRun::No }
+}
+
+// Particles packed for upload, in the same order as `entities`
+#[derive(Clone, Default)]
+struct GpuBatch {
+  generation: u64,
+  entities: Vec<Entity>,
+  particles: Vec<u8>,
+  params: Vec<u8>,
+  grid_cells: u32,
+  // Set while a batch is on the GPU and its results have not been applied yet
+  in_flight: bool,
+}
+
+// Results handed back from the render world, tagged with the generation they were computed for
+type Generation = (u64, Vec<u8>);
+
+#[derive(Clone, Default)]
+struct GpuResults(Arc<Mutex<Option<Generation>>>);
+
+fn push_f32(bytes: &mut Vec<u8>, value: f32) {
+  bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+  bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn read_f32(bytes: &[u8], offset: usize) -> f32 {
+  f32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+fn upload_particles(
+  settings: Res<SimulationSettings>,
+  spatial_index: Res<SpatialIndex>,
+  mut batch: ResMut<GpuBatch>,
+  mut progress: ResMut<TickProgress>,
+  particles: Query<(&Particle, Option<&Static>)>,
+) {
+  // The CPU systems are skipped on this backend, so the tick is done once it is queued
+  progress.finish();
+  if batch.in_flight { return }
+
+  batch.entities.clear();
+  batch.particles.clear();
+  for chunk in spatial_index.chunks() {
+    for entity in spatial_index.chunk_entities(chunk) {
+      if let Ok((particle, fixed)) = particles.get(entity) {
+        batch.entities.push(entity);
+        push_f32(&mut batch.particles, particle.position.x);
+        push_f32(&mut batch.particles, particle.position.y);
+        push_f32(&mut batch.particles, particle.velocity.x);
+        push_f32(&mut batch.particles, particle.velocity.y);
+        push_f32(&mut batch.particles, particle.mass);
+        push_f32(&mut batch.particles, particle.elasticity);
+        push_u32(&mut batch.particles, if fixed.is_some() { STATIC_FLAG } else { 0 });
+        push_u32(&mut batch.particles, 0);
+      }
+    }
+  }
+  if batch.entities.is_empty() { return }
+
+  let bounds = spatial_index.bounds();
+  let grid_min = bounds.min().floor().as_ivec2();
+  let grid_size = (bounds.max().floor().as_ivec2() - grid_min + IVec2::ONE).as_uvec2();
+  batch.grid_cells = grid_size.x * grid_size.y;
+
+  let count = batch.entities.len() as u32;
+  batch.params.clear();
+  push_f32(&mut batch.params, settings.gravity.x);
+  push_f32(&mut batch.params, settings.gravity.y);
+  push_f32(&mut batch.params, settings.tick_delta());
+  push_u32(&mut batch.params, count);
+  push_f32(&mut batch.params, bounds.left);
+  push_f32(&mut batch.params, bounds.bottom);
+  push_f32(&mut batch.params, bounds.right);
+  push_f32(&mut batch.params, bounds.top);
+  batch.params.extend_from_slice(&grid_min.x.to_le_bytes());
+  batch.params.extend_from_slice(&grid_min.y.to_le_bytes());
+  push_u32(&mut batch.params, grid_size.x);
+  push_u32(&mut batch.params, grid_size.y);
+  push_f32(&mut batch.params, settings.velocity_quantum());
+  push_f32(&mut batch.params, settings.sleep_speed);
+  push_u32(&mut batch.params, match settings.restitution {
+    CombineRule::Average => 0,
+    CombineRule::Min => 1,
+    CombineRule::Max => 2,
+    CombineRule::Multiply => 3,
+  });
+  // Uniforms are padded to 16 bytes
+  push_f32(&mut batch.params, 0.);
+  debug_assert_eq!(batch.params.len(), PARAMS_SIZE, "params out of step with simulation.wgsl");
+
+  batch.generation += 1;
+  batch.in_flight = true;
+}
+
+fn apply_results(
+  results: Res<GpuResults>,
+  mut batch: ResMut<GpuBatch>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut particles: Query<(&mut Particle, &mut Transform), Without<Static>>,
+) {
+  let data = match results.0.lock().unwrap().take() {
+    Some((generation, data)) if generation == batch.generation => data,
+    _ => return,
+  };
+  batch.in_flight = false;
+  // An empty result means the shaders were not ready, the batch is simply uploaded again
+  if data.is_empty() { return }
+
+  for (index, entity) in batch.entities.iter().enumerate() {
+    let (mut particle, mut transform) = match particles.get_mut(*entity) {
+      Ok(particle) => particle,
+      Err(_) => continue,
+    };
+    let offset = index * PARTICLE_SIZE;
+    let position = Vec2::new(read_f32(&data, offset), read_f32(&data, offset + 4));
+    let velocity = Vec2::new(read_f32(&data, offset + 8), read_f32(&data, offset + 12));
+
+    let current_point = particle.position.floor().as_ivec2();
+    let new_point = position.floor().as_ivec2();
+    if current_point != new_point {
+      spatial_index.remove_entity(current_point, *entity);
+      spatial_index.insert(new_point, *entity);
+    }
+    particle.position = position;
+    particle.velocity = velocity;
+    transform.translation = new_point.as_vec2().extend(0.) * Particle::SPRITE_SIZE;
+  }
+}
+
+struct ExtractedBatch(GpuBatch);
+
+fn extract_batch(mut commands: Commands, batch: Res<GpuBatch>, mut last_generation: Local<u64>) {
+  if batch.in_flight && batch.generation != *last_generation {
+    *last_generation = batch.generation;
+    commands.insert_resource(ExtractedBatch(batch.clone()));
+  }
+}
+
+struct SimulationBuffers {
+  generation: u64,
+  bind_group: BindGroup,
+  output: Buffer,
+  readback: Buffer,
+  size: u64,
+  particle_count: u32,
+  grid_cells: u32,
+}
+
+fn prepare_buffers(
+  mut commands: Commands,
+  batch: Option<Res<ExtractedBatch>>,
+  pipeline: Res<SimulationPipeline>,
+  render_device: Res<RenderDevice>,
+) {
+  let batch = match batch {
+    Some(ref batch) => &batch.0,
+    None => return,
+  };
+  let size = batch.particles.len() as u64;
+
+  let params = render_device.create_buffer_with_data(&BufferInitDescriptor {
+    label: Some("simulation_params"),
+    contents: &batch.params,
+    usage: BufferUsages::UNIFORM,
+  });
+  let input = render_device.create_buffer_with_data(&BufferInitDescriptor {
+    label: Some("simulation_particles_in"),
+    contents: &batch.particles,
+    usage: BufferUsages::STORAGE,
+  });
+  let output = render_device.create_buffer(&BufferDescriptor {
+    label: Some("simulation_particles_out"),
+    size,
+    usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+    mapped_at_creation: false,
+  });
+  let grid = render_device.create_buffer(&BufferDescriptor {
+    label: Some("simulation_grid"),
+    size: batch.grid_cells as u64 * 4,
+    usage: BufferUsages::STORAGE,
+    mapped_at_creation: false,
+  });
+  let readback = render_device.create_buffer(&BufferDescriptor {
+    label: Some("simulation_readback"),
+    size,
+    usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+    mapped_at_creation: false,
+  });
+
+  let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+    label: Some("simulation_bind_group"),
+    layout: &pipeline.layout,
+    entries: &[
+      BindGroupEntry { binding: 0, resource: params.as_entire_binding() },
+      BindGroupEntry { binding: 1, resource: input.as_entire_binding() },
+      BindGroupEntry { binding: 2, resource: output.as_entire_binding() },
+      BindGroupEntry { binding: 3, resource: grid.as_entire_binding() },
+    ],
+  });
+
+  commands.insert_resource(SimulationBuffers {
+    generation: batch.generation,
+    bind_group,
+    output,
+    readback,
+    size,
+    particle_count: (batch.particles.len() / PARTICLE_SIZE) as u32,
+    grid_cells: batch.grid_cells,
+  });
+  commands.remove_resource::<ExtractedBatch>();
+}
+
+fn read_back(
+  mut commands: Commands,
+  buffers: Option<Res<SimulationBuffers>>,
+  results: Res<GpuResults>,
+  pipeline: Res<SimulationPipeline>,
+  pipeline_cache: Res<PipelineCache>,
+  render_device: Res<RenderDevice>,
+) {
+  let buffers = match buffers {
+    Some(buffers) => buffers,
+    None => return,
+  };
+  commands.remove_resource::<SimulationBuffers>();
+
+  if !pipeline.is_ready(&pipeline_cache) {
+    *results.0.lock().unwrap() = Some((buffers.generation, Vec::new()));
+    return;
+  }
+
+  // The compute pass was submitted during the render stage, so this only waits on the copy
+  let slice = buffers.readback.slice(..);
+  render_device.map_buffer(&slice, MapMode::Read);
+  let data = slice.get_mapped_range().to_vec();
+  buffers.readback.unmap();
+
+  *results.0.lock().unwrap() = Some((buffers.generation, data));
+}
+
+struct SimulationPipeline {
+  layout: BindGroupLayout,
+  clear_grid: CachedComputePipelineId,
+  scatter: CachedComputePipelineId,
+  integrate: CachedComputePipelineId,
+}
+
+impl SimulationPipeline {
+  fn is_ready(&self, pipeline_cache: &PipelineCache) -> bool {
+    [self.clear_grid, self.scatter, self.integrate]
+      .iter()
+      .all(|id| pipeline_cache.get_compute_pipeline(*id).is_some())
+  }
+}
+
+fn storage_entry(binding: u32, read_only: bool) -> BindGroupLayoutEntry {
+  BindGroupLayoutEntry {
+    binding,
+    visibility: ShaderStages::COMPUTE,
+    ty: BindingType::Buffer {
+      ty: BufferBindingType::Storage { read_only },
+      has_dynamic_offset: false,
+      min_binding_size: None,
+    },
+    count: None,
+  }
+}
+
+impl FromWorld for SimulationPipeline {
+  fn from_world(world: &mut World) -> Self {
+    let layout = world.resource::<RenderDevice>().create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("simulation_layout"),
+      entries: &[
+        BindGroupLayoutEntry {
+          binding: 0,
+          visibility: ShaderStages::COMPUTE,
+          ty: BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: BufferSize::new(PARAMS_SIZE as u64),
+          },
+          count: None,
+        },
+        storage_entry(1, true),
+        storage_entry(2, false),
+        storage_entry(3, false),
+      ],
+    });
+
+    let shader = world.resource::<AssetServer>().load("shaders/simulation.wgsl");
+    let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+    let mut queue = |entry_point: &'static str| {
+      pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+        label: Some(Cow::from(entry_point)),
+        layout: Some(vec![layout.clone()]),
+        shader: shader.clone(),
+        shader_defs: vec![],
+        entry_point: Cow::from(entry_point),
+      })
+    };
+
+    Self {
+      clear_grid: queue("clear_grid"),
+      scatter: queue("scatter"),
+      integrate: queue("integrate"),
+      layout,
+    }
+  }
+}
+
+struct SimulationNode;
+
+impl render_graph::Node for SimulationNode {
+  fn run(
+    &self,
+    _graph: &mut render_graph::RenderGraphContext,
+    render_context: &mut RenderContext,
+    world: &World,
+  ) -> Result<(), render_graph::NodeRunError> {
+    let buffers = match world.get_resource::<SimulationBuffers>() {
+      Some(buffers) => buffers,
+      None => return Ok(()),
+    };
+    let pipeline = world.resource::<SimulationPipeline>();
+    let pipeline_cache = world.resource::<PipelineCache>();
+    let (clear_grid, scatter, integrate) = match (
+      pipeline_cache.get_compute_pipeline(pipeline.clear_grid),
+      pipeline_cache.get_compute_pipeline(pipeline.scatter),
+      pipeline_cache.get_compute_pipeline(pipeline.integrate),
+    ) {
+      (Some(clear_grid), Some(scatter), Some(integrate)) => (clear_grid, scatter, integrate),
+      // Shaders are still compiling, `read_back` reports an empty result for this batch
+      _ => return Ok(()),
+    };
+
+    let particle_groups = buffers.particle_count.div_ceil(WORKGROUP_SIZE);
+    let grid_groups = buffers.grid_cells.div_ceil(WORKGROUP_SIZE);
+    {
+      let mut pass = render_context.command_encoder.begin_compute_pass(&ComputePassDescriptor {
+        label: Some("particle_simulation"),
+      });
+      pass.set_bind_group(0, &buffers.bind_group, &[]);
+      pass.set_pipeline(clear_grid);
+      pass.dispatch(grid_groups, 1, 1);
+      pass.set_pipeline(scatter);
+      pass.dispatch(particle_groups, 1, 1);
+      pass.set_pipeline(integrate);
+      pass.dispatch(particle_groups, 1, 1);
+    }
+    render_context.command_encoder.copy_buffer_to_buffer(&buffers.output, 0, &buffers.readback, 0, buffers.size);
+
+    Ok(())
+  }
+}
@@ -0,0 +1,106 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{despawn_particle, simulation::SimulationStep, world_state::WorldState, Particle, SpatialIndex, Static};
+
+// Named groups of particles that scenarios, the console and gameplay built on top act on as a
+// whole. Particles join a group through their `Tag`, a `GroupCommand` applies an operation to every
+// member at once.
+pub struct GroupsPlugin;
+
+impl Plugin for GroupsPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<SelectedGroups>()
+      .add_event::<GroupCommand>()
+      .add_system(select_groups.label("select_groups").after(SimulationStep::Commit))
+      .add_system(apply_group_operations.after("select_groups"));
+  }
+}
+
+// The group a particle belongs to
+#[derive(Component, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Tag(pub String);
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum GroupOperation {
+  // Turns the members into static cells where they are
+  Freeze,
+  Delete,
+  // Draws the members in this color from now on
+  Recolor((f32, f32, f32)),
+  // Momentum added to every moving member, so lighter ones are pushed faster. In cells per second
+  // for a mass of 1.
+  Impulse((f32, f32)),
+}
+
+impl GroupOperation {
+  // From the words after the group's name in the console, like `recolor 1 0 0`
+  pub fn parse(words: &[String]) -> Option<Self> {
+    let number = |index: usize| words.get(index)?.parse::<f32>().ok().filter(|value| value.is_finite());
+    match words.first()?.to_lowercase().as_str() {
+      "freeze" => Some(GroupOperation::Freeze),
+      "delete" => Some(GroupOperation::Delete),
+      "recolor" => Some(GroupOperation::Recolor((number(1)?, number(2)?, number(3)?))),
+      "impulse" => Some(GroupOperation::Impulse((number(1)?, number(2)?))),
+      _ => None,
+    }
+  }
+}
+
+// Applies `operation` to the particles tagged `tag` as they are when it is handled
+#[derive(Clone, Debug)]
+pub struct GroupCommand {
+  pub tag: String,
+  pub operation: GroupOperation,
+}
+
+// Members of the groups commanded this frame, picked before any of them changes
+#[derive(Default)]
+struct SelectedGroups(Vec<(GroupOperation, Vec<Entity>)>);
+
+fn select_groups(world_state: WorldState, mut commands: EventReader<GroupCommand>, mut selected: ResMut<SelectedGroups>) {
+  for command in commands.iter() {
+    selected.0.push((command.operation, world_state.tagged(&command.tag)));
+  }
+}
+
+fn apply_group_operations(
+  mut commands: Commands,
+  mut selected: ResMut<SelectedGroups>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut particles: Query<(&mut Particle, &mut Sprite, &mut Transform, Option<&Static>)>,
+) {
+  for (operation, members) in std::mem::take(&mut selected.0) {
+    for entity in members {
+      let (mut particle, mut sprite, mut transform, fixed) = match particles.get_mut(entity) {
+        Ok(particle) => particle,
+        Err(_) => continue,
+      };
+      let cell = particle.position.floor().as_ivec2();
+      match operation {
+        GroupOperation::Freeze => {
+          if fixed.is_some() || spatial_index.get(&cell) != Some(&entity) { continue }
+          // Reinserting adds the cell's collider along with the `Static` marker
+          spatial_index.remove(&cell);
+          particle.velocity = Vec2::ZERO;
+          particle.position = cell.as_vec2();
+          transform.translation = cell.as_vec2().extend(0.) * Particle::SPRITE_SIZE;
+          spatial_index.insert_static(cell, entity);
+          commands.entity(entity).insert(Static);
+        },
+        GroupOperation::Delete => despawn_particle(&mut commands, &mut spatial_index, entity, &particle),
+        GroupOperation::Recolor((r, g, b)) => {
+          let alpha = sprite.color.a();
+          sprite.color = *Color::rgb(r, g, b).set_a(alpha);
+        },
+        GroupOperation::Impulse((x, y)) => {
+          if fixed.is_some() { continue }
+          let mass = particle.mass.max(f32::EPSILON);
+          particle.velocity += Vec2::new(x, y) / mass;
+          spatial_index.wake(cell);
+        },
+      }
+    }
+  }
+}
@@ -0,0 +1,181 @@
+use bevy::{prelude::*, ecs::schedule::ShouldRun, math::const_ivec2};
+use rand::Rng;
+
+use crate::{
+  despawn_particle, spawn_particle,
+  material::{MaterialId, MaterialRegistry},
+  simulation::{SimulationClock, SimulationRng, SimulationStep},
+  Particle, SpatialIndex, ReactionEvent, Static,
+};
+
+// Plants grow once every few simulation ticks
+const GROWTH_INTERVAL: u64 = 4;
+
+const NEIGHBORS: [IVec2; 8] = [
+  const_ivec2!([-1, -1]), const_ivec2!([0, -1]), const_ivec2!([1, -1]),
+  const_ivec2!([-1, 0]), const_ivec2!([1, 0]),
+  const_ivec2!([-1, 1]), const_ivec2!([0, 1]), const_ivec2!([1, 1]),
+];
+
+pub struct GrowthPlugin;
+
+impl Plugin for GrowthPlugin {
+  fn build(&self, app: &mut App) {
+    app.add_system_set(SystemSet::new()
+      .with_run_criteria(RunCriteria::pipe("fixed_tick", growth_tick))
+      .with_system(germinate_seeds.label("germinate").after(SimulationStep::Commit))
+      .with_system(absorb_nutrients.label("absorb").after("germinate"))
+      .with_system(grow_sprouts.after("absorb"))
+    );
+  }
+}
+
+// A germinated seed, collects energy from the water and soil around it
+#[derive(Component)]
+pub struct Root {
+  pub energy: u32,
+}
+
+// The growing tip of a plant, consumes energy from its root to extend upwards
+#[derive(Component)]
+pub struct Sprout {
+  pub root: Entity,
+  pub height: u32,
+}
+
+impl Sprout {
+  const MAX_HEIGHT: u32 = 12;
+  const BRANCH_INTERVAL: u32 = 3;
+  const BRANCH_CHANCE: f64 = 0.5;
+}
+
+fn growth_tick(In(should_run): In<ShouldRun>, clock: Res<SimulationClock>) -> ShouldRun {
+  if clock.ticked && clock.tick.is_multiple_of(GROWTH_INTERVAL) { return should_run }
+  match should_run {
+    ShouldRun::YesAndCheckAgain => ShouldRun::NoAndCheckAgain,
+    ShouldRun::Yes => ShouldRun::No,
+    other => other,
+  }
+}
+
+fn is_nutrient(material: MaterialId) -> bool {
+  material == MaterialId::WATER || material == MaterialId::SOIL
+}
+
+fn germinate_seeds(
+  mut commands: Commands,
+  mut spatial_index: ResMut<SpatialIndex>,
+  materials: Res<MaterialRegistry>,
+  mut seeds: Query<(Entity, &mut Particle, &mut MaterialId, &mut Sprite), Without<Static>>,
+  mut reactions: EventWriter<ReactionEvent>,
+) {
+  let germinating: Vec<(Entity, IVec2)> = seeds
+    .iter()
+    .filter(|(_, _, material, _)| **material == MaterialId::SEED)
+    .map(|(entity, particle, _, _)| (entity, particle.position.floor().as_ivec2()))
+    .filter(|(_, point)| {
+      NEIGHBORS.iter().any(|offset| {
+        spatial_index
+          .get(&(*point + *offset))
+          .and_then(|entity| seeds.get(*entity).ok())
+          .is_some_and(|(_, _, material, _)| is_nutrient(*material))
+      })
+    })
+    .collect();
+
+  for (entity, point) in germinating {
+    if let Ok((_, mut particle, mut material, mut sprite)) = seeds.get_mut(entity) {
+      reactions.send(ReactionEvent { entity, cell: point, from: *material, to: MaterialId::PLANT });
+      *material = MaterialId::PLANT;
+      sprite.color = materials.get(MaterialId::PLANT).color;
+      particle.velocity = Vec2::ZERO;
+      spatial_index.remove_entity(point, entity);
+      spatial_index.insert_static(point, entity);
+      commands.entity(entity)
+        .insert(Static)
+        .insert(Root { energy: 0 })
+        .insert(Sprout { root: entity, height: 0 });
+    }
+  }
+}
+
+fn absorb_nutrients(
+  mut commands: Commands,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut roots: Query<(Entity, &Particle, &mut Root)>,
+  nutrients: Query<(&Particle, &MaterialId), Without<Static>>,
+) {
+  // Neighboring roots compete for the same cells, so they go in cell order rather than query order
+  let mut order: Vec<(IVec2, Entity)> = roots
+    .iter()
+    .map(|(entity, particle, _)| (particle.position.floor().as_ivec2(), entity))
+    .collect();
+  order.sort_unstable_by_key(|(point, _)| (point.y, point.x));
+
+  for (point, entity) in order {
+    let mut root = match roots.get_mut(entity) {
+      Ok((_, _, root)) => root,
+      Err(_) => continue,
+    };
+    let consumed = NEIGHBORS.iter().find_map(|offset| {
+      let entity = *spatial_index.get(&(point + *offset))?;
+      let (nutrient, material) = nutrients.get(entity).ok()?;
+      is_nutrient(*material).then_some((entity, nutrient))
+    });
+
+    // Roots only consume a single cell per step so growth is gradual
+    if let Some((entity, nutrient)) = consumed {
+      despawn_particle(&mut commands, &mut spatial_index, entity, nutrient);
+      root.energy += 1;
+    }
+  }
+}
+
+fn grow_sprouts(
+  mut commands: Commands,
+  mut spatial_index: ResMut<SpatialIndex>,
+  materials: Res<MaterialRegistry>,
+  mut rng: ResMut<SimulationRng>,
+  sprouts: Query<(Entity, &Particle, &Sprout)>,
+  mut roots: Query<&mut Root>,
+) {
+  // Cell order keeps the random draws identical between deterministic runs
+  let mut order: Vec<_> = sprouts.iter().collect();
+  order.sort_unstable_by_key(|(_, particle, _)| {
+    let point = particle.position.floor().as_ivec2();
+    (point.y, point.x)
+  });
+
+  let rng = &mut rng.0;
+  for (entity, particle, sprout) in order {
+    let mut root = match roots.get_mut(sprout.root) {
+      Ok(root) => root,
+      Err(_) => {
+        // The root was removed so this plant can no longer grow
+        commands.entity(entity).remove::<Sprout>();
+        continue;
+      }
+    };
+
+    let point = particle.position.floor().as_ivec2();
+    let above = point + IVec2::Y;
+    if root.energy == 0 || !spatial_index.is_free(above) { continue }
+
+    let height = sprout.height + 1;
+    root.energy -= 1;
+    commands.entity(entity).remove::<Sprout>();
+    let tip = spawn_particle(&mut commands, &mut spatial_index, &materials, above, MaterialId::PLANT);
+    if height < Sprout::MAX_HEIGHT {
+      commands.entity(tip).insert(Sprout { root: sprout.root, height });
+    }
+
+    if height % Sprout::BRANCH_INTERVAL == 0 && root.energy > 0 && rng.gen_bool(Sprout::BRANCH_CHANCE) {
+      let side = if rng.gen_bool(0.5) { IVec2::new(-1, 1) } else { IVec2::new(1, 1) };
+      if spatial_index.is_free(point + side) {
+        root.energy -= 1;
+        let branch = spawn_particle(&mut commands, &mut spatial_index, &materials, point + side, MaterialId::PLANT);
+        commands.entity(branch).insert(Sprout { root: sprout.root, height });
+      }
+    }
+  }
+}
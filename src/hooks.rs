@@ -0,0 +1,310 @@
+use std::sync::{Arc, Mutex};
+
+use bevy::{prelude::*, utils::HashSet};
+
+use crate::{
+  boids::CaughtEvent,
+  console::{ConsoleApp, ConsoleCommand, ConsoleLog},
+  material::{MaterialId, MaterialRegistry},
+  objectives::ObjectiveEvent,
+  sensors::SensorEvent,
+  simulation::{SimulationStep, TickProgress},
+  Contacts, Particle, ParticleEscapedEvent, ReactionEvent,
+};
+
+pub struct HooksPlugin;
+
+impl Plugin for HooksPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<ArrakoidsHooks>()
+      .init_resource::<Watch>()
+      .add_console_command("watch", "watch <collisions|spawns|despawns|reactions|caught|sensors|escaped|objectives> [off], prints what the hooks see")
+      // `Contacts` only hold the latest tick's collisions, so they are told about right after it
+      .add_system_set(SystemSet::new()
+        .with_run_criteria("fixed_tick")
+        .with_system(run_collision_hooks.after(SimulationStep::Commit))
+      )
+      // PostUpdate sees the spawns and despawns the fixed step queued as commands
+      .add_system_to_stage(CoreStage::PostUpdate, run_hooks)
+      .add_system(run_watch_command)
+      .add_system_to_stage(CoreStage::Last, print_watched);
+  }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ParticleInfo {
+  pub entity: Entity,
+  pub cell: IVec2,
+  pub material: MaterialId,
+}
+
+// `other` is `None` for collisions with the world bounds or a static cell
+#[derive(Clone, Copy, Debug)]
+pub struct CollisionInfo {
+  pub particle: ParticleInfo,
+  pub other: Option<ParticleInfo>,
+}
+
+type Hook<T> = Box<dyn Fn(&T) + Send + Sync>;
+
+// Callbacks for embedders that want to follow the world without writing systems of their own.
+// They run once per frame, after the frame's fixed steps, in the order they were registered.
+// Collision hooks run after each tick instead.
+#[derive(Default)]
+pub struct ArrakoidsHooks {
+  collision: Vec<Hook<CollisionInfo>>,
+  spawn: Vec<Hook<ParticleInfo>>,
+  // Despawned entities no longer have components to describe them
+  despawn: Vec<Hook<Entity>>,
+  reaction: Vec<Hook<ReactionEvent>>,
+  caught: Vec<Hook<CaughtEvent>>,
+  sensor: Vec<Hook<SensorEvent>>,
+  escaped: Vec<Hook<ParticleEscapedEvent>>,
+  objective: Vec<Hook<ObjectiveEvent>>,
+}
+
+impl ArrakoidsHooks {
+  fn is_empty(&self) -> bool {
+    self.collision.is_empty() && self.spawn.is_empty() && self.despawn.is_empty() && self.reaction.is_empty()
+      && self.caught.is_empty() && self.sensor.is_empty() && self.escaped.is_empty() && self.objective.is_empty()
+  }
+
+  pub fn on_collision(&mut self, hook: impl Fn(&CollisionInfo) + Send + Sync + 'static) -> &mut Self {
+    self.collision.push(Box::new(hook));
+    self
+  }
+
+  pub fn on_spawn(&mut self, hook: impl Fn(&ParticleInfo) + Send + Sync + 'static) -> &mut Self {
+    self.spawn.push(Box::new(hook));
+    self
+  }
+
+  pub fn on_despawn(&mut self, hook: impl Fn(&Entity) + Send + Sync + 'static) -> &mut Self {
+    self.despawn.push(Box::new(hook));
+    self
+  }
+
+  pub fn on_reaction(&mut self, hook: impl Fn(&ReactionEvent) + Send + Sync + 'static) -> &mut Self {
+    self.reaction.push(Box::new(hook));
+    self
+  }
+
+  pub fn on_caught(&mut self, hook: impl Fn(&CaughtEvent) + Send + Sync + 'static) -> &mut Self {
+    self.caught.push(Box::new(hook));
+    self
+  }
+
+  pub fn on_sensor(&mut self, hook: impl Fn(&SensorEvent) + Send + Sync + 'static) -> &mut Self {
+    self.sensor.push(Box::new(hook));
+    self
+  }
+
+  pub fn on_escaped(&mut self, hook: impl Fn(&ParticleEscapedEvent) + Send + Sync + 'static) -> &mut Self {
+    self.escaped.push(Box::new(hook));
+    self
+  }
+
+  pub fn on_objective(&mut self, hook: impl Fn(&ObjectiveEvent) + Send + Sync + 'static) -> &mut Self {
+    self.objective.push(Box::new(hook));
+    self
+  }
+}
+
+fn run_collision_hooks(
+  hooks: Res<ArrakoidsHooks>,
+  progress: Res<TickProgress>,
+  contacts: Res<Contacts>,
+  particles: Query<(&Particle, &MaterialId)>,
+) {
+  if hooks.collision.is_empty() || !progress.is_complete() { return }
+  let info = |entity: Entity| {
+    let (particle, material) = particles.get(entity).ok()?;
+    Some(ParticleInfo { entity, cell: particle.position.floor().as_ivec2(), material: *material })
+  };
+
+  for collision in contacts.iter() {
+    let (a, b) = collision.entities();
+    // Either side may have been despawned since the collision
+    let particle = match info(a) {
+      Some(particle) => particle,
+      None => continue,
+    };
+    let collision = CollisionInfo { particle, other: b.and_then(info) };
+    hooks.collision.iter().for_each(|hook| hook(&collision));
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_hooks(
+  hooks: Res<ArrakoidsHooks>,
+  mut reactions: EventReader<ReactionEvent>,
+  mut caught: EventReader<CaughtEvent>,
+  mut sensor_events: EventReader<SensorEvent>,
+  mut escaped: EventReader<ParticleEscapedEvent>,
+  mut objectives: EventReader<ObjectiveEvent>,
+  spawned: Query<(Entity, &Particle, &MaterialId), Added<MaterialId>>,
+  despawned: RemovedComponents<Particle>,
+) {
+  if hooks.is_empty() { return }
+
+  for (entity, particle, material) in spawned.iter() {
+    let spawn = ParticleInfo { entity, cell: particle.position.floor().as_ivec2(), material: *material };
+    hooks.spawn.iter().for_each(|hook| hook(&spawn));
+  }
+
+  for entity in despawned.iter() {
+    hooks.despawn.iter().for_each(|hook| hook(&entity));
+  }
+
+  for reaction in reactions.iter() {
+    hooks.reaction.iter().for_each(|hook| hook(reaction));
+  }
+
+  for catch in caught.iter() {
+    hooks.caught.iter().for_each(|hook| hook(catch));
+  }
+
+  for sensor_event in sensor_events.iter() {
+    hooks.sensor.iter().for_each(|hook| hook(sensor_event));
+  }
+
+  for escape in escaped.iter() {
+    hooks.escaped.iter().for_each(|hook| hook(escape));
+  }
+
+  for objective in objectives.iter() {
+    hooks.objective.iter().for_each(|hook| hook(objective));
+  }
+}
+
+// What `watch` can print, one kind of hook each
+const WATCHABLE: [&str; 8] = ["collisions", "spawns", "despawns", "reactions", "caught", "sensors", "escaped", "objectives"];
+// Lines `watch` prints per frame, the others are only counted
+const WATCH_LINES: usize = 4;
+
+// The hooks `watch` registered, which stay registered once it is turned off and print nothing
+#[derive(Default)]
+struct Watch {
+  registered: HashSet<String>,
+  shared: Arc<Mutex<Watched>>,
+}
+
+#[derive(Default)]
+struct Watched {
+  kinds: HashSet<String>,
+  lines: Vec<String>,
+}
+
+// Registers a hook that prints what it is handed while its kind is watched
+fn watching<T>(
+  shared: &Arc<Mutex<Watched>>,
+  kind: &str,
+  describe: impl Fn(&T) -> String + Send + Sync + 'static,
+) -> impl Fn(&T) + Send + Sync + 'static {
+  let (shared, kind) = (shared.clone(), kind.to_string());
+  move |value| {
+    if let Ok(mut watched) = shared.lock() {
+      if watched.kinds.contains(&kind) {
+        let line = describe(value);
+        watched.lines.push(line);
+      }
+    }
+  }
+}
+
+fn run_watch_command(
+  materials: Res<MaterialRegistry>,
+  mut hooks: ResMut<ArrakoidsHooks>,
+  mut watch: ResMut<Watch>,
+  mut entered: EventReader<ConsoleCommand>,
+  mut log: Option<ResMut<ConsoleLog>>,
+) {
+  for command in entered.iter().filter(|command| command.name == "watch") {
+    let kind = command.args.first().map(|kind| kind.to_lowercase()).unwrap_or_default();
+    let off = command.args.get(1).map(String::as_str) == Some("off");
+    // Materials are named as they were when the hook was registered
+    let names: Arc<Vec<String>> = Arc::new(materials.iter().map(|(_, material)| material.name.clone()).collect());
+    let particle = move |info: &ParticleInfo| {
+      let name = names.get(info.material.0).map_or("Particle", String::as_str);
+      format!("{} {:?} at {} {}", name, info.entity, info.cell.x, info.cell.y)
+    };
+
+    let shared = watch.shared.clone();
+    if !WATCHABLE.contains(&kind.as_str()) {
+      if let Some(log) = log.as_mut() {
+        log.print("watch <collisions|spawns|despawns|reactions|caught|sensors|escaped|objectives> [off]");
+      }
+      continue;
+    }
+    if watch.registered.insert(kind.clone()) {
+      match kind.as_str() {
+        "collisions" => {
+          hooks.on_collision(watching(&shared, &kind, move |collision: &CollisionInfo| match collision.other {
+            Some(other) => format!("{} hit {}", particle(&collision.particle), particle(&other)),
+            None => format!("{} hit a wall", particle(&collision.particle)),
+          }));
+        },
+        "spawns" => { hooks.on_spawn(watching(&shared, &kind, move |info: &ParticleInfo| format!("Spawned {}", particle(info)))); },
+        "despawns" => { hooks.on_despawn(watching(&shared, &kind, |entity: &Entity| format!("Despawned {:?}", entity))); },
+        "reactions" => {
+          hooks.on_reaction(watching(&shared, &kind, move |reaction: &ReactionEvent| {
+            let to = ParticleInfo { entity: reaction.entity, cell: reaction.cell, material: reaction.to };
+            format!("{} was {}", particle(&to), particle(&ParticleInfo { material: reaction.from, ..to }))
+          }));
+        },
+        "caught" => {
+          hooks.on_caught(watching(&shared, &kind, |caught: &CaughtEvent| {
+            format!("Predator {:?} caught {:?} at {} {}", caught.predator, caught.prey, caught.cell.x, caught.cell.y)
+          }));
+        },
+        "sensors" => {
+          hooks.on_sensor(watching(&shared, &kind, |sensor_event: &SensorEvent| match *sensor_event {
+            SensorEvent::Enter { sensor, particle } => format!("{:?} entered sensor {:?}", particle, sensor),
+            SensorEvent::Exit { sensor, particle } => format!("{:?} left sensor {:?}", particle, sensor),
+          }));
+        },
+        "escaped" => {
+          hooks.on_escaped(watching(&shared, &kind, |ParticleEscapedEvent(entity, position): &ParticleEscapedEvent| {
+            format!("{:?} escaped to {:.1} {:.1}", entity, position.x, position.y)
+          }));
+        },
+        _ => {
+          hooks.on_objective(watching(&shared, &kind, |objective: &ObjectiveEvent| match *objective {
+            ObjectiveEvent::Completed { index, points } => format!("Objective {} completed for {} points", index, points),
+            ObjectiveEvent::Failed { index } => format!("Objective {} failed", index),
+          }));
+        },
+      }
+    }
+
+    if let Ok(mut watched) = shared.lock() {
+      if off {
+        watched.kinds.remove(&kind);
+      } else {
+        watched.kinds.insert(kind.clone());
+      }
+    }
+    let answer = format!("{} {}", if off { "Stopped watching" } else { "Watching" }, kind);
+    if let Some(log) = log.as_mut() {
+      log.print(answer);
+    }
+  }
+}
+
+fn print_watched(watch: Res<Watch>, mut log: Option<ResMut<ConsoleLog>>) {
+  let lines = match watch.shared.lock() {
+    Ok(mut watched) if !watched.lines.is_empty() => std::mem::take(&mut watched.lines),
+    _ => return,
+  };
+  let log = match log.as_mut() {
+    Some(log) => log,
+    None => return,
+  };
+  for line in lines.iter().take(WATCH_LINES) {
+    log.print(line.clone());
+  }
+  if lines.len() > WATCH_LINES {
+    log.print(format!("and {} more", lines.len() - WATCH_LINES));
+  }
+}
@@ -0,0 +1,83 @@
+use bevy::prelude::*;
+
+use crate::{
+  inventory::Inventory,
+  locale::Locale,
+  material::MaterialRegistry,
+  objectives::{Goal, ObjectiveStatus, Objectives},
+};
+
+// The score, the loaded scenario's objectives and what is left of rationed materials in the top right
+// corner, shown while there are any
+pub struct HudPlugin;
+
+impl Plugin for HudPlugin {
+  fn build(&self, app: &mut App) {
+    app.add_system(update_hud);
+  }
+}
+
+#[derive(Component)]
+struct HudText;
+
+fn update_hud(
+  mut commands: Commands,
+  asset_server: Res<AssetServer>,
+  objectives: Res<Objectives>,
+  inventory: Res<Inventory>,
+  materials: Res<MaterialRegistry>,
+  locale: Res<Locale>,
+  mut texts: Query<(Entity, &mut Text), With<HudText>>,
+) {
+  if objectives.is_empty() && !inventory.limited {
+    for (entity, _) in texts.iter() {
+      commands.entity(entity).despawn_recursive();
+    }
+    return;
+  }
+  if texts.is_empty() {
+    let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+    commands
+      .spawn_bundle(TextBundle {
+        style: Style {
+          position_type: PositionType::Absolute,
+          position: Rect { right: Val::Px(6.), top: Val::Px(6.), ..Default::default() },
+          ..Default::default()
+        },
+        text: Text::with_section("", TextStyle { font, font_size: 16., color: Color::WHITE }, Default::default()),
+        ..Default::default()
+      })
+      .insert(HudText);
+    return;
+  }
+
+  let mut lines = Vec::new();
+  if !objectives.is_empty() {
+    lines.push(locale.format("objectives.score", &[("score", &objectives.score)]));
+  }
+  for (objective, status, progress) in objectives.iter() {
+    let key = match status {
+      ObjectiveStatus::InProgress => "objectives.in_progress",
+      ObjectiveStatus::Completed => "objectives.completed",
+      ObjectiveStatus::Failed => "objectives.failed",
+    };
+    let progress = match objective.goal {
+      Goal::Collect { count, .. } => format!("{}/{}", progress, count),
+      Goal::Survive { seconds, .. } => locale.format("objectives.seconds", &[
+        ("elapsed", &(progress as u32)),
+        ("seconds", &seconds),
+      ]),
+    };
+    lines.push(locale.format(key, &[("description", &objective.description), ("progress", &progress)]));
+  }
+  if inventory.limited {
+    lines.push(locale.get("inventory.title").to_string());
+    for (material, count) in inventory.iter() {
+      let name = locale.material(&materials.get(material).name);
+      lines.push(locale.format("inventory.stock", &[("material", &name), ("count", &count)]));
+    }
+  }
+  for (_, mut text) in texts.iter_mut() {
+    text.sections[0].value = lines.join("\n");
+  }
+}
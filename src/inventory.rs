@@ -0,0 +1,136 @@
+use bevy::{prelude::*, utils::{HashMap, HashSet}};
+use serde::Deserialize;
+
+use crate::{
+  despawn_particle,
+  material::{MaterialId, MaterialRegistry},
+  scenario::LoadScenario,
+  sensors::{Sensor, SensorEvent},
+  simulation::SimulationStep,
+  Particle, SpatialIndex,
+};
+
+// Particles the player has taken out of the world, to be put back in later. Scenarios can ration
+// materials, then the brush and emitters only place what is in stock and the vacuum and sinks are
+// how it fills up again.
+pub struct InventoryPlugin;
+
+impl Plugin for InventoryPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<Inventory>()
+      .add_system(load_inventory)
+      .add_system_set(SystemSet::new()
+        .with_run_criteria("fixed_tick")
+        .with_system(collect_sinks.after(SimulationStep::Commit))
+      );
+  }
+}
+
+// What a scenario hands the player
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Rations {
+  // Starting amount of each material by name, anything not listed cannot be placed at all
+  pub stock: HashMap<String, u32>,
+  // Rectangles given by their corners that take in particles moving into them and add them to the
+  // stock
+  #[serde(default)]
+  pub sinks: Vec<((i32, i32), (i32, i32))>,
+}
+
+// Marks the sensors of sinks
+#[derive(Component)]
+struct Sink;
+
+#[derive(Default)]
+pub struct Inventory {
+  stock: HashMap<MaterialId, u32>,
+  // Whether placing particles uses up the stock, otherwise there is always enough
+  pub limited: bool,
+}
+
+impl Inventory {
+  pub fn add(&mut self, material: MaterialId, count: u32) {
+    *self.stock.entry(material).or_insert(0) += count;
+  }
+
+  // Takes up to `count` of `material` and returns how many there were
+  pub fn take(&mut self, material: MaterialId, count: u32) -> u32 {
+    match self.stock.get_mut(&material) {
+      Some(stock) => {
+        let taken = count.min(*stock);
+        *stock -= taken;
+        taken
+      },
+      None => 0,
+    }
+  }
+
+  // Uses up one of `material` for a particle about to be placed, if there is one left
+  pub fn spend(&mut self, material: MaterialId) -> bool {
+    !self.limited || self.take(material, 1) == 1
+  }
+
+  // Materials in stock with how many of each, in registry order
+  pub fn iter(&self) -> impl Iterator<Item = (MaterialId, u32)> {
+    let mut stock = self.stock.iter().map(|(material, count)| (*material, *count)).collect::<Vec<_>>();
+    stock.sort_unstable_by_key(|(material, _)| material.0);
+    stock.into_iter()
+  }
+}
+
+fn load_inventory(
+  mut commands: Commands,
+  mut events: EventReader<LoadScenario>,
+  materials: Res<MaterialRegistry>,
+  mut inventory: ResMut<Inventory>,
+  sinks: Query<Entity, With<Sink>>,
+) {
+  let LoadScenario(scenario) = match events.iter().last() {
+    Some(event) => event,
+    None => return,
+  };
+  for entity in sinks.iter() {
+    commands.entity(entity).despawn();
+  }
+  *inventory = Inventory::default();
+  let rations = match &scenario.inventory {
+    Some(rations) if !scenario.sandbox => rations,
+    _ => return,
+  };
+
+  inventory.limited = true;
+  for (name, count) in rations.stock.iter() {
+    match materials.find(name) {
+      Some(material) => inventory.add(material, *count),
+      None => warn!("Unknown material {} in scenario inventory", name),
+    }
+  }
+  for (from, to) in rations.sinks.iter() {
+    let sensor = Sensor::new(IVec2::new(from.0, from.1), IVec2::new(to.0, to.1));
+    commands.spawn().insert(sensor).insert(Sink);
+  }
+}
+
+fn collect_sinks(
+  mut commands: Commands,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut inventory: ResMut<Inventory>,
+  mut sensor_events: EventReader<SensorEvent>,
+  sinks: Query<(), With<Sink>>,
+  particles: Query<(&Particle, &MaterialId)>,
+) {
+  // A particle can cross into several sinks at once and is only taken by the first
+  let mut taken = HashSet::default();
+  for event in sensor_events.iter() {
+    let (sensor, entity) = match *event {
+      SensorEvent::Enter { sensor, particle } => (sensor, particle),
+      SensorEvent::Exit { .. } => continue,
+    };
+    if !sinks.contains(sensor) || !taken.insert(entity) { continue }
+    if let Ok((particle, material)) = particles.get(entity) {
+      inventory.add(*material, 1);
+      despawn_particle(&mut commands, &mut spatial_index, entity, particle);
+    }
+  }
+}
@@ -0,0 +1,224 @@
+use bevy::{prelude::*, utils::HashSet};
+
+use crate::{
+  material::{MaterialId, MaterialRegistry},
+  simulation::{SimulationClock, SimulationSettings, SimulationStep},
+  spawn_particle, Particle, SpatialIndex, Static,
+};
+
+// Static colliders that move things along, either by dragging what rests on them or by moving
+// themselves
+pub struct KinematicPlugin;
+
+impl Plugin for KinematicPlugin {
+  fn build(&self, app: &mut App) {
+    app.add_system_set(SystemSet::new()
+      .with_run_criteria("fixed_tick")
+      .with_system(drive_conveyors.label(SimulationStep::Integrate))
+      .with_system(move_platforms.label(SimulationStep::Integrate))
+    );
+  }
+}
+
+// A static cell that sets the velocity of the particle resting on it, along the surface, to
+// `speed` cells per second. Positive speeds move to the right of the direction gravity pulls in.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Conveyor {
+  pub speed: f32,
+}
+
+// Static cells that travel between waypoints as one, pushing the particles in their way. The
+// waypoints are positions of its bottom left corner, it goes back to the first after the last.
+#[derive(Component, Clone, Debug)]
+pub struct Platform {
+  pub cells: Vec<Entity>,
+  pub waypoints: Vec<IVec2>,
+  // Cells per second
+  pub speed: f32,
+  next: usize,
+  // Where the platform is headed, its cells follow one cell at a time
+  position: Vec2,
+  // Where its bottom left corner is
+  origin: IVec2,
+}
+
+// Spawns a line of `material` from `from` to `to` driving the particles on top of it
+pub fn spawn_conveyor(
+  commands: &mut Commands,
+  spatial_index: &mut SpatialIndex,
+  materials: &MaterialRegistry,
+  from: IVec2,
+  to: IVec2,
+  material: MaterialId,
+  speed: f32,
+) {
+  for cell in line(from, to) {
+    if !spatial_index.is_free(cell) { continue }
+    let entity = spawn_particle(commands, spatial_index, materials, cell, material);
+    commands.entity(entity).insert(Conveyor { speed });
+  }
+}
+
+// Spawns a rectangle of `material` with corners `from` and `to`, which sets off from there along
+// `path`, given for its bottom left corner, and then comes back. Returns the platform's entity.
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_platform(
+  commands: &mut Commands,
+  spatial_index: &mut SpatialIndex,
+  materials: &MaterialRegistry,
+  from: IVec2,
+  to: IVec2,
+  material: MaterialId,
+  path: Vec<IVec2>,
+  speed: f32,
+) -> Entity {
+  let (min, max) = (from.min(to), from.max(to));
+  let mut cells = Vec::new();
+  for y in min.y..=max.y {
+    for x in min.x..=max.x {
+      let cell = IVec2::new(x, y);
+      if spatial_index.is_free(cell) {
+        cells.push(spawn_particle(commands, spatial_index, materials, cell, material));
+      }
+    }
+  }
+
+  let waypoints = std::iter::once(min).chain(path).collect();
+  commands.spawn().insert(Platform { cells, waypoints, speed, next: 0, position: min.as_vec2(), origin: min }).id()
+}
+
+fn line(from: IVec2, to: IVec2) -> impl Iterator<Item = IVec2> {
+  let steps = (to - from).abs().max_element().max(1);
+  (0..=steps).map(move |step| (from.as_vec2() + (to - from).as_vec2() * step as f32 / steps as f32).round().as_ivec2())
+}
+
+fn drive_conveyors(
+  clock: Res<SimulationClock>,
+  settings: Res<SimulationSettings>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  conveyors: Query<(&Particle, &Conveyor), With<Static>>,
+  mut particles: Query<&mut Particle, Without<Static>>,
+) {
+  // A budgeted tick runs its systems over several frames but should only drive once
+  if !clock.ticked { return }
+  let down = settings.gravity.normalize_or_zero();
+  // Without gravity nothing rests on anything
+  if down == Vec2::ZERO { return }
+  let along = down.perp();
+
+  for (belt, conveyor) in conveyors.iter() {
+    let above = (belt.position - down).round().as_ivec2();
+    let mut particle = match spatial_index.get(&above).and_then(|entity| particles.get_mut(*entity).ok()) {
+      Some(particle) => particle,
+      None => continue,
+    };
+    let speed = particle.velocity.dot(along);
+    particle.velocity += along * (conveyor.speed - speed);
+    // Right against the belt the least bit of falling would count as hitting it and stop the
+    // particle, so it rides in the middle of its cell
+    let center = above.as_vec2() + Vec2::splat(0.5);
+    let sag = (particle.position - center).dot(down);
+    particle.position -= down * sag;
+    spatial_index.wake(above);
+  }
+}
+
+fn move_platforms(
+  clock: Res<SimulationClock>,
+  settings: Res<SimulationSettings>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut platforms: Query<&mut Platform>,
+  mut particles: Query<(&mut Particle, &mut Transform, Option<&Static>)>,
+) {
+  if !clock.ticked { return }
+
+  for mut platform in platforms.iter_mut() {
+    platform.cells.retain(|entity| particles.get(*entity).is_ok());
+    if platform.cells.is_empty() { continue }
+
+    let speed = platform.speed;
+    let step = speed * settings.tick_delta();
+    let target = platform.waypoints[platform.next].as_vec2();
+    let offset = target - platform.position;
+    if offset.length() <= step {
+      platform.position = target;
+      platform.next = (platform.next + 1) % platform.waypoints.len();
+    } else {
+      platform.position += offset.normalize() * step;
+    }
+
+    // One cell at a time, so everything in the way is pushed instead of jumped over
+    let destination = platform.position.round().as_ivec2();
+    while platform.origin != destination {
+      let delta = destination - platform.origin;
+      let direction = if delta.x != 0 { IVec2::new(delta.x.signum(), 0) } else { IVec2::new(0, delta.y.signum()) };
+      if !shift_platform(&platform.cells, direction, speed, &mut spatial_index, &mut particles) {
+        // Blocked, it waits where it is until the way clears
+        platform.position = platform.origin.as_vec2();
+        break;
+      }
+      platform.origin += direction;
+    }
+  }
+}
+
+// Moves every cell of a platform one cell in `direction`, pushing the particles in front of it
+// along. Returns false without moving anything when a static cell, another platform or the
+// world bounds are in the way.
+fn shift_platform(
+  cells: &[Entity],
+  direction: IVec2,
+  speed: f32,
+  spatial_index: &mut SpatialIndex,
+  particles: &mut Query<(&mut Particle, &mut Transform, Option<&Static>)>,
+) -> bool {
+  let own: HashSet<Entity> = cells.iter().copied().collect();
+  let points: Vec<IVec2> = cells
+    .iter()
+    .filter_map(|entity| particles.get(*entity).ok())
+    .map(|(particle, _, _)| particle.position.floor().as_ivec2())
+    .collect();
+
+  // Each row of particles in front of a cell moves along one, which needs a free cell at its end
+  let mut pushed: Vec<(Entity, IVec2)> = Vec::new();
+  for point in points.iter() {
+    let mut ahead = *point + direction;
+    while let Some(entity) = spatial_index.get(&ahead).copied() {
+      if own.contains(&entity) { break }
+      match particles.get(entity) {
+        Ok((_, _, None)) => pushed.push((entity, ahead)),
+        _ => return false,
+      }
+      ahead += direction;
+    }
+    if spatial_index.get(&ahead).is_none() && !spatial_index.is_free(ahead) { return false }
+  }
+
+  // Farthest first, so no particle lands on one that has yet to move
+  pushed.sort_by_key(|(_, point)| -point.dot(direction));
+  for (entity, point) in pushed {
+    spatial_index.move_entity(point, point + direction);
+    if let Ok((mut particle, mut transform, _)) = particles.get_mut(entity) {
+      particle.position += direction.as_vec2();
+      let push = direction.as_vec2();
+      let along = particle.velocity.dot(push);
+      if along < speed {
+        particle.velocity += push * (speed - along);
+      }
+      transform.translation = (point + direction).as_vec2().extend(0.) * Particle::SPRITE_SIZE;
+    }
+  }
+
+  // All of the platform leaves its cells before any of it lands, it may overlap itself
+  for (entity, point) in cells.iter().zip(points.iter()) {
+    spatial_index.remove_entity(*point, *entity);
+  }
+  for (entity, point) in cells.iter().zip(points.iter()) {
+    spatial_index.insert_static(*point + direction, *entity);
+    if let Ok((mut particle, mut transform, _)) = particles.get_mut(*entity) {
+      particle.position += direction.as_vec2();
+      transform.translation = (*point + direction).as_vec2().extend(0.) * Particle::SPRITE_SIZE;
+    }
+  }
+  true
+}
@@ -0,0 +1,49 @@
+use bevy::prelude::*;
+use bitflags::bitflags;
+
+bitflags! {
+  pub struct Layers: u32 {
+    const DEFAULT = 1 << 0;
+    const GAS = 1 << 1;
+    // Fixed cells gas drifts through
+    const GRATE = 1 << 2;
+    const BOID = 1 << 3;
+    // Particles that are only there to look at
+    const DECORATION = 1 << 4;
+    // Short lived bits thrown off by other particles
+    const DEBRIS = 1 << 5;
+  }
+}
+
+// Two particles only collide when each one's mask includes the other's layer, and they are not
+// parts of the same group. Particles without this component are on `Layers::DEFAULT` and collide
+// with everything.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CollisionLayers {
+  pub layer: Layers,
+  pub mask: Layers,
+  // The body the particle is part of, such as a `RigidCluster`, whose parts only move together
+  pub group: Option<Entity>,
+}
+
+impl Default for CollisionLayers {
+  fn default() -> Self {
+    Self { layer: Layers::DEFAULT, mask: Layers::all(), group: None }
+  }
+}
+
+impl CollisionLayers {
+  pub fn new(layer: Layers, mask: Layers) -> Self {
+    Self { layer, mask, group: None }
+  }
+
+  pub fn collides_with(&self, other: &CollisionLayers) -> bool {
+    if self.group.is_some() && self.group == other.group { return false }
+    self.mask.intersects(other.layer) && other.mask.intersects(self.layer)
+  }
+}
+
+pub fn collides(a: Entity, b: Entity, layers: &Query<&CollisionLayers>) -> bool {
+  let layers_of = |entity| layers.get(entity).copied().unwrap_or_default();
+  layers_of(a).collides_with(&layers_of(b))
+}
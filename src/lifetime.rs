@@ -0,0 +1,72 @@
+use bevy::prelude::*;
+
+use crate::{
+  despawn_particle, spawn_particle_with_velocity,
+  material::{MaterialId, MaterialRegistry},
+  simulation::SimulationStep,
+  Particle, SpatialIndex,
+};
+
+pub struct LifetimePlugin;
+
+impl Plugin for LifetimePlugin {
+  fn build(&self, app: &mut App) {
+    app.add_system_set(SystemSet::new()
+      .with_run_criteria("fixed_tick")
+      .with_system(age_particles.after(SimulationStep::Commit))
+    );
+  }
+}
+
+// What happens to a particle once its lifetime is over
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Expiry {
+  Despawn,
+  // Replaced in place by a particle of the given material, keeping its velocity
+  Convert(MaterialId),
+  // Turns more transparent with age and is despawned at the end
+  FadeOut,
+}
+
+// Ticks a particle lives for, set from its material on spawn
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Lifetime {
+  pub ticks: u64,
+  pub expiry: Expiry,
+}
+
+// Ticks a particle with a `Lifetime` has lived so far
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct Age(pub u64);
+
+fn age_particles(
+  mut commands: Commands,
+  mut spatial_index: ResMut<SpatialIndex>,
+  materials: Res<MaterialRegistry>,
+  mut particles: Query<(Entity, &Particle, &MaterialId, &Lifetime, &mut Age, &mut Sprite)>,
+) {
+  let mut expired = Vec::new();
+  for (entity, particle, material, lifetime, mut age, mut sprite) in particles.iter_mut() {
+    age.0 += 1;
+    if age.0 >= lifetime.ticks {
+      expired.push((particle.position.floor().as_ivec2(), entity));
+    } else if lifetime.expiry == Expiry::FadeOut {
+      let alpha = materials.get(*material).color.a();
+      sprite.color.set_a(alpha * (1. - age.0 as f32 / lifetime.ticks as f32));
+    }
+  }
+
+  // Conversions spawn new entities, so they go in cell order rather than query order
+  expired.sort_unstable_by_key(|(cell, _)| (cell.y, cell.x));
+  for (cell, entity) in expired {
+    let (particle, lifetime) = match particles.get(entity) {
+      Ok((_, particle, _, lifetime, _, _)) => (particle, *lifetime),
+      Err(_) => continue,
+    };
+    let velocity = particle.velocity;
+    despawn_particle(&mut commands, &mut spatial_index, entity, particle);
+    if let Expiry::Convert(material) = lifetime.expiry {
+      spawn_particle_with_velocity(&mut commands, &mut spatial_index, &materials, cell, material, velocity);
+    }
+  }
+}
@@ -0,0 +1,108 @@
+use std::{fmt::Display, fs, path::Path};
+
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::config::Config;
+
+// Always there to fall back to, whatever is in the locales directory
+const ENGLISH: &str = include_str!("../assets/locales/en.toml");
+
+// Text shown in the UI, looked up by key in the current language's table. Tables are TOML files in
+// `assets/locales` named after their language code, nested tables join their keys with dots and
+// braces in a string are filled in by the game. Keys a table leaves out are shown in English.
+pub struct LocalePlugin;
+
+impl Plugin for LocalePlugin {
+  fn build(&self, app: &mut App) {
+    app.init_resource::<Locale>();
+  }
+}
+
+pub struct Locale {
+  pub language: String,
+  strings: HashMap<String, String>,
+  fallback: HashMap<String, String>,
+}
+
+impl FromWorld for Locale {
+  fn from_world(world: &mut World) -> Self {
+    let language = world.get_resource::<Config>().map_or_else(|| "en".to_string(), |config| config.language.clone());
+    Self::load(&language)
+  }
+}
+
+impl Locale {
+  pub const DIRECTORY: &'static str = "assets/locales";
+
+  // Unknown languages and tables that fail to parse show everything in English
+  pub fn load(language: &str) -> Self {
+    let fallback = parse_table(ENGLISH).unwrap_or_default();
+    let path = Path::new(Self::DIRECTORY).join(language).with_extension("toml");
+    let strings = match fs::read_to_string(&path).map(|contents| parse_table(&contents)) {
+      Ok(Ok(strings)) => strings,
+      Ok(Err(error)) => {
+        error!("Failed to parse {}: {}", path.display(), error);
+        HashMap::default()
+      },
+      Err(_) => HashMap::default(),
+    };
+    Self { language: language.to_string(), strings, fallback }
+  }
+
+  // Codes of the languages with a table, sorted
+  pub fn languages() -> Vec<String> {
+    let mut languages = fs::read_dir(Self::DIRECTORY)
+      .map(|entries| {
+        entries
+          .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+          .filter(|path| path.extension().is_some_and(|extension| extension == "toml"))
+          .filter_map(|path| Some(path.file_stem()?.to_str()?.to_string()))
+          .collect::<Vec<_>>()
+      })
+      .unwrap_or_default();
+    if languages.is_empty() {
+      languages.push("en".to_string());
+    }
+    languages.sort();
+    languages
+  }
+
+  // The key itself when no table has it, so missing strings stand out
+  pub fn get<'a>(&'a self, key: &'a str) -> &'a str {
+    self.strings.get(key).or_else(|| self.fallback.get(key)).map_or(key, String::as_str)
+  }
+
+  // Replaces every `{name}` in the string with its value
+  pub fn format(&self, key: &str, args: &[(&str, &dyn Display)]) -> String {
+    args.iter().fold(self.get(key).to_string(), |text, (name, value)| text.replace(&format!("{{{}}}", name), &value.to_string()))
+  }
+
+  // Materials from packs have no entry and keep the name they were registered with
+  pub fn material<'a>(&'a self, name: &'a str) -> &'a str {
+    let key = format!("materials.{}", name.to_lowercase());
+    self.strings.get(&key).or_else(|| self.fallback.get(&key)).map_or(name, String::as_str)
+  }
+
+  pub fn on_off(&self, on: bool) -> &str {
+    self.get(if on { "common.on" } else { "common.off" })
+  }
+}
+
+fn parse_table(contents: &str) -> Result<HashMap<String, String>, toml::de::Error> {
+  let mut strings = HashMap::default();
+  flatten("", &toml::from_str(contents)?, &mut strings);
+  Ok(strings)
+}
+
+fn flatten(prefix: &str, value: &toml::Value, strings: &mut HashMap<String, String>) {
+  match value {
+    toml::Value::Table(table) => {
+      for (key, value) in table {
+        let key = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+        flatten(&key, value, strings);
+      }
+    },
+    toml::Value::String(text) => { strings.insert(prefix.to_string(), text.clone()); },
+    _ => {},
+  }
+}
@@ -0,0 +1,240 @@
+use bevy::{prelude::*, math::const_ivec2, utils::{HashMap, HashSet}};
+use serde::Deserialize;
+
+use crate::{
+  despawn_particle,
+  material::{MaterialId, MaterialRegistry},
+  simulation::{SimulationClock, SimulationSettings, SimulationStep},
+  spawn_particle, Particle, SpatialIndex, Static,
+};
+
+const NEIGHBORS: [IVec2; 4] = [const_ivec2!([-1, 0]), const_ivec2!([1, 0]), const_ivec2!([0, -1]), const_ivec2!([0, 1])];
+
+// How much brighter wires carrying a signal are drawn
+const GLOW: f32 = 1.8;
+
+// Contraptions built from particles. Connected plate cells are pressed while enough particles rest
+// on them and power the wires next to them, connected wire cells carry a signal all the way, gates
+// combine the wires around them into the wire they face and connected door cells open while a
+// wire or plate next to them is powered.
+pub struct MachinesPlugin;
+
+impl Plugin for MachinesPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<Signals>()
+      .add_system_set(SystemSet::new()
+        .with_run_criteria("fixed_tick")
+        .with_system(run_machines.after(SimulationStep::Commit))
+      );
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum GateKind {
+  And,
+  Or,
+  Not,
+  Xor,
+}
+
+// A gate cell powers the wire at `output`, relative to it, from the other wires around it. Its
+// output changes a tick after its inputs, so gates wired into a loop hold their state. Gate cells
+// without one, like painted ones, are `Or` gates facing up.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Gate {
+  pub kind: GateKind,
+  pub output: IVec2,
+}
+
+impl Default for Gate {
+  fn default() -> Self {
+    Self { kind: GateKind::Or, output: IVec2::Y }
+  }
+}
+
+impl Gate {
+  fn evaluate(&self, inputs: &[bool]) -> bool {
+    match self.kind {
+      GateKind::And => !inputs.is_empty() && inputs.iter().all(|input| *input),
+      GateKind::Or => inputs.iter().any(|input| *input),
+      GateKind::Not => !inputs.iter().any(|input| *input),
+      GateKind::Xor => inputs.iter().filter(|input| **input).count() % 2 == 1,
+    }
+  }
+}
+
+// Number of particles that have to rest on a plate, stacked or side by side, for it to be pressed.
+// A plate made of several cells takes the highest of theirs, plates without one need a single
+// particle.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct PlateThreshold(pub u32);
+
+// What the machines did last tick
+#[derive(Default)]
+pub struct Signals {
+  // Cells of powered wires and pressed plates
+  pub powered: HashSet<IVec2>,
+  // Gate cells whose output is on
+  pub gates: HashSet<IVec2>,
+}
+
+// Spawns a line of plate cells from `from` to `to` that needs `threshold` particles on it
+pub fn spawn_plate(
+  commands: &mut Commands,
+  spatial_index: &mut SpatialIndex,
+  materials: &MaterialRegistry,
+  from: IVec2,
+  to: IVec2,
+  threshold: u32,
+) {
+  let (min, max) = (from.min(to), from.max(to));
+  for y in min.y..=max.y {
+    for x in min.x..=max.x {
+      let cell = IVec2::new(x, y);
+      if !spatial_index.is_free(cell) { continue }
+      let entity = spawn_particle(commands, spatial_index, materials, cell, MaterialId::PLATE);
+      commands.entity(entity).insert(PlateThreshold(threshold));
+    }
+  }
+}
+
+pub fn spawn_gate(commands: &mut Commands, spatial_index: &mut SpatialIndex, materials: &MaterialRegistry, cell: IVec2, gate: Gate) {
+  if !spatial_index.is_free(cell) { return }
+  let entity = spawn_particle(commands, spatial_index, materials, cell, MaterialId::GATE);
+  commands.entity(entity).insert(gate);
+}
+
+// Groups of 4-connected cells, each flooded from its first cell in cell order
+fn connected(cells: &HashSet<IVec2>) -> Vec<Vec<IVec2>> {
+  let mut order: Vec<IVec2> = cells.iter().copied().collect();
+  order.sort_unstable_by_key(|cell| (cell.y, cell.x));
+  let mut visited = HashSet::default();
+  let mut groups = Vec::new();
+  for start in order {
+    if !visited.insert(start) { continue }
+    let mut group = vec![start];
+    let mut index = 0;
+    while index < group.len() {
+      let cell = group[index];
+      index += 1;
+      for offset in NEIGHBORS {
+        let neighbor = cell + offset;
+        if cells.contains(&neighbor) && visited.insert(neighbor) {
+          group.push(neighbor);
+        }
+      }
+    }
+    groups.push(group);
+  }
+  groups
+}
+
+type Parts<'w, 's> = Query<'w, 's, (&'static Particle, &'static MaterialId, &'static mut Sprite, Option<&'static Gate>, Option<&'static PlateThreshold>), With<Static>>;
+
+#[allow(clippy::too_many_arguments)]
+fn run_machines(
+  mut commands: Commands,
+  clock: Res<SimulationClock>,
+  settings: Res<SimulationSettings>,
+  materials: Res<MaterialRegistry>,
+  mut signals: ResMut<Signals>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut parts: Parts,
+  loose: Query<(), (With<Particle>, Without<Static>)>,
+) {
+  if !clock.ticked { return }
+  let mut wires = HashSet::default();
+  let mut plates = HashMap::default();
+  let mut doors: HashSet<IVec2> = spatial_index.open_doors.clone();
+  let mut gates = HashMap::default();
+  for (cell, entity) in spatial_index.iter() {
+    let (_, material, _, gate, threshold) = match parts.get(*entity) {
+      Ok(part) => part,
+      Err(_) => continue,
+    };
+    match *material {
+      MaterialId::WIRE => { wires.insert(*cell); },
+      MaterialId::PLATE => { plates.insert(*cell, threshold.map_or(1, |threshold| threshold.0)); },
+      MaterialId::DOOR => { doors.insert(*cell); },
+      MaterialId::GATE => { gates.insert(*cell, gate.copied().unwrap_or_default()); },
+      _ => {},
+    }
+  }
+  if wires.is_empty() && plates.is_empty() && doors.is_empty() && gates.is_empty() { return }
+
+  // Plates weigh the stacks of moving particles on top of them, so they need an up
+  let up = (-settings.gravity.normalize_or_zero()).round().as_ivec2();
+  let mut pressed = HashSet::default();
+  if up != IVec2::ZERO {
+    let plate_cells = plates.keys().copied().collect();
+    for plate in connected(&plate_cells) {
+      let mut load = 0;
+      for cell in plate.iter() {
+        let mut above = *cell + up;
+        while spatial_index.get(&above).is_some_and(|entity| loose.get(*entity).is_ok()) {
+          load += 1;
+          above += up;
+        }
+      }
+      let threshold = plate.iter().map(|cell| plates[cell]).max().unwrap_or(1);
+      if load >= threshold {
+        pressed.extend(plate);
+      }
+    }
+  }
+
+  // A wire carries a signal when any of its cells touches a pressed plate or the output of a gate
+  // that was on
+  let mut powered: HashSet<IVec2> = pressed.clone();
+  for wire in connected(&wires) {
+    let fed = wire.iter().any(|cell| {
+      NEIGHBORS.iter().any(|offset| pressed.contains(&(*cell + *offset)))
+        || gates.iter().any(|(gate_cell, gate)| signals.gates.contains(gate_cell) && *gate_cell + gate.output == *cell)
+    });
+    if fed {
+      powered.extend(wire);
+    }
+  }
+
+  let mut gates_on = HashSet::default();
+  for (cell, gate) in gates.iter() {
+    let inputs: Vec<bool> = NEIGHBORS
+      .iter()
+      .filter(|offset| **offset != gate.output && wires.contains(&(*cell + **offset)))
+      .map(|offset| powered.contains(&(*cell + *offset)))
+      .collect();
+    if gate.evaluate(&inputs) {
+      gates_on.insert(*cell);
+    }
+  }
+
+  // Doors open as a whole, and only close once nothing is left in the way
+  for door in connected(&doors) {
+    let open = door.iter().any(|cell| NEIGHBORS.iter().any(|offset| powered.contains(&(*cell + *offset))));
+    for cell in door {
+      if open && !spatial_index.open_doors.contains(&cell) {
+        if let Some(entity) = spatial_index.get(&cell).copied() {
+          if let Ok((particle, _, _, _, _)) = parts.get(entity) {
+            despawn_particle(&mut commands, &mut spatial_index, entity, particle);
+          }
+        }
+        spatial_index.open_doors.insert(cell);
+      } else if !open && spatial_index.open_doors.contains(&cell) && spatial_index.is_free(cell) {
+        spatial_index.open_doors.remove(&cell);
+        spawn_particle(&mut commands, &mut spatial_index, &materials, cell, MaterialId::DOOR);
+      }
+    }
+  }
+
+  *signals = Signals { powered, gates: gates_on };
+  for (cell, entity) in spatial_index.iter() {
+    if let Ok((_, &MaterialId::WIRE, mut sprite, _, _)) = parts.get_mut(*entity) {
+      let color = materials.get(MaterialId::WIRE).color;
+      let color = if signals.powered.contains(cell) { color * GLOW } else { color };
+      if sprite.color != color {
+        sprite.color = color;
+      }
+    }
+  }
+}
@@ -1,291 +1,1531 @@
-use std::{ops::{Deref, DerefMut}, hash::{Hash, BuildHasher, Hasher}};
+use bevy::{prelude::*, app::ScheduleRunnerSettings, tasks::ComputeTaskPool, diagnostic::LogDiagnosticsPlugin, log::{LogPlugin, LogSettings}, utils::{Duration, HashMap, HashSet, Instant}, math::const_vec2};
 
-use bevy::{prelude::*, utils::{HashMap, StableHashSet}, math::const_vec2, core::FixedTimestep};
+use actions::ActionsPlugin;
+use attractors::AttractorsPlugin;
+use autosave::AutosavePlugin;
+use args::Args;
+use bench::BenchPlugin;
+use boids::{Boid, BoidRole, BoidsPlugin, Predator, Prey};
+use camera::CameraPlugin;
+use capture::CapturePlugin;
+use clusters::ClustersPlugin;
+use config::Config;
+use console::ConsolePlugin;
+use cursor::{CursorPlugin, MainCamera};
+use environment::EnvironmentPlugin;
+use erosion::ErosionPlugin;
+use glow::GlowPlugin;
+use groups::GroupsPlugin;
+use growth::GrowthPlugin;
+use hooks::HooksPlugin;
+use hud::HudPlugin;
+use inventory::InventoryPlugin;
+use kinematic::KinematicPlugin;
+use layers::CollisionLayers;
+use machines::MachinesPlugin;
+use lifetime::{Age, LifetimePlugin};
+use locale::LocalePlugin;
+use material::{MaterialDef, MaterialId, MaterialRegistry};
+use menu::MenuPlugin;
+use metrics::MetricsPlugin;
+use net::{NetPlugin, NetRole};
+use objectives::ObjectivesPlugin;
+use palette::PalettePlugin;
+use phases::PhasesPlugin;
+use portals::Portal;
+use pool::{release_pooled, RecycleParticle};
+use pressure::PressurePlugin;
+use profiling::ProfilingPlugin;
+use renderer::RendererPlugin;
+use rewind::RewindPlugin;
+use scenario::ScenarioPlugin;
+use scenes::WorldScenePlugin;
+use simulation::{
+  cpu_backend, fixed_tick, Boundary, CombineRule, SimulationClock, SimulationDiagnostics, SimulationFocus, SimulationLod, SimulationRng,
+  SimulationSettings, SimulationStep, TickPhase, TickProgress, UpdateOrder,
+};
+use sensors::{Sensor, SensorEvent, SensorsPlugin};
+use spatial::{BoundsExt, SpatialIndex, SpatialIndexPlugin};
+use springs::SpringsPlugin;
+use terrain::TerrainPlugin;
+use stats::StatsPlugin;
+use streaming::StreamingPlugin;
+use streamlines::StreamlinesPlugin;
+use tools::ToolsPlugin;
+use touch::TouchPlugin;
+use trails::TrailsPlugin;
+use world_state::WorldStatePlugin;
+use worlds::WorldsPlugin;
+
+mod accessibility;
+mod actions;
+mod args;
+mod attractors;
+mod autosave;
+mod bench;
+mod boids;
+mod camera;
+mod capture;
+mod clusters;
+mod config;
+mod console;
+mod cursor;
+mod environment;
+mod erosion;
+#[cfg(feature = "gpu")]
+mod gpu;
+mod glow;
+mod groups;
+mod growth;
+mod hooks;
+mod hud;
+mod inventory;
+mod kinematic;
+mod layers;
+mod lifetime;
+mod locale;
+mod machines;
+mod material;
+mod menu;
+mod metrics;
+mod net;
+mod objectives;
+#[cfg(feature = "plugins")]
+mod packs;
+mod palette;
+mod phases;
+mod pool;
+mod portals;
+mod pressure;
+mod profiling;
+mod renderer;
+mod rewind;
+mod scenario;
+mod scenes;
+#[cfg(feature = "scripting")]
+mod scripting;
+mod sensors;
+mod simulation;
+mod snapshot;
+mod spatial;
+mod springs;
+mod stats;
+mod streaming;
+mod streamlines;
+mod terrain;
+mod tools;
+mod touch;
+mod trails;
+mod visualization;
+mod world_state;
+mod worlds;
 
 fn main() {
-  App::new()
-    .add_plugins(DefaultPlugins)
-    .insert_resource(ParticleLookup::new(40, 20))
-    .add_event::<ParticleCollisionEvent>()
-    .add_startup_system(setup)
-    .add_system(handle_collisions.label("collisions"))
+  let args = Args::parse();
+  let headless = args.net.is_headless();
+
+  let mut app = App::new();
+  app.insert_resource(LogSettings { level: args.log_level(), ..Default::default() });
+  if headless {
+    // A dedicated server only simulates, so it skips the window, rendering and input
+    app
+      .insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(1. / 60.)))
+      .add_plugins(MinimalPlugins)
+      .add_plugin(LogPlugin);
+  } else {
+    let config = Config::load(Config::PATH);
+    app
+      .insert_resource(config.window_descriptor())
+      .insert_resource(config)
+      .add_plugins(DefaultPlugins);
+  }
+
+  // Networked instances go straight into the shared world and benchmarks into theirs
+  let initial_state = if args.net == NetRole::Offline && args.benchmark.is_none() { AppState::MainMenu } else { AppState::Running };
+  add_simulation(&mut app, &args, initial_state);
+
+  if !headless {
+    app
+      .add_startup_system(setup)
+      .add_plugin(ActionsPlugin)
+      .add_plugin(AutosavePlugin)
+      .add_plugin(CameraPlugin)
+      .add_plugin(CapturePlugin)
+      .add_plugin(ConsolePlugin)
+      .add_plugin(CursorPlugin)
+      .add_plugin(GlowPlugin)
+      .add_plugin(HudPlugin)
+      .add_plugin(LocalePlugin)
+      .add_plugin(MenuPlugin)
+      .add_plugin(PalettePlugin)
+      .add_plugin(RendererPlugin)
+      .add_plugin(RewindPlugin)
+      .add_plugin(StatsPlugin)
+      .add_plugin(StreamingPlugin)
+      .add_plugin(StreamlinesPlugin)
+      .add_plugin(ToolsPlugin)
+      .add_plugin(TouchPlugin)
+      .add_plugin(TrailsPlugin)
+      .add_plugin(WorldsPlugin);
+
+    #[cfg(feature = "gpu")]
+    app.add_plugin(gpu::GpuSimulationPlugin);
+  }
+
+  app.add_plugin(NetPlugin { role: args.net, lockstep: args.lockstep });
+  if args.diagnostics {
+    app.add_plugin(LogDiagnosticsPlugin::default());
+  }
+  app.add_plugin(MetricsPlugin { csv: args.metrics_csv, prometheus: args.metrics_address });
+  app.run();
+}
+
+// Everything a world needs to simulate, without anything to show or play it. Besides the main world
+// it makes up the further worlds of `worlds`.
+pub fn add_simulation(app: &mut App, args: &Args, initial_state: AppState) {
+  // Infinite worlds only simulate the area around the camera in full
+  let mut settings = SimulationSettings {
+    debug_log: args.sim_debug,
+    lod: args.infinite.then(SimulationLod::default),
+    ..Default::default()
+  };
+  let gravity = args.gravity.unwrap_or_default();
+  gravity.apply(&mut settings);
+  app
+    .insert_resource(SpatialIndex::new(40, 20).with_infinite(args.infinite))
+    .init_resource::<MaterialRegistry>()
+    .insert_resource(settings)
+    .insert_resource(gravity)
+    .init_resource::<SimulationFocus>()
+    .init_resource::<SimulationClock>()
+    .init_resource::<SimulationDiagnostics>()
+    .init_resource::<SimulationRng>()
+    .init_resource::<TickProgress>()
+    .add_state(initial_state)
+    .init_resource::<Contacts>()
+    .init_resource::<Resting>()
+    .register_type::<WorldCollision>()
+    .register_type::<ParticleCollision>()
+    .add_event::<ParticleEscapedEvent>()
+    .add_event::<ReactionEvent>()
+    .add_startup_system(configure_simulation)
+    .add_system_to_stage(CoreStage::PostUpdate, attach_material.before("world_checksum"))
+    .add_system_to_stage(CoreStage::PostUpdate, separate_particles.before("world_checksum"))
+    // See `SimulationStep` for what each step does
     .add_system_set(SystemSet::new()
-      .with_run_criteria(FixedTimestep::step(0.25))
-      .with_system(discover_collisions.label("discover").after("collisions"))
-      .with_system(handle_movement.after("discover"))
+      .with_run_criteria(fixed_tick.label("fixed_tick"))
+      .with_system(sanitize_velocities.after(SimulationStep::Resolve).before(SimulationStep::Commit))
+      .with_system(age_chunks.after(SimulationStep::Commit).after("wake_changed"))
+      .with_system(release_pooled.after(SimulationStep::Commit))
     )
-    .run();
+    .add_system_set(SystemSet::new()
+      .with_run_criteria(RunCriteria::pipe("fixed_tick", cpu_backend))
+      .with_system(discover_collisions.label(SimulationStep::Collide).after(SimulationStep::Integrate))
+      .with_system(handle_collisions.label(SimulationStep::Resolve).after(SimulationStep::Collide))
+      .with_system(handle_movement.label(SimulationStep::Commit).after(SimulationStep::Resolve))
+    )
+    .add_plugin(AttractorsPlugin)
+    .add_plugin(BenchPlugin { stress: args.stress, benchmark: args.benchmark })
+    .add_plugin(BoidsPlugin)
+    .add_plugin(ClustersPlugin)
+    .add_plugin(EnvironmentPlugin)
+    .add_plugin(ErosionPlugin)
+    .add_plugin(GroupsPlugin)
+    .add_plugin(GrowthPlugin)
+    .add_plugin(HooksPlugin)
+    .add_plugin(InventoryPlugin)
+    .add_plugin(KinematicPlugin)
+    .add_plugin(LifetimePlugin)
+    .add_plugin(MachinesPlugin)
+    .add_plugin(ObjectivesPlugin)
+    .add_plugin(PhasesPlugin)
+    .add_plugin(PressurePlugin)
+    .add_plugin(ProfilingPlugin)
+    .add_plugin(ScenarioPlugin)
+    .add_plugin(SensorsPlugin)
+    .add_plugin(SpatialIndexPlugin)
+    .add_plugin(SpringsPlugin)
+    .add_plugin(TerrainPlugin)
+    .add_plugin(WorldScenePlugin)
+    .add_plugin(WorldStatePlugin);
+
+  // Registers the packs' materials, before anything that reads the registry on build
+  #[cfg(feature = "plugins")]
+  app.add_plugin(packs::MaterialPacksPlugin);
+
+  #[cfg(feature = "scripting")]
+  app.add_plugin(scripting::ScriptingPlugin);
 }
 
-pub trait BoundsExt {
-  fn outside(&self, point: Vec2) -> Option<Vec2>;
-  fn min(&self) -> Vec2;
-  fn max(&self) -> Vec2;
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AppState {
+  MainMenu,
+  Running,
+  Paused,
+  Settings,
+  // A saved world is being read and put back, nothing runs until it is all there
+  Loading,
 }
 
-impl BoundsExt for Rect<f32> {
-  fn outside(&self, point: Vec2) -> Option<Vec2> {
-    let mut normal = Vec2::ZERO;
-    if point.x < self.left {
-      normal.x = 1.;
-    } else if point.x > self.right {
-      normal.x = -1.;
-    }
-    if point.y < self.bottom {
-      normal.y = 1.;
-    } else if point.y > self.top {
-      normal.y = -1.;
+#[derive(Component, Clone, Debug, Default, Reflect)]
+#[reflect(Component)]
+pub struct Particle {
+  pub position: Vec2,
+  pub velocity: Vec2,
+  pub mass: f32,
+  pub elasticity: f32,
+}
+
+impl Particle {
+  const SPRITE_SIZE: f32 = 16.0;
+  // Default for `SimulationSettings::gravity`, in cells per second squared
+  const GRAVITY: Vec2 = const_vec2!([0., -4.]);
+
+  pub fn new(position: Vec2, mass: f32) -> Self {
+    Self { position, velocity: Vec2::ZERO, mass, elasticity: 0.5 }
+  }
+}
+
+// Marks particles that never move, their cells are treated as world colliders
+#[derive(Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct Static;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Collision {
+  World(WorldCollision),
+  Particle(ParticleCollision),
+}
+
+impl Collision {
+  // The particle that ran into something, and the other particle if it was one
+  pub fn entities(&self) -> (Entity, Option<Entity>) {
+    match self {
+      Self::World(collision) => (collision.entity, None),
+      Self::Particle(collision) => (collision.a, Some(collision.b)),
     }
-    if normal != Vec2::ZERO {
-      Some(normal)
-    } else {
-      None
+  }
+
+  fn with_impulse(self, impulse: f32) -> Self {
+    match self {
+      Self::World(collision) => Self::World(collision.with_impulse(impulse)),
+      Self::Particle(collision) => Self::Particle(collision.with_impulse(impulse)),
     }
   }
+}
 
-  fn min(&self) -> Vec2 {
-    Vec2::new(self.left, self.bottom)
+// A particle hitting a wall, terrain or a static particle. `normal` points away from what it hit.
+#[derive(Clone, Copy, Debug, Reflect)]
+pub struct WorldCollision {
+  pub entity: Entity,
+  pub normal: Vec2,
+  // Momentum it would lose coming to a stop against the world, before elasticity
+  pub impulse: f32,
+}
+
+impl WorldCollision {
+  pub fn new(entity: Entity, normal: Vec2) -> Self {
+    Self { entity, normal, impulse: 0. }
   }
 
-  fn max(&self) -> Vec2 {
-    Vec2::new(self.right, self.top)
+  pub fn with_impulse(mut self, impulse: f32) -> Self {
+    self.impulse = impulse;
+    self
   }
 }
 
-#[derive(Clone)]
-pub struct ParticleLookup {
-  bounds: Rect<f32>,
-  particles: HashMap<IVec2, Entity>,
+impl From<WorldCollision> for Collision {
+  fn from(collision: WorldCollision) -> Self {
+    Self::World(collision)
+  }
 }
 
-impl ParticleLookup {
-  pub fn new(width: i32, height: i32) -> Self {
-    Self {
-      bounds: Rect::<f32> {
-        left: -width as f32 / 2.,
-        right: width as f32 / 2.,
-        top: height as f32 / 2.,
-        bottom: -height as f32 / 2.,
-      },
-      particles: HashMap::new(),
-    }
-  }
+// Particle `a` moving into `b`, which is in `cell`
+#[derive(Clone, Copy, Debug, Reflect)]
+pub struct ParticleCollision {
+  pub a: Entity,
+  pub b: Entity,
+  pub cell: IVec2,
+  // Momentum exchanged if the two stopped moving towards each other, before elasticity
+  pub impulse: f32,
 }
 
-impl Deref for ParticleLookup {
-  type Target = HashMap<IVec2, Entity>;
+impl ParticleCollision {
+  pub fn new(a: Entity, b: Entity, cell: IVec2) -> Self {
+    Self { a, b, cell, impulse: 0. }
+  }
 
-  fn deref(&self) -> &Self::Target {
-    &self.particles
+  pub fn with_impulse(mut self, impulse: f32) -> Self {
+    self.impulse = impulse;
+    self
   }
 }
 
-impl DerefMut for ParticleLookup {
-  fn deref_mut(&mut self) -> &mut Self::Target {
-    &mut self.particles
+impl From<ParticleCollision> for Collision {
+  fn from(collision: ParticleCollision) -> Self {
+    Self::Particle(collision)
   }
 }
 
-#[derive(Component)]
-pub struct Particle {
-  pub position: Vec2,
-  pub velocity: Vec2,
-  pub mass: f32,
-  pub elasticity: f32,
+// The collisions of the latest tick, found during `SimulationStep::Collide` and solved by
+// `SimulationStep::Resolve`. Each pair of particles comes once, sorted by the entity that ran into
+// something. Everything after the solver reads them as they were found, until the next tick starts
+// looking for its own.
+#[derive(Default)]
+pub struct Contacts {
+  collisions: Vec<Collision>,
+  // Unordered pairs already in, both particles of a pair may run into each other
+  pairs: HashSet<(Entity, Entity)>,
+  // Set once every chunk was looked through, and once the solver is done with them
+  found: bool,
+  solved: bool,
 }
 
-impl Particle {
-  const SPRITE_SIZE: f32 = 16.0;
-  const GRAVITY: Vec2 = const_vec2!([0., -1.]);
+impl Contacts {
+  pub fn iter(&self) -> impl Iterator<Item = &Collision> {
+    self.collisions.iter()
+  }
 
-  pub fn new(position: Vec2, mass: f32) -> Self {
-    Self { position, velocity: Vec2::ZERO, mass, elasticity: 0.5 }
+  pub fn len(&self) -> usize {
+    self.collisions.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.collisions.is_empty()
+  }
+
+  // Whether every collision of the tick was found and the solver has yet to get to them
+  pub fn awaits_solver(&self) -> bool {
+    self.found && !self.solved
+  }
+
+  pub fn is_solved(&self) -> bool {
+    self.solved
+  }
+
+  fn clear(&mut self) {
+    self.collisions.clear();
+    self.pairs.clear();
+    self.found = false;
+    self.solved = false;
+  }
+
+  fn pair(a: Entity, b: Entity) -> (Entity, Entity) {
+    if b.to_bits() < a.to_bits() { (b, a) } else { (a, b) }
+  }
+
+  fn contains(&self, collision: &Collision) -> bool {
+    match collision.entities() {
+      (a, Some(b)) => self.pairs.contains(&Self::pair(a, b)),
+      _ => false,
+    }
+  }
+
+  fn push(&mut self, collision: Collision) {
+    if let (a, Some(b)) = collision.entities() {
+      self.pairs.insert(Self::pair(a, b));
+    }
+    self.collisions.push(collision);
+  }
+
+  // Chunk order depends on which chunks were awake, entity order does not
+  fn finish(&mut self) {
+    if self.found { return }
+    self.collisions.sort_by_key(|collision| {
+      let (a, b) = collision.entities();
+      (a.to_bits(), b.map_or(0, |b| b.to_bits() + 1))
+    });
+    self.found = true;
   }
 }
 
-pub enum ParticleCollisionEvent {
-  World(Entity, Vec2),
-  Particle(Entity, Entity),
+// A particle left the world with `Boundary::Despawn` and was removed, with where it was headed.
+// Read by embedders through `ArrakoidsHooks::on_escaped`.
+#[derive(Clone, Copy, Debug)]
+pub struct ParticleEscapedEvent(pub Entity, pub Vec2);
+
+// A particle turned from one material into another in place
+pub struct ReactionEvent {
+  pub entity: Entity,
+  pub cell: IVec2,
+  pub from: MaterialId,
+  pub to: MaterialId,
 }
 
 fn setup(mut commands: Commands) {
-  commands.spawn_bundle(OrthographicCameraBundle::new_2d());
+  commands.spawn_bundle(OrthographicCameraBundle::new_2d()).insert(MainCamera);
+}
+
+fn configure_simulation(config: Option<Res<Config>>, mut settings: ResMut<SimulationSettings>) {
+  if let Some(restitution) = config.as_ref().and_then(|config| config.restitution) {
+    settings.restitution = restitution;
+  }
+}
+
+pub fn spawn_particle(
+  commands: &mut Commands,
+  spatial_index: &mut SpatialIndex,
+  materials: &MaterialRegistry,
+  point: IVec2,
+  material: MaterialId,
+) -> Entity {
+  spawn_particle_with_velocity(commands, spatial_index, materials, point, material, Vec2::ZERO)
+}
 
-  for x in -0..3 {
-    if x == 0 { continue }
-    commands
-      .spawn_bundle(SpriteBundle {
-        transform: Transform::from_xyz(x as f32 * Particle::SPRITE_SIZE, 0.0, 0.0),
+// What a particle is spawned with, everything else comes from its material in `attach_material`
+// after the spawn. Spawning one directly adds it to the lookup as long as its cell is free.
+pub struct ParticleBundle {
+  pub particle: Particle,
+  pub material: MaterialId,
+  pub sprite: SpriteBundle,
+}
+
+impl ParticleBundle {
+  pub fn new(point: IVec2, material: MaterialId) -> Self {
+    Self {
+      // The mass and elasticity are replaced by the material's
+      particle: Particle::new(point.as_vec2(), 1.),
+      material,
+      sprite: SpriteBundle {
+        transform: Transform::from_translation(point.as_vec2().extend(0.) * Particle::SPRITE_SIZE),
         sprite: Sprite {
-          color: if x < 0 { Color::WHITE } else if x == 1 { Color::BLUE } else { Color::RED },
           custom_size: Some(Vec2::splat(Particle::SPRITE_SIZE)),
           ..Default::default()
         },
         ..Default::default()
-      })
-      .insert(Particle {
-        position: Vec2::new(x as f32, 0.),
-        velocity: Vec2::new((x as f32).signum(), 0.),
-        mass: 1.,
-        elasticity: 0.4,
-      });
+      },
+    }
+  }
+
+  pub fn with_velocity(mut self, velocity: Vec2) -> Self {
+    self.particle.velocity = velocity;
+    self
+  }
+}
+
+pub fn spawn_particle_with_velocity(
+  commands: &mut Commands,
+  spatial_index: &mut SpatialIndex,
+  materials: &MaterialRegistry,
+  point: IVec2,
+  material: MaterialId,
+  velocity: Vec2,
+) -> Entity {
+  let fixed = materials.get(material).fixed;
+  let velocity = if fixed { Vec2::ZERO } else { velocity };
+  let bundle = ParticleBundle::new(point, material).with_velocity(velocity);
+  let entity = match spatial_index.pool.take() {
+    Some(entity) => commands.entity(entity).insert_bundle((bundle.particle, bundle.material, bundle.sprite.transform)).id(),
+    None => commands.spawn_bundle(bundle.sprite).insert_bundle((bundle.particle, bundle.material)).id(),
+  };
+  // Taken right away rather than once the material is attached, so nothing else spawns on top
+  if fixed {
+    spatial_index.insert_static(point, entity);
+  } else {
+    spatial_index.insert(point, entity);
+  }
+  entity
+}
+
+// Gives newly spawned particles their material's look and behavior
+fn attach_material(
+  mut commands: Commands,
+  materials: Res<MaterialRegistry>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut particles: Query<(Entity, &mut Particle, &MaterialId, &mut Sprite), Added<Particle>>,
+) {
+  for (entity, mut particle, material, mut sprite) in particles.iter_mut() {
+    let def = materials.get(*material);
+    particle.mass = def.mass;
+    particle.elasticity = def.elasticity;
+    sprite.color = def.color;
+
+    let mut commands = commands.entity(entity);
+    commands.insert(def.layers);
+    if let Some(lifetime) = def.lifetime {
+      commands.insert(lifetime).insert(Age::default());
+    }
+    match def.boid {
+      Some(BoidRole::Predator) => { commands.insert(Boid::default()).insert(Predator); },
+      Some(BoidRole::Prey) => { commands.insert(Boid::default()).insert(Prey); },
+      None => {},
+    }
+    if def.fixed {
+      particle.velocity = Vec2::ZERO;
+      commands.insert(Static);
+    }
+
+    // Spawned as a bare bundle rather than through `spawn_particle`
+    let point = particle.position.floor().as_ivec2();
+    if spatial_index.get(&point) == Some(&entity) { continue }
+    if !spatial_index.is_free(point) {
+      warn!("Despawned {:?}, it was spawned in the taken cell {}", entity, point);
+      commands.despawn();
+    } else if def.fixed {
+      spatial_index.insert_static(point, entity);
+    } else {
+      spatial_index.insert(point, entity);
+    }
+  }
+}
+
+pub fn despawn_particle(
+  commands: &mut Commands,
+  spatial_index: &mut SpatialIndex,
+  entity: Entity,
+  particle: &Particle,
+) {
+  spatial_index.remove_entity(particle.position.floor().as_ivec2(), entity);
+  commands.add(RecycleParticle(entity));
+}
+
+// Despawns everything and starts over with an empty world of the given size, an infinite world
+// stays infinite
+pub fn clear_world(commands: &mut Commands, spatial_index: &mut SpatialIndex, width: i32, height: i32) {
+  for entity in spatial_index.values() {
+    commands.add(RecycleParticle(*entity));
   }
+  let pool = std::mem::take(&mut spatial_index.pool);
+  *spatial_index = SpatialIndex::new(width, height).with_infinite(spatial_index.infinite);
+  spatial_index.pool = pool;
 }
 
+#[allow(clippy::too_many_arguments)]
 fn discover_collisions(
-  particle_lookup: ResMut<ParticleLookup>,
-  mut query: Query<(Entity, &mut Particle)>,
-  mut collision_events: EventWriter<ParticleCollisionEvent>,
-  time: Res<Time>,
+  spatial_index: ResMut<SpatialIndex>,
+  mut progress: ResMut<TickProgress>,
+  mut query: Query<(&mut Particle, Option<&Boid>, &MaterialId), Without<Static>>,
+  layers: Query<&CollisionLayers>,
+  mut contacts: ResMut<Contacts>,
+  mut resting: ResMut<Resting>,
+  settings: Res<SimulationSettings>,
+  clock: Res<SimulationClock>,
+  materials: Res<MaterialRegistry>,
 ) {
-  let mut handled = StableHashSet::<u64>::default();
-  for (entity, mut particle) in query.iter_mut() {
-    particle.velocity += Particle::GRAVITY * time.delta_seconds();
-
-    if particle.velocity != Vec2::ZERO {
-      let current_point = particle.position.floor().as_ivec2();
-      let potential_position = particle.position + particle.velocity;
-      let potential_point = potential_position.floor().as_ivec2();
-
-      if potential_point != current_point {
-        if let Some(collision) = check_for_collision(entity, potential_position, &particle_lookup) {
-          if let ParticleCollisionEvent::Particle(a, b) = collision {
-            let mut hasher = handled.hasher().build_hasher();
-            a.hash(&mut hasher);
-            b.hash(&mut hasher);
-            let hash = hasher.finish();
-            if handled.contains(&hash) { continue; }
-
-            let mut hasher = handled.hasher().build_hasher();
-            b.hash(&mut hasher);
-            a.hash(&mut hasher);
-            let alternate = hasher.finish();
-            if handled.contains(&alternate) { continue; }
-
-            handled.insert(hash);
-          }
-
-          collision_events.send(collision);
+  let _span = info_span!("discover_collisions").entered();
+  if clock.ticked {
+    contacts.clear();
+    resting.0.retain(|entity| query.get(*entity).is_ok());
+  }
+  while let Some(entity) = progress.next_entity(TickPhase::Discover, &spatial_index) {
+    let (mut particle, boid, material) = match query.get_mut(entity) {
+      Ok(particle) => particle,
+      Err(_) => continue,
+    };
+    let interval = match update_interval(&progress, materials.get(*material), entity, particle.position, clock.tick) {
+      Some(interval) => interval,
+      None => continue,
+    };
+    let down = settings.gravity_at(particle.position).normalize_or_zero();
+    // Chunks go bottom up and so do their cells, whatever holds a particle up has been seen already
+    let cell = particle.position.floor().as_ivec2();
+    if boid.is_none() && down != Vec2::ZERO && settles(&particle, down, &settings)
+      && is_supported(entity, cell, down.round().as_ivec2(), &spatial_index, &resting, &layers, settings.boundary)
+    {
+      particle.velocity = Vec2::ZERO;
+      resting.0.insert(entity);
+      continue;
+    }
+    resting.0.remove(&entity);
+    // Boids fly and steer themselves
+    let gravity = if boid.is_some() { Vec2::ZERO } else { settings.gravity_at(particle.position) };
+    // Gravity is integrated and the path checked in `substeps` increments, so a fast particle cannot
+    // skip over the cell it collides with. Particles that skipped ticks take that many times more.
+    let substeps = settings.substeps.max(1) * interval;
+    let delta = settings.tick_delta() / settings.substeps.max(1) as f32;
+    let mut position = particle.position;
+    let mut found = None;
+    for step in 0..substeps {
+      particle.velocity += gravity * delta;
+      let next = position + settings.integrator.displacement(particle.velocity, gravity * delta) * delta;
+      if next.floor() != position.floor() {
+        found = check_for_collision(entity, position, next, &spatial_index, &layers, settings.boundary);
+        if found.is_some() {
+          // The rest of the tick's gravity still applies
+          particle.velocity += gravity * delta * (substeps - step - 1) as f32;
+          break;
         }
       }
+      position = next;
+    }
+
+    if let Some(collision) = found {
+      if settings.debug_log {
+        debug!("{:?} at {:?} found {:?}", entity, particle.position, collision);
+      }
+      if contacts.contains(&collision) { continue }
+      let impulse = collision_impulse(Body::from(&*particle), &collision, &query);
+      contacts.push(collision.with_impulse(impulse));
     }
   }
+  if !progress.is_discovering() {
+    contacts.finish();
+  }
+}
+
+// Particles standing still on something that holds them up: the floor, a collider, or another
+// resting particle right below. Gravity is left out for them, so a pile stands still instead of
+// falling into itself and being pushed back out every tick. Anything that gives a resting particle a
+// velocity, or takes away what is below it, has it fall again.
+#[derive(Default)]
+pub struct Resting(HashSet<Entity>);
+
+impl Resting {
+  pub fn contains(&self, entity: Entity) -> bool {
+    self.0.contains(&entity)
+  }
+}
+
+// Whether the particle is only pressing down on whatever is below it, too slow to bounce off it by
+// as much as a cell. Gravity would only bring it straight back.
+fn settles(particle: &Particle, down: Vec2, settings: &SimulationSettings) -> bool {
+  let along = particle.velocity.dot(down);
+  let across = (particle.velocity - along * down).length();
+  along * settings.tick_delta() > -settings.sleep_speed
+    && across * settings.tick_delta() < settings.sleep_speed
+    && particle.elasticity * along < (2. * settings.gravity.length()).sqrt()
+}
+
+// Whether something below the particle in `cell` holds it up, `down` being the cell direction of
+// gravity
+fn is_supported(
+  entity: Entity,
+  cell: IVec2,
+  down: IVec2,
+  spatial_index: &SpatialIndex,
+  resting: &Resting,
+  layers: &Query<&CollisionLayers>,
+  boundary: Boundary,
+) -> bool {
+  let below = cell + down;
+  if spatial_index.is_collider(below) { return true }
+  if spatial_index.outside(below.as_vec2() + Vec2::splat(0.5)).is_some() { return boundary == Boundary::Bounce }
+  spatial_index.get(&below).is_some_and(|other| resting.contains(*other) && layers::collides(entity, *other, layers))
+}
+
+// Momentum along the direction of a collision that it takes to stop the two bodies closing in on
+// each other. Static particles and the world count as immovable.
+fn collision_impulse(
+  particle: Body,
+  collision: &Collision,
+  query: &Query<(&mut Particle, Option<&Boid>, &MaterialId), Without<Static>>,
+) -> f32 {
+  match collision {
+    Collision::World(collision) => (-particle.velocity.dot(collision.normal)).max(0.) * particle.mass,
+    Collision::Particle(collision) => {
+      let direction = (collision.cell.as_vec2() + Vec2::splat(0.5) - particle.position).normalize_or_zero();
+      match query.get(collision.b) {
+        Ok((other, _, _)) => {
+          let closing = (particle.velocity - other.velocity).dot(direction).max(0.);
+          closing * particle.mass * other.mass / (particle.mass + other.mass)
+        },
+        Err(_) => particle.velocity.dot(direction).max(0.) * particle.mass,
+      }
+    },
+  }
 }
 
-fn calculate_collision(current: &Particle, other: &Particle) -> Vec2 {
-  (current.elasticity * other.mass * (other.velocity - current.velocity) + current.mass * current.velocity + other.mass * other.velocity) / (current.mass + other.mass)
+// Velocity of `current` after a collision with `other`, from fully inelastic at a restitution of 0 to
+// elastic at 1
+fn calculate_collision(current: &Body, other: &Body, restitution: f32) -> Vec2 {
+  (restitution * other.mass * (other.velocity - current.velocity) + current.mass * current.velocity + other.mass * other.velocity) / (current.mass + other.mass)
 }
 
 fn check_for_collision(
   entity: Entity,
+  position: Vec2,
   potential_position: Vec2,
-  particle_lookup: &ParticleLookup
-) -> Option<ParticleCollisionEvent> {
+  spatial_index: &SpatialIndex,
+  layers: &Query<&CollisionLayers>,
+  boundary: Boundary,
+) -> Option<Collision> {
+  // Normals come from the direction of travel, before any wrapping
+  let normal = (position.floor().as_ivec2() - potential_position.floor().as_ivec2()).signum().as_vec2();
+  let potential_position = match (boundary, spatial_index.outside(potential_position)) {
+    (_, None) => potential_position,
+    (Boundary::Bounce, Some(wall_normal)) => return Some(WorldCollision::new(entity, wall_normal).into()),
+    (Boundary::Wrap, Some(_)) => spatial_index.wrap(potential_position),
+    // Nothing to collide with out there, `handle_movement` removes the particle
+    (Boundary::Despawn, Some(_)) => return None,
+  };
   let potential_point = potential_position.floor().as_ivec2();
-  // println!("Looking at point {:?}", potential_point);
-  if let Some(wall_normal) = particle_lookup.bounds.outside(potential_position) {
-    Some(ParticleCollisionEvent::World(entity, wall_normal))
-  } else if let Some(colliding_entity) = particle_lookup.get(&potential_point) {
-    if *colliding_entity != entity {
-      Some(ParticleCollisionEvent::Particle(entity, *colliding_entity))
-    } else {
-      None
-    }
+  let colliding_entity = spatial_index.get(&potential_point).copied();
+  if colliding_entity == Some(entity) { return None }
+  // Particles on layers the other does not collide with pass through each other
+  if colliding_entity.is_some_and(|other| !layers::collides(entity, other, layers)) { return None }
+
+  // Terrain colliders have no particle in them
+  if spatial_index.is_collider(potential_point) {
+    Some(WorldCollision::new(entity, normal).into())
   } else {
-    None
+    colliding_entity.map(|other| ParticleCollision::new(entity, other, potential_point).into())
   }
 }
 
-fn resolve_particle(
-  entity: Entity,
-  mut particles: &mut Query<&mut Particle>,
-  particle_lookup: &ParticleLookup,
-) {
-  if let Some(particle) = particles.get(entity).ok() {
-    if particle.velocity != Vec2::ZERO {
-      let current_point = particle.position.floor().as_ivec2();
-      let potential_position = particle.position + particle.velocity;
-      let potential_point = potential_position.floor().as_ivec2();
-
-      // println!("Testing recursive collision: {:?} @ {:?} going to {:?}", entity, particle.position, potential_position);
-      if potential_point != current_point {
-        if let Some(collision) = check_for_collision(entity, potential_position, &particle_lookup) {
-          println!("Recursive collision occured: {:?} {:?}", entity, particle.velocity);
-          handle_collision(&collision, &mut particles, particle_lookup);
-        }
-      }
-    }
+// The parts of a particle the solver works on, velocities are only written back once it is done
+#[derive(Clone, Copy)]
+struct Body {
+  position: Vec2,
+  velocity: Vec2,
+  mass: f32,
+  elasticity: f32,
+}
+
+impl From<&Particle> for Body {
+  fn from(particle: &Particle) -> Self {
+    Self { position: particle.position, velocity: particle.velocity, mass: particle.mass, elasticity: particle.elasticity }
   }
 }
 
-fn handle_collision(
-  collision: &ParticleCollisionEvent,
-  mut particles: & mut Query<&mut Particle>,
-  particle_lookup: &ParticleLookup,
-) {
-  match collision {
-    ParticleCollisionEvent::Particle(entity_a, entity_b) => {
-      // TODO: If other entity is asleep awaken after after collision
-      if let Ok([mut particle_a, mut particle_b]) = particles.get_many_mut([*entity_a, *entity_b]) {
-        let new_a_velocity = calculate_collision(&particle_a, &particle_b);
-        let new_b_velocity = calculate_collision(&particle_b, &particle_a);
-        particle_a.velocity = (new_a_velocity * 100.).round() / 100.;
-        particle_b.velocity = (new_b_velocity * 100.).round() / 100.;
+// A particle touching another or the world. `normal` points away from the world for world contacts.
+#[derive(Clone, Copy)]
+struct Contact {
+  a: Entity,
+  b: Option<Entity>,
+  normal: Vec2,
+}
+
+// Resolves all of a tick's contacts together. Every iteration works out what each contact would
+// change its bodies' velocities by from the velocities the iteration started with, and moves every
+// body by the average of its contacts' changes. A particle against a wall and another particle at
+// once gets both, rather than whichever contact came last, and the order contacts were found in
+// does not matter. Contacts caused by the new velocities are appended for the following iterations.
+#[derive(Default)]
+struct ContactSolver {
+  contacts: Vec<Contact>,
+  known: HashSet<(Entity, Option<Entity>)>,
+  bodies: HashMap<Entity, Body>,
+  // Entities in the order they were first seen, so the results are written back in a stable order
+  order: Vec<Entity>,
+  restitution: CombineRule,
+}
 
-        // println!("Particle collision occured: {:?} {:?} | {:?} {:?}", entity_a, particle_a.velocity, entity_b, particle_b.velocity);
+impl ContactSolver {
+  fn add(&mut self, collision: &Collision, particles: &Query<&mut Particle>) {
+    let contact = match *collision {
+      Collision::World(collision) => Contact { a: collision.entity, b: None, normal: collision.normal },
+      Collision::Particle(collision) => Contact { a: collision.a, b: Some(collision.b), normal: Vec2::ZERO },
+    };
+    // Both orders of a pair describe the same contact
+    let key = match contact.b {
+      Some(b) if b.to_bits() < contact.a.to_bits() => (b, Some(contact.a)),
+      _ => (contact.a, contact.b),
+    };
+    if self.known.contains(&key) { return }
+    for entity in [Some(contact.a), contact.b].into_iter().flatten() {
+      if self.bodies.contains_key(&entity) { continue }
+      match particles.get(entity) {
+        Ok(particle) => {
+          self.bodies.insert(entity, Body::from(particle));
+          self.order.push(entity);
+        },
+        Err(_) => return,
+      }
+    }
+    self.known.insert(key);
+    self.contacts.push(contact);
+  }
 
-        // We now need to check if applied velocity on b causes another collision
-        resolve_particle(*entity_b, &mut particles, particle_lookup);
+  // How much the contact would change the velocities of its bodies, from their current ones
+  fn response(&self, contact: Contact) -> [Option<(Entity, Vec2)>; 2] {
+    let a = self.bodies[&contact.a];
+    match contact.b {
+      Some(b_entity) => {
+        let b = self.bodies[&b_entity];
+        // Only bodies moving towards each other collide, otherwise later iterations would undo earlier ones
+        let mut normal = (b.position.floor() - a.position.floor()).signum();
+        if normal == Vec2::ZERO {
+          normal = (a.velocity - b.velocity).signum();
+        }
+        if (a.velocity - b.velocity).dot(normal) <= 0. { return [None, None] }
 
-        // Now we need to check the new velocity to see if it will overlap on the
+        let restitution = self.restitution.combine(a.elasticity, b.elasticity);
+        [
+          Some((contact.a, calculate_collision(&a, &b, restitution) - a.velocity)),
+          Some((b_entity, calculate_collision(&b, &a, restitution) - b.velocity)),
+        ]
+      },
+      None => {
+        if a.velocity.dot(contact.normal) >= 0. { return [None, None] }
+        let change = -(1. + a.elasticity) * (a.velocity * contact.normal) * contact.normal.normalize();
+        [Some((contact.a, change)), None]
+      },
+    }
+  }
+
+  fn solve(
+    &mut self,
+    iterations: u32,
+    particles: &Query<&mut Particle>,
+    spatial_index: &SpatialIndex,
+    layers: &Query<&CollisionLayers>,
+    boundary: Boundary,
+    // Seconds the new velocities move the bodies for
+    delta: f32,
+  ) {
+    for _ in 0..iterations {
+      // Summed up along with how many contacts make up each sum
+      let mut changes = HashMap::<Entity, (Vec2, u32)>::default();
+      for contact in self.contacts.iter() {
+        for (entity, change) in self.response(*contact).into_iter().flatten() {
+          let (sum, count) = changes.entry(entity).or_default();
+          *sum += change;
+          *count += 1;
+        }
       }
-      if let Ok([mut particle_a, particle_b]) = particles.get_many_mut([*entity_a, *entity_b]) {
-        let new_a_velocity = calculate_collision(&particle_a, &particle_b);
-        particle_a.velocity = (new_a_velocity * 100.).round() / 100.;
+      if changes.is_empty() { break }
 
-        // Not sure if we need to check this side recursively.
+      let mut changed = Vec::new();
+      for entity in self.order.iter() {
+        if let Some((sum, count)) = changes.get(entity) {
+          self.bodies.get_mut(entity).unwrap().velocity += *sum / *count as f32;
+          changed.push(*entity);
+        }
       }
-    },
-    ParticleCollisionEvent::World(entity, normal) => {
-      if let Some(mut particle) = particles.get_mut(*entity).ok() {
-        particle.velocity = particle.velocity - (1. + particle.elasticity) * (particle.velocity * (*normal)) * (*normal).normalize();
-        particle.velocity = (particle.velocity * 100.).round() / 100.;
 
-        // println!("Wall collision on {:?} {:?}", entity, particle.velocity);
-        resolve_particle(*entity, particles, particle_lookup);
+      // New velocities can lead straight into another particle or the world
+      for entity in changed {
+        let body = self.bodies[&entity];
+        let current_point = body.position.floor().as_ivec2();
+        let potential_position = body.position + body.velocity * delta;
+        if potential_position.floor().as_ivec2() == current_point { continue }
+        if let Some(collision) = check_for_collision(entity, body.position, potential_position, spatial_index, layers, boundary) {
+          self.add(&collision, particles);
+        }
       }
     }
   }
 }
 
+// Solves every collision of a tick at once, right before the tick moves anything. Collisions found
+// over several frames of a budgeted tick are solved once the last of them is in.
 fn handle_collisions(
-  mut collision_events: EventReader<ParticleCollisionEvent>,
+  mut contacts: ResMut<Contacts>,
   mut particles: Query<&mut Particle>,
-  particle_lookup: Res<ParticleLookup>,
+  layers: Query<&CollisionLayers>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  settings: Res<SimulationSettings>,
 ) {
-  for collision in collision_events.iter() {
-    handle_collision(collision, &mut particles, &particle_lookup);
+  let _span = info_span!("handle_collisions").entered();
+  if !contacts.found || contacts.solved { return }
+  contacts.solved = true;
+
+  let mut solver = ContactSolver { restitution: settings.restitution, ..Default::default() };
+  for collision in contacts.iter() {
+    solver.add(collision, &particles);
+  }
+  if solver.contacts.is_empty() { return }
+  solver.solve(settings.solver_iterations, &particles, &spatial_index, &layers, settings.boundary, settings.tick_delta());
+
+  for entity in solver.order {
+    let mut particle = match particles.get_mut(entity) {
+      Ok(particle) => particle,
+      Err(_) => continue,
+    };
+    let velocity = settings.settle_velocity(solver.bodies[&entity].velocity);
+    if settings.debug_log {
+      debug!("{:?} at {:?} resolved from {:?} to {:?}", entity, particle.position, particle.velocity, velocity);
+    }
+    particle.velocity = velocity;
+    // Particles in sleeping chunks need their chunk awake to act on the new velocity
+    spatial_index.wake(particle.position.floor().as_ivec2());
+  }
+}
+
+fn age_chunks(progress: Res<TickProgress>, mut spatial_index: ResMut<SpatialIndex>) {
+  if progress.is_complete() {
+    spatial_index.age_active_chunks(|chunk| progress.simulates(chunk));
+  }
+}
+
+// Stacked and recursive collisions can blow velocities up or turn them into NaN, which would then
+// poison positions and the lookup
+fn sanitize_velocities(
+  mut particles: Query<(Entity, &mut Particle), Without<Static>>,
+  settings: Res<SimulationSettings>,
+  mut diagnostics: ResMut<SimulationDiagnostics>,
+) {
+  for (entity, mut particle) in particles.iter_mut() {
+    let velocity = particle.velocity;
+    let max_velocity = settings.max_velocity();
+    if velocity.is_finite() && velocity.length_squared() <= max_velocity * max_velocity { continue }
+
+    particle.velocity = if velocity.is_finite() { velocity.clamp_length_max(max_velocity) } else { Vec2::ZERO };
+    diagnostics.sanitized_velocities += 1;
+    warn!("Sanitized velocity of {:?} at {:?}: {:?} -> {:?}", entity, particle.position, velocity, particle.velocity);
   }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn handle_movement(
-  mut query: Query<(Entity, &mut Particle, &mut Transform)>,
-  mut particle_lookup: ResMut<ParticleLookup>,
+  mut commands: Commands,
+  mut query: Query<(&mut Particle, &mut Transform, Option<&Boid>, &MaterialId), Without<Static>>,
+  layers: Query<&CollisionLayers>,
+  materials: Res<MaterialRegistry>,
+  (sensors, portals): (Query<(Entity, &Sensor)>, Query<&Portal>),
+  (mut sensor_events, mut escaped_events): (EventWriter<SensorEvent>, EventWriter<ParticleEscapedEvent>),
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut progress: ResMut<TickProgress>,
+  mut diagnostics: ResMut<SimulationDiagnostics>,
+  resting: Res<Resting>,
+  settings: Res<SimulationSettings>,
+  clock: Res<SimulationClock>,
+  task_pool: Res<ComputeTaskPool>,
+  mut pending: Local<PendingMoves>,
 ) {
-  for (entity, mut particle, mut transform) in query.iter_mut() {
-    let current_point = particle.position.floor().as_ivec2();
-    let new_position = particle.position + particle.velocity;
-    let new_point = new_position.floor().as_ivec2();
-
-    // println!("{:?} @ {:?} ({:?}) with {:?} going to {:?} ({:?})", entity, particle.position, current_point, particle.velocity, new_position, new_point);
-    if current_point != new_point {
-      if particle_lookup.get(&current_point) == Some(&entity) {
-        particle_lookup.remove(&current_point);
+  let _span = info_span!("handle_movement").entered();
+  let buffered = settings.update_order == UpdateOrder::DoubleBuffered;
+  let checkerboard = settings.update_order == UpdateOrder::Checkerboard;
+  // A particle wrapping around could come out next to a chunk moving alongside its own
+  let parallel = checkerboard && settings.boundary != Boundary::Wrap;
+  let mut planned = Vec::new();
+  loop {
+    if parallel {
+      let pass = match progress.next_pass(&spatial_index) {
+        Some(pass) => pass,
+        None => break,
+      };
+      let mut chunks = Vec::with_capacity(pass.len());
+      for (chunk, entities) in pass {
+        let movers: Vec<Mover> = entities
+          .into_iter()
+          .filter_map(|entity| {
+            let (particle, _, boid, material) = query.get(entity).ok()?;
+            let mover = Mover::new(entity, particle, boid.is_some(), materials.get(*material), &progress, &resting, &settings, clock.tick);
+            if mover.is_none() && particle.velocity != Vec2::ZERO {
+              spatial_index.wake(particle.position.floor().as_ivec2());
+            }
+            mover
+          })
+          .collect();
+        chunks.push((chunk, movers));
       }
-      particle_lookup.insert(new_point, entity);
+      // Chunks of a pass never reach the same cell, so each can be moved against the lookup as the
+      // pass started along with its own moves
+      let (spatial_index_ref, layers, settings) = (&*spatial_index, &layers, &*settings);
+      let moved = task_pool.scope(|scope| {
+        for (chunk, movers) in chunks.iter() {
+          scope.spawn(async move {
+            let started = Instant::now();
+            let mut cells = Cells::new(spatial_index_ref, true);
+            let planned: Vec<Planned> = movers.iter().map(|mover| mover.plan(&mut cells, layers, settings)).collect();
+            (*chunk, planned, started.elapsed())
+          });
+        }
+      });
+      for (chunk, moves, cost) in moved {
+        progress.add_cost(chunk, cost);
+        planned.extend(moves);
+      }
+    } else {
+      let entity = match progress.next_entity(TickPhase::Movement, &spatial_index) {
+        Some(entity) => entity,
+        None => break,
+      };
+      let (particle, _, boid, material) = match query.get(entity) {
+        Ok(particle) => particle,
+        Err(_) => continue,
+      };
+      match Mover::new(entity, particle, boid.is_some(), materials.get(*material), &progress, &resting, &settings, clock.tick) {
+        Some(mover) => planned.push(mover.plan(&mut Cells::new(&spatial_index, checkerboard), &layers, &settings)),
+        // Its chunk must not fall asleep before it gets to move again
+        None if particle.velocity != Vec2::ZERO => spatial_index.wake(particle.position.floor().as_ivec2()),
+        None => {},
+      }
+    }
+
+    for Planned { entity, from: current_point, position, escaped, step } in planned.drain(..) {
+      let (mut particle, mut transform, boid, _) = match query.get_mut(entity) {
+        Ok(particle) => particle,
+        Err(_) => continue,
+      };
+      let mut new_position = position;
+      if let Some(position) = escaped {
+        if buffered {
+          pending.moves.push(BufferedMove { entity, from: current_point, to: Destination::Escaped(position), previous: particle.position });
+          continue;
+        }
+        despawn_particle(&mut commands, &mut spatial_index, entity, &particle);
+        escaped_events.send(ParticleEscapedEvent(entity, position));
+        diagnostics.escaped_particles += 1;
+        continue;
+      }
+      if let Some((exit_position, rotation)) = portals::destination(&portals, &spatial_index, current_point, new_position) {
+        new_position = exit_position;
+        particle.velocity = rotation * particle.velocity;
+      }
+      let mut new_point = new_position.floor().as_ivec2();
+      // A particle let out of a portal earlier in the pass may have taken the cell it was headed for
+      if parallel && new_point != current_point && !spatial_index.is_free(new_point) {
+        new_position = particle.position;
+        new_point = current_point;
+      }
+      // Resting on or held up by whatever is below, it may be able to sink through it
+      let down = settings.gravity_at(particle.position).normalize_or_zero();
+      let below = (current_point.as_vec2() + down).round().as_ivec2();
+      if new_point == current_point && boid.is_none() && down != Vec2::ZERO && step.dot(down) >= 0. {
+        if let Some(other) = spatial_index.get(&below) {
+          pending.sinking.push((entity, *other, current_point, below));
+        }
+      }
+
+      if settings.debug_log {
+        debug!("{:?} at {:?} with {:?} moving to {:?}", entity, particle.position, particle.velocity, new_position);
+      }
+      if current_point != new_point && buffered {
+        pending.moves.push(BufferedMove { entity, from: current_point, to: Destination::Cell(new_point), previous: particle.position });
+      } else if current_point != new_point {
+        if spatial_index.get(&current_point) == Some(&entity) {
+          spatial_index.remove(&current_point);
+        }
+        spatial_index.insert(new_point, entity);
+        sensors::detect_crossings(&sensors, &mut sensor_events, entity, current_point, new_point);
+      } else if particle.velocity != Vec2::ZERO {
+        // Still moving within its cell, so the chunk has to stay awake
+        spatial_index.wake(new_point);
+      }
+      particle.position = new_position;
+      transform.translation = new_point.as_vec2().extend(0.) * Particle::SPRITE_SIZE;
+    }
+  }
+
+  if buffered {
+    // Moves wait for the rest of the tick's particles, which can take several frames under a budget
+    if !progress.is_complete() { return }
+    let mut moves = std::mem::take(&mut pending.moves);
+    let winners = resolve_buffered_moves(&mut moves);
+    // Every mover leaves its cell before any arrives, a cell is only ever taken by one of them
+    for (index, mv) in moves.iter().enumerate() {
+      if winners[index] {
+        spatial_index.remove_entity(mv.from, mv.entity);
+      }
+    }
+    for (mv, won) in moves.into_iter().zip(winners) {
+      let (mut particle, mut transform, _, _) = match query.get_mut(mv.entity) {
+        Ok(particle) => particle,
+        Err(_) => continue,
+      };
+      match mv.to {
+        Destination::Escaped(position) if won => {
+          despawn_particle(&mut commands, &mut spatial_index, mv.entity, &particle);
+          escaped_events.send(ParticleEscapedEvent(mv.entity, position));
+          diagnostics.escaped_particles += 1;
+        },
+        Destination::Cell(cell) if won => {
+          spatial_index.insert(cell, mv.entity);
+          sensors::detect_crossings(&sensors, &mut sensor_events, mv.entity, mv.from, cell);
+        },
+        // Beaten to its cell, the particle stays where it was and collides with the winner next tick
+        _ => {
+          particle.position = mv.previous;
+          transform.translation = mv.from.as_vec2().extend(0.) * Particle::SPRITE_SIZE;
+          spatial_index.wake(mv.from);
+        },
+      }
+    }
+  }
+
+  // A denser particle swaps places with a lighter one below it when either is a liquid, so stone
+  // sinks, oil floats on water and wood comes up through it. Mass is per cell, so it doubles as
+  // the density.
+  for (entity, other, cell, below) in std::mem::take(&mut pending.sinking) {
+    if spatial_index.get(&cell) != Some(&entity) || spatial_index.get(&below) != Some(&other) { continue }
+    let (position, mass, liquid) = match query.get(entity) {
+      Ok((particle, _, _, material)) => (particle.position, particle.mass, materials.get(*material).liquid),
+      Err(_) => continue,
+    };
+    let (other_position, other_mass, other_liquid) = match query.get(other) {
+      Ok((particle, _, None, material)) => (particle.position, particle.mass, materials.get(*material).liquid),
+      _ => continue,
+    };
+    if mass <= other_mass || !(liquid || other_liquid) { continue }
+
+    for (entity, from, to, position) in [(entity, cell, below, other_position), (other, below, cell, position)] {
+      spatial_index.insert(to, entity);
+      if let Ok((mut particle, mut transform, _, _)) = query.get_mut(entity) {
+        particle.position = position;
+        transform.translation = to.as_vec2().extend(0.) * Particle::SPRITE_SIZE;
+      }
+      sensors::detect_crossings(&sensors, &mut sensor_events, entity, from, to);
+    }
+  }
+}
+
+// Cells out from its own a particle pushed out of another's cell can end up
+const SEPARATION_RADIUS: i32 = 2;
+// How much closer a cell against gravity counts as when pushing a particle out, in cells per cell
+const SEPARATION_LIFT: f32 = 0.25;
+
+// Movement only ever takes a particle into a free cell, but spawning, swapping and teleporting can
+// still leave one inside a cell the lookup gives to another, where collisions do not see it. The
+// lighter of the two is pushed out into the nearest free cell, so the lookup holds every particle
+// again. Runs once the frame's commands were applied, so particles despawned during it are gone.
+fn separate_particles(
+  progress: Res<TickProgress>,
+  settings: Res<SimulationSettings>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut diagnostics: ResMut<SimulationDiagnostics>,
+  mut particles: Query<(Entity, &mut Particle, &mut Transform, Option<&Static>)>,
+) {
+  if !progress.is_complete() || !spatial_index.is_changed() { return }
+  let mut overlapping: Vec<(IVec2, Entity)> = particles
+    .iter()
+    .filter(|(_, _, _, fixed)| fixed.is_none())
+    .map(|(entity, particle, _, _)| (particle.position.floor().as_ivec2(), entity))
+    .filter(|(cell, entity)| spatial_index.get(cell) != Some(entity))
+    .collect();
+  if overlapping.is_empty() { return }
+  overlapping.sort_unstable_by_key(|(cell, entity)| (cell.y, cell.x, entity.to_bits()));
+
+  let up = -settings.gravity.normalize_or_zero();
+  for (cell, entity) in overlapping {
+    let (mass, drawn) = match particles.get(entity) {
+      Ok((_, particle, transform, _)) => (particle.mass, (transform.translation.truncate() / Particle::SPRITE_SIZE).round().as_ivec2()),
+      Err(_) => continue,
+    };
+    // A particle the lookup lost track of is still in the cell it was drawn in
+    spatial_index.remove_entity(drawn, entity);
+    if spatial_index.is_free(cell) {
+      spatial_index.insert(cell, entity);
+      continue;
+    }
+
+    // Static cells, terrain and the edges of the world never give way
+    let occupant = spatial_index.get(&cell).copied().filter(|other| {
+      matches!(particles.get(*other), Ok((_, particle, _, None)) if particle.mass < mass)
+    });
+    let pushed = occupant.unwrap_or(entity);
+    let position = match particles.get(pushed) {
+      Ok((_, particle, _, _)) => particle.position,
+      Err(_) => continue,
+    };
+    let free = match nearest_free_cell(&spatial_index, position, up) {
+      Some(free) => free,
+      // Boxed in, it gets another try once something around it moves
+      None => continue,
+    };
+    if pushed != entity {
+      spatial_index.remove(&cell);
+      spatial_index.insert(cell, entity);
+    }
+    spatial_index.insert(free, pushed);
+    if let Ok((_, mut particle, mut transform, _)) = particles.get_mut(pushed) {
+      particle.position += (free - cell).as_vec2();
+      transform.translation = free.as_vec2().extend(0.) * Particle::SPRITE_SIZE;
+    }
+    diagnostics.separated_particles += 1;
+    if settings.debug_log {
+      debug!("{:?} pushed out of {:?} into {:?}", pushed, cell, free);
     }
-    particle.position = new_position;
-    transform.translation = new_point.as_vec2().extend(0.) * Particle::SPRITE_SIZE;
   }
-  // println!("----");
+}
+
+// Nearest free cell within `SEPARATION_RADIUS` of the one `position` is in. Cells against gravity
+// are favoured, so a particle pushed out of a stack ends up on top of it rather than under it.
+fn nearest_free_cell(spatial_index: &SpatialIndex, position: Vec2, up: Vec2) -> Option<IVec2> {
+  let cell = position.floor().as_ivec2();
+  let cost = |candidate: IVec2| {
+    let offset = candidate.as_vec2() + Vec2::splat(0.5) - position;
+    offset.length() - offset.dot(up) * SEPARATION_LIFT
+  };
+  (-SEPARATION_RADIUS..=SEPARATION_RADIUS)
+    .flat_map(|y| (-SEPARATION_RADIUS..=SEPARATION_RADIUS).map(move |x| cell + IVec2::new(x, y)))
+    .filter(|candidate| *candidate != cell && spatial_index.is_free(*candidate))
+    .min_by(|a, b| cost(*a).total_cmp(&cost(*b)))
+}
+
+// Ticks' worth of movement a particle makes up for this tick, or `None` when it skips it. Chunks
+// far from the camera are only simulated every few ticks as a whole, the others follow the
+// material's `update_interval`.
+fn update_interval(progress: &TickProgress, material: &MaterialDef, entity: Entity, position: Vec2, tick: u64) -> Option<u32> {
+  match progress.chunk_interval(SpatialIndex::chunk_of(position.floor().as_ivec2())) {
+    Some(interval) if interval > 1 => Some(interval),
+    _ if material.updates_on(entity, tick) => Some(material.update_interval.max(1)),
+    _ => None,
+  }
+}
+
+// Cells a particle starting in `point` may end up in under `UpdateOrder::Checkerboard`, from half a
+// chunk before its chunk to half a chunk after it
+fn checkerboard_reach(point: IVec2) -> (IVec2, IVec2) {
+  let half = SpatialIndex::CHUNK_SIZE / 2;
+  let min = SpatialIndex::chunk_of(point) * SpatialIndex::CHUNK_SIZE;
+  (min - IVec2::splat(half), min + IVec2::splat(SpatialIndex::CHUNK_SIZE - 1 + half))
+}
+
+// A particle about to move, with what it moves by
+struct Mover {
+  entity: Entity,
+  position: Vec2,
+  velocity: Vec2,
+  gravity: Vec2,
+  substeps: u32,
+}
+
+impl Mover {
+  // `None` when the particle does not move this tick
+  #[allow(clippy::too_many_arguments)]
+  fn new(
+    entity: Entity,
+    particle: &Particle,
+    boid: bool,
+    material: &MaterialDef,
+    progress: &TickProgress,
+    resting: &Resting,
+    settings: &SimulationSettings,
+    tick: u64,
+  ) -> Option<Self> {
+    let interval = update_interval(progress, material, entity, particle.position, tick)?;
+    // The velocity already includes the tick's gravity from `discover_collisions`
+    let gravity = if boid || resting.contains(entity) { Vec2::ZERO } else { settings.gravity_at(particle.position) * settings.tick_delta() };
+    Some(Self {
+      entity,
+      position: particle.position,
+      velocity: particle.velocity,
+      gravity,
+      // Makes up for the ticks skipped since it last moved with that many more substeps
+      substeps: settings.substeps.max(1) * interval,
+    })
+  }
+
+  // Moves in `substeps` increments and stops short of the first cell that is taken or out of bounds.
+  // Cells holding something it does not collide with are passed through. Portals are left to the
+  // caller.
+  fn plan(&self, cells: &mut Cells, layers: &Query<&CollisionLayers>, settings: &SimulationSettings) -> Planned {
+    let Mover { entity, position, velocity, gravity, substeps } = *self;
+    let spatial_index = cells.spatial_index;
+    let current_point = position.floor().as_ivec2();
+    let passable = |cells: &Cells, position: Vec2| {
+      let point = position.floor().as_ivec2();
+      spatial_index.outside(position).is_none()
+        && cells.get(point).is_some_and(|other| other != entity && !layers::collides(entity, other, layers))
+    };
+    let advance = |position: Vec2| {
+      if settings.boundary == Boundary::Wrap { spatial_index.wrap(position) } else { position }
+    };
+    let step = settings.integrator.displacement(velocity, gravity) * settings.tick_delta() / settings.substeps.max(1) as f32;
+    let reach = cells.checkerboard.then(|| checkerboard_reach(current_point));
+    let within_reach = |position: Vec2| {
+      reach.is_none_or(|(min, max)| {
+        let point = position.floor().as_ivec2();
+        point.cmpge(min).all() && point.cmple(max).all()
+      })
+    };
+    let mut new_position = position;
+    let mut resting_position = position;
+    let mut escaped = None;
+    for _ in 0..substeps {
+      // Checked before wrapping, a particle going through the edge of the world is still close by
+      if !within_reach(new_position + step) { break }
+      let next = advance(new_position + step);
+      if settings.boundary == Boundary::Despawn && spatial_index.outside(next).is_some() {
+        escaped = Some(next);
+        break;
+      }
+      let next_point = next.floor().as_ivec2();
+      if next_point != new_position.floor().as_ivec2() && !cells.is_free(next_point) && !passable(cells, next) { break }
+      new_position = next;
+      if next_point == current_point || cells.is_free(next_point) {
+        resting_position = next;
+      }
+    }
+    // The lookup holds one particle per cell, so one that would stop inside a cell it passes through
+    // is carried on to the free cell beyond, or back to the last free cell it went through
+    let heading = step.normalize_or_zero();
+    while new_position.floor().as_ivec2() != current_point && !cells.is_free(new_position.floor().as_ivec2()) {
+      let next = advance(new_position + heading);
+      if !within_reach(new_position + heading) || (!cells.is_free(next.floor().as_ivec2()) && !passable(cells, next)) {
+        new_position = resting_position;
+        break;
+      }
+      new_position = next;
+    }
+
+    if escaped.is_some() {
+      cells.leave(entity, current_point);
+    } else {
+      cells.enter(entity, current_point, new_position.floor().as_ivec2());
+    }
+    Planned { entity, from: current_point, position: new_position, escaped, step }
+  }
+}
+
+// Where a particle's move takes it, before it goes through any portal
+struct Planned {
+  entity: Entity,
+  from: IVec2,
+  position: Vec2,
+  // Left the world through an edge that despawns it, at this position
+  escaped: Option<Vec2>,
+  step: Vec2,
+}
+
+// The lookup as it is seen by moves that have not been written into it yet, along with the cells
+// those moves took and left
+struct Cells<'a> {
+  spatial_index: &'a SpatialIndex,
+  // Moves are kept to `checkerboard_reach`
+  checkerboard: bool,
+  changed: HashMap<IVec2, Option<Entity>>,
+}
+
+impl<'a> Cells<'a> {
+  fn new(spatial_index: &'a SpatialIndex, checkerboard: bool) -> Self {
+    Self { spatial_index, checkerboard, changed: HashMap::default() }
+  }
+
+  fn get(&self, cell: IVec2) -> Option<Entity> {
+    match self.changed.get(&cell) {
+      Some(entity) => *entity,
+      None => self.spatial_index.get(&cell).copied(),
+    }
+  }
+
+  fn is_free(&self, cell: IVec2) -> bool {
+    self.spatial_index.outside(cell.as_vec2()).is_none() && self.get(cell).is_none() && !self.spatial_index.is_collider(cell)
+  }
+
+  fn leave(&mut self, entity: Entity, cell: IVec2) {
+    if self.get(cell) == Some(entity) {
+      self.changed.insert(cell, None);
+    }
+  }
+
+  fn enter(&mut self, entity: Entity, from: IVec2, to: IVec2) {
+    if from == to { return }
+    self.leave(entity, from);
+    self.changed.insert(to, Some(entity));
+  }
+}
+
+enum Destination {
+  Cell(IVec2),
+  // Left the world through an edge that despawns it, at this position
+  Escaped(Vec2),
+}
+
+// A move made against the world as the tick started, see `UpdateOrder::DoubleBuffered`
+struct BufferedMove {
+  entity: Entity,
+  from: IVec2,
+  to: Destination,
+  // Position before the move, restored when another particle gets the cell
+  previous: Vec2,
+}
+
+// Kept between frames while a tick is spread over several of them
+#[derive(Default)]
+struct PendingMoves {
+  moves: Vec<BufferedMove>,
+  // Particles resting on another, with their cells, that may sink through it
+  sinking: Vec<(Entity, Entity, IVec2, IVec2)>,
+}
+
+// Which moves go ahead. A cell wanted by several particles goes to the one that started closest to
+// it, then to the first in cell order, which does not depend on the order the moves were made in.
+fn resolve_buffered_moves(moves: &mut [BufferedMove]) -> Vec<bool> {
+  moves.sort_unstable_by_key(|mv| (mv.from.y, mv.from.x));
+  let mut claims = HashMap::<IVec2, (i32, usize)>::default();
+  for (index, mv) in moves.iter().enumerate() {
+    if let Destination::Cell(cell) = mv.to {
+      let distance = (cell - mv.from).dot(cell - mv.from);
+      let claim = claims.entry(cell).or_insert((distance, index));
+      if distance < claim.0 {
+        *claim = (distance, index);
+      }
+    }
+  }
+  moves
+    .iter()
+    .enumerate()
+    .map(|(index, mv)| match mv.to {
+      Destination::Cell(cell) => claims[&cell].1 == index,
+      Destination::Escaped(_) => true,
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use bevy::ecs::system::CommandQueue;
+
+  use super::*;
+
+  // A world with only the simulation in it, running one tick per frame
+  fn app() -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins);
+    add_simulation(&mut app, &Args::default(), AppState::Running);
+    let mut settings = app.world.resource_mut::<SimulationSettings>();
+    settings.deterministic = true;
+    settings.timestep = 1. / 60.;
+    // Everything is simulated, rather than only chunks woken up by an earlier tick
+    settings.chunk_activation = false;
+    // Startup systems and the first frame, which has no time passing
+    app.update();
+    app
+  }
+
+  fn spawn(app: &mut App, point: IVec2, material: MaterialId, velocity: Vec2) -> Entity {
+    let mut queue = CommandQueue::default();
+    let entity = app.world.resource_scope(|world, mut spatial_index: Mut<SpatialIndex>| {
+      let materials = world.resource::<MaterialRegistry>();
+      let mut commands = Commands::new(&mut queue, world);
+      spawn_particle_with_velocity(&mut commands, &mut spatial_index, materials, point, material, velocity)
+    });
+    queue.apply(&mut app.world);
+    entity
+  }
+
+  fn tick(app: &mut App) {
+    let timestep = app.world.resource::<SimulationSettings>().timestep;
+    std::thread::sleep(Duration::from_secs_f64(timestep));
+    app.update();
+  }
+
+  // The collisions a tick finds are solved and acted on within that same tick
+  #[test]
+  fn contacts_are_solved_in_the_tick_they_are_found() {
+    let mut app = app();
+    spawn(&mut app, IVec2::new(0, -3), MaterialId::STONE, Vec2::ZERO);
+    let falling = spawn(&mut app, IVec2::new(0, -2), MaterialId::SAND, Vec2::new(0., -16.));
+    tick(&mut app);
+
+    assert_eq!(app.world.resource::<SimulationClock>().tick, 1);
+    let contacts = app.world.resource::<Contacts>();
+    assert!(!contacts.is_empty());
+    assert!(contacts.is_solved());
+    assert!(!contacts.awaits_solver());
+    let particle = app.world.get::<Particle>(falling).unwrap();
+    assert!(particle.velocity.y > -16., "{:?}", particle.velocity);
+    assert_eq!(particle.position.floor().as_ivec2(), IVec2::new(0, -2));
+  }
+
+  // Moving a pass's chunks in parallel ends where moving them one at a time does
+  #[test]
+  fn checkerboard_passes_move_as_they_would_one_at_a_time() {
+    let run = |boundary| {
+      let mut app = app();
+      // Room for a few chunks of each pass, with nothing reaching the edges
+      app.insert_resource(SpatialIndex::new(96, 64));
+      let mut settings = app.world.resource_mut::<SimulationSettings>();
+      settings.update_order = UpdateOrder::Checkerboard;
+      settings.boundary = boundary;
+      let mut falling = Vec::new();
+      for x in -24..24 {
+        spawn(&mut app, IVec2::new(x, -20), MaterialId::STONE, Vec2::ZERO);
+        for y in -12..-4 {
+          let velocity = Vec2::new((x % 5) as f32 * 4., -16.);
+          falling.push(spawn(&mut app, IVec2::new(x, y + x.rem_euclid(3)), MaterialId::SAND, velocity));
+        }
+      }
+      for _ in 0..10 {
+        tick(&mut app);
+      }
+      falling.iter().map(|entity| app.world.get::<Particle>(*entity).unwrap().position).collect::<Vec<_>>()
+    };
+
+    // Chunks of a world that wraps around are moved one after another
+    assert_eq!(run(Boundary::Bounce), run(Boundary::Wrap));
+  }
 }
@@ -0,0 +1,208 @@
+use bevy::prelude::*;
+
+use crate::{boids::BoidRole, layers::{CollisionLayers, Layers}, lifetime::{Expiry, Lifetime}};
+
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Reflect)]
+#[reflect(Component)]
+pub struct MaterialId(pub usize);
+
+impl MaterialId {
+  pub const SAND: Self = Self(0);
+  pub const WATER: Self = Self(1);
+  pub const SOIL: Self = Self(2);
+  pub const SEED: Self = Self(3);
+  pub const PLANT: Self = Self(4);
+  pub const STONE: Self = Self(5);
+  pub const STEAM: Self = Self(6);
+  pub const SMOKE: Self = Self(7);
+  pub const SPARK: Self = Self(8);
+  pub const BIRD: Self = Self(9);
+  pub const HAWK: Self = Self(10);
+  pub const GRATE: Self = Self(11);
+  pub const SEDIMENT: Self = Self(12);
+  pub const OIL: Self = Self(13);
+  pub const WOOD: Self = Self(14);
+  pub const WIRE: Self = Self(15);
+  pub const PLATE: Self = Self(16);
+  pub const DOOR: Self = Self(17);
+  pub const GATE: Self = Self(18);
+  pub const ICE: Self = Self(19);
+  pub const LAVA: Self = Self(20);
+}
+
+#[derive(Clone, Debug)]
+pub struct MaterialDef {
+  pub name: String,
+  pub color: Color,
+  // Of one particle, which fills a cell, so it is also the density that decides what floats
+  pub mass: f32,
+  pub elasticity: f32,
+  // Fixed materials never move and act as colliders in the lookup
+  pub fixed: bool,
+  // Liquids carry pressure through connected cells, see `pressure`
+  pub liquid: bool,
+  // Thrown in lumps that hold together and tumble as one, see `clusters`
+  pub rigid: bool,
+  // Particles of materials with a lifetime expire after that many ticks
+  pub lifetime: Option<Lifetime>,
+  // Particles of materials with a role fly as boids
+  pub boid: Option<BoidRole>,
+  // What its particles collide with, see `CollisionLayers`
+  pub layers: CollisionLayers,
+  // Moving particles leave a fading trail, see `trails`
+  pub trail: bool,
+  // How brightly its particles glow, 0 for not at all and up to 1, see `glow`
+  pub emissive: f32,
+  // Its particles only move every this many ticks, making up for the ones skipped when they do.
+  // Saves work on slow materials that are mostly at rest.
+  pub update_interval: u32,
+}
+
+impl MaterialDef {
+  pub fn new(name: &str, color: Color, mass: f32, elasticity: f32) -> Self {
+    Self {
+      name: name.to_string(),
+      color,
+      mass,
+      elasticity,
+      fixed: false,
+      liquid: false,
+      rigid: false,
+      lifetime: None,
+      boid: None,
+      layers: CollisionLayers::default(),
+      trail: false,
+      emissive: 0.,
+      update_interval: 1,
+    }
+  }
+
+  pub fn fixed(mut self) -> Self {
+    self.fixed = true;
+    self
+  }
+
+  pub fn liquid(mut self) -> Self {
+    self.liquid = true;
+    self
+  }
+
+  pub fn rigid(mut self) -> Self {
+    self.rigid = true;
+    self
+  }
+
+  pub fn lifetime(mut self, ticks: u64, expiry: Expiry) -> Self {
+    self.lifetime = Some(Lifetime { ticks, expiry });
+    self
+  }
+
+  pub fn boid(mut self, role: BoidRole) -> Self {
+    self.boid = Some(role);
+    self
+  }
+
+  pub fn layers(mut self, layer: Layers, mask: Layers) -> Self {
+    self.layers = CollisionLayers::new(layer, mask);
+    self
+  }
+
+  pub fn trail(mut self) -> Self {
+    self.trail = true;
+    self
+  }
+
+  pub fn emissive(mut self, intensity: f32) -> Self {
+    self.emissive = intensity;
+    self
+  }
+
+  pub fn update_interval(mut self, ticks: u32) -> Self {
+    self.update_interval = ticks.max(1);
+    self
+  }
+
+  // Whether a particle of this material moves on `tick`. Particles are spread over the interval by
+  // entity, so they do not all move on the same tick.
+  pub fn updates_on(&self, entity: Entity, tick: u64) -> bool {
+    (tick + entity.id() as u64).is_multiple_of(self.update_interval.max(1) as u64)
+  }
+}
+
+pub struct MaterialRegistry {
+  materials: Vec<MaterialDef>,
+}
+
+impl MaterialRegistry {
+  pub fn register(&mut self, material: MaterialDef) -> MaterialId {
+    self.materials.push(material);
+    MaterialId(self.materials.len() - 1)
+  }
+
+  pub fn get(&self, id: MaterialId) -> &MaterialDef {
+    &self.materials[id.0]
+  }
+
+  pub fn contains(&self, id: MaterialId) -> bool {
+    id.0 < self.materials.len()
+  }
+
+  pub fn find(&self, name: &str) -> Option<MaterialId> {
+    self.iter().find(|(_, material)| material.name.eq_ignore_ascii_case(name)).map(|(id, _)| id)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (MaterialId, &MaterialDef)> {
+    self.materials.iter().enumerate().map(|(index, material)| (MaterialId(index), material))
+  }
+}
+
+impl Default for MaterialRegistry {
+  fn default() -> Self {
+    // Registration order must match the `MaterialId` constants
+    Self {
+      materials: vec![
+        MaterialDef::new("Sand", Color::rgb(0.86, 0.76, 0.46), 1., 0.4).update_interval(2),
+        MaterialDef::new("Water", Color::rgb(0.2, 0.4, 0.9), 0.8, 0.1).liquid(),
+        MaterialDef::new("Soil", Color::rgb(0.4, 0.26, 0.13), 1.2, 0.2).update_interval(2),
+        MaterialDef::new("Seed", Color::rgb(0.75, 0.6, 0.2), 0.5, 0.3),
+        MaterialDef::new("Plant", Color::rgb(0.2, 0.7, 0.25), 0.5, 0.2).fixed(),
+        MaterialDef::new("Stone", Color::GRAY, 2.5, 0.3).fixed(),
+        MaterialDef::new("Steam", Color::rgb(0.85, 0.88, 0.92), 0.1, 0.1)
+          .lifetime(40, Expiry::Convert(MaterialId::WATER))
+          .layers(Layers::GAS, Layers::all() - Layers::GRATE),
+        MaterialDef::new("Smoke", Color::rgb(0.3, 0.3, 0.3), 0.1, 0.1)
+          .lifetime(24, Expiry::FadeOut)
+          .layers(Layers::GAS, Layers::all() - Layers::GRATE),
+        // Sparks from the same burst fly apart instead of piling into each other
+        MaterialDef::new("Spark", Color::rgb(1., 0.8, 0.2), 0.2, 0.6)
+          .lifetime(8, Expiry::Despawn)
+          .layers(Layers::DEBRIS, Layers::all() - Layers::DEBRIS)
+          .trail()
+          .emissive(0.6),
+        MaterialDef::new("Bird", Color::rgb(0.9, 0.9, 0.85), 0.3, 0.5)
+          .boid(BoidRole::Prey)
+          .layers(Layers::BOID, Layers::all() - Layers::DECORATION)
+          .trail(),
+        MaterialDef::new("Hawk", Color::rgb(0.55, 0.3, 0.2), 0.6, 0.5)
+          .boid(BoidRole::Predator)
+          .layers(Layers::BOID, Layers::all() - Layers::DECORATION)
+          .trail(),
+        // Holds back everything but gas
+        MaterialDef::new("Grate", Color::rgb(0.35, 0.35, 0.4), 2., 0.3).fixed().layers(Layers::GRATE, Layers::all() - Layers::GAS),
+        // Granular material carried by a liquid, see `erosion`
+        MaterialDef::new("Sediment", Color::rgb(0.6, 0.52, 0.36), 0.9, 0.1).update_interval(2),
+        // Lighter than water, so both float on it
+        MaterialDef::new("Oil", Color::rgb(0.55, 0.45, 0.1), 0.6, 0.05).liquid(),
+        MaterialDef::new("Wood", Color::rgb(0.5, 0.35, 0.2), 0.5, 0.3).rigid(),
+        // Parts of machines, see `machines`
+        MaterialDef::new("Wire", Color::rgb(0.55, 0.3, 0.15), 2., 0.3).fixed(),
+        MaterialDef::new("Plate", Color::rgb(0.6, 0.6, 0.55), 2., 0.3).fixed(),
+        MaterialDef::new("Door", Color::rgb(0.3, 0.25, 0.35), 2., 0.3).fixed(),
+        MaterialDef::new("Gate", Color::rgb(0.25, 0.4, 0.3), 2., 0.3).fixed(),
+        // Frozen water, see `phases`
+        MaterialDef::new("Ice", Color::rgb(0.75, 0.88, 0.95), 0.9, 0.2).fixed(),
+        MaterialDef::new("Lava", Color::rgb(1., 0.35, 0.05), 2.2, 0.05).liquid().emissive(0.5),
+      ],
+    }
+  }
+}
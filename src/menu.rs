@@ -0,0 +1,381 @@
+use bevy::{prelude::*, app::AppExit};
+
+use crate::{
+  accessibility::ColorPalette,
+  actions::Action,
+  autosave::{Autosave, RestoreAutosave},
+  locale::Locale,
+  renderer::{RenderSettings, Renderer},
+  scenario::{LoadScenario, Scenario},
+  simulation::{Backend, Boundary, GravityPreset, Integrator, Scheduler, SimulationLod, SimulationSettings, UpdateOrder},
+  AppState,
+};
+
+const NORMAL_BUTTON: Color = Color::rgb(0.15, 0.15, 0.15);
+const HOVERED_BUTTON: Color = Color::rgb(0.25, 0.25, 0.25);
+const PRESSED_BUTTON: Color = Color::rgb(0.35, 0.55, 0.35);
+const TIMESTEPS: [f64; 4] = [0.05, 0.1, 0.25, 0.5];
+const FRAME_BUDGET_MS: f32 = 4.;
+
+pub struct MenuPlugin;
+
+impl Plugin for MenuPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .add_startup_system(setup_ui_camera)
+      .add_system(toggle_pause)
+      .add_system(rebuild_menu.label("rebuild_menu"))
+      .add_system(handle_buttons.after("rebuild_menu"));
+  }
+}
+
+#[derive(Component)]
+struct MenuRoot;
+
+#[derive(Component, Clone, Copy)]
+enum MenuButton {
+  Resume,
+  RestoreAutosave,
+  NewWorld,
+  LoadScenario,
+  Settings,
+  Timestep,
+  Scheduler,
+  ChunkActivation,
+  Integrator,
+  UpdateOrder,
+  Lod,
+  Boundary,
+  Gravity,
+  Backend,
+  Renderer,
+  Palette,
+  Patterns,
+  Language,
+  Back,
+  Quit,
+}
+
+impl MenuButton {
+  fn label(
+    &self,
+    settings: &SimulationSettings,
+    gravity: &GravityPreset,
+    render_settings: &RenderSettings,
+    autosave: &Autosave,
+    locale: &Locale,
+  ) -> String {
+    let key = match self {
+      MenuButton::Resume => "menu.resume",
+      MenuButton::RestoreAutosave => {
+        return locale.format("menu.restore_autosave", &[("particles", &autosave.recovered().unwrap_or(0))]);
+      },
+      MenuButton::NewWorld => "menu.new_world",
+      MenuButton::LoadScenario => "menu.load_scenario",
+      MenuButton::Settings => "menu.settings",
+      MenuButton::Timestep => return locale.format("menu.timestep", &[("seconds", &settings.timestep)]),
+      MenuButton::Scheduler => match settings.scheduler {
+        Scheduler::Fixed => "menu.scheduler_fixed",
+        Scheduler::Budgeted { budget_ms } => return locale.format("menu.scheduler_budgeted", &[("budget", &budget_ms)]),
+      },
+      MenuButton::ChunkActivation => {
+        return locale.format("menu.chunk_activation", &[("state", &locale.on_off(settings.chunk_activation))]);
+      },
+      MenuButton::Integrator => match settings.integrator {
+        Integrator::SemiImplicitEuler => "menu.integrator_euler",
+        Integrator::VelocityVerlet => "menu.integrator_verlet",
+      },
+      MenuButton::UpdateOrder => match settings.update_order {
+        UpdateOrder::Sequential => "menu.update_order_sequential",
+        UpdateOrder::DoubleBuffered => "menu.update_order_double_buffered",
+        UpdateOrder::Checkerboard => "menu.update_order_checkerboard",
+      },
+      MenuButton::Lod => match settings.lod {
+        None => "menu.lod_full",
+        Some(SimulationLod { far_interval: Some(interval), .. }) => return locale.format("menu.lod_interval", &[("interval", &interval)]),
+        Some(SimulationLod { far_interval: None, .. }) => "menu.lod_frozen",
+      },
+      MenuButton::Boundary => match settings.boundary {
+        Boundary::Bounce => "menu.boundary_bounce",
+        Boundary::Wrap => "menu.boundary_wrap",
+        Boundary::Despawn => "menu.boundary_despawn",
+      },
+      MenuButton::Gravity => match gravity {
+        GravityPreset::Earth => "menu.gravity_earth",
+        GravityPreset::Moon => "menu.gravity_moon",
+        GravityPreset::Jupiter => "menu.gravity_jupiter",
+        GravityPreset::ZeroG => "menu.gravity_zero",
+        GravityPreset::Planet => "menu.gravity_planet",
+      },
+      MenuButton::Backend => match settings.backend {
+        Backend::Cpu if cfg!(feature = "gpu") => "menu.backend_cpu",
+        Backend::Cpu => "menu.backend_cpu_only",
+        Backend::Gpu => "menu.backend_gpu",
+      },
+      MenuButton::Renderer => match render_settings.renderer {
+        Renderer::Sprites => "menu.renderer_sprites",
+        Renderer::Texture => "menu.renderer_texture",
+      },
+      MenuButton::Palette => match render_settings.palette {
+        ColorPalette::Standard => "menu.palette_standard",
+        ColorPalette::Deuteranopia => "menu.palette_deuteranopia",
+        ColorPalette::Protanopia => "menu.palette_protanopia",
+        ColorPalette::Tritanopia => "menu.palette_tritanopia",
+      },
+      MenuButton::Patterns => return locale.format("menu.patterns", &[("state", &locale.on_off(render_settings.patterns))]),
+      MenuButton::Language => return locale.format("menu.language", &[("language", &locale.get("name"))]),
+      MenuButton::Back => "menu.back",
+      MenuButton::Quit => "menu.quit",
+    };
+    locale.get(key).to_string()
+  }
+}
+
+fn setup_ui_camera(mut commands: Commands) {
+  commands.spawn_bundle(UiCameraBundle::default());
+}
+
+fn toggle_pause(actions: Res<Input<Action>>, mut state: ResMut<State<AppState>>) {
+  if !actions.just_pressed(Action::Pause) { return }
+  let result = match state.current() {
+    AppState::Running => state.push(AppState::Paused),
+    AppState::Paused | AppState::Settings => state.pop(),
+    AppState::MainMenu | AppState::Loading => Ok(()),
+  };
+  if let Err(error) = result {
+    error!("Could not toggle pause: {:?}", error);
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn rebuild_menu(
+  mut commands: Commands,
+  asset_server: Res<AssetServer>,
+  state: Res<State<AppState>>,
+  settings: Res<SimulationSettings>,
+  gravity: Res<GravityPreset>,
+  render_settings: Res<RenderSettings>,
+  autosave: Res<Autosave>,
+  locale: Res<Locale>,
+  roots: Query<Entity, With<MenuRoot>>,
+) {
+  if !state.is_changed() && !settings.is_changed() && !gravity.is_changed() && !render_settings.is_changed() && !locale.is_changed() {
+    return;
+  }
+
+  for entity in roots.iter() {
+    commands.entity(entity).despawn_recursive();
+  }
+
+  let buttons: &[MenuButton] = match state.current() {
+    // Asks first thing whether to go on where the last session left off
+    AppState::MainMenu if autosave.recovered().is_some() => &[
+      MenuButton::RestoreAutosave,
+      MenuButton::NewWorld,
+      MenuButton::LoadScenario,
+      MenuButton::Settings,
+      MenuButton::Quit,
+    ],
+    AppState::MainMenu => &[MenuButton::NewWorld, MenuButton::LoadScenario, MenuButton::Settings, MenuButton::Quit],
+    AppState::Paused => &[
+      MenuButton::Resume,
+      MenuButton::NewWorld,
+      MenuButton::LoadScenario,
+      MenuButton::Settings,
+      MenuButton::Quit,
+    ],
+    AppState::Settings => &[
+      MenuButton::Timestep,
+      MenuButton::Scheduler,
+      MenuButton::ChunkActivation,
+      MenuButton::Integrator,
+      MenuButton::UpdateOrder,
+      MenuButton::Lod,
+      MenuButton::Boundary,
+      MenuButton::Gravity,
+      MenuButton::Backend,
+      MenuButton::Renderer,
+      MenuButton::Palette,
+      MenuButton::Patterns,
+      MenuButton::Language,
+      MenuButton::Back,
+    ],
+    AppState::Running | AppState::Loading => return,
+  };
+
+  let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+  commands
+    .spawn_bundle(NodeBundle {
+      style: Style {
+        size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+        flex_direction: FlexDirection::ColumnReverse,
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..Default::default()
+      },
+      color: Color::rgba(0., 0., 0., 0.6).into(),
+      ..Default::default()
+    })
+    .insert(MenuRoot)
+    .with_children(|parent| {
+      for button in buttons {
+        parent
+          .spawn_bundle(ButtonBundle {
+            style: Style {
+              size: Size::new(Val::Px(240.), Val::Px(44.)),
+              margin: Rect::all(Val::Px(4.)),
+              justify_content: JustifyContent::Center,
+              align_items: AlignItems::Center,
+              ..Default::default()
+            },
+            color: NORMAL_BUTTON.into(),
+            ..Default::default()
+          })
+          .insert(*button)
+          .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+              text: Text::with_section(
+                button.label(&settings, &gravity, &render_settings, &autosave, &locale),
+                TextStyle { font: font.clone(), font_size: 28., color: Color::WHITE },
+                Default::default(),
+              ),
+              ..Default::default()
+            });
+          });
+      }
+    });
+}
+
+type Pressed<'w, 's> = Query<'w, 's, (&'static Interaction, &'static MenuButton, &'static mut UiColor), (Changed<Interaction>, With<Button>)>;
+
+#[allow(clippy::too_many_arguments)]
+fn handle_buttons(
+  mut state: ResMut<State<AppState>>,
+  mut settings: ResMut<SimulationSettings>,
+  mut gravity: ResMut<GravityPreset>,
+  mut render_settings: ResMut<RenderSettings>,
+  mut locale: ResMut<Locale>,
+  mut load_events: EventWriter<LoadScenario>,
+  mut restore_events: EventWriter<RestoreAutosave>,
+  mut exit_events: EventWriter<AppExit>,
+  mut buttons: Pressed,
+) {
+  for (interaction, button, mut color) in buttons.iter_mut() {
+    match *interaction {
+      Interaction::None => *color = NORMAL_BUTTON.into(),
+      Interaction::Hovered => *color = HOVERED_BUTTON.into(),
+      Interaction::Clicked => {
+        *color = PRESSED_BUTTON.into();
+        let result = match button {
+          MenuButton::Resume | MenuButton::Back => state.pop(),
+          MenuButton::RestoreAutosave => {
+            restore_events.send(RestoreAutosave);
+            state.replace(AppState::Loading)
+          },
+          MenuButton::NewWorld => {
+            load_events.send(LoadScenario(Scenario::default()));
+            state.replace(AppState::Running)
+          },
+          MenuButton::LoadScenario => match Scenario::load(Scenario::DEFAULT_PATH) {
+            Ok(scenario) => {
+              load_events.send(LoadScenario(scenario));
+              state.replace(AppState::Running)
+            },
+            Err(error) => {
+              error!("{}", error);
+              Ok(())
+            }
+          },
+          MenuButton::Settings => state.push(AppState::Settings),
+          MenuButton::Timestep => {
+            let index = TIMESTEPS.iter().position(|step| *step == settings.timestep).unwrap_or(0);
+            settings.timestep = TIMESTEPS[(index + 1) % TIMESTEPS.len()];
+            Ok(())
+          },
+          MenuButton::Scheduler => {
+            settings.scheduler = match settings.scheduler {
+              Scheduler::Fixed => Scheduler::Budgeted { budget_ms: FRAME_BUDGET_MS },
+              Scheduler::Budgeted { .. } => Scheduler::Fixed,
+            };
+            Ok(())
+          },
+          MenuButton::ChunkActivation => {
+            settings.chunk_activation = !settings.chunk_activation;
+            Ok(())
+          },
+          MenuButton::Integrator => {
+            settings.integrator = match settings.integrator {
+              Integrator::SemiImplicitEuler => Integrator::VelocityVerlet,
+              Integrator::VelocityVerlet => Integrator::SemiImplicitEuler,
+            };
+            Ok(())
+          },
+          MenuButton::UpdateOrder => {
+            settings.update_order = match settings.update_order {
+              UpdateOrder::Sequential => UpdateOrder::DoubleBuffered,
+              UpdateOrder::DoubleBuffered => UpdateOrder::Checkerboard,
+              UpdateOrder::Checkerboard => UpdateOrder::Sequential,
+            };
+            Ok(())
+          },
+          MenuButton::Lod => {
+            settings.lod = match settings.lod {
+              None => Some(SimulationLod::default()),
+              Some(SimulationLod { far_interval: Some(_), .. }) => Some(SimulationLod { far_interval: None, ..Default::default() }),
+              Some(SimulationLod { far_interval: None, .. }) => None,
+            };
+            Ok(())
+          },
+          MenuButton::Boundary => {
+            settings.boundary = match settings.boundary {
+              Boundary::Bounce => Boundary::Wrap,
+              Boundary::Wrap => Boundary::Despawn,
+              Boundary::Despawn => Boundary::Bounce,
+            };
+            Ok(())
+          },
+          MenuButton::Gravity => {
+            *gravity = gravity.next();
+            gravity.apply(&mut settings);
+            Ok(())
+          },
+          MenuButton::Backend => {
+            settings.backend = match settings.backend {
+              Backend::Cpu if cfg!(feature = "gpu") => Backend::Gpu,
+              _ => Backend::Cpu,
+            };
+            Ok(())
+          },
+          MenuButton::Renderer => {
+            render_settings.renderer = match render_settings.renderer {
+              Renderer::Sprites => Renderer::Texture,
+              Renderer::Texture => Renderer::Sprites,
+            };
+            Ok(())
+          },
+          MenuButton::Palette => {
+            render_settings.palette = render_settings.palette.next();
+            Ok(())
+          },
+          MenuButton::Patterns => {
+            render_settings.patterns = !render_settings.patterns;
+            Ok(())
+          },
+          MenuButton::Language => {
+            let languages = Locale::languages();
+            let index = languages.iter().position(|language| *language == locale.language).map_or(0, |index| index + 1);
+            *locale = Locale::load(&languages[index % languages.len()]);
+            Ok(())
+          },
+          MenuButton::Quit => {
+            exit_events.send(AppExit);
+            Ok(())
+          },
+        };
+        if let Err(error) = result {
+          error!("Could not change state: {:?}", error);
+        }
+      }
+    }
+  }
+}
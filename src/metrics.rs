@@ -0,0 +1,209 @@
+use std::{
+  fmt::Write as _,
+  fs::File,
+  io::{BufRead, BufReader, BufWriter, Write},
+  net::{SocketAddr, TcpListener},
+  path::PathBuf,
+  sync::{Arc, Mutex},
+  thread,
+  time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+
+use crate::{
+  material::{MaterialId, MaterialRegistry},
+  simulation::{SimulationClock, SimulationSettings, SimulationStep, TickProgress},
+  Contacts, SpatialIndex,
+};
+
+// A scraper that stops reading or writing midway is dropped after this, so it can not hold up the
+// ones after it
+const STREAM_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Records a sample of the world after every tick, for tracking performance over long runs
+pub struct MetricsPlugin {
+  // Samples are appended here as a CSV row per tick
+  pub csv: Option<PathBuf>,
+  // Latest sample is served here in the Prometheus text format
+  pub prometheus: Option<SocketAddr>,
+}
+
+impl Plugin for MetricsPlugin {
+  fn build(&self, app: &mut App) {
+    if self.csv.is_none() && self.prometheus.is_none() { return }
+
+    let mut metrics = Metrics::default();
+    if let Some(path) = &self.csv {
+      match File::create(path) {
+        Ok(file) => {
+          info!("Writing metrics to {}", path.display());
+          metrics.csv = Some(BufWriter::new(file));
+        },
+        Err(error) => error!("Could not create {}: {}", path.display(), error),
+      }
+    }
+    if let Some(address) = self.prometheus {
+      match serve(address, metrics.exposition.clone()) {
+        Ok(()) => info!("Serving metrics on http://{}/metrics", address),
+        Err(error) => error!("Could not serve metrics on {}: {}", address, error),
+      }
+    }
+
+    app
+      .insert_resource(metrics)
+      .add_system_set(SystemSet::new()
+        .with_run_criteria("fixed_tick")
+        .with_system(start_tick.before(SimulationStep::Integrate))
+        .with_system(record_tick.after(SimulationStep::Commit))
+      );
+  }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Sample {
+  tick: u64,
+  particles: usize,
+  // Particles in chunks that are not simulating
+  sleeping: usize,
+  collisions: usize,
+  // Wall time from the start of the tick to its end, which spans frames under `Scheduler::Budgeted`
+  duration: f64,
+  // Indexed by `MaterialId`, particles spawned during the tick only count from the next one
+  materials: Vec<usize>,
+}
+
+#[derive(Default)]
+struct Metrics {
+  started: Option<Instant>,
+  total_collisions: u64,
+  csv: Option<BufWriter<File>>,
+  header_written: bool,
+  // Latest sample in the Prometheus text format, shared with the server thread
+  exposition: Arc<Mutex<String>>,
+}
+
+fn start_tick(clock: Res<SimulationClock>, mut metrics: ResMut<Metrics>) {
+  if clock.ticked {
+    metrics.started = Some(Instant::now());
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn record_tick(
+  clock: Res<SimulationClock>,
+  settings: Res<SimulationSettings>,
+  progress: Res<TickProgress>,
+  spatial_index: Res<SpatialIndex>,
+  registry: Res<MaterialRegistry>,
+  contacts: Res<Contacts>,
+  materials: Query<&MaterialId>,
+  mut metrics: ResMut<Metrics>,
+) {
+  if !progress.is_complete() { return }
+  let started = match metrics.started.take() {
+    Some(started) => started,
+    None => return,
+  };
+
+  let mut sample = Sample {
+    tick: clock.tick,
+    particles: spatial_index.len(),
+    sleeping: if settings.chunk_activation { spatial_index.sleeping_len() } else { 0 },
+    collisions: contacts.len(),
+    duration: started.elapsed().as_secs_f64(),
+    materials: vec![0; registry.iter().count()],
+  };
+  for material in spatial_index.values().filter_map(|entity| materials.get(*entity).ok()) {
+    if let Some(count) = sample.materials.get_mut(material.0) {
+      *count += 1;
+    }
+  }
+  metrics.total_collisions += sample.collisions as u64;
+
+  if metrics.csv.is_some() {
+    if let Err(error) = write_row(&mut metrics, &registry, &sample) {
+      error!("Could not write metrics, stopping: {}", error);
+      metrics.csv = None;
+    }
+  }
+  let exposition = exposition(&sample, metrics.total_collisions, &registry);
+  if let Ok(mut shared) = metrics.exposition.lock() {
+    *shared = exposition;
+  }
+}
+
+// Rows are flushed as they are written, so a server that is killed rather than closed keeps its data
+fn write_row(metrics: &mut Metrics, registry: &MaterialRegistry, sample: &Sample) -> std::io::Result<()> {
+  let write_header = !metrics.header_written;
+  metrics.header_written = true;
+  let csv = match metrics.csv.as_mut() {
+    Some(csv) => csv,
+    None => return Ok(()),
+  };
+
+  if write_header {
+    write!(csv, "tick,particles,sleeping,collisions,tick_seconds")?;
+    for (_, material) in registry.iter() {
+      write!(csv, ",{}", material.name.to_lowercase())?;
+    }
+    writeln!(csv)?;
+  }
+  write!(csv, "{},{},{},{},{}", sample.tick, sample.particles, sample.sleeping, sample.collisions, sample.duration)?;
+  for count in sample.materials.iter() {
+    write!(csv, ",{}", count)?;
+  }
+  writeln!(csv)?;
+  csv.flush()
+}
+
+fn exposition(sample: &Sample, total_collisions: u64, registry: &MaterialRegistry) -> String {
+  let mut text = String::new();
+  let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+    let _ = writeln!(text, "# HELP arrakoids_{} {}\n# TYPE arrakoids_{} {}\narrakoids_{} {}", name, help, name, kind, name, value);
+  };
+  metric("tick", "counter", "Latest simulated tick.", sample.tick.to_string());
+  metric("particles", "gauge", "Particles in the world.", sample.particles.to_string());
+  metric("sleeping_particles", "gauge", "Particles in chunks that are not simulating.", sample.sleeping.to_string());
+  metric("tick_collisions", "gauge", "Collisions found during the latest tick.", sample.collisions.to_string());
+  metric("collisions_total", "counter", "Collisions found since startup.", total_collisions.to_string());
+  metric("tick_duration_seconds", "gauge", "Wall time the latest tick took.", sample.duration.to_string());
+
+  let _ = writeln!(text, "# HELP arrakoids_material_particles Particles of each material.");
+  let _ = writeln!(text, "# TYPE arrakoids_material_particles gauge");
+  for ((_, material), count) in registry.iter().zip(sample.materials.iter()) {
+    let _ = writeln!(text, "arrakoids_material_particles{{material=\"{}\"}} {}", material.name.to_lowercase(), count);
+  }
+  text
+}
+
+// Answers every request with the latest exposition, whatever its path, one connection at a time
+fn serve(address: SocketAddr, exposition: Arc<Mutex<String>>) -> std::io::Result<()> {
+  let listener = TcpListener::bind(address)?;
+  thread::spawn(move || {
+    for stream in listener.incoming() {
+      let mut stream = match stream {
+        Ok(stream) => stream,
+        Err(_) => continue,
+      };
+      if stream.set_read_timeout(Some(STREAM_TIMEOUT)).and(stream.set_write_timeout(Some(STREAM_TIMEOUT))).is_err() {
+        continue;
+      }
+      // The request itself does not matter, but it is read through its headers before answering
+      let mut reader = BufReader::new(&mut stream);
+      let mut line = String::new();
+      while reader.read_line(&mut line).is_ok_and(|read| read > 2) {
+        line.clear();
+      }
+
+      let body = exposition.lock().map(|body| body.clone()).unwrap_or_default();
+      let _ = write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body,
+      );
+    }
+  });
+  Ok(())
+}
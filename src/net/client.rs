@@ -0,0 +1,137 @@
+use std::{io, net::SocketAddr};
+
+use bevy::{prelude::*, utils::{HashMap, Instant}};
+
+use crate::{
+  clear_world, despawn_particle,
+  material::{MaterialId, MaterialRegistry},
+  spawn_particle,
+  tools::BrushStroke,
+  Particle, SpatialIndex,
+};
+
+use super::{protocol::{CellMap, ClientMessage, ServerMessage}, world_size, Socket, KEEPALIVE_INTERVAL};
+
+// A full update still waiting on some of its parts
+struct PendingFull {
+  sequence: u64,
+  parts: u16,
+  received: HashMap<u16, Vec<(i32, i32, u16)>>,
+}
+
+pub struct Client {
+  socket: Socket,
+  server: SocketAddr,
+  last_hello: Option<Instant>,
+  pending: Option<PendingFull>,
+  // Partial updates older than the latest full one are stale
+  latest_full: u64,
+}
+
+impl Client {
+  pub fn connect(server: SocketAddr) -> io::Result<Self> {
+    Ok(Self { socket: Socket::bind_for(server)?, server, last_hello: None, pending: None, latest_full: 0 })
+  }
+
+  fn send(&self, message: &ClientMessage) {
+    self.socket.send(self.server, message);
+  }
+}
+
+impl Drop for Client {
+  fn drop(&mut self) {
+    self.send(&ClientMessage::Bye);
+  }
+}
+
+pub(super) fn send_messages(mut client: ResMut<Client>, mut strokes: EventReader<BrushStroke>) {
+  let now = Instant::now();
+  if client.last_hello.is_none_or(|last| now.duration_since(last) >= KEEPALIVE_INTERVAL) {
+    client.send(&ClientMessage::Hello);
+    client.last_hello = Some(now);
+  }
+
+  for stroke in strokes.iter() {
+    client.send(&ClientMessage::Stroke(stroke.into()));
+  }
+}
+
+pub(super) fn receive_cells(
+  mut commands: Commands,
+  mut client: ResMut<Client>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  materials: Res<MaterialRegistry>,
+  particles: Query<(&Particle, &MaterialId)>,
+) {
+  while let Some((message, address)) = client.socket.receive::<ServerMessage>() {
+    if address != client.server { continue }
+    match message {
+      ServerMessage::Welcome { width, height } if world_size(&spatial_index) != (width, height) => {
+        // Cleared right away rather than through `LoadScenario`, so the full update that
+        // follows in this same frame is not wiped out with it
+        clear_world(&mut commands, &mut spatial_index, width, height);
+      },
+      ServerMessage::Cells { sequence, full: false, cells, cleared, .. } => {
+        if sequence < client.latest_full { continue }
+        for (x, y) in cleared {
+          set_cell(IVec2::new(x, y), None, &mut commands, &mut spatial_index, &materials, &particles);
+        }
+        for (x, y, material) in cells {
+          let material = Some(MaterialId(material as usize));
+          set_cell(IVec2::new(x, y), material, &mut commands, &mut spatial_index, &materials, &particles);
+        }
+      },
+      ServerMessage::Cells { sequence, full: true, part, parts, cells, .. } => {
+        if sequence < client.latest_full { continue }
+        if client.pending.as_ref().is_none_or(|pending| pending.sequence != sequence) {
+          client.pending = Some(PendingFull { sequence, parts, received: HashMap::new() });
+        }
+        let pending = client.pending.as_mut().unwrap();
+        pending.received.insert(part, cells);
+        if pending.received.len() < pending.parts as usize { continue }
+
+        let pending = client.pending.take().unwrap();
+        client.latest_full = pending.sequence;
+        let world: CellMap = pending
+          .received
+          .into_iter()
+          .flat_map(|(_, cells)| cells)
+          .map(|(x, y, material)| (IVec2::new(x, y), MaterialId(material as usize)))
+          .collect();
+
+        let stale: Vec<IVec2> = spatial_index.cells().filter(|cell| !world.contains_key(cell)).copied().collect();
+        for cell in stale {
+          set_cell(cell, None, &mut commands, &mut spatial_index, &materials, &particles);
+        }
+        for (cell, material) in world {
+          set_cell(cell, Some(material), &mut commands, &mut spatial_index, &materials, &particles);
+        }
+      },
+      _ => {},
+    }
+  }
+}
+
+// Makes the local cell match the host, only respawning when the material differs
+fn set_cell(
+  cell: IVec2,
+  material: Option<MaterialId>,
+  commands: &mut Commands,
+  spatial_index: &mut SpatialIndex,
+  materials: &MaterialRegistry,
+  particles: &Query<(&Particle, &MaterialId)>,
+) {
+  let material = material.filter(|material| material.0 < materials.iter().count());
+  if let Some(&entity) = spatial_index.get(&cell) {
+    match particles.get(entity) {
+      Ok((_, current)) if Some(*current) == material => return,
+      Ok((particle, _)) => despawn_particle(commands, spatial_index, entity, particle),
+      Err(_) => {
+        spatial_index.remove(&cell);
+      },
+    }
+  }
+  if let Some(material) = material {
+    spawn_particle(commands, spatial_index, materials, cell, material);
+  }
+}
@@ -0,0 +1,303 @@
+use std::{collections::{BTreeMap, VecDeque}, net::SocketAddr};
+
+use bevy::{prelude::*, utils::{HashMap, Instant}};
+
+use crate::{
+  clear_world,
+  inventory::Inventory,
+  material::MaterialRegistry,
+  simulation::{SimulationClock, SimulationRng, SimulationSettings, SimulationStep},
+  tools::{apply_stroke, BrushStroke},
+  world_state::WorldChecksum,
+  Particle, SpatialIndex,
+};
+
+use super::{
+  protocol::{ClientMessage, NetStroke, ServerMessage},
+  world_size, NetRole, Socket, CLIENT_TIMEOUT, KEEPALIVE_INTERVAL,
+};
+
+// Strokes are applied this many ticks after the host receives them, giving the inputs time to
+// reach every client before they are needed
+const INPUT_DELAY: u64 = 2;
+// How many recent ticks of inputs are repeated in every `Inputs` message
+const REDUNDANT_TICKS: usize = 16;
+const CHECKSUM_INTERVAL: u64 = 20;
+const CHECKSUM_HISTORY: usize = 32;
+
+pub(super) fn build(app: &mut App, role: NetRole) {
+  app.world.resource_mut::<SimulationSettings>().deterministic = true;
+  app.init_resource::<InputSchedule>();
+
+  match role {
+    NetRole::Offline => {},
+    NetRole::Host(address) | NetRole::DedicatedServer(address) => match Socket::bind(address) {
+      Ok(socket) => {
+        info!("Hosting lock-step session on {}", address);
+        app
+          .insert_resource(LockstepHost::new(socket))
+          .add_system_to_stage(CoreStage::PreUpdate, host_receive)
+          .add_system_to_stage(CoreStage::PostUpdate, host_checksum.after("world_checksum"))
+          .add_system_set(SystemSet::new()
+            .with_run_criteria("fixed_tick")
+            .with_system(host_send_inputs.label("lockstep_send").before("lockstep_apply"))
+            .with_system(apply_inputs.label("lockstep_apply").before(SimulationStep::Integrate))
+          );
+        // Dedicated servers have no brush of their own
+        if !role.is_headless() {
+          app.add_system(host_collect_strokes);
+        }
+      },
+      Err(error) => error!("Could not host on {}: {}", address, error),
+    },
+    NetRole::Client(server) => match Socket::bind_for(server) {
+      Ok(socket) => {
+        // Nothing runs until the host starts a session
+        app.world.resource_mut::<SimulationClock>().max_tick = Some(0);
+        app
+          .insert_resource(LockstepClient { socket, server, session: None, last_keepalive: None })
+          .add_system_to_stage(CoreStage::PreUpdate, client_receive)
+          .add_system_to_stage(CoreStage::PostUpdate, client_checksum.after("world_checksum"))
+          .add_system(client_send_strokes)
+          .add_system_set(SystemSet::new()
+            .with_run_criteria("fixed_tick")
+            .with_system(apply_inputs.label("lockstep_apply").before(SimulationStep::Integrate))
+          );
+      },
+      Err(error) => error!("Could not connect to {}: {}", server, error),
+    },
+  }
+}
+
+// Strokes every instance applies at the start of the given tick
+#[derive(Default)]
+struct InputSchedule {
+  ticks: BTreeMap<u64, Vec<BrushStroke>>,
+}
+
+struct LockstepHost {
+  socket: Socket,
+  clients: HashMap<SocketAddr, Instant>,
+  session: u64,
+  seed: u64,
+  // Strokes received since the last tick, scheduled on the next one
+  pending: Vec<NetStroke>,
+  history: VecDeque<(u64, Vec<NetStroke>)>,
+  checksums: BTreeMap<u64, u64>,
+}
+
+impl LockstepHost {
+  fn new(socket: Socket) -> Self {
+    Self {
+      socket,
+      clients: HashMap::new(),
+      session: 0,
+      seed: 0,
+      pending: Vec::new(),
+      history: VecDeque::new(),
+      checksums: BTreeMap::new(),
+    }
+  }
+
+  fn start_message(&self, spatial_index: &SpatialIndex) -> ServerMessage {
+    let (width, height) = world_size(spatial_index);
+    ServerMessage::Start { session: self.session, width, height, seed: self.seed }
+  }
+}
+
+struct LockstepClient {
+  socket: Socket,
+  server: SocketAddr,
+  session: Option<u64>,
+  last_keepalive: Option<Instant>,
+}
+
+// Puts an instance at the start of a fresh session, identical on every instance
+#[allow(clippy::too_many_arguments)]
+fn reset_session(
+  seed: u64,
+  width: i32,
+  height: i32,
+  commands: &mut Commands,
+  spatial_index: &mut SpatialIndex,
+  clock: &mut SimulationClock,
+  rng: &mut SimulationRng,
+  schedule: &mut InputSchedule,
+) {
+  clear_world(commands, spatial_index, width, height);
+  clock.tick = 0;
+  rng.reseed(seed);
+  schedule.ticks.clear();
+}
+
+fn host_receive(
+  mut commands: Commands,
+  mut host: ResMut<LockstepHost>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut clock: ResMut<SimulationClock>,
+  mut rng: ResMut<SimulationRng>,
+  mut schedule: ResMut<InputSchedule>,
+) {
+  let now = Instant::now();
+  let mut restart = false;
+  while let Some((message, address)) = host.socket.receive::<ClientMessage>() {
+    let known = host.clients.insert(address, now).is_some();
+    match message {
+      // Every instance has to start from the same world, so a new client restarts the session
+      ClientMessage::Hello if !known => {
+        info!("Client {} joined, restarting session", address);
+        restart = true;
+      },
+      ClientMessage::Hello => host.socket.send(address, &host.start_message(&spatial_index)),
+      ClientMessage::Ready { session } if session != host.session => {
+        host.socket.send(address, &host.start_message(&spatial_index));
+      },
+      ClientMessage::Stroke(stroke) if known => host.pending.push(stroke),
+      ClientMessage::Checksum { session, tick, value } if session == host.session => {
+        match host.checksums.get(&tick) {
+          Some(expected) if *expected != value => {
+            error!("Desync with {} at tick {}: expected {:016x}, got {:016x}", address, tick, expected, value);
+          },
+          _ => {},
+        }
+      },
+      ClientMessage::Bye => {
+        host.clients.remove(&address);
+        info!("Client {} left", address);
+      },
+      _ => {},
+    }
+  }
+  host.clients.retain(|_, last_seen| now.duration_since(*last_seen) < CLIENT_TIMEOUT);
+
+  if restart {
+    host.session += 1;
+    host.seed = rand::random();
+    host.pending.clear();
+    host.history.clear();
+    host.checksums.clear();
+    let (width, height) = world_size(&spatial_index);
+    reset_session(host.seed, width, height, &mut commands, &mut spatial_index, &mut clock, &mut rng, &mut schedule);
+
+    let start = host.start_message(&spatial_index);
+    for address in host.clients.keys() {
+      host.socket.send(*address, &start);
+    }
+  }
+}
+
+fn host_collect_strokes(mut host: ResMut<LockstepHost>, mut strokes: EventReader<BrushStroke>) {
+  host.pending.extend(strokes.iter().map(NetStroke::from));
+}
+
+fn host_send_inputs(
+  mut host: ResMut<LockstepHost>,
+  clock: Res<SimulationClock>,
+  materials: Res<MaterialRegistry>,
+  mut schedule: ResMut<InputSchedule>,
+) {
+  let tick = clock.tick + INPUT_DELAY;
+  let strokes = std::mem::take(&mut host.pending);
+  schedule.ticks.insert(tick, strokes.iter().filter_map(|stroke| stroke.to_stroke(&materials)).collect());
+
+  host.history.push_back((tick, strokes));
+  while host.history.len() > REDUNDANT_TICKS {
+    host.history.pop_front();
+  }
+
+  let message = ServerMessage::Inputs { session: host.session, ticks: host.history.iter().cloned().collect() };
+  for address in host.clients.keys() {
+    host.socket.send(*address, &message);
+  }
+}
+
+fn apply_inputs(
+  mut commands: Commands,
+  clock: Res<SimulationClock>,
+  mut schedule: ResMut<InputSchedule>,
+  materials: Res<MaterialRegistry>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut inventory: ResMut<Inventory>,
+  particles: Query<&Particle>,
+) {
+  for stroke in schedule.ticks.remove(&clock.tick).unwrap_or_default() {
+    apply_stroke(&stroke, &mut commands, &mut spatial_index, &materials, &mut inventory, &particles);
+  }
+}
+
+fn host_checksum(mut host: ResMut<LockstepHost>, clock: Res<SimulationClock>, checksum: Res<WorldChecksum>) {
+  if !clock.ticked || !checksum.tick.is_multiple_of(CHECKSUM_INTERVAL) { return }
+  host.checksums.insert(checksum.tick, checksum.value);
+  while host.checksums.len() > CHECKSUM_HISTORY {
+    host.checksums.pop_first();
+  }
+}
+
+fn client_receive(
+  mut commands: Commands,
+  mut client: ResMut<LockstepClient>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut clock: ResMut<SimulationClock>,
+  mut rng: ResMut<SimulationRng>,
+  mut schedule: ResMut<InputSchedule>,
+  materials: Res<MaterialRegistry>,
+) {
+  while let Some((message, address)) = client.socket.receive::<ServerMessage>() {
+    if address != client.server { continue }
+    match message {
+      ServerMessage::Start { session, width, height, seed } if client.session != Some(session) => {
+        info!("Starting lock-step session {}", session);
+        client.session = Some(session);
+        reset_session(seed, width, height, &mut commands, &mut spatial_index, &mut clock, &mut rng, &mut schedule);
+      },
+      ServerMessage::Inputs { session, ticks } if client.session == Some(session) => {
+        for (tick, strokes) in ticks {
+          if tick <= clock.tick { continue }
+          schedule.ticks.entry(tick).or_insert_with(|| {
+            strokes.iter().filter_map(|stroke| stroke.to_stroke(&materials)).collect()
+          });
+        }
+      },
+      _ => {},
+    }
+  }
+
+  // Only run ticks whose inputs are known, the first few never have any
+  if client.session.is_some() {
+    let mut next = clock.tick + 1;
+    while next <= INPUT_DELAY || schedule.ticks.contains_key(&next) {
+      next += 1;
+    }
+    clock.max_tick = Some(next - 1);
+  }
+}
+
+fn client_send_strokes(mut client: ResMut<LockstepClient>, mut strokes: EventReader<BrushStroke>) {
+  let now = Instant::now();
+  if client.last_keepalive.is_none_or(|last| now.duration_since(last) >= KEEPALIVE_INTERVAL) {
+    let message = match client.session {
+      Some(session) => ClientMessage::Ready { session },
+      None => ClientMessage::Hello,
+    };
+    client.socket.send(client.server, &message);
+    client.last_keepalive = Some(now);
+  }
+
+  for stroke in strokes.iter() {
+    client.socket.send(client.server, &ClientMessage::Stroke(stroke.into()));
+  }
+}
+
+fn client_checksum(client: Res<LockstepClient>, clock: Res<SimulationClock>, checksum: Res<WorldChecksum>) {
+  if !clock.ticked || !checksum.tick.is_multiple_of(CHECKSUM_INTERVAL) { return }
+  if let Some(session) = client.session {
+    let message = ClientMessage::Checksum { session, tick: checksum.tick, value: checksum.value };
+    client.socket.send(client.server, &message);
+  }
+}
+
+impl Drop for LockstepClient {
+  fn drop(&mut self) {
+    self.socket.send(self.server, &ClientMessage::Bye);
+  }
+}
@@ -0,0 +1,136 @@
+use std::{io, net::{SocketAddr, UdpSocket}};
+
+use bevy::{prelude::*, utils::Duration};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{material::MaterialId, simulation::{SimulationSettings, SimulationStep}, tools::StrokeTarget, Particle, SpatialIndex};
+
+use protocol::CellMap;
+
+mod client;
+mod lockstep;
+mod protocol;
+mod server;
+
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
+// Clients that stop sending keepalives are dropped after this long
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NetRole {
+  #[default]
+  Offline,
+  // Runs the authoritative simulation and plays locally at the same time
+  Host(SocketAddr),
+  // Same as `Host` but without a window, see `main`
+  DedicatedServer(SocketAddr),
+  // Mirrors the host's world and sends brush strokes to it
+  Client(SocketAddr),
+}
+
+impl NetRole {
+  pub fn is_headless(&self) -> bool {
+    matches!(self, NetRole::DedicatedServer(_))
+  }
+}
+
+pub struct NetPlugin {
+  pub role: NetRole,
+  // Exchange inputs and simulate everywhere instead of streaming the host's cells
+  pub lockstep: bool,
+}
+
+impl Plugin for NetPlugin {
+  fn build(&self, app: &mut App) {
+    app.insert_resource(self.role);
+    if self.role == NetRole::Offline { return }
+
+    // Strokes are sent to the host, or scheduled for a later tick, instead of painted right away
+    app.insert_resource(StrokeTarget::Network);
+    if self.lockstep {
+      lockstep::build(app, self.role);
+      return;
+    }
+
+    match self.role {
+      NetRole::Offline => {},
+      NetRole::Host(address) | NetRole::DedicatedServer(address) => match server::Server::bind(address) {
+        Ok(server) => {
+          info!("Hosting on {}", address);
+          // The host paints straight into its own world
+          app
+            .insert_resource(StrokeTarget::Local)
+            .insert_resource(server)
+            .add_system(server::receive_messages.before(SimulationStep::Integrate))
+            .add_system(server::broadcast_cells.after(SimulationStep::Commit));
+        },
+        Err(error) => error!("Could not host on {}: {}", address, error),
+      },
+      NetRole::Client(address) => match client::Client::connect(address) {
+        Ok(client) => {
+          // The host owns the simulation, clients only display what it sends
+          app.world.resource_mut::<SimulationSettings>().authoritative = false;
+          app
+            .insert_resource(client)
+            .add_system(client::send_messages)
+            .add_system(client::receive_cells);
+        },
+        Err(error) => error!("Could not connect to {}: {}", address, error),
+      },
+    }
+  }
+}
+
+// Non-blocking UDP socket exchanging bincode encoded messages
+struct Socket(UdpSocket);
+
+impl Socket {
+  fn bind(address: SocketAddr) -> io::Result<Self> {
+    let socket = UdpSocket::bind(address)?;
+    socket.set_nonblocking(true)?;
+    Ok(Self(socket))
+  }
+
+  // Binds an ephemeral port able to reach `server`
+  fn bind_for(server: SocketAddr) -> io::Result<Self> {
+    let local: SocketAddr = if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }.parse().unwrap();
+    Self::bind(local)
+  }
+
+  fn send(&self, address: SocketAddr, message: &impl Serialize) {
+    if let Some(bytes) = protocol::encode(message) {
+      if let Err(error) = self.0.send_to(&bytes, address) {
+        error!("Could not send to {}: {}", address, error);
+      }
+    }
+  }
+
+  // The next message waiting on the socket, skipping anything that does not decode
+  fn receive<T: DeserializeOwned>(&self) -> Option<(T, SocketAddr)> {
+    let mut buffer = [0; 65536];
+    loop {
+      match self.0.recv_from(&mut buffer) {
+        Ok((length, address)) => {
+          if let Some(message) = protocol::decode(&buffer[..length]) {
+            return Some((message, address));
+          }
+        },
+        Err(error) if error.kind() == io::ErrorKind::WouldBlock => return None,
+        Err(error) => {
+          error!("Could not receive: {}", error);
+          return None;
+        }
+      }
+    }
+  }
+}
+
+// Width and height the lookup was created with
+fn world_size(spatial_index: &SpatialIndex) -> (i32, i32) {
+  let bounds = spatial_index.bounds();
+  ((bounds.right - bounds.left) as i32, (bounds.top - bounds.bottom) as i32)
+}
+
+fn cell_map<'a>(particles: impl Iterator<Item = (&'a Particle, &'a MaterialId)>) -> CellMap {
+  particles.map(|(particle, material)| (particle.position.floor().as_ivec2(), *material)).collect()
+}
@@ -0,0 +1,128 @@
+use bevy::{prelude::*, utils::HashMap};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{material::{MaterialId, MaterialRegistry}, tools::{BrushStroke, Stamp}};
+
+// Keeps every datagram comfortably below the usual UDP payload limit
+pub const MAX_CELLS_PER_PACKET: usize = 512;
+
+// A `BrushStroke` as sent over the wire
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NetStroke {
+  pub cells: Vec<(i32, i32)>,
+  pub material: Option<usize>,
+  pub stamp: Option<Stamp>,
+}
+
+impl From<&BrushStroke> for NetStroke {
+  fn from(stroke: &BrushStroke) -> Self {
+    Self {
+      cells: stroke.cells.iter().map(|cell| (cell.x, cell.y)).collect(),
+      material: stroke.material.map(|material| material.0),
+      stamp: stroke.stamp,
+    }
+  }
+}
+
+impl NetStroke {
+  // `None` when the stroke names a material this instance does not have
+  pub fn to_stroke(&self, materials: &MaterialRegistry) -> Option<BrushStroke> {
+    let material = match self.material {
+      Some(id) if id < materials.iter().count() => Some(MaterialId(id)),
+      Some(_) => return None,
+      None => None,
+    };
+    Some(BrushStroke { cells: self.cells.iter().map(|(x, y)| IVec2::new(*x, *y)).collect(), material, stamp: self.stamp })
+  }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ClientMessage {
+  // Sent on connect and periodically after as a keepalive
+  Hello,
+  Stroke(NetStroke),
+  // Lock-step only, replaces `Hello` as the keepalive once the client has started `session`
+  Ready { session: u64 },
+  // Lock-step only, the client's checksum after simulating `tick`
+  Checksum { session: u64, tick: u64, value: u64 },
+  Bye,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ServerMessage {
+  Welcome { width: i32, height: i32 },
+  // One part of a cell update. Full updates list every occupied cell and are split over
+  // `parts` packets sharing a `sequence`, partial updates only carry what changed.
+  Cells {
+    sequence: u64,
+    full: bool,
+    part: u16,
+    parts: u16,
+    cells: Vec<(i32, i32, u16)>,
+    cleared: Vec<(i32, i32)>,
+  },
+  // Lock-step only, every instance resets to an empty world and the same seed
+  Start { session: u64, width: i32, height: i32, seed: u64 },
+  // Lock-step only, the strokes to apply at the start of each tick. Recent ticks are repeated in
+  // every message so a lost packet does not stall the client.
+  Inputs { session: u64, ticks: Vec<(u64, Vec<NetStroke>)> },
+}
+
+pub fn encode(message: &impl Serialize) -> Option<Vec<u8>> {
+  match bincode::serialize(message) {
+    Ok(bytes) => Some(bytes),
+    Err(error) => {
+      error!("Could not encode message: {}", error);
+      None
+    }
+  }
+}
+
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Option<T> {
+  bincode::deserialize(bytes).ok()
+}
+
+// The material in every occupied cell, what gets mirrored to clients
+pub type CellMap = HashMap<IVec2, MaterialId>;
+// A cell and the material in it, as sent to clients
+pub type CellUpdate = (i32, i32, u16);
+
+// Cells that differ between `previous` and `current`, as (changed, cleared)
+pub fn diff(previous: &CellMap, current: &CellMap) -> (Vec<CellUpdate>, Vec<(i32, i32)>) {
+  let mut changed: Vec<CellUpdate> = current
+    .iter()
+    .filter(|(cell, material)| previous.get(cell) != Some(material))
+    .map(|(cell, material)| (cell.x, cell.y, material.0 as u16))
+    .collect();
+  let mut cleared: Vec<(i32, i32)> = previous
+    .keys()
+    .filter(|cell| !current.contains_key(cell))
+    .map(|cell| (cell.x, cell.y))
+    .collect();
+  changed.sort_unstable();
+  cleared.sort_unstable();
+  (changed, cleared)
+}
+
+// Splits an update into packets of at most `MAX_CELLS_PER_PACKET` entries each
+pub fn split_cells(
+  sequence: u64,
+  full: bool,
+  cells: Vec<(i32, i32, u16)>,
+  cleared: Vec<(i32, i32)>,
+) -> Vec<ServerMessage> {
+  let cell_parts: Vec<_> = cells.chunks(MAX_CELLS_PER_PACKET).map(<[_]>::to_vec).collect();
+  let cleared_parts: Vec<_> = cleared.chunks(MAX_CELLS_PER_PACKET).map(<[_]>::to_vec).collect();
+  let parts = cell_parts.len().max(cleared_parts.len()).max(1);
+
+  (0..parts)
+    .map(|part| ServerMessage::Cells {
+      sequence,
+      full,
+      part: part as u16,
+      parts: parts as u16,
+      cells: cell_parts.get(part).cloned().unwrap_or_default(),
+      cleared: cleared_parts.get(part).cloned().unwrap_or_default(),
+    })
+    .collect()
+}
@@ -0,0 +1,135 @@
+use std::{io, net::SocketAddr};
+
+use bevy::{prelude::*, utils::{Duration, HashMap, HashSet, Instant}};
+
+use crate::{
+  inventory::Inventory,
+  material::{MaterialId, MaterialRegistry},
+  tools::apply_stroke,
+  Particle, SpatialIndex,
+};
+
+use super::{cell_map, protocol::{self, CellMap, ClientMessage, ServerMessage}, world_size, Socket, CLIENT_TIMEOUT};
+
+// Every client periodically gets the whole world to recover from lost packets
+const KEYFRAME_INTERVAL: Duration = Duration::from_secs(2);
+
+pub struct Server {
+  socket: Socket,
+  clients: HashMap<SocketAddr, Instant>,
+  // Clients that need the world size and a full update before any partial ones
+  joining: HashSet<SocketAddr>,
+  // What every client has been told so far
+  sent: CellMap,
+  world_size: (i32, i32),
+  sequence: u64,
+  last_keyframe: Instant,
+}
+
+impl Server {
+  pub fn bind(address: SocketAddr) -> io::Result<Self> {
+    Ok(Self {
+      socket: Socket::bind(address)?,
+      clients: HashMap::new(),
+      joining: HashSet::default(),
+      sent: CellMap::default(),
+      world_size: (0, 0),
+      sequence: 0,
+      last_keyframe: Instant::now(),
+    })
+  }
+
+  fn send_cells(&mut self, addresses: &[SocketAddr], full: bool, cells: Vec<(i32, i32, u16)>, cleared: Vec<(i32, i32)>) {
+    self.sequence += 1;
+    for message in protocol::split_cells(self.sequence, full, cells, cleared) {
+      for address in addresses {
+        self.socket.send(*address, &message);
+      }
+    }
+  }
+}
+
+pub(super) fn receive_messages(
+  mut commands: Commands,
+  mut server: ResMut<Server>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  materials: Res<MaterialRegistry>,
+  mut inventory: ResMut<Inventory>,
+  particles: Query<&Particle>,
+) {
+  while let Some((message, address)) = server.socket.receive::<ClientMessage>() {
+    match message {
+      ClientMessage::Hello => {
+        if server.clients.insert(address, Instant::now()).is_none() {
+          info!("Client {} joined", address);
+          server.joining.insert(address);
+        }
+      },
+      ClientMessage::Stroke(stroke) => {
+        if !server.clients.contains_key(&address) { continue }
+        if let Some(stroke) = stroke.to_stroke(&materials) {
+          apply_stroke(&stroke, &mut commands, &mut spatial_index, &materials, &mut inventory, &particles);
+        }
+      },
+      ClientMessage::Ready { .. } | ClientMessage::Checksum { .. } => {},
+      ClientMessage::Bye => {
+        if server.clients.remove(&address).is_some() {
+          info!("Client {} left", address);
+        }
+      },
+    }
+  }
+}
+
+pub(super) fn broadcast_cells(
+  mut server: ResMut<Server>,
+  spatial_index: Res<SpatialIndex>,
+  particles: Query<(&Particle, &MaterialId)>,
+) {
+  let now = Instant::now();
+  server.clients.retain(|address, last_seen| {
+    let alive = now.duration_since(*last_seen) < CLIENT_TIMEOUT;
+    if !alive {
+      info!("Client {} timed out", address);
+    }
+    alive
+  });
+  if server.clients.is_empty() { return }
+
+  // Loading a scenario can resize the world, which every client has to follow
+  let size = world_size(&spatial_index);
+  if size != server.world_size {
+    server.world_size = size;
+    let clients: Vec<SocketAddr> = server.clients.keys().copied().collect();
+    server.joining.extend(clients);
+  }
+
+  let current = cell_map(particles.iter());
+  let keyframe = now.duration_since(server.last_keyframe) >= KEYFRAME_INTERVAL;
+  let (full, partial): (Vec<SocketAddr>, Vec<SocketAddr>) = server
+    .clients
+    .keys()
+    .partition(|address| keyframe || server.joining.contains(*address));
+
+  let (width, height) = size;
+  for address in server.joining.iter().filter(|address| full.contains(address)) {
+    server.socket.send(*address, &ServerMessage::Welcome { width, height });
+  }
+
+  if !full.is_empty() {
+    let (cells, _) = protocol::diff(&CellMap::default(), &current);
+    server.send_cells(&full, true, cells, Vec::new());
+  }
+  if !partial.is_empty() {
+    let (cells, cleared) = protocol::diff(&server.sent, &current);
+    if !cells.is_empty() || !cleared.is_empty() {
+      server.send_cells(&partial, false, cells, cleared);
+    }
+  }
+
+  if keyframe {
+    server.last_keyframe = now;
+  }
+  server.joining.clear();
+  server.sent = current;
+}
@@ -0,0 +1,221 @@
+use bevy::{prelude::*, utils::HashSet};
+use serde::Deserialize;
+
+use crate::{
+  material::{MaterialId, MaterialRegistry},
+  scenario::LoadScenario,
+  sensors::{Sensor, SensorEvent},
+  simulation::{SimulationSettings, SimulationStep},
+  Particle,
+};
+
+// Goals a scenario sets for the player, which turn the sandbox into a game. Each completed one adds
+// its points to the score.
+pub struct ObjectivesPlugin;
+
+impl Plugin for ObjectivesPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<Objectives>()
+      .add_event::<ObjectiveEvent>()
+      .add_system(load_objectives)
+      .add_system_set(SystemSet::new()
+        .with_run_criteria("fixed_tick")
+        .with_system(track_objectives.label("track_objectives").after(SimulationStep::Commit))
+        .with_system(keep_score.after("track_objectives"))
+      );
+  }
+}
+
+fn default_points() -> u32 {
+  100
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub enum Goal {
+  // `count` particles of `material` inside the rectangle with corners `from` and `to` at once. Only
+  // particles moving in count, not ones spawned there.
+  Collect { material: String, from: (i32, i32), to: (i32, i32), count: u32 },
+  // Some `material` left in the world for `seconds` of simulated time. Fails if all of it is gone
+  // once there has been any.
+  Survive { material: String, seconds: f32 },
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Objective {
+  // What the HUD shows for it
+  pub description: String,
+  pub goal: Goal,
+  #[serde(default = "default_points")]
+  pub points: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ObjectiveStatus {
+  InProgress,
+  Completed,
+  Failed,
+}
+
+// Sent once as an objective is decided. `index` is its position in the scenario's list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ObjectiveEvent {
+  Completed { index: usize, points: u32 },
+  Failed { index: usize },
+}
+
+struct Tracker {
+  objective: Objective,
+  // `None` when the scenario names a material that is not registered, which never progresses
+  material: Option<MaterialId>,
+  sensor: Option<Entity>,
+  // Particles that moved into the sensor and have not left, whatever they are made of now
+  inside: HashSet<Entity>,
+  // Seconds `Goal::Survive` has lasted
+  elapsed: f32,
+  seen: bool,
+  status: ObjectiveStatus,
+}
+
+impl Tracker {
+  // How far along the goal is, in particles or seconds
+  fn progress(&self, materials: &Query<&MaterialId, With<Particle>>) -> f32 {
+    match self.objective.goal {
+      Goal::Collect { .. } => self.inside.iter().filter(|entity| self.is_target(materials, **entity)).count() as f32,
+      Goal::Survive { .. } => self.elapsed,
+    }
+  }
+
+  fn is_target(&self, materials: &Query<&MaterialId, With<Particle>>, entity: Entity) -> bool {
+    materials.get(entity).ok().copied() == self.material
+  }
+}
+
+// The loaded scenario's objectives and the score made on them so far
+#[derive(Default)]
+pub struct Objectives {
+  pub score: u32,
+  trackers: Vec<Tracker>,
+  // Progress as of the last tick, for the HUD
+  progress: Vec<f32>,
+}
+
+impl Objectives {
+  pub fn is_empty(&self) -> bool {
+    self.trackers.is_empty()
+  }
+
+  // Every objective with its status and progress, in the scenario's order
+  pub fn iter(&self) -> impl Iterator<Item = (&Objective, ObjectiveStatus, f32)> {
+    self.trackers.iter().zip(self.progress.iter()).map(|(tracker, progress)| (&tracker.objective, tracker.status, *progress))
+  }
+}
+
+fn load_objectives(
+  mut commands: Commands,
+  mut events: EventReader<LoadScenario>,
+  materials: Res<MaterialRegistry>,
+  mut objectives: ResMut<Objectives>,
+) {
+  let LoadScenario(scenario) = match events.iter().last() {
+    Some(event) => event,
+    None => return,
+  };
+  for sensor in objectives.trackers.iter().filter_map(|tracker| tracker.sensor) {
+    commands.entity(sensor).despawn();
+  }
+
+  let trackers = scenario
+    .objectives
+    .iter()
+    .map(|objective| {
+      let (material, sensor) = match &objective.goal {
+        Goal::Collect { material, from, to, .. } => {
+          let sensor = Sensor::new(IVec2::new(from.0, from.1), IVec2::new(to.0, to.1));
+          (material, Some(commands.spawn().insert(sensor).id()))
+        },
+        Goal::Survive { material, .. } => (material, None),
+      };
+      let material = materials.find(material);
+      if material.is_none() {
+        warn!("Unknown material in objective {}", objective.description);
+      }
+      Tracker {
+        objective: objective.clone(),
+        material,
+        sensor,
+        inside: HashSet::default(),
+        elapsed: 0.,
+        seen: false,
+        status: ObjectiveStatus::InProgress,
+      }
+    })
+    .collect::<Vec<_>>();
+  *objectives = Objectives { score: 0, progress: vec![0.; trackers.len()], trackers };
+}
+
+fn track_objectives(
+  settings: Res<SimulationSettings>,
+  mut objectives: ResMut<Objectives>,
+  mut sensor_events: EventReader<SensorEvent>,
+  mut objective_events: EventWriter<ObjectiveEvent>,
+  materials: Query<&MaterialId, With<Particle>>,
+) {
+  let sensor_events = sensor_events.iter().collect::<Vec<_>>();
+  if objectives.is_empty() { return }
+  let objectives = &mut *objectives;
+
+  for tracker in objectives.trackers.iter_mut() {
+    for event in sensor_events.iter() {
+      match **event {
+        SensorEvent::Enter { sensor, particle } if Some(sensor) == tracker.sensor => { tracker.inside.insert(particle); },
+        SensorEvent::Exit { sensor, particle } if Some(sensor) == tracker.sensor => { tracker.inside.remove(&particle); },
+        _ => {},
+      }
+    }
+    // Particles despawned inside leave no event behind
+    tracker.inside.retain(|entity| materials.contains(*entity));
+  }
+
+  for (index, tracker) in objectives.trackers.iter_mut().enumerate() {
+    if let (Goal::Survive { .. }, ObjectiveStatus::InProgress) = (&tracker.objective.goal, tracker.status) {
+      let material = tracker.material;
+      if materials.iter().any(|id| Some(*id) == material) {
+        tracker.seen = true;
+        tracker.elapsed += settings.tick_delta();
+      } else if tracker.seen {
+        tracker.status = ObjectiveStatus::Failed;
+        objective_events.send(ObjectiveEvent::Failed { index });
+      }
+    }
+
+    let progress = tracker.progress(&materials);
+    objectives.progress[index] = progress;
+    if tracker.status != ObjectiveStatus::InProgress { continue }
+    let target = match tracker.objective.goal {
+      Goal::Collect { count, .. } => count as f32,
+      Goal::Survive { seconds, .. } => seconds,
+    };
+    if progress >= target {
+      tracker.status = ObjectiveStatus::Completed;
+      objective_events.send(ObjectiveEvent::Completed { index, points: tracker.objective.points });
+    }
+  }
+}
+
+// Adds up the points of completed objectives, in the tick they are decided so a scenario loaded
+// later starts from zero
+fn keep_score(mut objectives: ResMut<Objectives>, mut objective_events: EventReader<ObjectiveEvent>) {
+  for event in objective_events.iter() {
+    let (index, points) = match *event {
+      ObjectiveEvent::Completed { index, points } => (index, Some(points)),
+      ObjectiveEvent::Failed { index } => (index, None),
+    };
+    let description = objectives.trackers.get(index).map_or("", |tracker| tracker.objective.description.as_str());
+    match points {
+      Some(points) => info!("Completed \"{}\" for {} points", description, points),
+      None => info!("Failed \"{}\"", description),
+    }
+    objectives.score += points.unwrap_or(0);
+  }
+}
@@ -0,0 +1,374 @@
+use std::{fmt, fs, path::Path, sync::Arc};
+
+use bevy::{prelude::*, utils::HashMap};
+use serde::Deserialize;
+use wasmtime::{Caller, Config, Engine, Extern, Instance, Linker, Module, Store, TypedFunc};
+
+use crate::{
+  despawn_particle,
+  material::{MaterialDef, MaterialId, MaterialRegistry},
+  simulation::{SimulationStep, TickProgress},
+  spawn_particle, BoundsExt, Collision, Contacts, Particle, SpatialIndex, Static,
+};
+
+// Material packs are WebAssembly modules loaded from `plugins/` at startup. ABI version 1:
+//
+// Exports
+//   memory
+//   arrakoids_abi_version() -> i32, must return ABI_VERSION
+//   arrakoids_manifest() -> i64, pointer in the low and length in the high 32 bits of a UTF-8
+//     RON `PackManifest` in memory
+//   arrakoids_move(material, x, y, vx: f32, vy: f32), optional, called every tick for each
+//     particle of the pack's materials. `material` is the index in the manifest.
+//   arrakoids_react(material, x, y, other, other_x, other_y), optional, called when a particle of
+//     the pack's materials collides. `other` is a material id, or -1 for the world bounds and
+//     static cells.
+//
+// Imports, module "arrakoids"
+//   material_at(x, y) -> i32, material id of the cell, -1 when empty, -2 outside the world
+//   material_id(name_ptr, name_len) -> i32, material id by name, -1 when unknown
+//   pack_material(index) -> i32, material id of the pack's `index`th material
+//   spawn(x, y, material), despawn(x, y), set_velocity(x, y, vx: f32, vy: f32), in cells per second
+//
+// Reads see the world as of the start of the callbacks, writes are applied once every pack ran.
+pub const ABI_VERSION: i32 = 1;
+const PLUGINS_DIR: &str = "plugins";
+// Instructions a pack may run per tick before it is cut off for that tick
+const FUEL_PER_TICK: u64 = 10_000_000;
+
+pub struct MaterialPacksPlugin;
+
+impl Plugin for MaterialPacksPlugin {
+  fn build(&self, app: &mut App) {
+    let mut packs = MaterialPacks::default();
+    let mut materials = app.world.get_resource_or_insert_with(MaterialRegistry::default);
+    if let Ok(entries) = fs::read_dir(PLUGINS_DIR) {
+      let mut paths: Vec<_> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "wasm"))
+        .collect();
+      // Material ids depend on load order, keep it stable between runs and instances
+      paths.sort();
+      for path in paths {
+        match Pack::load(&packs.engine, &path, &mut materials) {
+          Ok(pack) => {
+            info!("Loaded material pack {} from {}", pack.name, path.display());
+            packs.packs.push(pack);
+          },
+          Err(error) => error!("Could not load {}: {}", path.display(), error),
+        }
+      }
+    }
+
+    app
+      .insert_resource(packs)
+      .add_system_set(SystemSet::new()
+        .with_run_criteria("fixed_tick")
+        .with_system(run_packs.after(SimulationStep::Commit))
+      );
+  }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PackMaterial {
+  pub name: String,
+  pub color: (f32, f32, f32),
+  pub mass: f32,
+  pub elasticity: f32,
+  #[serde(default)]
+  pub fixed: bool,
+  #[serde(default)]
+  pub liquid: bool,
+  #[serde(default)]
+  pub rigid: bool,
+  #[serde(default)]
+  pub trail: bool,
+  #[serde(default)]
+  pub emissive: f32,
+  #[serde(default = "default_update_interval")]
+  pub update_interval: u32,
+}
+
+fn default_update_interval() -> u32 {
+  1
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PackManifest {
+  pub name: String,
+  pub materials: Vec<PackMaterial>,
+}
+
+#[derive(Debug)]
+pub enum PackError {
+  Wasm(wasmtime::Error),
+  Version(i32),
+  Manifest(ron::Error),
+  InvalidManifest,
+}
+
+impl fmt::Display for PackError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      PackError::Wasm(error) => write!(f, "invalid module: {}", error),
+      PackError::Version(version) => write!(f, "unsupported ABI version {}, expected {}", version, ABI_VERSION),
+      PackError::Manifest(error) => write!(f, "could not parse manifest: {}", error),
+      PackError::InvalidManifest => write!(f, "manifest is outside the module's memory"),
+    }
+  }
+}
+
+impl From<wasmtime::Error> for PackError {
+  fn from(error: wasmtime::Error) -> Self {
+    PackError::Wasm(error)
+  }
+}
+
+enum PackAction {
+  Spawn(IVec2, MaterialId),
+  Despawn(IVec2),
+  SetVelocity(IVec2, Vec2),
+}
+
+// The world as seen by the packs during one tick
+#[derive(Default)]
+struct WorldView {
+  bounds: Option<Rect<f32>>,
+  cells: HashMap<IVec2, MaterialId>,
+  names: Vec<String>,
+}
+
+#[derive(Default)]
+struct PackState {
+  world: Arc<WorldView>,
+  materials: Vec<MaterialId>,
+  actions: Vec<PackAction>,
+}
+
+impl PackState {
+  fn material_at(&self, cell: IVec2) -> i32 {
+    if self.world.bounds.is_some_and(|bounds| bounds.outside(cell.as_vec2()).is_some()) {
+      return -2;
+    }
+    self.world.cells.get(&cell).map_or(-1, |material| material.0 as i32)
+  }
+
+  fn material(&self, id: i32) -> Option<MaterialId> {
+    (id >= 0 && (id as usize) < self.world.names.len()).then_some(MaterialId(id as usize))
+  }
+}
+
+// Called with the particle and its cell, then its velocity or the other particle and its cell
+type MoveHook = TypedFunc<(i32, i32, i32, f32, f32), ()>;
+type ReactHook = TypedFunc<(i32, i32, i32, i32, i32, i32), ()>;
+
+struct Pack {
+  name: String,
+  store: Store<PackState>,
+  on_move: Option<MoveHook>,
+  on_react: Option<ReactHook>,
+}
+
+impl Pack {
+  fn load(engine: &Engine, path: &Path, materials: &mut MaterialRegistry) -> Result<Self, PackError> {
+    let module = Module::from_file(engine, path)?;
+    let mut store = Store::new(engine, PackState::default());
+    store.set_fuel(FUEL_PER_TICK)?;
+    let instance = create_linker(engine)?.instantiate(&mut store, &module)?;
+
+    let version = instance.get_typed_func::<(), i32>(&mut store, "arrakoids_abi_version")?.call(&mut store, ())?;
+    if version != ABI_VERSION {
+      return Err(PackError::Version(version));
+    }
+    let manifest = read_manifest(&instance, &mut store)?;
+
+    store.data_mut().materials = manifest.materials
+      .iter()
+      .map(|material| {
+        let (r, g, b) = material.color;
+        let mut def = MaterialDef::new(&material.name, Color::rgb(r, g, b), material.mass, material.elasticity);
+        def.fixed = material.fixed;
+        def.liquid = material.liquid;
+        def.rigid = material.rigid;
+        def.trail = material.trail;
+        def.emissive = material.emissive;
+        materials.register(def.update_interval(material.update_interval))
+      })
+      .collect();
+
+    Ok(Self {
+      name: manifest.name,
+      on_move: instance.get_typed_func(&mut store, "arrakoids_move").ok(),
+      on_react: instance.get_typed_func(&mut store, "arrakoids_react").ok(),
+      store,
+    })
+  }
+
+  fn local_material(&self, material: MaterialId) -> Option<i32> {
+    self.store.data().materials.iter().position(|id| *id == material).map(|index| index as i32)
+  }
+}
+
+fn read_manifest(instance: &Instance, store: &mut Store<PackState>) -> Result<PackManifest, PackError> {
+  let packed = instance.get_typed_func::<(), i64>(&mut *store, "arrakoids_manifest")?.call(&mut *store, ())?;
+  let (pointer, length) = ((packed as u64 & 0xffff_ffff) as usize, (packed as u64 >> 32) as usize);
+  let memory = instance.get_memory(&mut *store, "memory").ok_or(PackError::InvalidManifest)?;
+  let bytes = memory.data(&*store).get(pointer..pointer + length).ok_or(PackError::InvalidManifest)?;
+  let manifest = std::str::from_utf8(bytes).map_err(|_| PackError::InvalidManifest)?;
+  ron::from_str(manifest).map_err(PackError::Manifest)
+}
+
+fn create_linker(engine: &Engine) -> wasmtime::Result<Linker<PackState>> {
+  let mut linker = Linker::new(engine);
+  linker
+    .func_wrap("arrakoids", "material_at", |caller: Caller<PackState>, x: i32, y: i32| {
+      caller.data().material_at(IVec2::new(x, y))
+    })?
+    .func_wrap("arrakoids", "material_id", |mut caller: Caller<PackState>, pointer: i32, length: i32| {
+      let memory = match caller.get_export("memory") {
+        Some(Extern::Memory(memory)) => memory,
+        _ => return -1,
+      };
+      let (pointer, length) = (pointer as u32 as usize, length as u32 as usize);
+      let name = memory.data(&caller).get(pointer..pointer + length).and_then(|bytes| std::str::from_utf8(bytes).ok());
+      name
+        .and_then(|name| caller.data().world.names.iter().position(|other| other.eq_ignore_ascii_case(name)))
+        .map_or(-1, |index| index as i32)
+    })?
+    .func_wrap("arrakoids", "pack_material", |caller: Caller<PackState>, index: i32| {
+      caller.data().materials.get(index as usize).map_or(-1, |material| material.0 as i32)
+    })?
+    .func_wrap("arrakoids", "spawn", |mut caller: Caller<PackState>, x: i32, y: i32, material: i32| {
+      if let Some(material) = caller.data().material(material) {
+        caller.data_mut().actions.push(PackAction::Spawn(IVec2::new(x, y), material));
+      }
+    })?
+    .func_wrap("arrakoids", "despawn", |mut caller: Caller<PackState>, x: i32, y: i32| {
+      caller.data_mut().actions.push(PackAction::Despawn(IVec2::new(x, y)));
+    })?
+    .func_wrap("arrakoids", "set_velocity", |mut caller: Caller<PackState>, x: i32, y: i32, vx: f32, vy: f32| {
+      let velocity = Vec2::new(vx, vy);
+      if velocity.is_finite() {
+        caller.data_mut().actions.push(PackAction::SetVelocity(IVec2::new(x, y), velocity));
+      }
+    })?;
+  Ok(linker)
+}
+
+pub struct MaterialPacks {
+  engine: Engine,
+  packs: Vec<Pack>,
+}
+
+impl Default for MaterialPacks {
+  fn default() -> Self {
+    let mut config = Config::new();
+    config.consume_fuel(true);
+    Self { engine: Engine::new(&config).expect("default wasm engine config is valid"), packs: Vec::new() }
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_packs(
+  mut commands: Commands,
+  mut packs: ResMut<MaterialPacks>,
+  materials: Res<MaterialRegistry>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  progress: Res<TickProgress>,
+  contacts: Res<Contacts>,
+  mut particles: Query<(&mut Particle, &MaterialId)>,
+  statics: Query<(), With<Static>>,
+) {
+  if packs.packs.is_empty() { return }
+
+  let mut cells: Vec<(IVec2, Entity, MaterialId)> = spatial_index
+    .iter()
+    .filter_map(|(cell, entity)| Some((*cell, *entity, *particles.get(*entity).ok()?.1)))
+    .collect();
+  cells.sort_unstable_by_key(|(cell, _, _)| (cell.y, cell.x));
+  let world = Arc::new(WorldView {
+    bounds: (!spatial_index.infinite).then_some(spatial_index.bounds()),
+    cells: cells.iter().map(|(cell, _, material)| (*cell, *material)).collect(),
+    names: materials.iter().map(|(_, material)| material.name.clone()).collect(),
+  });
+  // A budgeted tick gets here every frame, its collisions are only passed on once it is done
+  let collisions: Vec<(Entity, Option<Entity>)> =
+    if progress.is_complete() { contacts.iter().map(Collision::entities).collect() } else { Vec::new() };
+
+  let mut actions = Vec::new();
+  for pack in packs.packs.iter_mut() {
+    pack.store.data_mut().world = world.clone();
+    if let Err(error) = pack.store.set_fuel(FUEL_PER_TICK).and_then(|_| run_pack(pack, &cells, &collisions, &particles)) {
+      warn!("Material pack {} failed this tick: {}", pack.name, error);
+    }
+    actions.append(&mut pack.store.data_mut().actions);
+  }
+
+  for action in actions {
+    match action {
+      PackAction::Spawn(cell, material) => {
+        if spatial_index.is_free(cell) {
+          spawn_particle(&mut commands, &mut spatial_index, &materials, cell, material);
+        }
+      },
+      PackAction::Despawn(cell) => {
+        let entity = match spatial_index.get(&cell) {
+          Some(entity) => *entity,
+          None => continue,
+        };
+        if let Ok((particle, _)) = particles.get(entity) {
+          despawn_particle(&mut commands, &mut spatial_index, entity, particle);
+        }
+      },
+      PackAction::SetVelocity(cell, velocity) => {
+        let entity = match spatial_index.get(&cell) {
+          Some(entity) if !statics.contains(*entity) => *entity,
+          _ => continue,
+        };
+        if let Ok((mut particle, _)) = particles.get_mut(entity) {
+          particle.velocity = velocity;
+          spatial_index.wake(cell);
+        }
+      },
+    }
+  }
+}
+
+fn run_pack(
+  pack: &mut Pack,
+  cells: &[(IVec2, Entity, MaterialId)],
+  collisions: &[(Entity, Option<Entity>)],
+  particles: &Query<(&mut Particle, &MaterialId)>,
+) -> wasmtime::Result<()> {
+  if let Some(on_move) = pack.on_move.clone() {
+    for (cell, entity, material) in cells {
+      let local = match pack.local_material(*material) {
+        Some(local) => local,
+        None => continue,
+      };
+      let velocity = particles.get(*entity).map_or(Vec2::ZERO, |(particle, _)| particle.velocity);
+      on_move.call(&mut pack.store, (local, cell.x, cell.y, velocity.x, velocity.y))?;
+    }
+  }
+
+  if let Some(on_react) = pack.on_react.clone() {
+    for (a, b) in collisions {
+      let (particle, material) = match particles.get(*a) {
+        Ok(particle) => particle,
+        Err(_) => continue,
+      };
+      let local = match pack.local_material(*material) {
+        Some(local) => local,
+        None => continue,
+      };
+      let cell = particle.position.floor().as_ivec2();
+      let (other, other_cell) = match b.and_then(|b| particles.get(b).ok()) {
+        Some((other, material)) => (material.0 as i32, other.position.floor().as_ivec2()),
+        None => (-1, cell),
+      };
+      on_react.call(&mut pack.store, (local, cell.x, cell.y, other, other_cell.x, other_cell.y))?;
+    }
+  }
+  Ok(())
+}
@@ -0,0 +1,260 @@
+use bevy::prelude::*;
+
+use crate::{
+  accessibility::display_color,
+  actions::Action,
+  config::Config,
+  locale::Locale,
+  material::{MaterialId, MaterialRegistry},
+  renderer::RenderSettings,
+  tools::Brush,
+  AppState,
+};
+
+const BUTTON_COLOR: Color = Color::rgb(0.15, 0.15, 0.15);
+const SELECTED_COLOR: Color = Color::rgb(0.35, 0.35, 0.35);
+const MAX_BRUSH_SIZE: i32 = 8;
+
+pub struct PalettePlugin;
+
+impl Plugin for PalettePlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .add_system_set(SystemSet::on_enter(AppState::Running).with_system(spawn_palette))
+      .add_system_set(SystemSet::on_exit(AppState::Running).with_system(despawn_palette))
+      .add_system_set(SystemSet::on_update(AppState::Running)
+        .with_system(select_material_button.label("select_material"))
+        .with_system(select_material_key.label("select_material"))
+        .with_system(cycle_material.label("select_material"))
+        .with_system(drag_size_slider.label("select_material"))
+        .with_system(update_palette.after("select_material"))
+        .with_system(update_swatches)
+      );
+  }
+}
+
+#[derive(Component)]
+struct PaletteRoot;
+
+#[derive(Component)]
+struct MaterialButton(MaterialId);
+
+// The square showing a material's color in the palette's colors
+#[derive(Component)]
+struct MaterialSwatch(MaterialId);
+
+#[derive(Component)]
+struct MaterialName(MaterialId);
+
+#[derive(Component)]
+struct SizeSlider;
+
+#[derive(Component)]
+struct SizeSliderFill;
+
+#[derive(Component)]
+struct SizeLabel;
+
+fn spawn_palette(
+  mut commands: Commands,
+  asset_server: Res<AssetServer>,
+  materials: Res<MaterialRegistry>,
+  locale: Res<Locale>,
+  brush: Res<Brush>,
+) {
+  let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+  let text_style = TextStyle { font, font_size: 18., color: Color::WHITE };
+
+  commands
+    .spawn_bundle(NodeBundle {
+      style: Style {
+        size: Size::new(Val::Percent(100.), Val::Px(48.)),
+        position_type: PositionType::Absolute,
+        position: Rect { left: Val::Px(0.), bottom: Val::Px(0.), ..Default::default() },
+        align_items: AlignItems::Center,
+        padding: Rect::all(Val::Px(4.)),
+        ..Default::default()
+      },
+      color: Color::rgba(0., 0., 0., 0.7).into(),
+      ..Default::default()
+    })
+    .insert(PaletteRoot)
+    // Lets the cursor tell the toolbar background apart from the world
+    .insert(Interaction::default())
+    .with_children(|parent| {
+      for (id, material) in materials.iter() {
+        parent
+          .spawn_bundle(ButtonBundle {
+            style: Style {
+              size: Size::new(Val::Auto, Val::Px(36.)),
+              margin: Rect::all(Val::Px(2.)),
+              padding: Rect::all(Val::Px(6.)),
+              align_items: AlignItems::Center,
+              ..Default::default()
+            },
+            color: BUTTON_COLOR.into(),
+            ..Default::default()
+          })
+          .insert(MaterialButton(id))
+          .with_children(|parent| {
+            parent.spawn_bundle(NodeBundle {
+              style: Style {
+                size: Size::new(Val::Px(16.), Val::Px(16.)),
+                margin: Rect { right: Val::Px(6.), ..Default::default() },
+                ..Default::default()
+              },
+              color: material.color.into(),
+              ..Default::default()
+            })
+            .insert(MaterialSwatch(id));
+            parent
+              .spawn_bundle(TextBundle {
+                text: Text::with_section(locale.material(&material.name), text_style.clone(), Default::default()),
+                ..Default::default()
+              })
+              .insert(MaterialName(id));
+          });
+      }
+
+      parent
+        .spawn_bundle(TextBundle {
+          style: Style { margin: Rect { left: Val::Px(16.), right: Val::Px(8.), ..Default::default() }, ..Default::default() },
+          text: Text::with_section(locale.format("palette.size", &[("size", &brush.size)]), text_style.clone(), Default::default()),
+          ..Default::default()
+        })
+        .insert(SizeLabel);
+
+      parent
+        .spawn_bundle(ButtonBundle {
+          style: Style { size: Size::new(Val::Px(160.), Val::Px(12.)), ..Default::default() },
+          color: BUTTON_COLOR.into(),
+          ..Default::default()
+        })
+        .insert(SizeSlider)
+        .with_children(|parent| {
+          parent
+            .spawn_bundle(NodeBundle {
+              style: Style {
+                size: Size::new(Val::Percent(slider_fraction(brush.size) * 100.), Val::Percent(100.)),
+                ..Default::default()
+              },
+              color: Color::rgb(0.6, 0.6, 0.6).into(),
+              ..Default::default()
+            })
+            .insert(SizeSliderFill);
+        });
+    });
+}
+
+fn despawn_palette(mut commands: Commands, roots: Query<Entity, With<PaletteRoot>>) {
+  for entity in roots.iter() {
+    commands.entity(entity).despawn_recursive();
+  }
+}
+
+fn slider_fraction(size: i32) -> f32 {
+  size as f32 / MAX_BRUSH_SIZE as f32
+}
+
+fn select_material_button(
+  mut brush: ResMut<Brush>,
+  buttons: Query<(&Interaction, &MaterialButton), Changed<Interaction>>,
+) {
+  for (interaction, button) in buttons.iter() {
+    if *interaction == Interaction::Clicked {
+      brush.material = button.0;
+    }
+  }
+}
+
+fn select_material_key(
+  config: Res<Config>,
+  keys: Res<Input<KeyCode>>,
+  materials: Res<MaterialRegistry>,
+  mut brush: ResMut<Brush>,
+) {
+  for (index, key) in config.material_keys.iter().enumerate() {
+    if keys.just_pressed(*key) {
+      if let Some((id, _)) = materials.iter().nth(index) {
+        brush.material = id;
+      }
+    }
+  }
+}
+
+// Wraps around at either end of the registry
+fn cycle_material(actions: Res<Input<Action>>, materials: Res<MaterialRegistry>, mut brush: ResMut<Brush>) {
+  let step = match (actions.just_pressed(Action::NextMaterial), actions.just_pressed(Action::PreviousMaterial)) {
+    (true, false) => 1,
+    (false, true) => -1,
+    _ => return,
+  };
+  let count = materials.iter().count() as i32;
+  brush.material = MaterialId((brush.material.0 as i32 + step).rem_euclid(count) as usize);
+}
+
+fn drag_size_slider(
+  windows: Res<Windows>,
+  mut brush: ResMut<Brush>,
+  sliders: Query<(&Interaction, &Node, &GlobalTransform), With<SizeSlider>>,
+) {
+  let cursor = match windows.get_primary().and_then(|window| window.cursor_position()) {
+    Some(cursor) => cursor,
+    None => return,
+  };
+
+  for (interaction, node, transform) in sliders.iter() {
+    if *interaction != Interaction::Clicked { continue }
+    let left = transform.translation.x - node.size.x / 2.;
+    let fraction = ((cursor.x - left) / node.size.x).clamp(0., 1.);
+    let size = (fraction * MAX_BRUSH_SIZE as f32).round() as i32;
+    if size != brush.size {
+      brush.size = size;
+    }
+  }
+}
+
+fn update_palette(
+  brush: Res<Brush>,
+  materials: Res<MaterialRegistry>,
+  locale: Res<Locale>,
+  mut buttons: Query<(&MaterialButton, &mut UiColor, &Interaction)>,
+  mut fills: Query<&mut Style, With<SizeSliderFill>>,
+  mut labels: Query<&mut Text, With<SizeLabel>>,
+  mut names: Query<(&MaterialName, &mut Text), Without<SizeLabel>>,
+) {
+  for (button, mut color, interaction) in buttons.iter_mut() {
+    *color = if button.0 == brush.material || *interaction == Interaction::Hovered {
+      SELECTED_COLOR.into()
+    } else {
+      BUTTON_COLOR.into()
+    };
+  }
+
+  if locale.is_changed() {
+    for (name, mut text) in names.iter_mut() {
+      text.sections[0].value = locale.material(&materials.get(name.0).name).to_string();
+    }
+  }
+
+  if !brush.is_changed() && !locale.is_changed() { return }
+  for mut style in fills.iter_mut() {
+    style.size.width = Val::Percent(slider_fraction(brush.size) * 100.);
+  }
+  for mut text in labels.iter_mut() {
+    text.sections[0].value = locale.format("palette.size", &[("size", &brush.size)]);
+  }
+}
+
+fn update_swatches(
+  render_settings: Res<RenderSettings>,
+  materials: Res<MaterialRegistry>,
+  added: Query<(), Added<MaterialSwatch>>,
+  mut swatches: Query<(&MaterialSwatch, &mut UiColor)>,
+) {
+  if !render_settings.is_changed() && added.is_empty() { return }
+  for (swatch, mut color) in swatches.iter_mut() {
+    let def = materials.get(swatch.0);
+    *color = display_color(render_settings.palette, false, swatch.0, def, IVec2::ZERO, def.color).into();
+  }
+}
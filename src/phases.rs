@@ -0,0 +1,188 @@
+use bevy::{prelude::*, math::const_ivec2};
+use serde::Deserialize;
+
+use crate::{
+  config::Config,
+  erosion::convert,
+  material::{MaterialId, MaterialRegistry},
+  simulation::{SimulationClock, SimulationSettings, SimulationStep},
+  Particle, SpatialIndex, ReactionEvent, Static,
+};
+
+const NEIGHBORS: [IVec2; 4] = [const_ivec2!([-1, 0]), const_ivec2!([1, 0]), const_ivec2!([0, -1]), const_ivec2!([0, 1])];
+
+// Water freezes into ice when the ambient temperature is low enough and the ice melts back once it
+// is warm again. Ice is fixed, so a frozen surface holds up what lands on it and blocks the flow.
+// Particles the beam heats past the ambient temperature boil, melt or catch fire the same way.
+pub struct PhasesPlugin;
+
+impl Plugin for PhasesPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<PhaseTransitions>()
+      .add_startup_system(configure_phases)
+      .add_system_set(SystemSet::new()
+        .with_run_criteria("fixed_tick")
+        .with_system(change_phase.after(SimulationStep::Commit))
+      );
+  }
+}
+
+// Temperatures in degrees, replaced by the `[phases]` table of the config. Between `freezing`
+// and `melting` neither happens, so a temperature right at the threshold does not flip back and
+// forth.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct PhaseTransitions {
+  // Water freezes below this
+  pub freezing: f32,
+  // Ice melts above this
+  pub melting: f32,
+  // Water turns to steam above this
+  pub boiling: f32,
+  // Wood and oil catch fire above this
+  pub ignition: f32,
+  // Stone melts into lava above this
+  pub fusion: f32,
+  // Degree ticks of heat a particle has to lose to freeze or gain to melt. A pond 10 degrees
+  // below freezing has its surface frozen after `latent_heat / 10` ticks.
+  pub latent_heat: f32,
+  // Share of its `Heat` a particle loses every second
+  pub cooling: f32,
+}
+
+impl Default for PhaseTransitions {
+  fn default() -> Self {
+    Self { freezing: 0., melting: 2., boiling: 100., ignition: 250., fusion: 1200., latent_heat: 100., cooling: 0.5 }
+  }
+}
+
+// A temperature and what a material turns into past it
+type Change = Option<(f32, MaterialId)>;
+
+impl PhaseTransitions {
+  // What a material turns into below a temperature and above one, if anything
+  fn changes(&self, material: MaterialId) -> (Change, Change) {
+    match material {
+      MaterialId::WATER => (Some((self.freezing, MaterialId::ICE)), Some((self.boiling, MaterialId::STEAM))),
+      MaterialId::ICE => (None, Some((self.melting, MaterialId::WATER))),
+      MaterialId::WOOD | MaterialId::OIL => (None, Some((self.ignition, MaterialId::SPARK))),
+      MaterialId::STONE => (None, Some((self.fusion, MaterialId::LAVA))),
+      _ => (None, None),
+    }
+  }
+}
+
+// Heat a particle gained towards its change at the higher temperature so far, negative when it lost
+// heat towards the one at the lower temperature. It is given back while the temperature is between
+// the two.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct LatentHeat(pub f32);
+
+// Degrees a particle is warmer than the ambient temperature, put there by the beam. It cools down
+// by `PhaseTransitions::cooling` and is removed once it is about gone.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct Heat(pub f32);
+
+fn configure_phases(config: Option<Res<Config>>, mut transitions: ResMut<PhaseTransitions>) {
+  if let Some(phases) = config.as_ref().and_then(|config| config.phases.as_ref()) {
+    *transitions = phases.clone();
+  }
+}
+
+type Changing<'w, 's> = Query<'w, 's, (
+  Entity,
+  &'static mut Particle,
+  &'static mut MaterialId,
+  &'static mut Sprite,
+  &'static mut Transform,
+  Option<&'static mut LatentHeat>,
+  Option<&'static mut Heat>,
+)>;
+
+#[allow(clippy::too_many_arguments)]
+fn change_phase(
+  mut commands: Commands,
+  clock: Res<SimulationClock>,
+  settings: Res<SimulationSettings>,
+  transitions: Res<PhaseTransitions>,
+  materials: Res<MaterialRegistry>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut particles: Changing,
+  mut reactions: EventWriter<ReactionEvent>,
+) {
+  if !clock.ticked { return }
+  let cooling = (-transitions.cooling * settings.tick_delta()).exp();
+
+  let mut changed = Vec::new();
+  for (entity, particle, material, _, _, latent_heat, heat) in particles.iter_mut() {
+    let heat = match heat {
+      Some(mut heat) => {
+        heat.0 *= cooling;
+        if heat.0 < 0.5 {
+          commands.entity(entity).remove::<Heat>();
+        }
+        heat.0
+      },
+      None => 0.,
+    };
+    let (cold, hot) = transitions.changes(*material);
+    let temperature = settings.temperature + heat;
+    let stored = latent_heat.as_ref().map_or(0., |latent_heat| latent_heat.0);
+    // Degrees past the threshold a particle is pushed towards changing by, up towards the hot change
+    // and down towards the cold one. Between the two the heat is given back.
+    let (drive, between) = match (cold, hot) {
+      (_, Some((above, _))) if temperature > above => (temperature - above, false),
+      (Some((below, _)), _) if temperature < below => (temperature - below, false),
+      (_, Some((above, _))) if stored > 0. => (temperature - above, true),
+      (Some((below, _)), _) if stored < 0. => (temperature - below, true),
+      _ => continue,
+    };
+    let point = particle.position.floor().as_ivec2();
+    // Surfaces in contact with the air change first, a particle surrounded by its own kind only
+    // goes along slowly
+    let exposure = 1 + NEIGHBORS.iter().filter(|offset| spatial_index.is_free(point + **offset)).count();
+    let total = stored + drive * exposure as f32;
+    let to = match (cold, hot) {
+      (_, Some((_, to))) if total >= transitions.latent_heat => Some(to),
+      (Some((_, to)), _) if total <= -transitions.latent_heat => Some(to),
+      _ => None,
+    };
+    if let Some(to) = to {
+      changed.push((point, entity, to));
+    } else if between && total * stored <= 0. {
+      commands.entity(entity).remove::<LatentHeat>();
+    } else {
+      match latent_heat {
+        Some(mut latent_heat) => latent_heat.0 = total,
+        None => { commands.entity(entity).insert(LatentHeat(total)); },
+      }
+    }
+  }
+
+  // Cell order keeps the lookup changes in the same order between deterministic runs
+  changed.sort_unstable_by_key(|(point, _, _)| (point.y, point.x));
+  for (point, entity, to) in changed {
+    if spatial_index.get(&point) != Some(&entity) { continue }
+    let (_, mut particle, mut material, mut sprite, mut transform, _, _) = match particles.get_mut(entity) {
+      Ok(particle) => particle,
+      Err(_) => continue,
+    };
+    let from = *material;
+    convert(&mut particle, &mut material, &mut sprite, to, &materials);
+    commands.entity(entity).remove::<LatentHeat>();
+    // Reinserting drops or adds the cell's collider along with the `Static` marker
+    spatial_index.remove(&point);
+    if materials.get(to).fixed {
+      particle.velocity = Vec2::ZERO;
+      particle.position = point.as_vec2();
+      transform.translation = point.as_vec2().extend(0.) * Particle::SPRITE_SIZE;
+      spatial_index.insert_static(point, entity);
+      commands.entity(entity).insert(Static);
+    } else {
+      spatial_index.insert(point, entity);
+      commands.entity(entity).remove::<Static>();
+    }
+    reactions.send(ReactionEvent { entity, cell: point, from, to });
+  }
+}
@@ -0,0 +1,96 @@
+use bevy::{ecs::system::Command, prelude::*};
+
+use crate::{
+  attractors::AttractorBody,
+  boids::{Boid, Predator, Prey, SteeringTarget},
+  clusters::ClusterMember,
+  erosion::Suspended,
+  groups::Tag,
+  growth::{Root, Sprout},
+  kinematic::Conveyor,
+  layers::CollisionLayers,
+  lifetime::{Age, Lifetime},
+  machines::{Gate, PlateThreshold},
+  material::MaterialId,
+  phases::{Heat, LatentHeat},
+  simulation::TickProgress,
+  trails::Trail,
+  Particle, SpatialIndex, Static,
+};
+
+// Entities kept past this many are despawned for good
+const MAX_POOLED: usize = 1 << 16;
+
+// Everything the simulation and its plugins put on particles, which a pooled entity is stripped of.
+// A component that is added to particles somewhere else has to be listed here too, or it would be
+// left on the next particle made from the entity.
+type ParticleComponents = (Particle, MaterialId, Static, CollisionLayers, Lifetime, Age, Boid, Predator, Prey, SteeringTarget, Trail);
+type BehaviorComponents = (ClusterMember, AttractorBody, Tag, Gate, PlateThreshold, LatentHeat, Heat, Suspended, Root, Sprout, Conveyor);
+
+// Emitters and lifetimes keep spawning and despawning particles. Instead of going away, despawned
+// particles keep their entity and sprite, hidden and stripped of everything that made them a
+// particle, and the next spawn only puts a `Particle`, `MaterialId` and `Transform` back on it. Churn
+// then reuses entities, their archetype's storage and sprites rather than allocating new ones.
+// `despawn_particle` and `spawn_particle_with_velocity` go through the pool on their own.
+//
+// The entity comes back as the same entity, so an entity only goes back into use a whole tick after
+// it was despawned: whatever still holds on to it has seen it without a `Particle` and let go by then.
+#[derive(Clone, Default)]
+pub struct ParticlePool {
+  entities: Vec<Entity>,
+  // Despawned since the last tick ended
+  released: Vec<Entity>,
+  // Spawns made from a pooled entity, for the benchmark
+  pub reused: u64,
+}
+
+impl ParticlePool {
+  pub fn len(&self) -> usize {
+    self.entities.len() + self.released.len()
+  }
+
+  // Whether every despawned entity can be reused already
+  pub fn is_ready(&self) -> bool {
+    self.released.is_empty()
+  }
+
+  pub fn take(&mut self) -> Option<Entity> {
+    let entity = self.entities.pop();
+    if entity.is_some() {
+      self.reused += 1;
+    }
+    entity
+  }
+}
+
+// Queued by `despawn_particle`. The particle's components are removed, so it counts as removed to
+// `RemovedComponents`, and its sprite is hidden until a spawn takes the entity out of the pool.
+pub struct RecycleParticle(pub Entity);
+
+impl Command for RecycleParticle {
+  fn write(self, world: &mut World) {
+    let full = world.resource::<SpatialIndex>().pool.len() >= MAX_POOLED;
+    let mut entity = match world.get_entity_mut(self.0) {
+      Some(entity) if entity.contains::<Particle>() => entity,
+      _ => return,
+    };
+    if full {
+      entity.despawn();
+      return;
+    }
+    entity.remove_bundle_intersection::<ParticleComponents>();
+    entity.remove_bundle_intersection::<BehaviorComponents>();
+    if let Some(mut visibility) = entity.get_mut::<Visibility>() {
+      visibility.is_visible = false;
+    }
+    world.resource_mut::<SpatialIndex>().pool.released.push(self.0);
+  }
+}
+
+// Entities despawned before the tick that just ended can be spawned from again
+pub fn release_pooled(progress: Res<TickProgress>, mut spatial_index: ResMut<SpatialIndex>) {
+  if !progress.is_complete() { return }
+  let pool = &mut spatial_index.pool;
+  let released = std::mem::take(&mut pool.released);
+  pool.entities.extend(released);
+}
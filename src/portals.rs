@@ -0,0 +1,51 @@
+use bevy::{math::Mat2, prelude::*};
+
+use crate::SpatialIndex;
+
+// A rectangle of cells linked to another portal. Particles moving into it come out of `exit` at
+// the same spot relative to its corner, with their velocity turned by `rotation` radians. Arriving
+// inside the exit does not send them back, they have to leave it and come in again.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Portal {
+  pub min: IVec2,
+  pub max: IVec2,
+  pub exit: Entity,
+  pub rotation: f32,
+}
+
+impl Portal {
+  pub fn contains(&self, cell: IVec2) -> bool {
+    cell.cmpge(self.min).all() && cell.cmple(self.max).all()
+  }
+
+  fn cells(&self) -> impl Iterator<Item = IVec2> {
+    let (min, max) = (self.min, self.max);
+    (min.y..=max.y).flat_map(move |y| (min.x..=max.x).map(move |x| IVec2::new(x, y)))
+  }
+}
+
+// Spawns two portals leading into each other, going from `a` to `b` turns velocities by
+// `rotation` and coming back turns them the other way. Corners are inclusive.
+pub fn spawn_portal_pair(commands: &mut Commands, a: (IVec2, IVec2), b: (IVec2, IVec2), rotation: f32) -> (Entity, Entity) {
+  let (a_entity, b_entity) = (commands.spawn().id(), commands.spawn().id());
+  commands.entity(a_entity).insert(Portal { min: a.0.min(a.1), max: a.0.max(a.1), exit: b_entity, rotation });
+  commands.entity(b_entity).insert(Portal { min: b.0.min(b.1), max: b.0.max(b.1), exit: a_entity, rotation: -rotation });
+  (a_entity, b_entity)
+}
+
+// Where a particle moving from `from` to `to` comes out if that takes it into a portal, and the
+// rotation to apply to its velocity. A particle whose spot in the exit is taken comes out of the
+// first free cell of it instead, and does not go through at all if the exit is full.
+pub fn destination(portals: &Query<&Portal>, spatial_index: &SpatialIndex, from: IVec2, to: Vec2) -> Option<(Vec2, Mat2)> {
+  let to_point = to.floor().as_ivec2();
+  let portal = portals.iter().find(|portal| portal.contains(to_point) && !portal.contains(from))?;
+  let exit = portals.get(portal.exit).ok()?;
+
+  let mapped = (exit.min + to_point - portal.min).min(exit.max);
+  let point = if spatial_index.is_free(mapped) {
+    mapped
+  } else {
+    exit.cells().find(|cell| spatial_index.is_free(*cell))?
+  };
+  Some((point.as_vec2() + to.fract(), Mat2::from_angle(portal.rotation)))
+}
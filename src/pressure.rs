@@ -0,0 +1,167 @@
+use bevy::{prelude::*, math::const_ivec2, utils::{HashMap, HashSet}};
+
+use crate::{
+  material::{MaterialId, MaterialRegistry},
+  simulation::{SimulationClock, SimulationSettings, SimulationStep},
+  Particle, SpatialIndex, Static,
+};
+
+const NEIGHBORS: [IVec2; 4] = [const_ivec2!([-1, 0]), const_ivec2!([1, 0]), const_ivec2!([0, -1]), const_ivec2!([0, 1])];
+
+// Cells per second a push adds each tick for every cell of pressure behind it, with the default
+// gravity and timestep a column of liquid 5 cells higher holds up what is pushed
+const PRESSURE_FORCE: f32 = 0.2;
+// Cells per second a push adds each tick at most
+const MAX_PUSH: f32 = 2.;
+// Cells per second pushed liquid is sped up to at most
+const MAX_SPEED: f32 = 6.;
+// Surfaces within this many cells of the highest one in their body count as level, so a calm pool
+// with a ragged surface does not bubble
+const LEVEL_TOLERANCE: f32 = 1.5;
+// Fraction of its velocity liquid in contact with more of its body loses each tick, which is what
+// lets sloshing settle down
+const VISCOSITY: f32 = 0.1;
+// Extra mass for every cell of pressure, deep liquid is slightly denser
+const COMPRESSION: f32 = 0.01;
+
+// Hydrostatic pressure in connected liquid, so liquid pushed down one side of a U-bend comes back
+// up the other until both are level. Also drives fountains and geysers.
+pub struct PressurePlugin;
+
+impl Plugin for PressurePlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<Pressure>()
+      .add_system_set(SystemSet::new()
+        .with_run_criteria("fixed_tick")
+        .with_system(apply_pressure.label(SimulationStep::Integrate))
+      );
+  }
+}
+
+// Pressure of every liquid cell in cells of liquid head: how far the highest surface of its body
+// is above it. Updated once per tick.
+#[derive(Default)]
+pub struct Pressure(pub HashMap<IVec2, f32>);
+
+fn apply_pressure(
+  clock: Res<SimulationClock>,
+  settings: Res<SimulationSettings>,
+  materials: Res<MaterialRegistry>,
+  mut pressure: ResMut<Pressure>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut liquids: Query<(&mut Particle, &MaterialId), Without<Static>>,
+) {
+  if !clock.ticked { return }
+  pressure.0.clear();
+  let down = settings.gravity.normalize_or_zero();
+  // Without gravity there is no up for liquid to be pushed towards
+  if down == Vec2::ZERO { return }
+  let above = |cell: IVec2| (cell.as_vec2() - down).round().as_ivec2();
+  let sides = [down.perp(), -down.perp()].map(|side| side.round().as_ivec2());
+  let height = |cell: IVec2| -cell.as_vec2().dot(down);
+
+  let cells: HashSet<IVec2> = spatial_index
+    .iter()
+    .filter(|(_, entity)| liquids.get(**entity).is_ok_and(|(_, material)| materials.get(*material).liquid))
+    .map(|(cell, _)| *cell)
+    .collect();
+
+  // Each body of connected liquid is flooded from one of its cells, in cell order to stay
+  // deterministic
+  let mut order: Vec<IVec2> = cells.iter().copied().collect();
+  order.sort_unstable_by_key(|cell| (cell.y, cell.x));
+  let mut visited = HashSet::default();
+  let mut pushes = Vec::new();
+  for start in order {
+    if !visited.insert(start) { continue }
+    let mut body = vec![start];
+    let mut index = 0;
+    while index < body.len() {
+      let cell = body[index];
+      index += 1;
+      for offset in NEIGHBORS {
+        let neighbor = cell + offset;
+        if cells.contains(&neighbor) && visited.insert(neighbor) {
+          body.push(neighbor);
+        }
+      }
+    }
+
+    // The cell just over the stack of particles on top of a cell, liquid or not, since all of it
+    // weighs on the body. Only stacks with room at the top can rise.
+    let top = |cell: IVec2| {
+      let mut top = above(cell);
+      while spatial_index.contains(&top) {
+        top = above(top);
+      }
+      top
+    };
+    let tops: Vec<IVec2> = body.iter().map(|cell| top(*cell)).collect();
+    // A gap with more of the body above it is a bubble rather than a surface, which fills from
+    // above instead
+    let column = |cell: IVec2| cell.as_vec2().dot(down.perp()).round() as i32;
+    let mut highest = HashMap::<i32, f32>::default();
+    for cell in body.iter() {
+      let entry = highest.entry(column(*cell)).or_insert(f32::MIN);
+      *entry = entry.max(height(*cell));
+    }
+    let open = |top: &IVec2| spatial_index.is_free(*top) && highest[&column(*top)] < height(*top);
+    // A sealed body is only pressed on by its own weight
+    let head = tops
+      .iter()
+      .filter(|top| open(top))
+      .map(|top| height(*top))
+      .reduce(f32::max)
+      .unwrap_or_else(|| tops.iter().map(|top| height(*top)).fold(f32::MIN, f32::max));
+    for (cell, top) in body.into_iter().zip(tops) {
+      let value = head - height(cell);
+      pressure.0.insert(cell, value);
+      // Liquid under a lower surface than the rest of its body is pushed up until they are level
+      let excess = head - height(top);
+      if open(&top) && excess > LEVEL_TOLERANCE {
+        pushes.push((cell, -down, (excess * PRESSURE_FORCE).min(MAX_PUSH)));
+      }
+      // and, while something holds it up, out into any room beside it, which is how liquid spreads
+      // and finds its way under things
+      let supported = !spatial_index.is_free((cell.as_vec2() + down).round().as_ivec2());
+      for side in sides {
+        if supported && value > LEVEL_TOLERANCE && spatial_index.is_free(cell + side) {
+          pushes.push((cell, side.as_vec2(), (value * PRESSURE_FORCE).min(MAX_PUSH)));
+        }
+      }
+    }
+  }
+
+  for (cell, value) in pressure.0.iter() {
+    let entity = match spatial_index.get(cell) {
+      Some(entity) => *entity,
+      None => continue,
+    };
+    if let Ok((mut particle, material)) = liquids.get_mut(entity) {
+      particle.mass = materials.get(*material).mass * (1. + COMPRESSION * value);
+      if NEIGHBORS.iter().any(|offset| pressure.0.contains_key(&(*cell + *offset))) {
+        particle.velocity *= 1. - VISCOSITY;
+      }
+    }
+  }
+  for (cell, direction, push) in pushes {
+    let entity = match spatial_index.get(&cell) {
+      Some(entity) => *entity,
+      None => continue,
+    };
+    if let Ok((mut particle, _)) = liquids.get_mut(entity) {
+      let speed = particle.velocity.dot(direction);
+      if speed < MAX_SPEED {
+        particle.velocity += direction * push.min(MAX_SPEED - speed);
+      }
+      // Resting right on the floor, the least bit of falling would count as hitting it and stop
+      // a sideways push, so the particle moves off from the middle of its cell
+      if direction.dot(down) == 0. {
+        let sag = (particle.position - (cell.as_vec2() + Vec2::splat(0.5))).dot(down);
+        particle.position -= down * sag;
+      }
+    }
+    spatial_index.wake(cell);
+  }
+}
@@ -0,0 +1,101 @@
+use bevy::{
+  diagnostic::{Diagnostic, DiagnosticId, Diagnostics},
+  prelude::*,
+  utils::{Duration, HashMap},
+};
+
+use crate::{simulation::{SimulationStep, TickProgress}, Contacts, SpatialIndex};
+
+// Simulation measurements for `LogDiagnosticsPlugin`, the systems themselves are covered by
+// tracing spans, see the `tracy` feature
+pub struct ProfilingPlugin;
+
+impl Plugin for ProfilingPlugin {
+  fn build(&self, app: &mut App) {
+    // `MinimalPlugins` leaves this out, so headless instances add it here
+    let mut diagnostics = app.world.get_resource_or_insert_with(Diagnostics::default);
+    diagnostics.add(Diagnostic::new(COLLISIONS_PER_TICK, "collisions_per_tick", HISTORY));
+    diagnostics.add(Diagnostic::new(LOOKUP_SIZE, "lookup_size", HISTORY));
+    diagnostics.add(Diagnostic::new(ACTIVE_CHUNKS, "active_chunks", HISTORY));
+
+    app
+      .init_resource::<ChunkStats>()
+      .add_system_set(SystemSet::new()
+        .with_run_criteria("fixed_tick")
+        .with_system(measure_tick.after(SimulationStep::Commit))
+        .with_system(measure_chunks.after(SimulationStep::Commit))
+      );
+  }
+}
+
+pub const COLLISIONS_PER_TICK: DiagnosticId = DiagnosticId::from_u128(0x5d2c_1f5a_8e07_4b1e_9f3a_6c4d_21b0_7e01);
+pub const LOOKUP_SIZE: DiagnosticId = DiagnosticId::from_u128(0x5d2c_1f5a_8e07_4b1e_9f3a_6c4d_21b0_7e02);
+pub const ACTIVE_CHUNKS: DiagnosticId = DiagnosticId::from_u128(0x5d2c_1f5a_8e07_4b1e_9f3a_6c4d_21b0_7e03);
+
+// Ticks averaged over
+const HISTORY: usize = 20;
+
+// Measurements of each occupied chunk, updated after every tick
+#[derive(Default)]
+pub struct ChunkStats {
+  chunks: HashMap<IVec2, ChunkStat>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ChunkStat {
+  pub particles: usize,
+  // Share of the last ticks the chunk was awake for, from 0 to 1
+  pub awake: f32,
+  // Time the latest tick spent on the chunk, zero while it sleeps
+  pub cost: Duration,
+}
+
+impl ChunkStats {
+  pub fn iter(&self) -> impl Iterator<Item = (&IVec2, &ChunkStat)> {
+    self.chunks.iter()
+  }
+
+  // The `count` chunks that took longest, costliest first
+  pub fn hottest(&self, count: usize) -> Vec<(IVec2, ChunkStat)> {
+    let mut chunks: Vec<_> = self.chunks.iter().map(|(chunk, stat)| (*chunk, *stat)).collect();
+    chunks.sort_by_key(|(chunk, stat)| (std::cmp::Reverse(stat.cost), chunk.y, chunk.x));
+    chunks.truncate(count);
+    chunks
+  }
+
+  pub fn max_cost(&self) -> Duration {
+    self.chunks.values().map(|stat| stat.cost).max().unwrap_or_default()
+  }
+}
+
+fn measure_tick(
+  progress: Res<TickProgress>,
+  spatial_index: Res<SpatialIndex>,
+  contacts: Res<Contacts>,
+  mut diagnostics: ResMut<Diagnostics>,
+) {
+  if !progress.is_complete() { return }
+
+  diagnostics.add_measurement(COLLISIONS_PER_TICK, contacts.len() as f64);
+  diagnostics.add_measurement(LOOKUP_SIZE, spatial_index.len() as f64);
+  diagnostics.add_measurement(ACTIVE_CHUNKS, spatial_index.active_chunks().len() as f64);
+}
+
+// Chunks emptied out are dropped, the awake share of the others is a running average over about
+// `HISTORY` ticks
+fn measure_chunks(progress: Res<TickProgress>, spatial_index: Res<SpatialIndex>, mut stats: ResMut<ChunkStats>) {
+  if !progress.is_complete() { return }
+
+  let costs = progress.chunk_costs();
+  let mut chunks = HashMap::default();
+  for chunk in spatial_index.chunks() {
+    let awake = if spatial_index.is_sleeping(chunk * SpatialIndex::CHUNK_SIZE) { 0. } else { 1. };
+    let previous = stats.chunks.get(&chunk).map_or(awake, |stat| stat.awake);
+    chunks.insert(chunk, ChunkStat {
+      particles: spatial_index.chunk_len(chunk),
+      awake: previous + (awake - previous) / HISTORY as f32,
+      cost: costs.get(&chunk).copied().unwrap_or_default(),
+    });
+  }
+  stats.chunks = chunks;
+}
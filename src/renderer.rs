@@ -0,0 +1,239 @@
+use bevy::{
+  prelude::*,
+  render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+
+use crate::{
+  accessibility::{self, ColorPalette},
+  actions::Action,
+  clusters::ClusterMember,
+  material::{MaterialId, MaterialRegistry},
+  profiling::ChunkStats,
+  simulation::SimulationStep,
+  visualization::{self, Visualization},
+  BoundsExt, Particle, SpatialIndex,
+};
+
+pub struct RendererPlugin;
+
+impl Plugin for RendererPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<RenderSettings>()
+      .add_startup_system(setup_grid_texture)
+      .add_system(cycle_visualization)
+      .add_system(resize_grid_texture.label("resize_grid_texture"))
+      .add_system(toggle_sprites.after("resize_grid_texture"))
+      .add_system(draw_grid_texture.after("resize_grid_texture").after(SimulationStep::Commit));
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Renderer {
+  // One sprite entity per particle
+  Sprites,
+  // Every cell is a pixel of a single texture drawn as one quad
+  Texture,
+}
+
+pub struct RenderSettings {
+  pub renderer: Renderer,
+  pub visualization: Visualization,
+  pub palette: ColorPalette,
+  // Overlays every material with a pattern of its own, see `accessibility`
+  pub patterns: bool,
+}
+
+impl Default for RenderSettings {
+  fn default() -> Self {
+    Self { renderer: Renderer::Texture, visualization: Visualization::Normal, palette: ColorPalette::Standard, patterns: false }
+  }
+}
+
+impl RenderSettings {
+  // Whether particles are drawn in other colors than their sprites'
+  fn recolors(&self) -> bool {
+    self.palette != ColorPalette::Standard || self.patterns
+  }
+
+  // The grid texture is drawn with the texture renderer, and over the sprites for visualizations
+  // and recoloring
+  fn uses_grid_texture(&self) -> bool {
+    self.renderer == Renderer::Texture || self.visualization != Visualization::Normal || self.recolors()
+  }
+}
+
+const OVERLAY_ALPHA: f32 = 0.6;
+
+// The image the grid is drawn into, covering every cell inside the lookup bounds
+struct GridTexture {
+  image: Handle<Image>,
+  quad: Entity,
+  min: IVec2,
+  size: UVec2,
+}
+
+pub fn grid_extent(spatial_index: &SpatialIndex) -> (IVec2, UVec2) {
+  let min = spatial_index.bounds().min().floor().as_ivec2();
+  let max = spatial_index.bounds().max().floor().as_ivec2();
+  (min, (max - min + IVec2::ONE).as_uvec2())
+}
+
+fn quad_transform(min: IVec2, size: UVec2) -> Transform {
+  // Cells are centered on their point, so the quad is centered between the first and last cell
+  let center = min.as_vec2() + (size.as_vec2() - Vec2::ONE) / 2.;
+  Transform::from_translation((center * Particle::SPRITE_SIZE).extend(-1.))
+}
+
+fn setup_grid_texture(
+  mut commands: Commands,
+  mut images: ResMut<Assets<Image>>,
+  spatial_index: Res<SpatialIndex>,
+) {
+  let (min, size) = grid_extent(&spatial_index);
+  let image = images.add(Image::new_fill(
+    Extent3d { width: size.x, height: size.y, depth_or_array_layers: 1 },
+    TextureDimension::D2,
+    &[0, 0, 0, 0],
+    TextureFormat::Rgba8UnormSrgb,
+  ));
+
+  let quad = commands.spawn_bundle(SpriteBundle {
+    texture: image.clone(),
+    transform: quad_transform(min, size),
+    sprite: Sprite {
+      custom_size: Some(size.as_vec2() * Particle::SPRITE_SIZE),
+      ..Default::default()
+    },
+    ..Default::default()
+  }).id();
+
+  commands.insert_resource(GridTexture { image, quad, min, size });
+}
+
+type Sprites<'w, 's> = Query<'w, 's, (&'static mut Visibility, &'static mut Transform, ChangeTrackers<Particle>, Option<ChangeTrackers<ClusterMember>>)>;
+
+// Shows either the per particle sprites or the grid quad, the quad goes on top of the sprites
+// when it shows a visualization over them. Clusters always show their sprites, which turn with them.
+fn toggle_sprites(
+  settings: Res<RenderSettings>,
+  grid_texture: Option<Res<GridTexture>>,
+  mut sprites: Sprites,
+  mut quads: Query<(&mut Visibility, &mut Transform), Without<Particle>>,
+  left_clusters: RemovedComponents<ClusterMember>,
+) {
+  let use_sprites = settings.renderer == Renderer::Sprites;
+  for (mut visible, _, tracker, member) in sprites.iter_mut() {
+    if settings.is_changed() || tracker.is_added() || member.as_ref().is_some_and(|member| member.is_added()) {
+      visible.is_visible = use_sprites || member.is_some();
+    }
+  }
+  // Particles left behind by a cluster go back to being drawn upright like any other
+  for entity in left_clusters.iter() {
+    if let Ok((mut visible, mut transform, _, _)) = sprites.get_mut(entity) {
+      visible.is_visible = use_sprites;
+      transform.rotation = Quat::IDENTITY;
+    }
+  }
+
+  if let Some(grid_texture) = grid_texture {
+    if let Ok((mut visible, mut transform)) = quads.get_mut(grid_texture.quad) {
+      visible.is_visible = settings.uses_grid_texture();
+      transform.translation.z = if use_sprites { 1. } else { -1. };
+    }
+  }
+}
+
+// Scenarios can change the world size, so the image follows the lookup bounds
+fn resize_grid_texture(
+  spatial_index: Res<SpatialIndex>,
+  grid_texture: Option<ResMut<GridTexture>>,
+  mut images: ResMut<Assets<Image>>,
+  mut quads: Query<(&mut Transform, &mut Sprite)>,
+) {
+  let mut grid_texture = match grid_texture {
+    Some(grid_texture) => grid_texture,
+    None => return,
+  };
+  let (min, size) = grid_extent(&spatial_index);
+  if min == grid_texture.min && size == grid_texture.size { return }
+
+  if let Some(image) = images.get_mut(&grid_texture.image) {
+    image.resize(Extent3d { width: size.x, height: size.y, depth_or_array_layers: 1 });
+  }
+  if let Ok((mut transform, mut sprite)) = quads.get_mut(grid_texture.quad) {
+    *transform = quad_transform(min, size);
+    sprite.custom_size = Some(size.as_vec2() * Particle::SPRITE_SIZE);
+  }
+  grid_texture.min = min;
+  grid_texture.size = size;
+}
+
+// Colors the pixel of `cell` in an image of the `size` cells from `min` on, cells outside of them are
+// left out
+pub fn set_cell(image: &mut Image, min: IVec2, size: UVec2, cell: IVec2, color: Color) {
+  let size = size.as_ivec2();
+  let cell = cell - min;
+  if cell.x < 0 || cell.y < 0 || cell.x >= size.x || cell.y >= size.y { return }
+
+  // Image rows run top to bottom while the world's y axis points up
+  let index = ((size.y - 1 - cell.y) * size.x + cell.x) as usize * 4;
+  let [r, g, b, a] = color.as_rgba_f32();
+  image.data[index..index + 4].copy_from_slice(&[
+    (r * 255.) as u8,
+    (g * 255.) as u8,
+    (b * 255.) as u8,
+    (a * 255.) as u8,
+  ]);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw_grid_texture(
+  settings: Res<RenderSettings>,
+  grid_texture: Option<Res<GridTexture>>,
+  spatial_index: Res<SpatialIndex>,
+  chunk_stats: Res<ChunkStats>,
+  materials: Res<MaterialRegistry>,
+  mut images: ResMut<Assets<Image>>,
+  particles: Query<(&Particle, &MaterialId, &Sprite)>,
+  loose: Query<(&Particle, &MaterialId, &Sprite), Without<ClusterMember>>,
+) {
+  if !settings.uses_grid_texture() { return }
+  let grid_texture = match grid_texture {
+    Some(grid_texture) => grid_texture,
+    None => return,
+  };
+  let image = match images.get_mut(&grid_texture.image) {
+    Some(image) => image,
+    None => return,
+  };
+
+  image.data.fill(0);
+  let mut set_pixel = |cell: IVec2, color: Color| set_cell(image, grid_texture.min, grid_texture.size, cell, color);
+
+  if settings.visualization == Visualization::Normal {
+    for (particle, material, sprite) in loose.iter() {
+      let cell = particle.position.floor().as_ivec2();
+      let color = if settings.recolors() {
+        accessibility::display_color(settings.palette, settings.patterns, *material, materials.get(*material), cell, sprite.color)
+      } else {
+        sprite.color
+      };
+      set_pixel(cell, color);
+    }
+    return;
+  }
+
+  // Over sprites the visualization is an overlay, so the particles stay visible underneath
+  let alpha = if settings.renderer == Renderer::Sprites { OVERLAY_ALPHA } else { 1. };
+  for (cell, value) in visualization::cell_values(settings.visualization, &spatial_index, &chunk_stats, &particles) {
+    set_pixel(cell, *visualization::ramp(value).set_a(alpha));
+  }
+}
+
+fn cycle_visualization(actions: Res<Input<Action>>, mut settings: ResMut<RenderSettings>) {
+  if actions.just_pressed(Action::CycleVisualization) {
+    settings.visualization = settings.visualization.next();
+    info!("Visualization: {:?}", settings.visualization);
+  }
+}
@@ -0,0 +1,122 @@
+use std::collections::VecDeque;
+
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::{
+  actions::Action,
+  despawn_particle, spawn_particle_with_velocity,
+  material::{MaterialId, MaterialRegistry},
+  net::NetRole,
+  simulation::{SimulationClock, SimulationSettings},
+  AppState, Particle, SpatialIndex,
+};
+
+pub struct RewindPlugin;
+
+impl Plugin for RewindPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<RewindBuffer>()
+      // Like the checksum, this runs once the tick's spawns and despawns are applied
+      .add_system_to_stage(CoreStage::PostUpdate, record_tick)
+      .add_system_set(SystemSet::on_update(AppState::Paused).with_system(rewind));
+  }
+}
+
+// Seconds of history kept
+const HISTORY_SECONDS: f64 = 30.;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct CellState {
+  material: MaterialId,
+  position: Vec2,
+  velocity: Vec2,
+}
+
+// Only particle state is kept, growth and lifetime progress start over for rewound particles
+#[derive(Default)]
+struct RewindBuffer {
+  // Every occupied cell as of the latest recorded tick
+  cells: HashMap<IVec2, CellState>,
+  // For each recorded tick, oldest first, what the cells it changed held the tick before
+  undo: VecDeque<Vec<(IVec2, Option<CellState>)>>,
+}
+
+fn record_tick(
+  clock: Res<SimulationClock>,
+  settings: Res<SimulationSettings>,
+  spatial_index: Res<SpatialIndex>,
+  particles: Query<(&Particle, &MaterialId)>,
+  mut buffer: ResMut<RewindBuffer>,
+) {
+  if !clock.ticked { return }
+
+  let cells: HashMap<IVec2, CellState> = spatial_index
+    .iter()
+    .filter_map(|(cell, entity)| {
+      let (particle, material) = particles.get(*entity).ok()?;
+      Some((*cell, CellState { material: *material, position: particle.position, velocity: particle.velocity }))
+    })
+    .collect();
+
+  // Only the cells that changed are stored, most of a settled world stays the same between ticks
+  let mut undo: Vec<(IVec2, Option<CellState>)> = buffer.cells
+    .iter()
+    .filter(|(cell, state)| cells.get(cell) != Some(state))
+    .map(|(cell, state)| (*cell, Some(*state)))
+    .collect();
+  undo.extend(cells.keys().filter(|cell| !buffer.cells.contains_key(cell)).map(|cell| (*cell, None)));
+
+  buffer.cells = cells;
+  buffer.undo.push_back(undo);
+  let capacity = (HISTORY_SECONDS / settings.timestep).ceil() as usize;
+  while buffer.undo.len() > capacity {
+    buffer.undo.pop_front();
+  }
+}
+
+// Steps back a tick every frame the rewind action is held while paused
+#[allow(clippy::too_many_arguments)]
+fn rewind(
+  mut commands: Commands,
+  actions: Res<Input<Action>>,
+  role: Res<NetRole>,
+  materials: Res<MaterialRegistry>,
+  mut clock: ResMut<SimulationClock>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut buffer: ResMut<RewindBuffer>,
+  particles: Query<&Particle>,
+) {
+  // Other instances would not follow, so networked worlds never rewind
+  if !actions.pressed(Action::Rewind) || *role != NetRole::Offline { return }
+  let undo = match buffer.undo.pop_back() {
+    Some(undo) => undo,
+    None => return,
+  };
+
+  for (cell, state) in undo {
+    if let Some(entity) = spatial_index.get(&cell).copied() {
+      if let Ok(particle) = particles.get(entity) {
+        despawn_particle(&mut commands, &mut spatial_index, entity, particle);
+      }
+    }
+    match state {
+      Some(state) => {
+        let entity = spawn_particle_with_velocity(
+          &mut commands, &mut spatial_index, &materials, cell, state.material, state.velocity,
+        );
+        // Spawning snaps to the cell, the recorded position keeps the particle where it was within it
+        let def = materials.get(state.material);
+        let mut particle = Particle::new(state.position, def.mass);
+        particle.elasticity = def.elasticity;
+        particle.velocity = if def.fixed { Vec2::ZERO } else { state.velocity };
+        commands.entity(entity).insert(particle);
+        buffer.cells.insert(cell, state);
+      },
+      None => {
+        buffer.cells.remove(&cell);
+      },
+    }
+  }
+  clock.tick = clock.tick.saturating_sub(1);
+}
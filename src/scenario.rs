@@ -0,0 +1,429 @@
+use std::{fmt, fs, io, path::Path};
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+  attractors::AttractorBody,
+  clusters::spawn_cluster,
+  despawn_particle, spawn_particle, spawn_particle_with_velocity,
+  environment::{EnvironmentTrack, ROOM_TEMPERATURE},
+  groups::{GroupCommand, GroupOperation, Tag},
+  inventory::{Inventory, Rations},
+  kinematic::{spawn_conveyor, spawn_platform, Platform},
+  machines::{spawn_gate, spawn_plate, Gate, GateKind},
+  material::{MaterialId, MaterialRegistry},
+  objectives::Objective,
+  simulation::{Boundary, GravityPreset, SimulationClock, SimulationSettings, SimulationStep},
+  portals::{spawn_portal_pair, Portal},
+  springs::{spawn_chain, spawn_cloth, spawn_soft_body},
+  terrain::Terrain,
+  Particle, SpatialIndex, Static,
+};
+
+pub struct ScenarioPlugin;
+
+impl Plugin for ScenarioPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<ScenarioRunner>()
+      .add_event::<LoadScenario>()
+      .add_system(load_scenario.label("load_scenario"))
+      .add_system_set(SystemSet::new()
+        .with_run_criteria("fixed_tick")
+        .with_system(run_timeline.after(SimulationStep::Commit))
+      );
+  }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ScenarioParticle {
+  pub material: String,
+  pub position: (i32, i32),
+  // Cells per second
+  #[serde(default)]
+  pub velocity: (f32, f32),
+  // Group the particle joins, for `TimelineEvent::Group`
+  #[serde(default)]
+  pub tag: Option<String>,
+}
+
+fn every_tick() -> u64 {
+  1
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub enum TimelineEvent {
+  // Spawns `count` particles at `position`, one every `every` ticks whenever the cell is free. Rationed
+  // materials come out of the `Inventory`.
+  Emitter {
+    material: String,
+    position: (i32, i32),
+    #[serde(default)]
+    velocity: (f32, f32),
+    #[serde(default = "every_tick")]
+    every: u64,
+    count: u32,
+    #[serde(default)]
+    tag: Option<String>,
+  },
+  // Pushes moving particles within `radius` cells away from `position` by up to `strength` cells per
+  // second, harder the closer they are
+  Explosion { position: (i32, i32), radius: f32, strength: f32 },
+  // Applies `operation` to every particle tagged `tag`
+  Group { tag: String, operation: GroupOperation },
+  // Replaces `SimulationSettings::gravity` until the next scenario is loaded, in cells per second
+  // squared
+  Gravity((f32, f32)),
+  // A rope of `material` from one cell to another, see `spawn_chain`
+  Chain { material: String, from: (i32, i32), to: (i32, i32) },
+  // A blob of `material`, see `spawn_soft_body`
+  SoftBody { material: String, center: (i32, i32), radius: f32 },
+  // A sheet of `material` hanging from `top_left`, fixed at `pins`, see `spawn_cloth`
+  Cloth {
+    material: String,
+    top_left: (i32, i32),
+    width: i32,
+    height: i32,
+    #[serde(default)]
+    pins: Vec<(i32, i32)>,
+  },
+  // A rigid block of `material` with corners `from` and `to`, thrown at `velocity` cells per second
+  // and turning at `spin` degrees per second, see `RigidCluster`
+  Block {
+    material: String,
+    from: (i32, i32),
+    to: (i32, i32),
+    #[serde(default)]
+    velocity: (f32, f32),
+    #[serde(default)]
+    spin: f32,
+  },
+  // A particle of `material` at `position` pulling the others towards it, see `AttractorBody`. A
+  // fixed material keeps it in place.
+  Attractor { material: String, position: (i32, i32), mass: f32 },
+  // Two linked portals given by their corners, see `Portal`. Velocities coming out of `exit` are
+  // turned by `rotation` degrees.
+  Portals {
+    entrance: ((i32, i32), (i32, i32)),
+    exit: ((i32, i32), (i32, i32)),
+    #[serde(default)]
+    rotation: f32,
+  },
+  // A belt of fixed `material` moving what rests on it, see `Conveyor`
+  Conveyor { material: String, from: (i32, i32), to: (i32, i32), speed: f32 },
+  // A block of fixed `material` with corners `from` and `to` travelling along `path` and back, see
+  // `Platform`
+  Platform { material: String, from: (i32, i32), to: (i32, i32), path: Vec<(i32, i32)>, speed: f32 },
+  // Plate cells from `from` to `to` pressed by `threshold` particles, see `machines`
+  Plate { from: (i32, i32), to: (i32, i32), threshold: u32 },
+  // A gate powering the wire at `output` relative to it, see `Gate`
+  Gate { position: (i32, i32), kind: GateKind, output: (i32, i32) },
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TimelineEntry {
+  // Ticks since the scenario was loaded, the event runs at the end of that tick
+  pub tick: u64,
+  pub event: TimelineEvent,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Scenario {
+  pub width: i32,
+  pub height: i32,
+  #[serde(default)]
+  pub particles: Vec<ScenarioParticle>,
+  #[serde(default)]
+  pub timeline: Vec<TimelineEntry>,
+  // Open worlds can let particles leave for good with `Despawn`
+  #[serde(default)]
+  pub boundary: Boundary,
+  // Ignores the size and lets particles go anywhere, see `SpatialIndex::infinite`
+  #[serde(default)]
+  pub infinite: bool,
+  // Path to a tile map for the terrain layer, see `Terrain::load`
+  #[serde(default)]
+  pub terrain: Option<String>,
+  // Gravity, wind and temperature over time, see `EnvironmentTrack`
+  #[serde(default)]
+  pub environment: EnvironmentTrack,
+  // Goals for the player, see `Objectives`
+  #[serde(default)]
+  pub objectives: Vec<Objective>,
+  // Materials the player has to make do with, see `Inventory`. Without any everything is unlimited.
+  #[serde(default)]
+  pub inventory: Option<Rations>,
+  // Ignores `inventory` and leaves everything unlimited as in the sandbox
+  #[serde(default)]
+  pub sandbox: bool,
+}
+
+impl Default for Scenario {
+  fn default() -> Self {
+    Self {
+      width: 40,
+      height: 20,
+      particles: Vec::new(),
+      timeline: Vec::new(),
+      boundary: Boundary::default(),
+      infinite: false,
+      terrain: None,
+      environment: EnvironmentTrack::default(),
+      objectives: Vec::new(),
+      inventory: None,
+      sandbox: false,
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+  Io(io::Error),
+  Parse(ron::Error),
+}
+
+impl fmt::Display for ScenarioError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ScenarioError::Io(error) => write!(f, "could not read scenario: {}", error),
+      ScenarioError::Parse(error) => write!(f, "could not parse scenario: {}", error),
+    }
+  }
+}
+
+impl Scenario {
+  pub const DEFAULT_PATH: &'static str = "scenarios/demo.ron";
+
+  pub fn load(path: impl AsRef<Path>) -> Result<Self, ScenarioError> {
+    let contents = fs::read_to_string(path).map_err(ScenarioError::Io)?;
+    ron::from_str(&contents).map_err(ScenarioError::Parse)
+  }
+}
+
+// Replaces the current world with the given scenario
+pub struct LoadScenario(pub Scenario);
+
+struct Emitter {
+  material: MaterialId,
+  point: IVec2,
+  velocity: Vec2,
+  every: u64,
+  remaining: u32,
+  next_tick: u64,
+  tag: Option<Tag>,
+}
+
+// Plays the loaded scenario's timeline
+#[derive(Default)]
+pub struct ScenarioRunner {
+  // Tick the scenario was loaded at
+  start: u64,
+  timeline: Vec<TimelineEntry>,
+  next: usize,
+  emitters: Vec<Emitter>,
+}
+
+type Fixtures<'w, 's> = Query<'w, 's, Entity, Or<(With<Portal>, With<Platform>)>>;
+
+#[allow(clippy::too_many_arguments)]
+fn load_scenario(
+  mut commands: Commands,
+  mut events: EventReader<LoadScenario>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  materials: Res<MaterialRegistry>,
+  clock: Res<SimulationClock>,
+  mut settings: ResMut<SimulationSettings>,
+  gravity: Res<GravityPreset>,
+  mut runner: ResMut<ScenarioRunner>,
+  mut terrain: ResMut<Terrain>,
+  mut environment: ResMut<EnvironmentTrack>,
+  particles: Query<(Entity, &Particle)>,
+  fixtures: Fixtures,
+) {
+  if let Some(LoadScenario(scenario)) = events.iter().last() {
+    let mut timeline = scenario.timeline.clone();
+    // Stable, so events at the same tick keep the order they were written in
+    timeline.sort_by_key(|entry| entry.tick);
+    *runner = ScenarioRunner { start: clock.tick, timeline, ..Default::default() };
+    gravity.apply(&mut settings);
+    settings.wind = Vec2::ZERO;
+    settings.temperature = ROOM_TEMPERATURE;
+    settings.boundary = scenario.boundary;
+    *environment = scenario.environment.clone().play(clock.tick);
+
+    for (entity, particle) in particles.iter() {
+      despawn_particle(&mut commands, &mut spatial_index, entity, particle);
+    }
+    for entity in fixtures.iter() {
+      commands.entity(entity).despawn();
+    }
+    let pool = std::mem::take(&mut spatial_index.pool);
+    *spatial_index = SpatialIndex::new(scenario.width, scenario.height);
+    spatial_index.pool = pool;
+    spatial_index.infinite = scenario.infinite;
+    // Registered before the particles so none of them end up inside it
+    *terrain = match &scenario.terrain {
+      Some(path) => Terrain::load(path).unwrap_or_else(|error| {
+        error!("Could not load terrain {}: {}", path, error);
+        Terrain::default()
+      }),
+      None => Terrain::default(),
+    };
+    terrain.register(&mut spatial_index);
+
+    for particle in scenario.particles.iter() {
+      let material = match materials.find(&particle.material) {
+        Some(material) => material,
+        None => {
+          warn!("Unknown material {} in scenario", particle.material);
+          continue;
+        }
+      };
+      let point = IVec2::new(particle.position.0, particle.position.1);
+      let velocity = Vec2::new(particle.velocity.0, particle.velocity.1);
+      if !spatial_index.is_free(point) { continue }
+      let entity = spawn_particle_with_velocity(&mut commands, &mut spatial_index, &materials, point, material, velocity);
+      if let Some(tag) = &particle.tag {
+        commands.entity(entity).insert(Tag(tag.clone()));
+      }
+    }
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_timeline(
+  mut commands: Commands,
+  clock: Res<SimulationClock>,
+  materials: Res<MaterialRegistry>,
+  mut settings: ResMut<SimulationSettings>,
+  mut runner: ResMut<ScenarioRunner>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut inventory: ResMut<Inventory>,
+  mut groups: EventWriter<GroupCommand>,
+  mut particles: Query<&mut Particle, Without<Static>>,
+) {
+  let tick = clock.tick.saturating_sub(runner.start);
+  while let Some(entry) = runner.timeline.get(runner.next).cloned() {
+    if entry.tick > tick { break }
+    runner.next += 1;
+
+    match entry.event {
+      TimelineEvent::Emitter { material, position, velocity, every, count, tag } => match materials.find(&material) {
+        Some(material) => runner.emitters.push(Emitter {
+          material,
+          point: IVec2::new(position.0, position.1),
+          velocity: Vec2::new(velocity.0, velocity.1),
+          every: every.max(1),
+          remaining: count,
+          next_tick: tick,
+          tag: tag.map(Tag),
+        }),
+        None => warn!("Unknown material {} in scenario timeline", material),
+      },
+      TimelineEvent::Explosion { position, radius, strength } => {
+        let center = Vec2::new(position.0 as f32, position.1 as f32);
+        for mut particle in particles.iter_mut() {
+          let offset = particle.position - center;
+          let distance = offset.length();
+          if distance > radius { continue }
+          // Particles right at the center are thrown straight up
+          let direction = if offset == Vec2::ZERO { Vec2::Y } else { offset / distance };
+          particle.velocity += direction * strength * (1. - distance / radius.max(f32::EPSILON));
+          spatial_index.wake(particle.position.floor().as_ivec2());
+        }
+      },
+      TimelineEvent::Group { tag, operation } => groups.send(GroupCommand { tag, operation }),
+      TimelineEvent::Gravity((x, y)) => settings.gravity = Vec2::new(x, y),
+      TimelineEvent::Chain { material, from, to } => match materials.find(&material) {
+        Some(material) => {
+          let (from, to) = (IVec2::new(from.0, from.1), IVec2::new(to.0, to.1));
+          spawn_chain(&mut commands, &mut spatial_index, &materials, from, to, material);
+        },
+        None => warn!("Unknown material {} in scenario timeline", material),
+      },
+      TimelineEvent::SoftBody { material, center, radius } => match materials.find(&material) {
+        Some(material) => {
+          let center = IVec2::new(center.0, center.1);
+          spawn_soft_body(&mut commands, &mut spatial_index, &materials, center, radius, material);
+        },
+        None => warn!("Unknown material {} in scenario timeline", material),
+      },
+      TimelineEvent::Cloth { material, top_left, width, height, pins } => match materials.find(&material) {
+        Some(material) => {
+          let top_left = IVec2::new(top_left.0, top_left.1);
+          let pins: Vec<IVec2> = pins.into_iter().map(|(x, y)| IVec2::new(x, y)).collect();
+          spawn_cloth(&mut commands, &mut spatial_index, &materials, top_left, width, height, material, &pins);
+        },
+        None => warn!("Unknown material {} in scenario timeline", material),
+      },
+      TimelineEvent::Block { material, from, to, velocity, spin } => match materials.find(&material) {
+        Some(material) => {
+          let (min, max) = (IVec2::new(from.0.min(to.0), from.1.min(to.1)), IVec2::new(from.0.max(to.0), from.1.max(to.1)));
+          let cells = (min.y..=max.y).flat_map(|y| (min.x..=max.x).map(move |x| IVec2::new(x, y)));
+          let velocity = Vec2::new(velocity.0, velocity.1);
+          spawn_cluster(&mut commands, &mut spatial_index, &materials, cells, material, velocity, spin.to_radians());
+        },
+        None => warn!("Unknown material {} in scenario timeline", material),
+      },
+      TimelineEvent::Attractor { material, position, mass } => match materials.find(&material) {
+        Some(material) => {
+          let point = IVec2::new(position.0, position.1);
+          if spatial_index.is_free(point) {
+            let entity = spawn_particle(&mut commands, &mut spatial_index, &materials, point, material);
+            commands.entity(entity).insert(AttractorBody::new(mass));
+          } else {
+            warn!("No room for an attractor at {}", point);
+          }
+        },
+        None => warn!("Unknown material {} in scenario timeline", material),
+      },
+      TimelineEvent::Portals { entrance, exit, rotation } => {
+        let corners = |((x0, y0), (x1, y1)): ((i32, i32), (i32, i32))| (IVec2::new(x0, y0), IVec2::new(x1, y1));
+        spawn_portal_pair(&mut commands, corners(entrance), corners(exit), rotation.to_radians());
+      },
+      TimelineEvent::Conveyor { material, from, to, speed } => match fixed_material(&materials, &material) {
+        Some(material) => {
+          let (from, to) = (IVec2::new(from.0, from.1), IVec2::new(to.0, to.1));
+          spawn_conveyor(&mut commands, &mut spatial_index, &materials, from, to, material, speed);
+        },
+        None => warn!("Conveyors need a fixed material, not {}", material),
+      },
+      TimelineEvent::Platform { material, from, to, path, speed } => match fixed_material(&materials, &material) {
+        Some(material) => {
+          let (from, to) = (IVec2::new(from.0, from.1), IVec2::new(to.0, to.1));
+          let path = path.into_iter().map(|(x, y)| IVec2::new(x, y)).collect();
+          spawn_platform(&mut commands, &mut spatial_index, &materials, from, to, material, path, speed);
+        },
+        None => warn!("Platforms need a fixed material, not {}", material),
+      },
+      TimelineEvent::Plate { from, to, threshold } => {
+        let (from, to) = (IVec2::new(from.0, from.1), IVec2::new(to.0, to.1));
+        spawn_plate(&mut commands, &mut spatial_index, &materials, from, to, threshold);
+      },
+      TimelineEvent::Gate { position, kind, output } => {
+        let gate = Gate { kind, output: IVec2::new(output.0, output.1) };
+        spawn_gate(&mut commands, &mut spatial_index, &materials, IVec2::new(position.0, position.1), gate);
+      },
+    }
+  }
+
+  for emitter in runner.emitters.iter_mut() {
+    if emitter.remaining == 0 || emitter.next_tick > tick || !spatial_index.is_free(emitter.point) { continue }
+    // Rationed emitters wait for the stock to fill up again
+    if !inventory.spend(emitter.material) { continue }
+    let entity = spawn_particle_with_velocity(
+      &mut commands, &mut spatial_index, &materials, emitter.point, emitter.material, emitter.velocity,
+    );
+    if let Some(tag) = &emitter.tag {
+      commands.entity(entity).insert(tag.clone());
+    }
+    emitter.remaining -= 1;
+    emitter.next_tick = tick + emitter.every;
+  }
+  runner.emitters.retain(|emitter| emitter.remaining > 0);
+}
+
+fn fixed_material(materials: &MaterialRegistry, name: &str) -> Option<MaterialId> {
+  materials.find(name).filter(|material| materials.get(*material).fixed)
+}
@@ -0,0 +1,120 @@
+use std::{fs, path::Path};
+
+use bevy::{ecs::entity::EntityMap, prelude::*, reflect::TypeRegistry, scene::serde::SceneDeserializer};
+use serde::de::DeserializeSeed;
+
+use crate::{
+  actions::Action,
+  despawn_particle,
+  material::{MaterialId, MaterialRegistry},
+  scenario::ScenarioError,
+  simulation::SimulationStep,
+  spawn_particle_with_velocity, Particle, SpatialIndex, Static,
+};
+
+// Saves and loads the particles as a standard Bevy `DynamicScene`, so worlds can be opened with
+// scene tooling or spawned into other apps. Material ids index the `MaterialRegistry` they were
+// saved with.
+pub struct WorldScenePlugin;
+
+impl Plugin for WorldScenePlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .register_type::<Particle>()
+      .register_type::<MaterialId>()
+      .register_type::<Static>()
+      .add_system(export_world_scene.after(SimulationStep::Commit))
+      .add_system(import_world_scene.after(SimulationStep::Commit));
+  }
+}
+
+pub const WORLD_SCENE_PATH: &str = "scenes/world.scn.ron";
+
+// A scene with an entity for every particle, holding its reflected components
+pub fn world_scene<'a>(
+  particles: impl Iterator<Item = (&'a Particle, &'a MaterialId, &'a Transform, bool)>,
+  type_registry: &TypeRegistry,
+) -> DynamicScene {
+  let mut world = World::new();
+  for (particle, material, transform, fixed) in particles {
+    let mut entity = world.spawn();
+    entity.insert_bundle((particle.clone(), *material, *transform));
+    if fixed {
+      entity.insert(Static);
+    }
+  }
+  DynamicScene::from_world(&world, type_registry)
+}
+
+pub fn save_world_scene<'a>(
+  path: impl AsRef<Path>,
+  particles: impl Iterator<Item = (&'a Particle, &'a MaterialId, &'a Transform, bool)>,
+  type_registry: &TypeRegistry,
+) -> Result<(), String> {
+  let contents = world_scene(particles, type_registry).serialize_ron(type_registry).map_err(|error| error.to_string())?;
+  let path = path.as_ref();
+  path.parent().map_or(Ok(()), fs::create_dir_all).and_then(|_| fs::write(path, contents)).map_err(|error| error.to_string())
+}
+
+pub fn load_world_scene(path: impl AsRef<Path>, type_registry: &TypeRegistry) -> Result<Vec<(Particle, MaterialId)>, ScenarioError> {
+  let contents = fs::read(path).map_err(ScenarioError::Io)?;
+  let mut deserializer = ron::de::Deserializer::from_bytes(&contents).map_err(ScenarioError::Parse)?;
+  let scene = SceneDeserializer { type_registry: &type_registry.read() }
+    .deserialize(&mut deserializer)
+    .map_err(ScenarioError::Parse)?;
+
+  // Written into a world of its own, so only the particles themselves are taken over
+  let mut world = World::new();
+  world.insert_resource(type_registry.clone());
+  if let Err(error) = scene.write_to_world(&mut world, &mut EntityMap::default()) {
+    warn!("Could not spawn all of the scene: {}", error);
+  }
+  Ok(world.query::<(&Particle, &MaterialId)>().iter(&world).map(|(particle, material)| (particle.clone(), *material)).collect())
+}
+
+fn export_world_scene(
+  actions: Option<Res<Input<Action>>>,
+  type_registry: Res<TypeRegistry>,
+  particles: Query<(&Particle, &MaterialId, &Transform, Option<&Static>)>,
+) {
+  if !actions.is_some_and(|actions| actions.just_pressed(Action::ExportScene)) { return }
+  let particles = particles.iter().map(|(particle, material, transform, fixed)| (particle, material, transform, fixed.is_some()));
+  let result = save_world_scene(WORLD_SCENE_PATH, particles, &type_registry);
+  match result {
+    Ok(()) => info!("Saved scene to {}", WORLD_SCENE_PATH),
+    Err(error) => error!("Could not save scene to {}: {}", WORLD_SCENE_PATH, error),
+  }
+}
+
+fn import_world_scene(
+  mut commands: Commands,
+  actions: Option<Res<Input<Action>>>,
+  type_registry: Res<TypeRegistry>,
+  materials: Res<MaterialRegistry>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  particles: Query<(Entity, &Particle)>,
+) {
+  if !actions.is_some_and(|actions| actions.just_pressed(Action::ImportScene)) { return }
+  let loaded = match load_world_scene(WORLD_SCENE_PATH, &type_registry) {
+    Ok(loaded) => loaded,
+    Err(error) => {
+      error!("Could not load scene {}: {}", WORLD_SCENE_PATH, error);
+      return;
+    },
+  };
+
+  for (entity, particle) in particles.iter() {
+    despawn_particle(&mut commands, &mut spatial_index, entity, particle);
+  }
+  for (particle, material) in loaded {
+    if !materials.contains(material) {
+      warn!("Unknown material {:?} in scene", material);
+      continue;
+    }
+    let point = particle.position.floor().as_ivec2();
+    if !spatial_index.is_free(point) { continue }
+    let entity = spawn_particle_with_velocity(&mut commands, &mut spatial_index, &materials, point, material, particle.velocity);
+    // Keeps the exact position within the cell, mass and elasticity still come from the material
+    commands.entity(entity).insert(particle);
+  }
+}
@@ -0,0 +1,358 @@
+use std::{fs, path::Path, sync::{Arc, Mutex}};
+
+use bevy::{prelude::*, utils::HashMap};
+use rhai::{Array, Dynamic, Engine, Map, Scope, AST, FLOAT, INT};
+
+use crate::{
+  console::{ConsoleApp, ConsoleCommand, ConsoleLog},
+  despawn_particle,
+  material::{MaterialId, MaterialRegistry},
+  simulation::{SimulationClock, SimulationStep, TickProgress},
+  spawn_particle, BoundsExt, Collision, Contacts, Particle, SpatialIndex, Static,
+};
+
+pub struct ScriptingPlugin;
+
+impl Plugin for ScriptingPlugin {
+  fn build(&self, app: &mut App) {
+    app.init_resource::<Scripts>().init_console();
+    for name in app.world.resource::<Scripts>().commands() {
+      app.add_console_command(&name, &format!("{} ..., from {}", name, GLOBAL_SCRIPT));
+    }
+    app
+      .add_system(run_spawn_hooks.after(SimulationStep::Commit))
+      .add_system(run_script_commands.after(SimulationStep::Commit))
+      .add_system_set(SystemSet::new()
+        .with_run_criteria("fixed_tick")
+        .with_system(run_tick_hooks.after(SimulationStep::Commit))
+      );
+  }
+}
+
+// Scripts are plain Rhai files, hooks are optional functions defined in them:
+//   on_tick(world, tick) in global.rhai, on_tick(world, particle) in material scripts
+//   on_collision(world, a, b), `b` is () for collisions with the world bounds or a static cell
+//   on_spawn(world, particle)
+//   command_<name>(world, args) in global.rhai, run by the console command <name> with its words as
+//   an array of strings, whatever it returns is printed
+// Particles are maps of x, y, vx, vy and material. `world` offers material_at(x, y), is_free(x, y),
+// spawn_particle(x, y, material), despawn_particle(x, y) and set_velocity(x, y, vx, vy). Velocities
+// are in cells per second.
+const SCRIPTS_DIR: &str = "scripts";
+const GLOBAL_SCRIPT: &str = "global.rhai";
+const MATERIALS_DIR: &str = "materials";
+const COMMAND_PREFIX: &str = "command_";
+// Bounds runaway scripts so a loop cannot freeze the simulation
+const MAX_OPERATIONS: u64 = 100_000;
+
+pub struct Scripts {
+  engine: Engine,
+  global: Option<AST>,
+  // Scripts attached to a material, named after it: scripts/materials/sand.rhai
+  materials: HashMap<MaterialId, AST>,
+}
+
+impl FromWorld for Scripts {
+  fn from_world(world: &mut World) -> Self {
+    let materials = world.get_resource_or_insert_with(MaterialRegistry::default);
+    let mut scripts = Self { engine: create_engine(), global: None, materials: HashMap::new() };
+
+    let directory = Path::new(SCRIPTS_DIR);
+    scripts.global = scripts.compile(&directory.join(GLOBAL_SCRIPT));
+    if let Ok(entries) = fs::read_dir(directory.join(MATERIALS_DIR)) {
+      for path in entries.flatten().map(|entry| entry.path()) {
+        if path.extension().is_none_or(|extension| extension != "rhai") { continue }
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        match materials.find(&name) {
+          Some(material) => {
+            if let Some(ast) = scripts.compile(&path) {
+              scripts.materials.insert(material, ast);
+            }
+          },
+          None => warn!("Script {} does not match any material", path.display()),
+        }
+      }
+    }
+    scripts
+  }
+}
+
+impl Scripts {
+  fn compile(&self, path: &Path) -> Option<AST> {
+    let source = fs::read_to_string(path).ok()?;
+    match self.engine.compile(source) {
+      Ok(ast) => {
+        info!("Loaded script {}", path.display());
+        Some(ast)
+      },
+      Err(error) => {
+        error!("Could not compile {}: {}", path.display(), error);
+        None
+      }
+    }
+  }
+
+  fn is_empty(&self) -> bool {
+    self.global.is_none() && self.materials.is_empty()
+  }
+
+  // Console commands defined by the global script
+  fn commands(&self) -> Vec<String> {
+    self.global.iter()
+      .flat_map(|ast| ast.iter_functions())
+      .filter_map(|function| function.name.strip_prefix(COMMAND_PREFIX).map(str::to_string))
+      .collect()
+  }
+
+  fn call(&self, ast: &AST, name: &str, args: impl rhai::FuncArgs) {
+    let defined = ast.iter_functions().any(|function| function.name == name);
+    if !defined { return }
+    if let Err(error) = self.engine.call_fn::<Dynamic>(&mut Scope::new(), ast, name, args) {
+      warn!("Script error in {}: {}", name, error);
+    }
+  }
+
+  // Calls a hook on the global script and on the script of `material`, if they define it
+  fn call_hook(&self, material: Option<MaterialId>, name: &str, args: impl rhai::FuncArgs + Clone) {
+    if let Some(ast) = self.global.as_ref() {
+      self.call(ast, name, args.clone());
+    }
+    if let Some(ast) = material.and_then(|material| self.materials.get(&material)) {
+      self.call(ast, name, args);
+    }
+  }
+}
+
+fn create_engine() -> Engine {
+  let mut engine = Engine::new();
+  engine.set_max_operations(MAX_OPERATIONS);
+  // Debug builds default to much shallower limits, keep scripts behaving the same in both
+  engine.set_max_expr_depths(64, 32);
+  engine
+    .register_type_with_name::<ScriptWorld>("World")
+    .register_fn("material_at", ScriptWorld::material_at)
+    .register_fn("is_free", ScriptWorld::is_free)
+    .register_fn("spawn_particle", ScriptWorld::spawn)
+    .register_fn("despawn_particle", ScriptWorld::despawn)
+    .register_fn("set_velocity", ScriptWorld::set_velocity);
+  engine
+}
+
+enum ScriptAction {
+  Spawn(IVec2, MaterialId),
+  Despawn(IVec2),
+  SetVelocity(IVec2, Vec2),
+}
+
+// What scripts see of the world. Reads come from a snapshot taken before the hooks run, writes
+// are queued and applied once every hook has finished, so scripts never touch the ECS directly.
+struct WorldSnapshot {
+  // `None` in an infinite world
+  bounds: Option<Rect<f32>>,
+  cells: HashMap<IVec2, MaterialId>,
+  names: Vec<String>,
+  actions: Vec<ScriptAction>,
+}
+
+#[derive(Clone)]
+pub struct ScriptWorld(Arc<Mutex<WorldSnapshot>>);
+
+impl ScriptWorld {
+  fn material_at(&mut self, x: INT, y: INT) -> Dynamic {
+    let snapshot = self.0.lock().unwrap();
+    match snapshot.cells.get(&IVec2::new(x as i32, y as i32)) {
+      Some(material) => snapshot.names[material.0].clone().into(),
+      None => Dynamic::UNIT,
+    }
+  }
+
+  fn is_free(&mut self, x: INT, y: INT) -> bool {
+    let snapshot = self.0.lock().unwrap();
+    let cell = IVec2::new(x as i32, y as i32);
+    snapshot.bounds.is_none_or(|bounds| bounds.outside(cell.as_vec2()).is_none()) && !snapshot.cells.contains_key(&cell)
+  }
+
+  fn spawn(&mut self, x: INT, y: INT, material: &str) -> bool {
+    let mut snapshot = self.0.lock().unwrap();
+    let material = match snapshot.names.iter().position(|name| name.eq_ignore_ascii_case(material)) {
+      Some(index) => MaterialId(index),
+      None => return false,
+    };
+    snapshot.actions.push(ScriptAction::Spawn(IVec2::new(x as i32, y as i32), material));
+    true
+  }
+
+  fn despawn(&mut self, x: INT, y: INT) {
+    self.0.lock().unwrap().actions.push(ScriptAction::Despawn(IVec2::new(x as i32, y as i32)));
+  }
+
+  fn set_velocity(&mut self, x: INT, y: INT, vx: FLOAT, vy: FLOAT) {
+    let velocity = Vec2::new(vx as f32, vy as f32);
+    if !velocity.is_finite() { return }
+    self.0.lock().unwrap().actions.push(ScriptAction::SetVelocity(IVec2::new(x as i32, y as i32), velocity));
+  }
+
+  fn new(spatial_index: &SpatialIndex, materials: &MaterialRegistry, particles: &Query<(&mut Particle, &MaterialId)>) -> Self {
+    let cells = spatial_index
+      .iter()
+      .filter_map(|(cell, entity)| Some((*cell, *particles.get(*entity).ok()?.1)))
+      .collect();
+    let names = materials.iter().map(|(_, material)| material.name.clone()).collect();
+    let bounds = (!spatial_index.infinite).then_some(spatial_index.bounds());
+    Self(Arc::new(Mutex::new(WorldSnapshot { bounds, cells, names, actions: Vec::new() })))
+  }
+
+  fn apply(
+    &self,
+    commands: &mut Commands,
+    spatial_index: &mut SpatialIndex,
+    materials: &MaterialRegistry,
+    particles: &mut Query<(&mut Particle, &MaterialId)>,
+    statics: &Query<(), With<Static>>,
+  ) {
+    let actions = std::mem::take(&mut self.0.lock().unwrap().actions);
+    for action in actions {
+      match action {
+        ScriptAction::Spawn(cell, material) => {
+          if spatial_index.is_free(cell) {
+            spawn_particle(commands, spatial_index, materials, cell, material);
+          }
+        },
+        ScriptAction::Despawn(cell) => {
+          let entity = match spatial_index.get(&cell) {
+            Some(entity) => *entity,
+            None => continue,
+          };
+          if let Ok((particle, _)) = particles.get(entity) {
+            despawn_particle(commands, spatial_index, entity, particle);
+          }
+        },
+        ScriptAction::SetVelocity(cell, velocity) => {
+          let entity = match spatial_index.get(&cell) {
+            Some(entity) if !statics.contains(*entity) => *entity,
+            _ => continue,
+          };
+          if let Ok((mut particle, _)) = particles.get_mut(entity) {
+            particle.velocity = velocity;
+            spatial_index.wake(cell);
+          }
+        },
+      }
+    }
+  }
+}
+
+fn particle_map(particle: &Particle, material: MaterialId, materials: &MaterialRegistry) -> Map {
+  let cell = particle.position.floor().as_ivec2();
+  let mut map = Map::new();
+  map.insert("x".into(), (cell.x as INT).into());
+  map.insert("y".into(), (cell.y as INT).into());
+  map.insert("vx".into(), (particle.velocity.x as FLOAT).into());
+  map.insert("vy".into(), (particle.velocity.y as FLOAT).into());
+  map.insert("material".into(), materials.get(material).name.clone().into());
+  map
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_tick_hooks(
+  mut commands: Commands,
+  scripts: Res<Scripts>,
+  clock: Res<SimulationClock>,
+  progress: Res<TickProgress>,
+  contacts: Res<Contacts>,
+  materials: Res<MaterialRegistry>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut particles: Query<(&mut Particle, &MaterialId)>,
+  statics: Query<(), With<Static>>,
+) {
+  if scripts.is_empty() { return }
+  let world = ScriptWorld::new(&spatial_index, &materials, &particles);
+
+  if let Some(ast) = scripts.global.as_ref() {
+    scripts.call(ast, "on_tick", (world.clone(), clock.tick as INT));
+  }
+  // Cell order keeps queued actions in the same order every run
+  let mut cells: Vec<(IVec2, Entity)> = spatial_index.iter().map(|(cell, entity)| (*cell, *entity)).collect();
+  cells.sort_unstable_by_key(|(cell, _)| (cell.y, cell.x));
+  for (_, entity) in cells {
+    let (particle, material) = match particles.get(entity) {
+      Ok(particle) => particle,
+      Err(_) => continue,
+    };
+    if let Some(ast) = scripts.materials.get(material) {
+      scripts.call(ast, "on_tick", (world.clone(), particle_map(particle, *material, &materials)));
+    }
+  }
+
+  // A budgeted tick gets here every frame, its collisions are only told about once it is done
+  let collisions = if progress.is_complete() { contacts.iter().map(Collision::entities).collect() } else { Vec::new() };
+  for (a, b) in collisions {
+    let (particle_a, material_a) = match particles.get(a) {
+      Ok(particle) => particle,
+      Err(_) => continue,
+    };
+    let other = b
+      .and_then(|b| particles.get(b).ok())
+      .map_or(Dynamic::UNIT, |(particle, material)| particle_map(particle, *material, &materials).into());
+    let args = (world.clone(), particle_map(particle_a, *material_a, &materials), other);
+    scripts.call_hook(Some(*material_a), "on_collision", args);
+  }
+
+  world.apply(&mut commands, &mut spatial_index, &materials, &mut particles, &statics);
+}
+
+fn run_spawn_hooks(
+  mut commands: Commands,
+  scripts: Res<Scripts>,
+  materials: Res<MaterialRegistry>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut particles: Query<(&mut Particle, &MaterialId)>,
+  // Filtering on `Particle` would conflict with the mutable query above, both are added together
+  spawned: Query<Entity, Added<MaterialId>>,
+  statics: Query<(), With<Static>>,
+) {
+  if scripts.is_empty() || spawned.is_empty() { return }
+  let world = ScriptWorld::new(&spatial_index, &materials, &particles);
+
+  for entity in spawned.iter() {
+    if let Ok((particle, material)) = particles.get(entity) {
+      let args = (world.clone(), particle_map(particle, *material, &materials));
+      scripts.call_hook(Some(*material), "on_spawn", args);
+    }
+  }
+
+  world.apply(&mut commands, &mut spatial_index, &materials, &mut particles, &statics);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_script_commands(
+  mut commands: Commands,
+  scripts: Res<Scripts>,
+  materials: Res<MaterialRegistry>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut entered: EventReader<ConsoleCommand>,
+  log: Option<ResMut<ConsoleLog>>,
+  mut particles: Query<(&mut Particle, &MaterialId)>,
+  statics: Query<(), With<Static>>,
+) {
+  let ast = match scripts.global.as_ref() {
+    Some(ast) => ast,
+    None => return,
+  };
+  let mut log = log;
+  for command in entered.iter() {
+    let name = format!("{}{}", COMMAND_PREFIX, command.name);
+    if !ast.iter_functions().any(|function| function.name == name) { continue }
+    let world = ScriptWorld::new(&spatial_index, &materials, &particles);
+    let args: Array = command.args.iter().cloned().map(Dynamic::from).collect();
+    let output = match scripts.engine.call_fn::<Dynamic>(&mut Scope::new(), ast, &name, (world.clone(), args)) {
+      Ok(output) if output.is_unit() => None,
+      Ok(output) => Some(output.to_string()),
+      Err(error) => Some(format!("Script error in {}: {}", name, error)),
+    };
+    if let (Some(output), Some(log)) = (output, log.as_mut()) {
+      log.print(output);
+    }
+    world.apply(&mut commands, &mut spatial_index, &materials, &mut particles, &statics);
+  }
+}
@@ -0,0 +1,51 @@
+use bevy::prelude::*;
+
+pub struct SensorsPlugin;
+
+impl Plugin for SensorsPlugin {
+  fn build(&self, app: &mut App) {
+    app.add_event::<SensorEvent>();
+  }
+}
+
+// A rectangle of cells that reports particles moving in and out of it, without them noticing. The
+// corners are inclusive.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Sensor {
+  pub min: IVec2,
+  pub max: IVec2,
+}
+
+impl Sensor {
+  pub fn new(a: IVec2, b: IVec2) -> Self {
+    Self { min: a.min(b), max: a.max(b) }
+  }
+
+  pub fn contains(&self, cell: IVec2) -> bool {
+    cell.cmpge(self.min).all() && cell.cmple(self.max).all()
+  }
+}
+
+// Sent by `handle_movement` as a particle moves across the edge of a sensor. Particles spawned
+// or despawned inside one are not reported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SensorEvent {
+  Enter { sensor: Entity, particle: Entity },
+  Exit { sensor: Entity, particle: Entity },
+}
+
+pub fn detect_crossings(
+  sensors: &Query<(Entity, &Sensor)>,
+  sensor_events: &mut EventWriter<SensorEvent>,
+  particle: Entity,
+  from: IVec2,
+  to: IVec2,
+) {
+  for (sensor_entity, sensor) in sensors.iter() {
+    match (sensor.contains(from), sensor.contains(to)) {
+      (false, true) => sensor_events.send(SensorEvent::Enter { sensor: sensor_entity, particle }),
+      (true, false) => sensor_events.send(SensorEvent::Exit { sensor: sensor_entity, particle }),
+      _ => {},
+    }
+  }
+}
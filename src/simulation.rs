@@ -0,0 +1,685 @@
+use std::str::FromStr;
+
+use bevy::{prelude::*, ecs::schedule::ShouldRun, utils::{Duration, HashMap, HashSet, Instant}};
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{environment::ROOM_TEMPERATURE, AppState, Particle, SpatialIndex};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scheduler {
+  // Every due tick runs to completion within the frame
+  Fixed,
+  // A tick is spread over as many frames as needed to stay within the per-frame budget
+  Budgeted { budget_ms: f32 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+  // Reference implementation, runs the collision and movement systems on the main thread
+  Cpu,
+  // Experimental compute shader, only available with the `gpu` feature
+  Gpu,
+}
+
+// How a tick turns a particle's acceleration into movement, only the CPU backend follows it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Integrator {
+  // Velocity is updated first and the particle moves by the new velocity
+  #[default]
+  SemiImplicitEuler,
+  // The particle moves by the average of its velocity before and after the update, exact for
+  // constant acceleration such as free fall
+  VelocityVerlet,
+}
+
+// How moves made during a tick are seen by the particles moving after them, only the CPU backend
+// follows it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateOrder {
+  // Moves are applied as they are made, so particles later in cell order see the world after the
+  // earlier ones moved. Columns fall as a whole, but motion is biased towards the end of the order.
+  #[default]
+  Sequential,
+  // Every particle moves against the world as the tick started and the moves are applied together
+  // at the end of it, so the outcome does not depend on the order particles are processed in
+  DoubleBuffered,
+  // Chunks move in four passes, one for each corner of a 2x2 block of chunks. A particle goes no
+  // further than half a chunk past its own, so the chunks of a pass, two chunks apart, never reach
+  // the same cell and are moved in parallel. Portals are gone through once the pass is done. A world
+  // that wraps around moves its chunks one after another, as a particle going through an edge comes
+  // out next to chunks of the same pass.
+  Checkerboard,
+}
+
+// Simulates the chunks around the camera every tick and the ones further out more coarsely, so a
+// large or infinite world only costs as much as the area being looked at
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SimulationLod {
+  // Chunks from the one the camera is over that are simulated every tick
+  pub radius: i32,
+  // Ticks between updates of the chunks further out, which make up for the skipped ones with as
+  // many more substeps. `None` freezes them until the camera comes close again.
+  pub far_interval: Option<u32>,
+}
+
+impl Default for SimulationLod {
+  fn default() -> Self {
+    Self { radius: 2, far_interval: Some(4) }
+  }
+}
+
+// Cell at the center of the view, which `SimulationLod` is measured from. Without a camera, like on
+// a dedicated server, it is `None` and everything is simulated in full.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SimulationFocus(pub Option<IVec2>);
+
+impl Integrator {
+  // How far a particle moves over a step in which `acceleration` was added to its velocity, given
+  // the velocity it ended the step with
+  pub fn displacement(self, velocity: Vec2, acceleration: Vec2) -> Vec2 {
+    match self {
+      Integrator::SemiImplicitEuler => velocity,
+      Integrator::VelocityVerlet => velocity - acceleration * 0.5,
+    }
+  }
+}
+
+// How the elasticities of two colliding particles make up the restitution of their collision. Both
+// particles bounce off each other with the same restitution, whichever of them ran into the other.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CombineRule {
+  #[default]
+  Average,
+  // The less elastic particle decides, anything hitting clay stops dead
+  Min,
+  // The more elastic particle decides, anything hitting rubber bounces
+  Max,
+  Multiply,
+}
+
+impl CombineRule {
+  pub fn combine(self, a: f32, b: f32) -> f32 {
+    match self {
+      CombineRule::Average => (a + b) / 2.,
+      CombineRule::Min => a.min(b),
+      CombineRule::Max => a.max(b),
+      CombineRule::Multiply => a * b,
+    }
+  }
+}
+
+// Gravity to start worlds with, picked with `--gravity` or in the settings menu. Scenarios start
+// from it too, their own gravity events change it from there.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GravityPreset {
+  #[default]
+  Earth,
+  Moon,
+  Jupiter,
+  // Nothing falls, particles only drift and bounce off each other like billiard balls
+  ZeroG,
+  // Pulls towards the middle of the world from every side, see `SimulationSettings::gravity_center`
+  Planet,
+}
+
+impl GravityPreset {
+  pub fn next(self) -> Self {
+    match self {
+      GravityPreset::Earth => GravityPreset::Moon,
+      GravityPreset::Moon => GravityPreset::Jupiter,
+      GravityPreset::Jupiter => GravityPreset::ZeroG,
+      GravityPreset::ZeroG => GravityPreset::Planet,
+      GravityPreset::Planet => GravityPreset::Earth,
+    }
+  }
+
+  // Sets the gravity of `settings`, relative to Earth's `Particle::GRAVITY` by how strong surface
+  // gravity is on each
+  pub fn apply(self, settings: &mut SimulationSettings) {
+    let strength = match self {
+      GravityPreset::Earth | GravityPreset::Planet => 1.,
+      GravityPreset::Moon => 0.165,
+      GravityPreset::Jupiter => 2.53,
+      GravityPreset::ZeroG => 0.,
+    };
+    settings.gravity = Particle::GRAVITY * strength;
+    settings.gravity_center = (self == GravityPreset::Planet).then_some(Vec2::ZERO);
+  }
+}
+
+impl FromStr for GravityPreset {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value {
+      "earth" => Ok(Self::Earth),
+      "moon" => Ok(Self::Moon),
+      "jupiter" => Ok(Self::Jupiter),
+      "zero-g" | "zero" => Ok(Self::ZeroG),
+      "planet" => Ok(Self::Planet),
+      _ => Err(format!("unknown gravity {}, expected earth, moon, jupiter, zero-g or planet", value)),
+    }
+  }
+}
+
+// What happens to particles reaching the edge of the world, only the CPU backend follows it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Boundary {
+  // The edges act as walls
+  #[default]
+  Bounce,
+  // Particles leaving one edge come back in through the opposite one
+  Wrap,
+  // Particles leaving the world are removed
+  Despawn,
+}
+
+pub struct SimulationSettings {
+  // Seconds of real time between simulation ticks
+  pub timestep: f64,
+  pub scheduler: Scheduler,
+  // Skip chunks where nothing has changed recently
+  pub chunk_activation: bool,
+  pub backend: Backend,
+  // Whether this instance runs the simulation at all, network clients mirror the host instead
+  pub authoritative: bool,
+  // Runs exactly one tick per frame with a fixed delta so every instance starting from the same
+  // world and inputs ends up in the same state, required for lock-step networking
+  pub deterministic: bool,
+  // Cells per tick a particle may move at most, faster particles are slowed down to it. Velocities
+  // are in cells per second, so how fast that is depends on the timestep.
+  pub max_speed: f32,
+  // Passes the contact solver makes over a tick's collisions
+  pub solver_iterations: u32,
+  // Increments each tick's gravity integration and movement is split into
+  pub substeps: u32,
+  // Logs every collision and move at the debug level, far too much output for normal use
+  pub debug_log: bool,
+  // Acceleration applied to every moving particle, in cells per second squared
+  pub gravity: Vec2,
+  // Planet mode: gravity pulls towards this point with the strength of `gravity` instead of along
+  // it. Only the CPU backend follows it.
+  pub gravity_center: Option<Vec2>,
+  pub integrator: Integrator,
+  pub update_order: UpdateOrder,
+  // Level of detail by distance from the camera, everything is simulated in full without one
+  pub lod: Option<SimulationLod>,
+  pub boundary: Boundary,
+  // Decimal places velocities are rounded to after a collision, or `None` to keep them exact.
+  // Coarse rounding leaves slow particles stuck and stops drips.
+  pub velocity_precision: Option<u32>,
+  // Restitution of collisions between particles, collisions with the world use the particle's own
+  // elasticity
+  pub restitution: CombineRule,
+  // Cells per tick under which a particle coming out of a collision is stopped, so settled
+  // particles come to rest and their chunk can sleep
+  pub sleep_speed: f32,
+  // Acceleration pushing moving particles for a mass of 1, see `environment`
+  pub wind: Vec2,
+  // Ambient temperature in degrees, see `PhaseTransitions` for what freezes and melts
+  pub temperature: f32,
+}
+
+impl SimulationSettings {
+  pub fn uses_gpu(&self) -> bool {
+    cfg!(feature = "gpu") && self.backend == Backend::Gpu && !self.deterministic
+  }
+
+  // Seconds of simulated time a tick advances by. Always the timestep, however long the frames
+  // running the tick took, so particles cover the same distance per second at any frame rate.
+  pub fn tick_delta(&self) -> f32 {
+    self.timestep as f32
+  }
+
+  // Fastest velocity in cells per second, `max_speed` cells per tick
+  pub fn max_velocity(&self) -> f32 {
+    self.max_speed / self.tick_delta()
+  }
+
+  // Gravity on a particle at `position`
+  pub fn gravity_at(&self, position: Vec2) -> Vec2 {
+    match self.gravity_center {
+      Some(center) => (center - position).normalize_or_zero() * self.gravity.length(),
+      None => self.gravity,
+    }
+  }
+
+  // Smallest velocity step kept, 0 when velocities are not rounded
+  pub fn velocity_quantum(&self) -> f32 {
+    self.velocity_precision.map_or(0., |places| 10f32.powi(-(places as i32)))
+  }
+
+  // Applies `velocity_precision` and `sleep_speed` to a velocity resolved by a collision
+  pub fn settle_velocity(&self, velocity: Vec2) -> Vec2 {
+    let quantum = self.velocity_quantum();
+    let velocity = if quantum > 0. { (velocity / quantum).round() * quantum } else { velocity };
+    if velocity.length() * self.tick_delta() < self.sleep_speed { Vec2::ZERO } else { velocity }
+  }
+}
+
+impl Default for SimulationSettings {
+  fn default() -> Self {
+    Self {
+      timestep: 0.25,
+      scheduler: Scheduler::Fixed,
+      chunk_activation: true,
+      backend: Backend::Cpu,
+      authoritative: true,
+      deterministic: false,
+      // A particle never skips past a whole chunk in one tick
+      max_speed: SpatialIndex::CHUNK_SIZE as f32,
+      solver_iterations: 4,
+      substeps: 4,
+      debug_log: false,
+      gravity: Particle::GRAVITY,
+      gravity_center: None,
+      integrator: Integrator::default(),
+      update_order: UpdateOrder::default(),
+      lod: None,
+      boundary: Boundary::default(),
+      velocity_precision: None,
+      restitution: CombineRule::default(),
+      sleep_speed: 0.01,
+      wind: Vec2::ZERO,
+      temperature: ROOM_TEMPERATURE,
+    }
+  }
+}
+
+// Counters for problems the simulation recovered from
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SimulationDiagnostics {
+  // Velocities that were not finite or over `SimulationSettings::max_speed`
+  pub sanitized_velocities: u64,
+  // Particles removed for leaving the world, see `Boundary::Despawn`
+  pub escaped_particles: u64,
+  // Particles pushed out of a cell another particle held
+  pub separated_particles: u64,
+}
+
+// Randomness used by the simulation, reseeded when a deterministic session starts
+pub struct SimulationRng(pub StdRng);
+
+impl Default for SimulationRng {
+  fn default() -> Self {
+    Self(StdRng::from_entropy())
+  }
+}
+
+impl SimulationRng {
+  pub fn reseed(&mut self, seed: u64) {
+    self.0 = StdRng::seed_from_u64(seed);
+  }
+}
+
+#[derive(Default)]
+pub struct SimulationClock {
+  accumulator: f64,
+  looping: bool,
+  // Whether the latest run of the fixed tick criteria started a new tick
+  pub ticked: bool,
+  pub tick: u64,
+  // Ticks past this are held back, lock-step clients set it to the last tick they have inputs for
+  pub max_tick: Option<u64>,
+}
+
+impl SimulationClock {
+  fn start_tick(&mut self, progress: &mut TickProgress, settings: &SimulationSettings, focus: &SimulationFocus) {
+    self.tick += 1;
+    self.ticked = true;
+    progress.start(settings, focus, self.tick);
+  }
+}
+
+// The steps every tick goes through, in order and all on the fixed timestep:
+// - `Integrate` changes velocities ahead of the tick, forces, steering and conveyors go here
+// - `Collide` integrates gravity along each particle's path and adds the first thing in the way to
+//   the `Contacts`
+// - `Resolve` solves the tick's collisions into new velocities and sanitizes them
+// - `Commit` moves the particles by their velocities and updates the lookup
+// Anything that reacts to where particles ended up runs after `Commit`, which the GPU backend also
+// labels its step with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, SystemLabel)]
+pub enum SimulationStep {
+  Integrate,
+  Collide,
+  Resolve,
+  Commit,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TickPhase {
+  #[default]
+  Idle,
+  Discover,
+  Movement,
+}
+
+// Tracks how far through the grid the current tick has progressed, chunk by chunk
+#[derive(Default)]
+pub struct TickProgress {
+  phase: TickPhase,
+  active_only: bool,
+  // Movement goes through the chunks in the passes of `UpdateOrder::Checkerboard`
+  checkerboard: bool,
+  // Level of detail along with the chunk it is measured from
+  lod: Option<(SimulationLod, IVec2)>,
+  tick: u64,
+  loaded: bool,
+  chunks: Vec<IVec2>,
+  entities: Vec<Entity>,
+  processed: HashSet<Entity>,
+  deadline: Option<Instant>,
+  chunks_this_frame: usize,
+  // Chunk being processed and when it was started
+  current_chunk: Option<(IVec2, Instant)>,
+  // Time spent on each chunk over both phases, for this tick and the last complete one
+  costs: HashMap<IVec2, Duration>,
+  last_costs: HashMap<IVec2, Duration>,
+}
+
+impl TickProgress {
+  fn start(&mut self, settings: &SimulationSettings, focus: &SimulationFocus, tick: u64) {
+    self.phase = TickPhase::Discover;
+    self.active_only = settings.chunk_activation;
+    self.checkerboard = settings.update_order == UpdateOrder::Checkerboard;
+    self.lod = settings.lod.zip(focus.0.map(SpatialIndex::chunk_of));
+    self.tick = tick;
+    self.loaded = false;
+    self.entities.clear();
+    self.processed.clear();
+    self.current_chunk = None;
+    self.last_costs = std::mem::take(&mut self.costs);
+  }
+
+  fn set_deadline(&mut self, deadline: Option<Instant>) {
+    self.deadline = deadline;
+    self.chunks_this_frame = 0;
+  }
+
+  // Ends the tick without walking the chunks, for backends that simulate everything at once
+  #[cfg(feature = "gpu")]
+  pub fn finish(&mut self) {
+    self.phase = TickPhase::Idle;
+  }
+
+  pub fn is_complete(&self) -> bool {
+    self.phase == TickPhase::Idle
+  }
+
+  // Whether collisions are still being looked for, a budgeted tick can take several frames to
+  pub fn is_discovering(&self) -> bool {
+    self.phase == TickPhase::Discover
+  }
+
+  // Time the CPU backend spent on each chunk it simulated in the latest complete tick, the time
+  // between frames of a budgeted tick is left out
+  pub fn chunk_costs(&self) -> &HashMap<IVec2, Duration> {
+    if self.is_complete() { &self.costs } else { &self.last_costs }
+  }
+
+  fn finish_chunk(&mut self) {
+    if let Some((chunk, started)) = self.current_chunk.take() {
+      *self.costs.entry(chunk).or_default() += started.elapsed();
+    }
+  }
+
+  // Ticks between updates of `chunk` under the `SimulationLod`, `None` while it is frozen
+  pub fn chunk_interval(&self, chunk: IVec2) -> Option<u32> {
+    match self.lod {
+      Some((lod, center)) if (chunk - center).abs().max_element() > lod.radius => lod.far_interval.map(|interval| interval.max(1)),
+      _ => Some(1),
+    }
+  }
+
+  // Whether `chunk` is simulated by the current tick
+  pub fn simulates(&self, chunk: IVec2) -> bool {
+    self.chunk_interval(chunk).is_some_and(|interval| self.tick.is_multiple_of(interval as u64))
+  }
+
+  // Always allows at least one chunk per frame so a tight budget still makes progress
+  fn out_of_time(&self) -> bool {
+    self.chunks_this_frame > 0 && self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+  }
+
+  fn load(&mut self, phase: TickPhase, spatial_index: &SpatialIndex) {
+    if self.loaded { return }
+    let chunks = if self.active_only { spatial_index.active_chunks() } else { spatial_index.chunks() };
+    self.chunks = chunks.into_iter().filter(|chunk| self.simulates(*chunk)).collect();
+    if self.checkerboard && phase == TickPhase::Movement {
+      // Stable, so each pass keeps the cell order
+      self.chunks.sort_by_key(|chunk| checkerboard_pass(*chunk));
+    }
+    self.chunks.reverse();
+    self.processed.clear();
+    self.loaded = true;
+  }
+
+  fn finish_phase(&mut self) {
+    self.phase = match self.phase {
+      TickPhase::Discover => TickPhase::Movement,
+      _ => TickPhase::Idle,
+    };
+    self.loaded = false;
+  }
+
+  // Yields the next entity to process for `phase`, or `None` once the phase is finished or
+  // this frame's budget is spent. Each entity is yielded at most once per phase.
+  pub fn next_entity(&mut self, phase: TickPhase, spatial_index: &SpatialIndex) -> Option<Entity> {
+    if self.phase != phase { return None }
+    self.load(phase, spatial_index);
+
+    loop {
+      if let Some(entity) = self.entities.pop() {
+        if self.processed.insert(entity) {
+          return Some(entity);
+        }
+        continue;
+      }
+
+      self.finish_chunk();
+      if self.out_of_time() { return None }
+      match self.chunks.pop() {
+        Some(chunk) => {
+          self.chunks_this_frame += 1;
+          self.current_chunk = Some((chunk, Instant::now()));
+          self.entities = spatial_index.chunk_entities(chunk);
+          self.entities.reverse();
+        },
+        None => {
+          self.finish_phase();
+          return None;
+        }
+      }
+    }
+  }
+
+  // Yields the chunks of the next pass of `UpdateOrder::Checkerboard` along with the entities in
+  // them, in cell order, or `None` once movement is finished or this frame's budget is spent. Like
+  // `next_entity`, each entity is yielded at most once.
+  pub fn next_pass(&mut self, spatial_index: &SpatialIndex) -> Option<Vec<(IVec2, Vec<Entity>)>> {
+    if self.phase != TickPhase::Movement { return None }
+    self.load(TickPhase::Movement, spatial_index);
+    if self.out_of_time() { return None }
+
+    let pass = match self.chunks.last() {
+      Some(chunk) => checkerboard_pass(*chunk),
+      None => {
+        self.finish_phase();
+        return None;
+      },
+    };
+    let mut chunks = Vec::new();
+    while let Some(chunk) = self.chunks.pop() {
+      if checkerboard_pass(chunk) != pass {
+        self.chunks.push(chunk);
+        break;
+      }
+      let processed = &mut self.processed;
+      let entities = spatial_index.chunk_entities(chunk).into_iter().filter(|entity| processed.insert(*entity)).collect();
+      chunks.push((chunk, entities));
+    }
+    self.chunks_this_frame += chunks.len();
+    Some(chunks)
+  }
+
+  // Time spent on a chunk moved outside of `next_entity`
+  pub fn add_cost(&mut self, chunk: IVec2, cost: Duration) {
+    *self.costs.entry(chunk).or_default() += cost;
+  }
+}
+
+// Which of the four passes of `UpdateOrder::Checkerboard` a chunk moves in
+fn checkerboard_pass(chunk: IVec2) -> (i32, i32) {
+  (chunk.y.rem_euclid(2), chunk.x.rem_euclid(2))
+}
+
+pub fn cpu_backend(In(should_run): In<ShouldRun>, settings: Res<SimulationSettings>) -> ShouldRun {
+  if settings.uses_gpu() { ShouldRun::No } else { should_run }
+}
+
+// Drop in replacement for `FixedTimestep` that only accumulates time while the world is running,
+// so resuming from a menu does not replay every tick missed while paused
+pub fn fixed_tick(
+  time: Res<Time>,
+  settings: Res<SimulationSettings>,
+  state: Res<State<AppState>>,
+  focus: Res<SimulationFocus>,
+  mut clock: ResMut<SimulationClock>,
+  mut progress: ResMut<TickProgress>,
+) -> ShouldRun {
+  clock.ticked = false;
+  if *state.current() != AppState::Running || !settings.authoritative {
+    clock.looping = false;
+    return ShouldRun::No;
+  }
+
+  if settings.deterministic {
+    // A single tick per frame keeps the order of ticks and per frame systems the same everywhere
+    clock.looping = false;
+    clock.accumulator += time.delta_seconds_f64();
+    if clock.max_tick.is_some_and(|max_tick| clock.tick >= max_tick) {
+      clock.accumulator = clock.accumulator.min(settings.timestep);
+      return ShouldRun::No;
+    }
+    if clock.accumulator < settings.timestep {
+      return ShouldRun::No;
+    }
+    clock.accumulator -= settings.timestep;
+    clock.start_tick(&mut progress, &settings, &focus);
+    progress.set_deadline(None);
+    return ShouldRun::Yes;
+  }
+
+  match settings.scheduler {
+    Scheduler::Fixed => {
+      if !clock.looping {
+        clock.accumulator += time.delta_seconds_f64();
+      }
+
+      if clock.accumulator >= settings.timestep {
+        clock.accumulator -= settings.timestep;
+        clock.looping = true;
+        clock.start_tick(&mut progress, &settings, &focus);
+        progress.set_deadline(None);
+        ShouldRun::YesAndCheckAgain
+      } else {
+        clock.looping = false;
+        ShouldRun::No
+      }
+    },
+    Scheduler::Budgeted { budget_ms } => {
+      clock.looping = false;
+      if progress.phase == TickPhase::Idle {
+        // Missed ticks are dropped rather than queued, otherwise a slow tick would snowball
+        clock.accumulator = (clock.accumulator + time.delta_seconds_f64()).min(settings.timestep);
+        if clock.accumulator < settings.timestep {
+          return ShouldRun::No;
+        }
+        clock.accumulator -= settings.timestep;
+        clock.start_tick(&mut progress, &settings, &focus);
+      }
+
+      progress.set_deadline(Some(Instant::now() + Duration::from_secs_f32(budget_ms / 1000.)));
+      ShouldRun::Yes
+    },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Where a particle thrown at `velocity` is after `steps` steps of `delta` seconds under gravity
+  fn fall(integrator: Integrator, velocity: Vec2, steps: u32, delta: f32) -> Vec2 {
+    let (mut position, mut velocity) = (Vec2::ZERO, velocity);
+    for _ in 0..steps {
+      let acceleration = Particle::GRAVITY * delta;
+      velocity += acceleration;
+      position += integrator.displacement(velocity, acceleration) * delta;
+    }
+    position
+  }
+
+  fn expected(velocity: Vec2, time: f32) -> Vec2 {
+    velocity * time + 0.5 * Particle::GRAVITY * time * time
+  }
+
+  #[test]
+  fn verlet_follows_constant_gravity() {
+    let velocity = Vec2::new(3., 5.);
+    for (steps, delta) in [(4, 0.25), (60, 1. / 60.), (200, 0.05)] {
+      let time = steps as f32 * delta;
+      let error = fall(Integrator::VelocityVerlet, velocity, steps, delta).distance(expected(velocity, time));
+      assert!(error < 1e-3, "{} after {} steps of {}", error, steps, delta);
+    }
+  }
+
+  // Semi-implicit Euler runs ahead by half a step of gravity for every second of falling
+  #[test]
+  fn euler_follows_constant_gravity_to_first_order() {
+    let velocity = Vec2::new(3., 5.);
+    for (steps, delta) in [(4, 0.25), (60, 1. / 60.), (200, 0.05)] {
+      let time = steps as f32 * delta;
+      let error = fall(Integrator::SemiImplicitEuler, velocity, steps, delta).distance(expected(velocity, time));
+      let bound = 0.5 * Particle::GRAVITY.length() * delta * time;
+      assert!((error - bound).abs() < 1e-3, "{} after {} steps of {}, expected {}", error, steps, delta, bound);
+    }
+  }
+
+  #[test]
+  fn average_combines_to_the_mean() {
+    assert_eq!(CombineRule::Average.combine(0.2, 0.8), 0.5);
+    assert_eq!(CombineRule::Average.combine(0.8, 0.2), 0.5);
+    assert_eq!(CombineRule::Average.combine(0., 0.6), 0.3);
+    assert_eq!(CombineRule::Average.combine(0., 0.), 0.);
+  }
+
+  #[test]
+  fn min_takes_the_less_elastic() {
+    assert_eq!(CombineRule::Min.combine(0.2, 0.8), 0.2);
+    assert_eq!(CombineRule::Min.combine(0.8, 0.2), 0.2);
+    assert_eq!(CombineRule::Min.combine(0., 0.9), 0.);
+    assert_eq!(CombineRule::Min.combine(0., 0.), 0.);
+  }
+
+  #[test]
+  fn max_takes_the_more_elastic() {
+    assert_eq!(CombineRule::Max.combine(0.2, 0.8), 0.8);
+    assert_eq!(CombineRule::Max.combine(0.8, 0.2), 0.8);
+    assert_eq!(CombineRule::Max.combine(0., 0.9), 0.9);
+    assert_eq!(CombineRule::Max.combine(0., 0.), 0.);
+  }
+
+  #[test]
+  fn multiply_takes_the_product() {
+    assert_eq!(CombineRule::Multiply.combine(0.5, 0.8), 0.4);
+    assert_eq!(CombineRule::Multiply.combine(0.8, 0.5), 0.4);
+    assert_eq!(CombineRule::Multiply.combine(0., 0.9), 0.);
+    assert_eq!(CombineRule::Multiply.combine(1., 0.3), 0.3);
+  }
+}
@@ -0,0 +1,502 @@
+use std::{collections::{BTreeMap, VecDeque}, io::{self, Read, Write}};
+
+use bevy::math::IVec2;
+use bitflags::bitflags;
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+
+use crate::simulation::Boundary;
+
+// The file format of saved worlds. A snapshot starts with a header giving the version of the format
+// it was written in and flags for its optional parts, followed by the body in that version's layout.
+// Snapshots of older versions are migrated to the current layout as they are read, so saves keep
+// loading across releases. Files without a header are the first version.
+const MAGIC: [u8; 4] = *b"ARKS";
+pub const VERSION: u16 = 3;
+
+// Particles are grouped into chunks of 16 by 16 cells. The format has its own chunks so it does not
+// change along with `SpatialIndex::CHUNK_SIZE`.
+const CHUNK_BITS: i32 = 4;
+// Steps a cell is split into for the position of a particle within it
+const POSITION_STEPS: f32 = 64.;
+// Steps a cell per second is split into for velocities
+const VELOCITY_STEPS: f32 = 64.;
+// Materials a packed particle has room for
+const MAX_MATERIALS: usize = 1 << 12;
+// Bytes of a packed particle, with fixed point or with full velocities
+const PACKED_SIZE: usize = 8;
+const PACKED_FULL_SIZE: usize = 12;
+
+bitflags! {
+  // Readers refuse flags they do not know, those come from a newer release
+  pub struct SnapshotFlags: u32 {
+    // The body is compressed, with gzip up to version 2 and zstd from version 3 on
+    const COMPRESSED = 1 << 0;
+    // The world has no bounds, the size is only the area it started with
+    const INFINITE = 1 << 1;
+    // `SavedSettings` follow the particles, or the chunk index from version 3 on
+    const SETTINGS = 1 << 2;
+    // Particles have their velocities as two `f32`, some were too fast for the fixed point ones
+    const FULL_VELOCITIES = 1 << 3;
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedParticle {
+  // Index into `Snapshot::materials`
+  pub material: u16,
+  pub position: (f32, f32),
+  pub velocity: (f32, f32),
+}
+
+// Simulation settings that belong to the world rather than to the player
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedSettings {
+  pub gravity: (f32, f32),
+  pub boundary: Boundary,
+}
+
+// Material ids can change between launches as packs come and go, so particles refer to their
+// material by name
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+  pub width: i32,
+  pub height: i32,
+  pub infinite: bool,
+  pub materials: Vec<String>,
+  pub particles: Vec<SavedParticle>,
+  pub settings: Option<SavedSettings>,
+}
+
+// Everything before the particles. The chunk index lists the chunks in the order their particles
+// follow in, with how many each has, so a reader can go through them one at a time.
+#[derive(Serialize, Deserialize)]
+struct BodyV3 {
+  width: i32,
+  height: i32,
+  materials: Vec<String>,
+  chunks: Vec<((i32, i32), u32)>,
+}
+
+// Up to version 2 the particles were written out as they are in memory
+#[derive(Serialize, Deserialize)]
+struct BodyV2 {
+  width: i32,
+  height: i32,
+  materials: Vec<String>,
+  particles: Vec<SavedParticle>,
+}
+
+// The first version, a gzipped body without a header or settings
+#[derive(Serialize, Deserialize)]
+struct BodyV1 {
+  width: i32,
+  height: i32,
+  infinite: bool,
+  materials: Vec<String>,
+  particles: Vec<SavedParticle>,
+}
+
+impl From<BodyV1> for Snapshot {
+  fn from(body: BodyV1) -> Self {
+    Snapshot {
+      width: body.width,
+      height: body.height,
+      infinite: body.infinite,
+      materials: body.materials,
+      particles: body.particles,
+      settings: None,
+    }
+  }
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn cell_of(particle: &SavedParticle) -> IVec2 {
+  IVec2::new(particle.position.0.floor() as i32, particle.position.1.floor() as i32)
+}
+
+// From the lowest bits: the cell within the chunk (4 and 4), the material (12) and the position
+// within the cell (6 and 6), followed by the velocity as two fixed point numbers of 16 bits, or as two
+// `f32` when `full`
+fn pack(particle: &SavedParticle, full: bool, bytes: &mut Vec<u8>) {
+  let cell = cell_of(particle);
+  let local = cell & ((1 << CHUNK_BITS) - 1);
+  let within = |position: f32, cell: i32| ((position - cell as f32) * POSITION_STEPS).clamp(0., POSITION_STEPS - 1.) as u32;
+  let bits = local.x as u32
+    | (local.y as u32) << 4
+    | (particle.material as u32) << 8
+    | within(particle.position.0, cell.x) << 20
+    | within(particle.position.1, cell.y) << 26;
+  bytes.extend_from_slice(&bits.to_le_bytes());
+  if full {
+    bytes.extend_from_slice(&particle.velocity.0.to_le_bytes());
+    bytes.extend_from_slice(&particle.velocity.1.to_le_bytes());
+  } else {
+    bytes.extend_from_slice(&fixed_velocity(particle.velocity.0).to_le_bytes());
+    bytes.extend_from_slice(&fixed_velocity(particle.velocity.1).to_le_bytes());
+  }
+}
+
+fn fixed_velocity(velocity: f32) -> i16 {
+  (velocity * VELOCITY_STEPS).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+// Whether `velocity` comes back from `fixed_velocity` as it was, give or take a step
+fn fits_fixed(velocity: f32) -> bool {
+  (velocity * VELOCITY_STEPS).round().abs() <= i16::MAX as f32
+}
+
+fn unpack(bytes: &[u8], chunk: IVec2) -> SavedParticle {
+  let bits = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+  let field = |shift: u32, width: u32| (bits >> shift) & ((1 << width) - 1);
+  let cell = (chunk << CHUNK_BITS) + IVec2::new(field(0, 4) as i32, field(4, 4) as i32);
+  // In the middle of the step it was rounded down to
+  let within = |steps: u32| (steps as f32 + 0.5) / POSITION_STEPS;
+  let velocity = match bytes.len() {
+    PACKED_FULL_SIZE => (
+      f32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+      f32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+    ),
+    _ => {
+      let velocity = |low: u8, high: u8| i16::from_le_bytes([low, high]) as f32 / VELOCITY_STEPS;
+      (velocity(bytes[4], bytes[5]), velocity(bytes[6], bytes[7]))
+    },
+  };
+  SavedParticle {
+    material: field(8, 12) as u16,
+    position: (cell.x as f32 + within(field(20, 6)), cell.y as f32 + within(field(26, 6))),
+    velocity,
+  }
+}
+
+// Writes `snapshot` in the current version. Positions are rounded to a 64th of a cell and velocities
+// to a 64th of a cell per second, unless one is too fast for that and all are written in full.
+pub fn write(mut writer: impl Write, snapshot: &Snapshot, compressed: bool) -> io::Result<()> {
+  if snapshot.materials.len() > MAX_MATERIALS {
+    return Err(invalid(format!("snapshots hold up to {} materials", MAX_MATERIALS)));
+  }
+  let mut flags = SnapshotFlags::empty();
+  flags.set(SnapshotFlags::COMPRESSED, compressed);
+  flags.set(SnapshotFlags::INFINITE, snapshot.infinite);
+  flags.set(SnapshotFlags::SETTINGS, snapshot.settings.is_some());
+  let full = !snapshot.particles.iter().all(|particle| fits_fixed(particle.velocity.0) && fits_fixed(particle.velocity.1));
+  flags.set(SnapshotFlags::FULL_VELOCITIES, full);
+  writer.write_all(&MAGIC)?;
+  writer.write_all(&VERSION.to_le_bytes())?;
+  writer.write_all(&flags.bits().to_le_bytes())?;
+
+  if compressed {
+    let mut encoder = zstd::Encoder::new(writer, 0)?;
+    write_body(&mut encoder, snapshot, full)?;
+    encoder.finish()?.flush()
+  } else {
+    write_body(&mut writer, snapshot, full)?;
+    writer.flush()
+  }
+}
+
+fn write_body(writer: &mut impl Write, snapshot: &Snapshot, full: bool) -> io::Result<()> {
+  // Row by row, like the rest of the world's ordering
+  let mut chunks = BTreeMap::<(i32, i32), Vec<&SavedParticle>>::new();
+  for particle in snapshot.particles.iter() {
+    let chunk = cell_of(particle) >> CHUNK_BITS;
+    chunks.entry((chunk.y, chunk.x)).or_default().push(particle);
+  }
+  let body = BodyV3 {
+    width: snapshot.width,
+    height: snapshot.height,
+    materials: snapshot.materials.clone(),
+    chunks: chunks.iter().map(|((y, x), particles)| ((*x, *y), particles.len() as u32)).collect(),
+  };
+  bincode::serialize_into(&mut *writer, &body).map_err(io::Error::other)?;
+  if let Some(settings) = &snapshot.settings {
+    bincode::serialize_into(&mut *writer, settings).map_err(io::Error::other)?;
+  }
+
+  let mut bytes = Vec::new();
+  for particles in chunks.values() {
+    bytes.clear();
+    for particle in particles {
+      pack(particle, full, &mut bytes);
+    }
+    writer.write_all(&bytes)?;
+  }
+  Ok(())
+}
+
+// Reads a snapshot a chunk at a time. Snapshots from before version 3 have no chunks and come in
+// as one.
+pub struct SnapshotReader<'a> {
+  pub width: i32,
+  pub height: i32,
+  pub infinite: bool,
+  pub materials: Vec<String>,
+  pub settings: Option<SavedSettings>,
+  // Chunks whose particles are still to be read, in the order they follow in
+  chunks: VecDeque<(IVec2, u32)>,
+  // Bytes of each of their particles
+  packed_size: usize,
+  body: Box<dyn Read + 'a>,
+  // All the particles of an older version
+  migrated: Option<Vec<SavedParticle>>,
+}
+
+impl<'a> SnapshotReader<'a> {
+  // Reads everything up to the particles of a snapshot of any version this release knows
+  pub fn new(mut reader: impl Read + 'a) -> io::Result<Self> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != MAGIC {
+      let legacy: BodyV1 = bincode::deserialize_from(GzDecoder::new(io::Cursor::new(magic).chain(reader)))
+        .map_err(io::Error::other)?;
+      return Ok(Self::migrated(legacy.into()));
+    }
+
+    let mut version = [0; 2];
+    reader.read_exact(&mut version)?;
+    let version = u16::from_le_bytes(version);
+    let mut flags = [0; 4];
+    reader.read_exact(&mut flags)?;
+    let flags = u32::from_le_bytes(flags);
+    if version > VERSION {
+      return Err(invalid(format!("snapshot version {} is newer than this release's {}", version, VERSION)));
+    }
+    let flags = SnapshotFlags::from_bits(flags).ok_or_else(|| invalid(format!("unknown snapshot flags {:#x}", flags)))?;
+    let compressed = flags.contains(SnapshotFlags::COMPRESSED);
+    let settings = |body: &mut dyn Read| -> io::Result<Option<SavedSettings>> {
+      if !flags.contains(SnapshotFlags::SETTINGS) { return Ok(None) }
+      bincode::deserialize_from(body).map(Some).map_err(io::Error::other)
+    };
+
+    match version {
+      3 => {
+        let mut body: Box<dyn Read + 'a> = if compressed {
+          Box::new(zstd::Decoder::new(reader)?)
+        } else {
+          Box::new(io::BufReader::new(reader))
+        };
+        let header: BodyV3 = bincode::deserialize_from(&mut body).map_err(io::Error::other)?;
+        let settings = settings(&mut body)?;
+        Ok(Self {
+          width: header.width,
+          height: header.height,
+          infinite: flags.contains(SnapshotFlags::INFINITE),
+          materials: header.materials,
+          settings,
+          chunks: header.chunks.into_iter().map(|((x, y), count)| (IVec2::new(x, y), count)).collect(),
+          packed_size: if flags.contains(SnapshotFlags::FULL_VELOCITIES) { PACKED_FULL_SIZE } else { PACKED_SIZE },
+          body,
+          migrated: None,
+        })
+      },
+      2 => {
+        let mut body: Box<dyn Read + 'a> = if compressed {
+          Box::new(GzDecoder::new(reader))
+        } else {
+          Box::new(io::BufReader::new(reader))
+        };
+        let particles: BodyV2 = bincode::deserialize_from(&mut body).map_err(io::Error::other)?;
+        let settings = settings(&mut body)?;
+        Ok(Self::migrated(Snapshot {
+          width: particles.width,
+          height: particles.height,
+          infinite: flags.contains(SnapshotFlags::INFINITE),
+          materials: particles.materials,
+          particles: particles.particles,
+          settings,
+        }))
+      },
+      // The first version never had a header
+      _ => Err(invalid(format!("unknown snapshot version {}", version))),
+    }
+  }
+
+  fn migrated(snapshot: Snapshot) -> Self {
+    Self {
+      width: snapshot.width,
+      height: snapshot.height,
+      infinite: snapshot.infinite,
+      materials: snapshot.materials,
+      settings: snapshot.settings,
+      chunks: VecDeque::new(),
+      packed_size: PACKED_SIZE,
+      body: Box::new(io::empty()),
+      migrated: Some(snapshot.particles),
+    }
+  }
+
+  // Particles still to be read, known before any of them are
+  pub fn particles(&self) -> usize {
+    let migrated = self.migrated.as_ref().map_or(0, Vec::len);
+    migrated + self.chunks.iter().map(|(_, count)| *count as usize).sum::<usize>()
+  }
+
+  // The particles of the next chunk, or `None` once all were read
+  pub fn next_chunk(&mut self) -> io::Result<Option<Vec<SavedParticle>>> {
+    if let Some(particles) = self.migrated.take() {
+      return Ok(Some(particles));
+    }
+    let (chunk, count) = match self.chunks.pop_front() {
+      Some(chunk) => chunk,
+      None => return Ok(None),
+    };
+    // Read up to what the chunk says it holds rather than allocated for it up front, a damaged
+    // count then runs out of data instead of memory
+    let size = count as u64 * self.packed_size as u64;
+    let mut bytes = Vec::new();
+    (&mut self.body).take(size).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != size {
+      return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("chunk {} ends early", chunk)));
+    }
+    Ok(Some(bytes.chunks_exact(self.packed_size).map(|bytes| unpack(bytes, chunk)).collect()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use flate2::{write::GzEncoder, Compression};
+
+  use crate::simulation::SimulationSettings;
+
+  use super::*;
+
+  fn particles() -> Vec<SavedParticle> {
+    vec![
+      SavedParticle { material: 0, position: (0.5, 0.5), velocity: (0., -4.) },
+      SavedParticle { material: 1, position: (-17.25, 3.75), velocity: (1.5, 0.25) },
+      SavedParticle { material: 1, position: (40.125, -9.5), velocity: (-12., 8.) },
+    ]
+  }
+
+  fn snapshot() -> Snapshot {
+    Snapshot {
+      width: 80,
+      height: 40,
+      infinite: true,
+      materials: vec!["Sand".into(), "Water".into()],
+      particles: particles(),
+      settings: Some(SavedSettings { gravity: (0., -9.81), boundary: Boundary::Bounce }),
+    }
+  }
+
+  fn read_all(bytes: &[u8]) -> (SnapshotReader<'_>, Vec<SavedParticle>) {
+    let mut reader = SnapshotReader::new(bytes).unwrap();
+    let expected = reader.particles();
+    let mut particles = Vec::new();
+    while let Some(chunk) = reader.next_chunk().unwrap() {
+      particles.extend(chunk);
+    }
+    assert_eq!(particles.len(), expected);
+    (reader, particles)
+  }
+
+  fn header(version: u16, flags: SnapshotFlags) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&version.to_le_bytes());
+    bytes.extend_from_slice(&flags.bits().to_le_bytes());
+    bytes
+  }
+
+  #[test]
+  fn reads_legacy_gzip_without_header() {
+    let body = BodyV1 { width: 80, height: 40, infinite: true, materials: vec!["Sand".into(), "Water".into()], particles: particles() };
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    bincode::serialize_into(&mut encoder, &body).unwrap();
+    let bytes = encoder.finish().unwrap();
+
+    let (reader, read) = read_all(&bytes);
+    assert_eq!((reader.width, reader.height, reader.infinite), (80, 40, true));
+    assert_eq!(reader.materials, vec!["Sand", "Water"]);
+    assert_eq!(reader.settings, None);
+    assert_eq!(read, particles());
+  }
+
+  #[test]
+  fn reads_version_2() {
+    let snapshot = snapshot();
+    let body = BodyV2 { width: 80, height: 40, materials: snapshot.materials.clone(), particles: particles() };
+    for compressed in [false, true] {
+      let mut flags = SnapshotFlags::INFINITE | SnapshotFlags::SETTINGS;
+      flags.set(SnapshotFlags::COMPRESSED, compressed);
+      let mut bytes = header(2, flags);
+      let mut encoded = Vec::new();
+      bincode::serialize_into(&mut encoded, &body).unwrap();
+      bincode::serialize_into(&mut encoded, &snapshot.settings.unwrap()).unwrap();
+      if compressed {
+        let mut encoder = GzEncoder::new(bytes, Compression::default());
+        encoder.write_all(&encoded).unwrap();
+        bytes = encoder.finish().unwrap();
+      } else {
+        bytes.extend(encoded);
+      }
+
+      let (reader, read) = read_all(&bytes);
+      assert_eq!((reader.width, reader.height, reader.infinite), (80, 40, true));
+      assert_eq!(reader.materials, snapshot.materials);
+      assert_eq!(reader.settings, snapshot.settings);
+      assert_eq!(read, particles());
+    }
+  }
+
+  #[test]
+  fn round_trips_version_3() {
+    let snapshot = snapshot();
+    for compressed in [false, true] {
+      let mut bytes = Vec::new();
+      write(&mut bytes, &snapshot, compressed).unwrap();
+
+      let (reader, mut read) = read_all(&bytes);
+      assert_eq!((reader.width, reader.height, reader.infinite), (80, 40, true));
+      assert_eq!(reader.materials, snapshot.materials);
+      assert_eq!(reader.settings, snapshot.settings);
+      // Chunks come row by row rather than in the order the particles were given in
+      read.sort_by(|a, b| a.position.0.total_cmp(&b.position.0));
+      let mut expected = particles();
+      expected.sort_by(|a, b| a.position.0.total_cmp(&b.position.0));
+      for (read, expected) in read.iter().zip(expected.iter()) {
+        assert_eq!(read.material, expected.material);
+        assert!((read.position.0 - expected.position.0).abs() <= 1. / POSITION_STEPS);
+        assert!((read.position.1 - expected.position.1).abs() <= 1. / POSITION_STEPS);
+        assert!((read.velocity.0 - expected.velocity.0).abs() <= 0.5 / VELOCITY_STEPS);
+        assert!((read.velocity.1 - expected.velocity.1).abs() <= 0.5 / VELOCITY_STEPS);
+      }
+    }
+  }
+
+  #[test]
+  fn round_trips_velocities_too_fast_for_fixed_point() {
+    // At 60 ticks a second
+    let fastest = SimulationSettings { timestep: 1. / 60., ..Default::default() }.max_velocity();
+    assert!(!fits_fixed(fastest));
+    let mut snapshot = snapshot();
+    snapshot.particles[1].velocity = (fastest, -fastest);
+    let mut bytes = Vec::new();
+    write(&mut bytes, &snapshot, true).unwrap();
+
+    let (_, read) = read_all(&bytes);
+    let read = read.iter().find(|particle| particle.position.0 < 0.).unwrap();
+    assert_eq!(read.velocity, (fastest, -fastest));
+  }
+
+  #[test]
+  fn refuses_chunks_cut_short() {
+    let mut bytes = Vec::new();
+    write(&mut bytes, &snapshot(), false).unwrap();
+    bytes.truncate(bytes.len() - 1);
+    let mut reader = SnapshotReader::new(&bytes[..]).unwrap();
+    let mut result = Ok(None);
+    for _ in 0..reader.chunks.len() {
+      result = reader.next_chunk();
+    }
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+  }
+
+  #[test]
+  fn refuses_newer_versions() {
+    let bytes = header(VERSION + 1, SnapshotFlags::empty());
+    assert!(SnapshotReader::new(&bytes[..]).is_err());
+  }
+}
@@ -0,0 +1,573 @@
+use bevy::{prelude::*, utils::{HashMap, HashSet}};
+
+use crate::{
+  pool::ParticlePool,
+  simulation::{SimulationSettings, SimulationStep, TickProgress},
+  Particle,
+};
+
+// Checks the `SpatialIndex` against the particles after every tick while the simulation's debug
+// log is on, anything out of sync is a bug in whatever moved or despawned the particle
+pub struct SpatialIndexPlugin;
+
+impl Plugin for SpatialIndexPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .add_event::<CellChangedEvent>()
+      // Once within the tick, so the chunks the tick moved particles into are awake before they
+      // are aged, and once after everything else that changed the world during the frame
+      .add_system_set(SystemSet::new()
+        .with_run_criteria("fixed_tick")
+        .with_system(verify_index.after(SimulationStep::Commit))
+        .with_system(publish_cell_changes.label("tick_cell_changes").after(SimulationStep::Commit))
+        .with_system(wake_changed_cells.label("wake_changed").after("tick_cell_changes"))
+      )
+      .add_system_to_stage(CoreStage::PostUpdate, publish_cell_changes.label("cell_changes"))
+      .add_system_to_stage(CoreStage::PostUpdate, wake_changed_cells.after("cell_changes"));
+  }
+}
+
+// A cell that became occupied or was freed. Whatever rested on or against it may have to move now.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CellChangedEvent(pub IVec2);
+
+pub trait BoundsExt {
+  fn outside(&self, point: Vec2) -> Option<Vec2>;
+  fn min(&self) -> Vec2;
+  fn max(&self) -> Vec2;
+  fn wrap(&self, point: Vec2) -> Vec2;
+}
+
+impl BoundsExt for Rect<f32> {
+  fn outside(&self, point: Vec2) -> Option<Vec2> {
+    let mut normal = Vec2::ZERO;
+    if point.x < self.left {
+      normal.x = 1.;
+    } else if point.x > self.right {
+      normal.x = -1.;
+    }
+    if point.y < self.bottom {
+      normal.y = 1.;
+    } else if point.y > self.top {
+      normal.y = -1.;
+    }
+    if normal != Vec2::ZERO {
+      Some(normal)
+    } else {
+      None
+    }
+  }
+
+  fn min(&self) -> Vec2 {
+    Vec2::new(self.left, self.bottom)
+  }
+
+  fn max(&self) -> Vec2 {
+    Vec2::new(self.right, self.top)
+  }
+
+  // Brings a point that left through one edge back in through the opposite one
+  fn wrap(&self, point: Vec2) -> Vec2 {
+    if self.outside(point).is_none() { return point }
+    let size = self.max() - self.min();
+    Vec2::new(
+      self.left + (point.x - self.left).rem_euclid(size.x),
+      self.bottom + (point.y - self.bottom).rem_euclid(size.y),
+    )
+  }
+}
+
+// The first cell `SpatialIndex::raycast` ran into
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+  pub cell: IVec2,
+  // `None` for terrain, which has no particle
+  pub entity: Option<Entity>,
+  // In cells along the ray
+  pub distance: f32,
+  // Where the ray entered the cell
+  pub point: Vec2,
+  // Of the side the ray entered the cell through, zero for a ray starting inside it
+  pub normal: IVec2,
+}
+
+// Which particle is in which cell, along with the colliders and the chunks the cells are grouped
+// into for sleeping. Cells, colliders and chunks are only changed together through its methods, so
+// they can not get out of sync with each other.
+#[derive(Clone)]
+pub struct SpatialIndex {
+  // In an infinite world this is only the area the world started with, which the texture
+  // renderer, captures and the GPU backend still cover
+  bounds: Rect<f32>,
+  // Without bounds particles can go anywhere, chunks far from the camera are unloaded to disk
+  pub infinite: bool,
+  // Chunks whose particles were written to disk, see `streaming`
+  pub unloaded: HashSet<IVec2>,
+  // Whether the solid terrain tiles are among the colliders, a new lookup starts without them
+  pub has_terrain: bool,
+  // Cells of doors a signal holds open, see `machines`. Forgotten along with the rest of the world.
+  pub open_doors: HashSet<IVec2>,
+  particles: HashMap<IVec2, Entity>,
+  colliders: HashSet<IVec2>,
+  chunks: HashMap<IVec2, HashSet<IVec2>>,
+  // Chunks that changed recently, mapped to the number of ticks they stay awake for
+  active: HashMap<IVec2, u8>,
+  // Cells occupied or freed since the last `CellChangedEvent`s were sent
+  changed: Vec<IVec2>,
+  // Entities of despawned particles for spawns to reuse, kept when the world is replaced
+  pub pool: ParticlePool,
+}
+
+impl SpatialIndex {
+  pub fn new(width: i32, height: i32) -> Self {
+    Self {
+      bounds: Rect::<f32> {
+        left: -width as f32 / 2.,
+        right: width as f32 / 2.,
+        top: height as f32 / 2.,
+        bottom: -height as f32 / 2.,
+      },
+      infinite: false,
+      unloaded: HashSet::default(),
+      has_terrain: false,
+      open_doors: HashSet::default(),
+      particles: HashMap::new(),
+      colliders: HashSet::default(),
+      chunks: HashMap::new(),
+      active: HashMap::new(),
+      changed: Vec::new(),
+      pool: ParticlePool::default(),
+    }
+  }
+
+  pub fn with_infinite(mut self, infinite: bool) -> Self {
+    self.infinite = infinite;
+    self
+  }
+
+  pub const CHUNK_SIZE: i32 = 16;
+  const ACTIVE_TICKS: u8 = 2;
+
+  pub fn chunk_of(point: IVec2) -> IVec2 {
+    IVec2::new(point.x.div_euclid(Self::CHUNK_SIZE), point.y.div_euclid(Self::CHUNK_SIZE))
+  }
+
+  pub fn bounds(&self) -> Rect<f32> {
+    self.bounds
+  }
+
+  pub fn get(&self, point: &IVec2) -> Option<&Entity> {
+    self.particles.get(point)
+  }
+
+  pub fn contains(&self, point: &IVec2) -> bool {
+    self.particles.contains_key(point)
+  }
+
+  pub fn len(&self) -> usize {
+    self.particles.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.particles.is_empty()
+  }
+
+  // Occupied cells with their particle, in no particular order
+  pub fn iter(&self) -> impl Iterator<Item = (&IVec2, &Entity)> {
+    self.particles.iter()
+  }
+
+  pub fn cells(&self) -> impl Iterator<Item = &IVec2> {
+    self.particles.keys()
+  }
+
+  pub fn values(&self) -> impl Iterator<Item = &Entity> {
+    self.particles.values()
+  }
+
+  // Occupied cells from `min` to `max`, both included, with their particle in cell order
+  pub fn query(&self, min: IVec2, max: IVec2) -> Vec<(IVec2, Entity)> {
+    let _span = trace_span!("lookup_query").entered();
+    let (from, to) = (Self::chunk_of(min), Self::chunk_of(max));
+    let mut found = Vec::new();
+    for chunk_y in from.y..=to.y {
+      for chunk_x in from.x..=to.x {
+        let cells = match self.chunks.get(&IVec2::new(chunk_x, chunk_y)) {
+          Some(cells) => cells,
+          None => continue,
+        };
+        found.extend(cells
+          .iter()
+          .filter(|cell| cell.cmpge(min).all() && cell.cmple(max).all())
+          .filter_map(|cell| self.particles.get(cell).map(|entity| (*cell, *entity))));
+      }
+    }
+    found.sort_unstable_by_key(|(cell, _)| (cell.y, cell.x));
+    found
+  }
+
+  // The `k` particles nearest to `point` within `radius` cells that pass `filter`, closest first and
+  // ties in cell order. Rings of chunks are searched outwards from the point's until none further
+  // out can hold a closer one, so AI built on top does not have to go through every particle.
+  // Distances are to the cells' corners and do not wrap around the world's edges.
+  pub fn k_nearest(&self, point: Vec2, k: usize, radius: f32, filter: impl Fn(IVec2, Entity) -> bool) -> Vec<(IVec2, Entity)> {
+    let _span = trace_span!("lookup_k_nearest").entered();
+    let center = Self::chunk_of(point.floor().as_ivec2());
+    // No chunk is further out than this many rings, nor holds anything within the radius past it
+    let reach = self.chunks.keys().map(|chunk| (*chunk - center).abs().max_element()).max();
+    let reach = match reach {
+      Some(reach) if k > 0 => reach.min(((radius / Self::CHUNK_SIZE as f32).ceil() as i32).saturating_add(1)),
+      _ => return Vec::new(),
+    };
+
+    let mut found: Vec<(f32, IVec2, Entity)> = Vec::new();
+    for ring in 0..=reach {
+      // Cells in this ring and any further out are at least this far away
+      let gap = ((ring - 1).max(0) * Self::CHUNK_SIZE) as f32;
+      if found.len() == k && found[k - 1].0 <= gap * gap { break }
+      for chunk in chunk_ring(center, ring) {
+        let cells = match self.chunks.get(&chunk) {
+          Some(cells) => cells,
+          None => continue,
+        };
+        found.extend(cells
+          .iter()
+          .filter_map(|cell| self.particles.get(cell).map(|entity| (*cell, *entity)))
+          .map(|(cell, entity)| (cell.as_vec2().distance_squared(point), cell, entity))
+          .filter(|(distance, cell, entity)| *distance <= radius * radius && filter(*cell, *entity)));
+      }
+      found.sort_unstable_by(|(a, a_cell, _), (b, b_cell, _)| {
+        a.total_cmp(b).then_with(|| (a_cell.y, a_cell.x).cmp(&(b_cell.y, b_cell.x)))
+      });
+      found.truncate(k);
+    }
+    found.into_iter().map(|(_, cell, entity)| (cell, entity)).collect()
+  }
+
+  // Places `entity` in `point`, returning the one that was there before
+  pub fn insert(&mut self, point: IVec2, entity: Entity) -> Option<Entity> {
+    let _span = trace_span!("lookup_insert").entered();
+    self.changed.push(point);
+    self.chunks.entry(Self::chunk_of(point)).or_default().insert(point);
+    self.particles.insert(point, entity)
+  }
+
+  pub fn remove(&mut self, point: &IVec2) -> Option<Entity> {
+    let _span = trace_span!("lookup_remove").entered();
+    let entity = self.particles.remove(point)?;
+    self.changed.push(*point);
+    self.colliders.remove(point);
+    let chunk = Self::chunk_of(*point);
+    if let Some(cells) = self.chunks.get_mut(&chunk) {
+      cells.remove(point);
+      if cells.is_empty() {
+        self.chunks.remove(&chunk);
+      }
+    }
+    Some(entity)
+  }
+
+  // Occupied chunks in a stable order
+  pub fn chunks(&self) -> Vec<IVec2> {
+    let _span = trace_span!("lookup_chunks").entered();
+    let mut chunks: Vec<IVec2> = self.chunks.keys().copied().collect();
+    chunks.sort_by_key(|chunk| (chunk.y, chunk.x));
+    chunks
+  }
+
+  // Awake chunks in a stable order, only these need simulating
+  pub fn active_chunks(&self) -> Vec<IVec2> {
+    let mut chunks = self.chunks();
+    chunks.retain(|chunk| self.active.contains_key(chunk));
+    chunks
+  }
+
+  // Keeps the chunk containing `point` simulating, along with any neighbor sharing the border
+  // the point sits on so particles resting against it notice the change
+  pub fn wake(&mut self, point: IVec2) {
+    let chunk = Self::chunk_of(point);
+    let local = point - chunk * Self::CHUNK_SIZE;
+    let border = |value: i32| {
+      if value == 0 { -1 } else if value == Self::CHUNK_SIZE - 1 { 1 } else { 0 }
+    };
+    let offset = IVec2::new(border(local.x), border(local.y));
+
+    self.active.insert(chunk, Self::ACTIVE_TICKS);
+    if offset.x != 0 {
+      self.active.insert(chunk + IVec2::new(offset.x, 0), Self::ACTIVE_TICKS);
+    }
+    if offset.y != 0 {
+      self.active.insert(chunk + IVec2::new(0, offset.y), Self::ACTIVE_TICKS);
+    }
+    if offset.x != 0 && offset.y != 0 {
+      self.active.insert(chunk + offset, Self::ACTIVE_TICKS);
+    }
+  }
+
+  // Called once per completed tick with whether a chunk was simulated by it, chunks nothing
+  // happened in fall asleep. Chunks that were skipped stay as they are.
+  pub fn age_active_chunks(&mut self, simulated: impl Fn(IVec2) -> bool) {
+    self.active.retain(|chunk, ticks| {
+      if !simulated(*chunk) { return true }
+      *ticks -= 1;
+      *ticks > 0
+    });
+  }
+
+  // Particles inside a chunk
+  pub fn chunk_len(&self, chunk: IVec2) -> usize {
+    self.chunks.get(&chunk).map_or(0, |cells| cells.len())
+  }
+
+  // Entities inside a chunk in a stable order
+  pub fn chunk_entities(&self, chunk: IVec2) -> Vec<Entity> {
+    let _span = trace_span!("lookup_chunk_entities").entered();
+    let mut cells: Vec<IVec2> = match self.chunks.get(&chunk) {
+      Some(cells) => cells.iter().copied().collect(),
+      None => return Vec::new(),
+    };
+    cells.sort_by_key(|cell| (cell.y, cell.x));
+    cells.iter().filter_map(|cell| self.particles.get(cell).copied()).collect()
+  }
+
+  // Particles in chunks that are not simulating
+  pub fn sleeping_len(&self) -> usize {
+    self.chunks.iter().filter(|(chunk, _)| !self.active.contains_key(chunk)).map(|(_, cells)| cells.len()).sum()
+  }
+
+  // Whether the chunk containing `point` is skipped by the simulation for now
+  pub fn is_sleeping(&self, point: IVec2) -> bool {
+    !self.active.contains_key(&Self::chunk_of(point))
+  }
+
+  pub fn is_collider(&self, point: IVec2) -> bool {
+    self.colliders.contains(&point)
+  }
+
+  // Normal of the edge `point` is past, never in an infinite world
+  pub fn outside(&self, point: Vec2) -> Option<Vec2> {
+    if self.infinite { None } else { self.bounds.outside(point) }
+  }
+
+  pub fn wrap(&self, point: Vec2) -> Vec2 {
+    if self.infinite { point } else { self.bounds.wrap(point) }
+  }
+
+  // Walks the cells `origin + direction * distance` passes through for up to `max_distance` cells,
+  // returning the first particle or collider that passes `filter`. The ones that do not are seen
+  // through, `filter` is called for each in the order the ray reaches them. Rays stop at the world's
+  // edges, in an infinite world only `max_distance` does.
+  pub fn raycast(
+    &self,
+    origin: Vec2,
+    direction: Vec2,
+    max_distance: f32,
+    mut filter: impl FnMut(IVec2, Option<Entity>) -> bool,
+  ) -> Option<RayHit> {
+    let direction = direction.normalize_or_zero();
+    if direction == Vec2::ZERO || !origin.is_finite() || max_distance.is_nan() || max_distance < 0. { return None }
+    if self.infinite && !max_distance.is_finite() { return None }
+
+    let sign = |value: f32| if value > 0. { 1 } else if value < 0. { -1 } else { 0 };
+    let step = IVec2::new(sign(direction.x), sign(direction.y));
+    // Distance along the ray between crossings of vertical and of horizontal cell edges
+    let delta = Vec2::new(1. / direction.x.abs(), 1. / direction.y.abs());
+    let mut cell = origin.floor().as_ivec2();
+    // Distance to the next crossing of each
+    let first = |origin: f32, cell: i32, step: i32, delta: f32| match step {
+      1 => (cell as f32 + 1. - origin) * delta,
+      -1 => (origin - cell as f32) * delta,
+      _ => f32::INFINITY,
+    };
+    let mut next = Vec2::new(first(origin.x, cell.x, step.x, delta.x), first(origin.y, cell.y, step.y, delta.y));
+    let mut distance = 0.;
+    let mut normal = IVec2::ZERO;
+
+    loop {
+      if self.outside(cell.as_vec2()).is_some() { return None }
+      let entity = self.particles.get(&cell).copied();
+      if (entity.is_some() || self.colliders.contains(&cell)) && filter(cell, entity) {
+        return Some(RayHit { cell, entity, distance, point: origin + direction * distance, normal });
+      }
+      if next.x < next.y {
+        distance = next.x;
+        next.x += delta.x;
+        cell.x += step.x;
+        normal = IVec2::new(-step.x, 0);
+      } else {
+        distance = next.y;
+        next.y += delta.y;
+        cell.y += step.y;
+        normal = IVec2::new(0, -step.y);
+      }
+      if distance > max_distance { return None }
+    }
+  }
+
+  pub fn is_free(&self, point: IVec2) -> bool {
+    self.outside(point.as_vec2()).is_none()
+      && !self.particles.contains_key(&point)
+      && !self.colliders.contains(&point)
+  }
+
+  pub fn insert_static(&mut self, point: IVec2, entity: Entity) {
+    self.insert(point, entity);
+    self.colliders.insert(point);
+  }
+
+  // Terrain cells are colliders without a particle in them, see `terrain`
+  pub fn insert_terrain(&mut self, point: IVec2) {
+    self.colliders.insert(point);
+    self.changed.push(point);
+  }
+
+  // Moves the particle in `from` to `to`, a collider stays one. Nothing changes when `from` is empty
+  // or `to` is taken.
+  pub fn move_entity(&mut self, from: IVec2, to: IVec2) -> bool {
+    if from == to || self.particles.contains_key(&to) { return false }
+    let collider = self.colliders.contains(&from);
+    let entity = match self.remove(&from) {
+      Some(entity) => entity,
+      None => return false,
+    };
+    if collider {
+      self.insert_static(to, entity);
+    } else {
+      self.insert(to, entity);
+    }
+    true
+  }
+
+  pub fn remove_entity(&mut self, point: IVec2, entity: Entity) {
+    if self.particles.get(&point) == Some(&entity) {
+      self.remove(&point);
+    }
+  }
+}
+
+// Chunks exactly `ring` chunks away from `center` along either axis
+fn chunk_ring(center: IVec2, ring: i32) -> Vec<IVec2> {
+  if ring == 0 { return vec![center] }
+  let mut chunks = Vec::with_capacity(8 * ring as usize);
+  for x in -ring..=ring {
+    chunks.push(center + IVec2::new(x, -ring));
+    chunks.push(center + IVec2::new(x, ring));
+  }
+  for y in 1 - ring..ring {
+    chunks.push(center + IVec2::new(-ring, y));
+    chunks.push(center + IVec2::new(ring, y));
+  }
+  chunks
+}
+
+fn publish_cell_changes(mut spatial_index: ResMut<SpatialIndex>, mut events: EventWriter<CellChangedEvent>) {
+  if spatial_index.changed.is_empty() { return }
+  let mut changed = std::mem::take(&mut spatial_index.changed);
+  changed.sort_unstable_by_key(|cell| (cell.y, cell.x));
+  changed.dedup();
+  events.send_batch(changed.into_iter().map(CellChangedEvent));
+}
+
+// Changed cells keep their chunk simulating, and the chunks next to them when they are on the
+// border, so the particles around them notice the change. A pile whose support was taken away
+// collapses even where it had come to rest.
+fn wake_changed_cells(mut spatial_index: ResMut<SpatialIndex>, mut events: EventReader<CellChangedEvent>) {
+  for CellChangedEvent(cell) in events.iter() {
+    spatial_index.wake(*cell);
+  }
+}
+
+fn verify_index(
+  settings: Res<SimulationSettings>,
+  progress: Res<TickProgress>,
+  spatial_index: Res<SpatialIndex>,
+  particles: Query<&Particle>,
+) {
+  if !settings.debug_log || !progress.is_complete() { return }
+  let mut missing = 0;
+  let mut misplaced = 0;
+  for (cell, entity) in spatial_index.iter() {
+    match particles.get(*entity) {
+      Ok(particle) if particle.position.floor().as_ivec2() != *cell => misplaced += 1,
+      Ok(_) => {},
+      Err(_) => missing += 1,
+    }
+  }
+  if missing + misplaced > 0 {
+    warn!("Spatial index out of sync: {} cells without a particle, {} particles outside their cell", missing, misplaced);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn raycast_stops_at_the_first_particle() {
+    let mut spatial_index = SpatialIndex::new(32, 32);
+    let (near, far) = (Entity::from_raw(1), Entity::from_raw(2));
+    spatial_index.insert(IVec2::new(4, 0), near);
+    spatial_index.insert(IVec2::new(8, 0), far);
+
+    let hit = spatial_index.raycast(Vec2::new(0.5, 0.5), Vec2::X, 16., |_, _| true).unwrap();
+    assert_eq!(hit, RayHit { cell: IVec2::new(4, 0), entity: Some(near), distance: 3.5, point: Vec2::new(4., 0.5), normal: IVec2::new(-1, 0) });
+
+    let hit = spatial_index.raycast(Vec2::new(0.5, 0.5), Vec2::X, 16., |_, entity| entity != Some(near)).unwrap();
+    assert_eq!(hit.entity, Some(far));
+    assert!(spatial_index.raycast(Vec2::new(0.5, 0.5), Vec2::X, 3., |_, _| true).is_none());
+  }
+
+  #[test]
+  fn raycast_reports_the_side_it_entered() {
+    let mut spatial_index = SpatialIndex::new(32, 32);
+    spatial_index.insert_terrain(IVec2::new(0, -3));
+
+    let hit = spatial_index.raycast(Vec2::new(0.5, 0.5), Vec2::new(0., -1.), 16., |_, _| true).unwrap();
+    assert_eq!((hit.cell, hit.entity, hit.normal), (IVec2::new(0, -3), None, IVec2::new(0, 1)));
+    assert!((hit.distance - 2.5).abs() < 1e-5);
+
+    // A ray starting inside a cell hits it right away, from no side
+    let hit = spatial_index.raycast(Vec2::new(0.5, -2.5), Vec2::X, 16., |_, _| true).unwrap();
+    assert_eq!((hit.distance, hit.normal), (0., IVec2::ZERO));
+  }
+
+  fn spatial_index(particles: &[(i32, i32)]) -> (SpatialIndex, Vec<Entity>) {
+    let mut spatial_index = SpatialIndex::new(64, 64);
+    let entities: Vec<Entity> = (0..particles.len() as u32).map(Entity::from_raw).collect();
+    for ((x, y), entity) in particles.iter().zip(entities.iter()) {
+      spatial_index.insert(IVec2::new(*x, *y), *entity);
+    }
+    (spatial_index, entities)
+  }
+
+  #[test]
+  fn k_nearest_looks_past_the_points_chunk() {
+    // Across the chunk's edge from the point, and in its far corner
+    let (spatial_index, entities) = spatial_index(&[(-1, 1), (14, 14)]);
+    let nearest = spatial_index.k_nearest(Vec2::new(1., 1.), 1, f32::INFINITY, |_, _| true);
+    assert_eq!(nearest, vec![(IVec2::new(-1, 1), entities[0])]);
+  }
+
+  #[test]
+  fn k_nearest_keeps_within_the_radius() {
+    let (spatial_index, entities) = spatial_index(&[(3, 0), (0, 5)]);
+    let nearest = spatial_index.k_nearest(Vec2::ZERO, 2, 4., |_, _| true);
+    assert_eq!(nearest, vec![(IVec2::new(3, 0), entities[0])]);
+    assert!(spatial_index.k_nearest(Vec2::ZERO, 2, 2.5, |_, _| true).is_empty());
+  }
+
+  #[test]
+  fn k_nearest_skips_what_the_filter_refuses() {
+    let (spatial_index, entities) = spatial_index(&[(1, 0), (2, 0), (3, 0)]);
+    let nearest = spatial_index.k_nearest(Vec2::ZERO, 2, f32::INFINITY, |_, entity| entity != entities[0]);
+    assert_eq!(nearest, vec![(IVec2::new(2, 0), entities[1]), (IVec2::new(3, 0), entities[2])]);
+  }
+
+  #[test]
+  fn k_nearest_returns_everything_when_k_is_larger() {
+    // The two at the same distance come in cell order
+    let (spatial_index, entities) = spatial_index(&[(20, 20), (0, 2), (-2, 0)]);
+    let nearest = spatial_index.k_nearest(Vec2::ZERO, 10, f32::INFINITY, |_, _| true);
+    assert_eq!(nearest, vec![(IVec2::new(-2, 0), entities[2]), (IVec2::new(0, 2), entities[1]), (IVec2::new(20, 20), entities[0])]);
+  }
+}
@@ -0,0 +1,319 @@
+use bevy::prelude::*;
+
+use crate::{
+  material::{MaterialId, MaterialRegistry},
+  simulation::{SimulationClock, SimulationSettings, SimulationStep},
+  spawn_particle, Particle, SpatialIndex, Static,
+};
+
+pub struct SpringsPlugin;
+
+impl Plugin for SpringsPlugin {
+  fn build(&self, app: &mut App) {
+    app.add_system_set(SystemSet::new()
+      .with_run_criteria("fixed_tick")
+      .with_system(solve_springs.label("springs").label(SimulationStep::Integrate))
+      .with_system(inflate_soft_bodies.after("springs").label(SimulationStep::Integrate))
+    );
+  }
+}
+
+// Pulls two particles toward `rest_length` cells apart. Springs are entities of their own, so a
+// particle can be linked to any number of others.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Spring {
+  pub a: Entity,
+  pub b: Entity,
+  pub rest_length: f32,
+  pub stiffness: f32,
+  // Resists the ends moving apart or together, stops the spring from oscillating forever
+  pub damping: f32,
+  // Stretched past this length the spring snaps
+  pub tear_length: Option<f32>,
+}
+
+impl Spring {
+  pub const STIFFNESS: f32 = 32.;
+  pub const DAMPING: f32 = 2.;
+
+  // A spring at rest at the current distance between `a` and `b`
+  pub fn between(a: Entity, b: Entity, a_position: Vec2, b_position: Vec2) -> Self {
+    Self {
+      a,
+      b,
+      rest_length: a_position.distance(b_position),
+      stiffness: Self::STIFFNESS,
+      damping: Self::DAMPING,
+      tear_length: None,
+    }
+  }
+
+  // Snaps once stretched to `stretch` times its rest length
+  pub fn tearing_at(mut self, stretch: f32) -> Self {
+    self.tear_length = Some(self.rest_length * stretch);
+    self
+  }
+}
+
+// Links a line of particles from `from` to `to` with springs, spawning `material` on every free
+// cell along it. Cells already holding a particle are linked as they are, so starting or ending
+// on a fixed material anchors the chain.
+pub fn spawn_chain(
+  commands: &mut Commands,
+  spatial_index: &mut SpatialIndex,
+  materials: &MaterialRegistry,
+  from: IVec2,
+  to: IVec2,
+  material: MaterialId,
+) -> Vec<Entity> {
+  let steps = (to - from).abs().max_element().max(1);
+  let mut links: Vec<(Entity, IVec2)> = Vec::new();
+  for step in 0..=steps {
+    let cell = (from.as_vec2() + (to - from).as_vec2() * step as f32 / steps as f32).round().as_ivec2();
+    if links.last().is_some_and(|(_, last)| *last == cell) { continue }
+    let entity = match spatial_index.get(&cell) {
+      Some(entity) => *entity,
+      None if spatial_index.is_free(cell) => spawn_particle(commands, spatial_index, materials, cell, material),
+      None => continue,
+    };
+    links.push((entity, cell));
+  }
+
+  for pair in links.windows(2) {
+    let ((a, a_cell), (b, b_cell)) = (pair[0], pair[1]);
+    commands.spawn().insert(Spring::between(a, b, a_cell.as_vec2(), b_cell.as_vec2()));
+  }
+  links.into_iter().map(|(entity, _)| entity).collect()
+}
+
+// How far cloth stretches before it tears, against its rest length
+const CLOTH_TEAR_STRETCH: f32 = 5.;
+
+// A sheet of `material` `width` cells wide hanging `height` cells down from `top_left`, every
+// particle linked to its neighbors by springs along the rows and columns and to its diagonal ones
+// by shear springs. The particles at `pins`, offsets from `top_left` going right and down, are
+// fixed in place. The sheet drapes over whatever it falls on and tears where it is pulled too far.
+// Cells already taken are left out of the sheet.
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_cloth(
+  commands: &mut Commands,
+  spatial_index: &mut SpatialIndex,
+  materials: &MaterialRegistry,
+  top_left: IVec2,
+  width: i32,
+  height: i32,
+  material: MaterialId,
+  pins: &[IVec2],
+) -> Vec<Entity> {
+  let cell_of = |x: i32, y: i32| top_left + IVec2::new(x, -y);
+  let mut grid = vec![None; (width.max(0) * height.max(0)) as usize];
+  for y in 0..height {
+    for x in 0..width {
+      let cell = cell_of(x, y);
+      if !spatial_index.is_free(cell) { continue }
+      let entity = spawn_particle(commands, spatial_index, materials, cell, material);
+      if pins.contains(&IVec2::new(x, y)) {
+        // Reinserting adds the cell's collider along with the `Static` marker
+        spatial_index.insert_static(cell, entity);
+        commands.entity(entity).insert(Static);
+      }
+      grid[(y * width + x) as usize] = Some(entity);
+    }
+  }
+
+  let at = |x: i32, y: i32| (x >= 0 && x < width && y < height).then(|| grid[(y * width + x) as usize]).flatten();
+  for y in 0..height {
+    for x in 0..width {
+      let a = match at(x, y) {
+        Some(a) => a,
+        None => continue,
+      };
+      // Right and down hold the weave, the diagonals keep it from shearing flat
+      for (dx, dy) in [(1, 0), (0, 1), (1, 1), (-1, 1)] {
+        if let Some(b) = at(x + dx, y + dy) {
+          let spring = Spring::between(a, b, cell_of(x, y).as_vec2(), cell_of(x + dx, y + dy).as_vec2());
+          commands.spawn().insert(spring.tearing_at(CLOTH_TEAR_STRETCH));
+        }
+      }
+    }
+  }
+  grid.into_iter().flatten().collect()
+}
+
+// A closed ring of particles held together by springs and pushed outward by the gas it encloses,
+// so it squashes against what it lands on and springs back
+#[derive(Component, Clone, Debug)]
+pub struct SoftBody {
+  // In counter-clockwise order
+  pub particles: Vec<Entity>,
+  // Area the ring encloses with no pressure difference, in cells
+  pub rest_area: f32,
+  pub pressure: f32,
+}
+
+impl SoftBody {
+  pub const PRESSURE: f32 = 16.;
+}
+
+// Spawns a ring of `material` around `center` linked by springs to its neighbors and the ones
+// after them, with pressure keeping it round. Returns the soft body's entity.
+pub fn spawn_soft_body(
+  commands: &mut Commands,
+  spatial_index: &mut SpatialIndex,
+  materials: &MaterialRegistry,
+  center: IVec2,
+  radius: f32,
+  material: MaterialId,
+) -> Entity {
+  let count = (std::f32::consts::TAU * radius).round().max(6.) as usize;
+  let mut cells: Vec<IVec2> = Vec::new();
+  for index in 0..count {
+    let angle = std::f32::consts::TAU * index as f32 / count as f32;
+    let cell = (center.as_vec2() + Vec2::new(angle.cos(), angle.sin()) * radius).round().as_ivec2();
+    if !cells.contains(&cell) && spatial_index.is_free(cell) {
+      cells.push(cell);
+    }
+  }
+
+  let particles: Vec<Entity> = cells
+    .iter()
+    .map(|cell| spawn_particle(commands, spatial_index, materials, *cell, material))
+    .collect();
+  // Neighbors hold the outline, the springs skipping one keep it from folding
+  for skip in [1, 2] {
+    if particles.len() <= skip * 2 { continue }
+    for index in 0..particles.len() {
+      let other = (index + skip) % particles.len();
+      let spring = Spring::between(particles[index], particles[other], cells[index].as_vec2(), cells[other].as_vec2());
+      commands.spawn().insert(spring);
+    }
+  }
+
+  let positions: Vec<Vec2> = cells.iter().map(|cell| cell.as_vec2()).collect();
+  commands.spawn().insert(SoftBody { particles, rest_area: polygon_area(&positions), pressure: SoftBody::PRESSURE }).id()
+}
+
+// Signed area of a polygon, positive when its points go counter-clockwise
+fn polygon_area(points: &[Vec2]) -> f32 {
+  let count = points.len();
+  (0..count).map(|index| points[index].perp_dot(points[(index + 1) % count])).sum::<f32>() / 2.
+}
+
+fn solve_springs(
+  mut commands: Commands,
+  clock: Res<SimulationClock>,
+  settings: Res<SimulationSettings>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  springs: Query<(Entity, &Spring)>,
+  mut particles: Query<(&mut Particle, Option<&Static>)>,
+) {
+  // A budgeted tick runs its systems over several frames but should only apply the forces once
+  if !clock.ticked { return }
+  let delta = settings.tick_delta();
+
+  let mut live = Vec::new();
+  for (entity, spring) in springs.iter() {
+    let ends = match particles.get_many([spring.a, spring.b]) {
+      Ok(ends) => ends,
+      // A spring goes with either of its ends
+      Err(_) => {
+        commands.entity(entity).despawn();
+        continue;
+      },
+    };
+    let length = ends[0].0.position.distance(ends[1].0.position);
+    if spring.tear_length.is_some_and(|tear_length| length > tear_length) {
+      commands.entity(entity).despawn();
+      continue;
+    }
+    live.push(spring);
+  }
+
+  // Each pass sees the velocities the ones before left, so a pull passes along a whole chain of
+  // springs in a tick rather than one link a tick. The stretch is pulled on a share of it per pass.
+  let iterations = settings.solver_iterations.max(1);
+  for _ in 0..iterations {
+    for spring in live.iter() {
+      let [(mut a, a_fixed), (mut b, b_fixed)] = match particles.get_many_mut([spring.a, spring.b]) {
+        Ok(ends) => ends,
+        Err(_) => continue,
+      };
+
+      let offset = b.position - a.position;
+      let length = offset.length();
+      if length <= f32::EPSILON { continue }
+      let direction = offset / length;
+      let stretch = length - spring.rest_length;
+      let closing = (b.velocity - a.velocity).dot(direction);
+      // The mass the spring pulls on, one end's alone when the other is fixed
+      let mass = match (a_fixed, b_fixed) {
+        (None, None) => a.mass * b.mass / (a.mass + b.mass),
+        (None, Some(_)) => a.mass,
+        (Some(_), None) => b.mass,
+        (Some(_), Some(_)) => continue,
+      };
+      // A spring stiffer than a tick can follow would overshoot its rest length further every tick
+      // and fling its ends apart, so it pulls at most hard enough to close the stretch in one tick
+      let stiffness = spring.stiffness.min(mass / (delta * delta)) / iterations as f32;
+      let damping = spring.damping.min(mass / delta);
+      let force = direction * (stiffness * stretch + damping * closing) * delta;
+
+      // Fixed particles do not move, the other end takes all of it
+      if a_fixed.is_none() {
+        let mass = a.mass;
+        a.velocity += force / mass;
+        spatial_index.wake(a.position.floor().as_ivec2());
+      }
+      if b_fixed.is_none() {
+        let mass = b.mass;
+        b.velocity -= force / mass;
+        spatial_index.wake(b.position.floor().as_ivec2());
+      }
+    }
+  }
+}
+
+fn inflate_soft_bodies(
+  mut commands: Commands,
+  clock: Res<SimulationClock>,
+  settings: Res<SimulationSettings>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  bodies: Query<(Entity, &SoftBody)>,
+  mut particles: Query<(&mut Particle, Option<&Static>)>,
+) {
+  if !clock.ticked { return }
+  let delta = settings.tick_delta();
+
+  for (entity, body) in bodies.iter() {
+    let positions: Option<Vec<Vec2>> = body.particles
+      .iter()
+      .map(|particle| particles.get(*particle).ok().map(|(particle, _)| particle.position))
+      .collect();
+    // Once part of the ring is gone it no longer encloses anything, the springs hold what is left
+    let positions = match positions {
+      Some(positions) if positions.len() >= 3 => positions,
+      _ => {
+        commands.entity(entity).despawn();
+        continue;
+      },
+    };
+    let area = polygon_area(&positions);
+    if area <= f32::EPSILON || body.rest_area <= f32::EPSILON { continue }
+    let pressure = body.pressure * (body.rest_area / area - 1.);
+
+    // Each edge is pushed out along its normal in proportion to its length, split between its ends
+    let count = positions.len();
+    for index in 0..count {
+      let next = (index + 1) % count;
+      let edge = positions[next] - positions[index];
+      let force = Vec2::new(edge.y, -edge.x) * pressure * delta / 2.;
+      for end in [index, next] {
+        if let Ok((mut particle, None)) = particles.get_mut(body.particles[end]) {
+          let mass = particle.mass;
+          particle.velocity += force / mass;
+          spatial_index.wake(particle.position.floor().as_ivec2());
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,82 @@
+use bevy::prelude::*;
+
+use crate::{
+  actions::Action,
+  locale::Locale,
+  profiling::ChunkStats,
+  simulation::{SimulationClock, SimulationDiagnostics},
+  SpatialIndex,
+};
+
+// A corner panel of simulation counters, toggled with `Action::ToggleStats`
+pub struct StatsPlugin;
+
+impl Plugin for StatsPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .add_system(toggle_stats)
+      .add_system(update_stats);
+  }
+}
+
+#[derive(Component)]
+struct StatsText;
+
+fn toggle_stats(
+  mut commands: Commands,
+  asset_server: Res<AssetServer>,
+  actions: Res<Input<Action>>,
+  panels: Query<Entity, With<StatsText>>,
+) {
+  if !actions.just_pressed(Action::ToggleStats) { return }
+  if !panels.is_empty() {
+    for entity in panels.iter() {
+      commands.entity(entity).despawn_recursive();
+    }
+    return;
+  }
+
+  let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+  commands
+    .spawn_bundle(TextBundle {
+      style: Style {
+        position_type: PositionType::Absolute,
+        position: Rect { left: Val::Px(6.), top: Val::Px(6.), ..Default::default() },
+        ..Default::default()
+      },
+      text: Text::with_section("", TextStyle { font, font_size: 16., color: Color::WHITE }, Default::default()),
+      ..Default::default()
+    })
+    .insert(StatsText);
+}
+
+fn update_stats(
+  clock: Res<SimulationClock>,
+  diagnostics: Res<SimulationDiagnostics>,
+  spatial_index: Res<SpatialIndex>,
+  chunk_stats: Res<ChunkStats>,
+  locale: Res<Locale>,
+  mut texts: Query<&mut Text, With<StatsText>>,
+) {
+  for mut text in texts.iter_mut() {
+    let mut panel = locale.format("stats.panel", &[
+      ("tick", &clock.tick),
+      ("particles", &spatial_index.len()),
+      ("chunks", &spatial_index.active_chunks().len()),
+      ("escaped", &diagnostics.escaped_particles),
+      ("sanitized", &diagnostics.sanitized_velocities),
+      ("separated", &diagnostics.separated_particles),
+    ]);
+    if let Some((chunk, stat)) = chunk_stats.hottest(1).first() {
+      panel.push('\n');
+      panel.push_str(&locale.format("stats.hottest", &[
+        ("x", &chunk.x),
+        ("y", &chunk.y),
+        ("cost", &format!("{:.3}", stat.cost.as_secs_f64() * 1000.)),
+        ("particles", &stat.particles),
+        ("awake", &format!("{:.0}", stat.awake * 100.)),
+      ]));
+    }
+    text.sections[0].value = panel;
+  }
+}
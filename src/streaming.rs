@@ -0,0 +1,134 @@
+use std::{fs, io, path::PathBuf};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  cursor::MainCamera,
+  despawn_particle,
+  material::{MaterialId, MaterialRegistry},
+  simulation::{SimulationStep, TickProgress},
+  spawn_particle_with_velocity, Particle, SpatialIndex,
+};
+
+// Keeps an infinite world to the chunks around the camera, writing the rest to disk and reading
+// them back as the camera comes close again
+pub struct StreamingPlugin;
+
+impl Plugin for StreamingPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .add_startup_system(clear_stored_chunks)
+      .add_system_set(SystemSet::new()
+        .with_run_criteria("fixed_tick")
+        .with_system(stream_chunks.after(SimulationStep::Commit))
+      );
+  }
+}
+
+const DIRECTORY: &str = "chunks";
+// Chunks from the camera's chunk that are kept loaded, and past which they are unloaded. The gap
+// stops a chunk on the edge from being written and read back every time the camera moves a bit.
+const LOAD_RADIUS: i32 = 3;
+const UNLOAD_RADIUS: i32 = 5;
+
+// Material ids only hold for the session, which is as long as stored chunks are kept for
+#[derive(Serialize, Deserialize)]
+struct StoredParticle {
+  material: usize,
+  position: (f32, f32),
+  velocity: (f32, f32),
+}
+
+fn chunk_path(chunk: IVec2) -> PathBuf {
+  PathBuf::from(DIRECTORY).join(format!("{}_{}.bin", chunk.x, chunk.y))
+}
+
+// Chunks left over from an earlier session belong to a different world
+fn clear_stored_chunks() {
+  match fs::remove_dir_all(DIRECTORY) {
+    Err(error) if error.kind() != io::ErrorKind::NotFound => warn!("Could not clear stored chunks: {}", error),
+    _ => {},
+  }
+}
+
+// Runs once a tick is complete, so no chunk disappears halfway through being simulated. Springs,
+// conveyors and other components beyond the material do not survive being unloaded.
+fn stream_chunks(
+  mut commands: Commands,
+  progress: Res<TickProgress>,
+  materials: Res<MaterialRegistry>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  cameras: Query<&Transform, With<MainCamera>>,
+  particles: Query<(&Particle, &MaterialId)>,
+) {
+  if !spatial_index.infinite || !progress.is_complete() { return }
+  let camera = match cameras.get_single() {
+    Ok(transform) => (transform.translation.truncate() / Particle::SPRITE_SIZE).floor().as_ivec2(),
+    Err(_) => return,
+  };
+  let center = SpatialIndex::chunk_of(camera);
+  let distance = |chunk: IVec2| (chunk - center).abs().max_element();
+
+  for chunk in spatial_index.chunks() {
+    if distance(chunk) <= UNLOAD_RADIUS { continue }
+    // Particles can wander into a chunk that is already on disk, they join what is stored there
+    let mut stored = if spatial_index.unloaded.contains(&chunk) {
+      read_chunk(chunk).unwrap_or_else(|error| {
+        error!("Could not load chunk {}: {}", chunk, error);
+        Vec::new()
+      })
+    } else {
+      Vec::new()
+    };
+    for entity in spatial_index.chunk_entities(chunk) {
+      let (particle, material) = match particles.get(entity) {
+        Ok(particle) => particle,
+        Err(_) => continue,
+      };
+      stored.push(StoredParticle {
+        material: material.0,
+        position: particle.position.into(),
+        velocity: particle.velocity.into(),
+      });
+      despawn_particle(&mut commands, &mut spatial_index, entity, particle);
+    }
+    if let Err(error) = write_chunk(chunk, &stored) {
+      error!("Could not unload chunk {}: {}", chunk, error);
+    }
+    spatial_index.unloaded.insert(chunk);
+  }
+
+  let nearby: Vec<IVec2> = spatial_index.unloaded.iter().copied().filter(|chunk| distance(*chunk) <= LOAD_RADIUS).collect();
+  for chunk in nearby {
+    spatial_index.unloaded.remove(&chunk);
+    let stored = match read_chunk(chunk) {
+      Ok(stored) => stored,
+      Err(error) => {
+        error!("Could not load chunk {}: {}", chunk, error);
+        continue;
+      },
+    };
+    for particle in stored {
+      let position = Vec2::from(particle.position);
+      let point = position.floor().as_ivec2();
+      // Whatever moved into the chunk while it was unloaded keeps its cell
+      if particle.material >= materials.iter().count() || !spatial_index.is_free(point) { continue }
+      let velocity = Vec2::from(particle.velocity);
+      spawn_particle_with_velocity(&mut commands, &mut spatial_index, &materials, point, MaterialId(particle.material), velocity);
+    }
+  }
+}
+
+fn write_chunk(chunk: IVec2, particles: &[StoredParticle]) -> io::Result<()> {
+  fs::create_dir_all(DIRECTORY)?;
+  let bytes = bincode::serialize(particles).map_err(io::Error::other)?;
+  fs::write(chunk_path(chunk), bytes)
+}
+
+fn read_chunk(chunk: IVec2) -> io::Result<Vec<StoredParticle>> {
+  let path = chunk_path(chunk);
+  let bytes = fs::read(&path)?;
+  fs::remove_file(path)?;
+  bincode::deserialize(&bytes).map_err(io::Error::other)
+}
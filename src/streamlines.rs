@@ -0,0 +1,187 @@
+use bevy::prelude::*;
+
+use crate::{
+  actions::Action,
+  simulation::{SimulationClock, SimulationSettings, SimulationStep},
+  visualization::ramp,
+  BoundsExt, Particle, SpatialIndex,
+};
+
+// Shows where the wind blows, for tuning wind setups: streamlines of dots drifting along it across
+// the world, or tracers let in on the side it blows from that drift with it until they run into
+// something. Cycled with `Action::CycleWindView` and only drawn while there is wind.
+pub struct StreamlinesPlugin;
+
+impl Plugin for StreamlinesPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<WindView>()
+      .init_resource::<Tracers>()
+      .init_resource::<DotPool>()
+      .add_system(cycle_wind_view)
+      .add_system(move_tracers.label("move_tracers").after(SimulationStep::Commit))
+      .add_system(draw_wind.after("move_tracers"));
+  }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WindView {
+  #[default]
+  Off,
+  Streamlines,
+  Tracers,
+}
+
+impl WindView {
+  pub fn next(self) -> Self {
+    match self {
+      WindView::Off => WindView::Streamlines,
+      WindView::Streamlines => WindView::Tracers,
+      WindView::Tracers => WindView::Off,
+    }
+  }
+}
+
+// Cells between the streamlines, across the wind and along it
+const STREAMLINE_SPACING: i32 = 6;
+// Dots of a streamline, one cell apart, brightening towards its head like an arrow
+const STREAMLINE_LENGTH: usize = 4;
+// Wind that reads as the strongest, the rest is colored against it
+const MAX_WIND: f32 = 8.;
+// Ticks between waves of tracers, and cells between the tracers of a wave
+const TRACER_INTERVAL: u64 = 4;
+const TRACER_SPACING: i32 = 3;
+const MAX_TRACERS: usize = 2000;
+const DOT_SIZE: f32 = 3.;
+
+// Massless points that go where the air does, nothing in the simulation feels them. The wind is
+// taken for the speed of the air in cells per second.
+#[derive(Default)]
+struct Tracers(Vec<Vec2>);
+
+#[derive(Component)]
+struct WindDot;
+
+// Dot sprites are reused between frames, the ones not needed are hidden
+#[derive(Default)]
+struct DotPool(Vec<Entity>);
+
+fn cycle_wind_view(actions: Res<Input<Action>>, mut view: ResMut<WindView>, mut tracers: ResMut<Tracers>) {
+  if !actions.just_pressed(Action::CycleWindView) { return }
+  *view = view.next();
+  tracers.0.clear();
+  info!("Showing wind as {:?}", *view);
+}
+
+// Moves the tracers a tick along and lets a wave of new ones in every `TRACER_INTERVAL` ticks.
+// Tracers are dropped where they leave the world or reach a cell that is taken.
+fn move_tracers(
+  view: Res<WindView>,
+  clock: Res<SimulationClock>,
+  settings: Res<SimulationSettings>,
+  spatial_index: Res<SpatialIndex>,
+  mut tracers: ResMut<Tracers>,
+) {
+  if *view != WindView::Tracers || !clock.ticked { return }
+  let wind = settings.wind;
+  if wind == Vec2::ZERO {
+    tracers.0.clear();
+    return;
+  }
+
+  let step = wind * settings.tick_delta();
+  tracers.0.retain_mut(|position| {
+    *position += step;
+    spatial_index.is_free(position.floor().as_ivec2())
+  });
+
+  if !clock.tick.is_multiple_of(TRACER_INTERVAL) { return }
+  let (min, max) = (spatial_index.bounds().min(), spatial_index.bounds().max());
+  let mut wave = Vec::new();
+  // The edges the wind blows in from, both of them when it blows at an angle
+  if wind.x != 0. {
+    let x = if wind.x > 0. { min.x } else { max.x - 1. };
+    wave.extend((min.y as i32..max.y as i32).step_by(TRACER_SPACING as usize).map(|y| Vec2::new(x, y as f32 + 0.5)));
+  }
+  if wind.y != 0. {
+    let y = if wind.y > 0. { min.y } else { max.y - 1. };
+    wave.extend((min.x as i32..max.x as i32).step_by(TRACER_SPACING as usize).map(|x| Vec2::new(x as f32 + 0.5, y)));
+  }
+  let room = MAX_TRACERS.saturating_sub(tracers.0.len());
+  tracers.0.extend(wave.into_iter().filter(|position| spatial_index.is_free(position.floor().as_ivec2())).take(room));
+}
+
+// Where the dots go and how bright they are
+fn streamline_dots(wind: Vec2, phase: f32, spatial_index: &SpatialIndex) -> Vec<(Vec2, f32)> {
+  let direction = wind.normalize();
+  let (min, max) = (spatial_index.bounds().min().as_ivec2(), spatial_index.bounds().max().as_ivec2());
+  let mut dots = Vec::new();
+  for y in (min.y..max.y).step_by(STREAMLINE_SPACING as usize) {
+    for x in (min.x..max.x).step_by(STREAMLINE_SPACING as usize) {
+      let seed = IVec2::new(x, y).as_vec2() + Vec2::splat(STREAMLINE_SPACING as f32 / 2.);
+      for index in 0..STREAMLINE_LENGTH {
+        let position = seed + direction * (index as f32 + phase);
+        // A streamline ends at whatever is in its way
+        if !spatial_index.is_free(position.floor().as_ivec2()) { break }
+        dots.push((position, (index + 1) as f32 / STREAMLINE_LENGTH as f32));
+      }
+    }
+  }
+  dots
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw_wind(
+  mut commands: Commands,
+  view: Res<WindView>,
+  time: Res<Time>,
+  settings: Res<SimulationSettings>,
+  spatial_index: Res<SpatialIndex>,
+  tracers: Res<Tracers>,
+  mut pool: ResMut<DotPool>,
+  mut dots: Query<(&mut Sprite, &mut Transform, &mut Visibility), With<WindDot>>,
+) {
+  let wind = settings.wind;
+  let color = ramp(wind.length() / MAX_WIND);
+  let shown = match *view {
+    _ if wind == Vec2::ZERO => Vec::new(),
+    WindView::Off => Vec::new(),
+    // Dots move a cell along their streamline every cell the wind carries the air, then start over
+    WindView::Streamlines => streamline_dots(wind, (time.seconds_since_startup() as f32 * wind.length()).fract(), &spatial_index),
+    WindView::Tracers => tracers.0.iter().map(|position| (*position, 1.)).collect(),
+  };
+
+  for (index, (position, brightness)) in shown.iter().enumerate() {
+    let color = *color.clone().set_a(0.8 * brightness);
+    // Over the particles
+    let translation = (*position * Particle::SPRITE_SIZE).extend(2.);
+    match pool.0.get(index).and_then(|entity| dots.get_mut(*entity).ok()) {
+      Some((mut sprite, mut transform, mut visibility)) => {
+        sprite.color = color;
+        transform.translation = translation;
+        visibility.is_visible = true;
+      },
+      None => {
+        let entity = commands
+          .spawn_bundle(SpriteBundle {
+            sprite: Sprite { color, custom_size: Some(Vec2::splat(DOT_SIZE)), ..Default::default() },
+            transform: Transform::from_translation(translation),
+            ..Default::default()
+          })
+          .insert(WindDot)
+          .id();
+        if index < pool.0.len() {
+          pool.0[index] = entity;
+        } else {
+          pool.0.push(entity);
+        }
+      },
+    }
+  }
+
+  for entity in pool.0.iter().skip(shown.len()) {
+    if let Ok((_, _, mut visibility)) = dots.get_mut(*entity) {
+      visibility.is_visible = false;
+    }
+  }
+}
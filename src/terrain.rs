@@ -0,0 +1,108 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{scenario::ScenarioError, Particle, SpatialIndex};
+
+// A static background layer of tiles drawn beneath the particles, whose solid tiles are world
+// colliders. Particles cannot be placed in or removed from them.
+pub struct TerrainPlugin;
+
+impl Plugin for TerrainPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<Terrain>()
+      .add_system(register_terrain)
+      .add_system(draw_terrain);
+  }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TileDef {
+  pub color: (f32, f32, f32),
+  #[serde(default)]
+  pub solid: bool,
+}
+
+// The file format, e.g.
+//
+// (
+//   origin: (-20, -10),
+//   tiles: { '#': (color: (0.35, 0.33, 0.32), solid: true) },
+//   rows: ["  ##", "####"],
+// )
+//
+// Rows go from the top down and `origin` is the cell of the bottom left tile. Characters missing
+// from `tiles`, like spaces, are left empty.
+#[derive(Clone, Debug, Deserialize)]
+struct TileMap {
+  origin: (i32, i32),
+  tiles: HashMap<char, TileDef>,
+  rows: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct Terrain {
+  pub tiles: HashMap<IVec2, TileDef>,
+}
+
+impl Terrain {
+  pub fn load(path: impl AsRef<Path>) -> Result<Self, ScenarioError> {
+    let contents = fs::read_to_string(path).map_err(ScenarioError::Io)?;
+    let map: TileMap = ron::from_str(&contents).map_err(ScenarioError::Parse)?;
+
+    let origin = IVec2::new(map.origin.0, map.origin.1);
+    let mut tiles = HashMap::new();
+    for (row, line) in map.rows.iter().rev().enumerate() {
+      for (column, symbol) in line.chars().enumerate() {
+        if let Some(tile) = map.tiles.get(&symbol) {
+          tiles.insert(origin + IVec2::new(column as i32, row as i32), tile.clone());
+        }
+      }
+    }
+    Ok(Self { tiles })
+  }
+
+  // Makes the solid tiles colliders, except where a particle already is
+  pub fn register(&self, spatial_index: &mut SpatialIndex) {
+    for (point, tile) in self.tiles.iter() {
+      if tile.solid && spatial_index.get(point).is_none() {
+        spatial_index.insert_terrain(*point);
+      }
+    }
+    spatial_index.has_terrain = true;
+  }
+}
+
+#[derive(Component)]
+struct TerrainTile;
+
+// Clearing the world or loading a scenario starts a new lookup, which needs the terrain again
+fn register_terrain(terrain: Res<Terrain>, mut spatial_index: ResMut<SpatialIndex>) {
+  if !spatial_index.has_terrain {
+    terrain.register(&mut spatial_index);
+  }
+}
+
+fn draw_terrain(mut commands: Commands, terrain: Res<Terrain>, sprites: Query<Entity, With<TerrainTile>>) {
+  if !terrain.is_changed() { return }
+  for entity in sprites.iter() {
+    commands.entity(entity).despawn();
+  }
+
+  for (point, tile) in terrain.tiles.iter() {
+    commands
+      .spawn_bundle(SpriteBundle {
+        sprite: Sprite {
+          color: Color::rgb(tile.color.0, tile.color.1, tile.color.2),
+          custom_size: Some(Vec2::splat(Particle::SPRITE_SIZE)),
+          ..Default::default()
+        },
+        // Beneath the particles and the grid texture
+        transform: Transform::from_translation((point.as_vec2() * Particle::SPRITE_SIZE).extend(-2.)),
+        ..Default::default()
+      })
+      .insert(TerrainTile);
+  }
+}
@@ -0,0 +1,137 @@
+use bevy::prelude::*;
+
+use crate::{
+  actions::Action,
+  cursor::Cursor,
+  despawn_particle,
+  phases::Heat,
+  Particle, SpatialIndex,
+};
+
+use super::ActiveTool;
+
+// Cells the beam reaches at most
+const MAX_LENGTH: f32 = 64.;
+// Degrees per second the beam warms a particle at its origin by, fading to nothing at `MAX_LENGTH`.
+// Particles cool at `PhaseTransitions::cooling`, so none gets past `HEATING / cooling` degrees over
+// the ambient temperature
+const HEATING: f32 = 1000.;
+// Pixels across
+const BEAM_WIDTH: f32 = 3.;
+
+#[derive(Component)]
+pub(super) struct BeamSprite;
+
+#[derive(Default)]
+pub(super) struct Beam {
+  // Cell the beam comes out of while it is held
+  origin: Option<IVec2>,
+  // Where it stops, in the raycast's cells, which have their corner where a sprite has its center
+  end: Option<Vec2>,
+  cutting: bool,
+}
+
+// Pressing places the beam where the cursor is, from then on it shoots towards the cursor for as
+// long as it is held, through every particle in its way up to the first terrain. `Action::Primary`
+// heats them, less the further they are, until `phases` finds them hot enough to boil, melt or
+// catch fire, `Action::Secondary` cuts through them.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn fire_beam(
+  mut commands: Commands,
+  time: Res<Time>,
+  tool: Res<ActiveTool>,
+  cursor: Res<Cursor>,
+  actions: Res<Input<Action>>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut beam: ResMut<Beam>,
+  mut particles: Query<(&Particle, Option<&mut Heat>)>,
+) {
+  beam.cutting = actions.pressed(Action::Secondary);
+  if *tool != ActiveTool::Beam || !(beam.cutting || actions.pressed(Action::Primary)) {
+    *beam = Beam::default();
+    return;
+  }
+  if beam.origin.is_none() && !cursor.over_ui {
+    beam.origin = cursor.cell;
+  }
+  let (origin, target) = match (beam.origin, cursor.world) {
+    (Some(origin), Some(target)) => (origin.as_vec2() + 0.5, target / Particle::SPRITE_SIZE + 0.5),
+    _ => return,
+  };
+  let direction = (target - origin).normalize_or_zero();
+  if direction == Vec2::ZERO {
+    beam.end = None;
+    return;
+  }
+
+  // Particles it goes through, with how far along the beam they are
+  let mut passed = Vec::new();
+  let hit = spatial_index.raycast(origin, direction, MAX_LENGTH, |cell, entity| match entity {
+    Some(entity) => {
+      passed.push((entity, (cell.as_vec2() + 0.5 - origin).dot(direction).max(0.)));
+      false
+    },
+    // Terrain takes neither heat nor cuts, and stops the beam
+    None => true,
+  });
+  beam.end = Some(hit.map_or(origin + direction * MAX_LENGTH, |hit| hit.point));
+
+  for (entity, distance) in passed {
+    let (particle, heat) = match particles.get_mut(entity) {
+      Ok(particle) => particle,
+      Err(_) => continue,
+    };
+    if beam.cutting {
+      despawn_particle(&mut commands, &mut spatial_index, entity, particle);
+      continue;
+    }
+    let warming = HEATING * (1. - distance / MAX_LENGTH).max(0.) * time.delta_seconds();
+    match heat {
+      Some(mut heat) => heat.0 += warming,
+      None => { commands.entity(entity).insert(Heat(warming)); },
+    }
+  }
+}
+
+pub(super) fn draw_beam(
+  mut commands: Commands,
+  beam: Res<Beam>,
+  mut sprites: Query<(Entity, &mut Sprite, &mut Transform), With<BeamSprite>>,
+) {
+  let (origin, end) = match (beam.origin, beam.end) {
+    (Some(origin), Some(end)) => (origin.as_vec2(), (end - 0.5) * Particle::SPRITE_SIZE),
+    _ => {
+      for (entity, _, _) in sprites.iter() {
+        commands.entity(entity).despawn();
+      }
+      return;
+    },
+  };
+  let origin = origin * Particle::SPRITE_SIZE;
+  let offset = end - origin;
+  let size = Vec2::new(offset.length(), BEAM_WIDTH);
+  let color = if beam.cutting { Color::rgba(0.4, 0.9, 1., 0.8) } else { Color::rgba(1., 0.4, 0.1, 0.8) };
+  let transform = Transform {
+    // Over the particles
+    translation: ((origin + end) / 2.).extend(2.),
+    rotation: Quat::from_rotation_z(offset.y.atan2(offset.x)),
+    ..Default::default()
+  };
+
+  match sprites.iter_mut().next() {
+    Some((_, mut sprite, mut current)) => {
+      sprite.custom_size = Some(size);
+      sprite.color = color;
+      *current = transform;
+    },
+    None => {
+      commands
+        .spawn_bundle(SpriteBundle {
+          sprite: Sprite { color, custom_size: Some(size), ..Default::default() },
+          transform,
+          ..Default::default()
+        })
+        .insert(BeamSprite);
+    },
+  }
+}
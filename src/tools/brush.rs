@@ -0,0 +1,233 @@
+use bevy::{prelude::*, math::Mat2, utils::HashSet};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  actions::Action,
+  config::Config,
+  cursor::Cursor,
+  despawn_particle,
+  inventory::Inventory,
+  material::{MaterialId, MaterialRegistry},
+  phases::LatentHeat,
+  spawn_particle_with_velocity, Particle, SpatialIndex,
+};
+
+use super::ActiveTool;
+
+pub struct Brush {
+  pub material: MaterialId,
+  pub size: i32,
+  pub symmetry: Symmetry,
+  // Where strokes are mirrored around
+  pub symmetry_center: IVec2,
+  // Given to every particle the brush places, see `pick_material`
+  pub stamp: Option<Stamp>,
+}
+
+// State of a particle picked up along with its material, for the brush to place copies of it
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Stamp {
+  pub velocity: Vec2,
+  // See `LatentHeat`
+  pub latent_heat: Option<f32>,
+}
+
+// Repeats every stroke of the brush around `Brush::symmetry_center`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Symmetry {
+  #[default]
+  None,
+  // Mirrored across the vertical line through the center
+  MirrorX,
+  // Mirrored across the horizontal line through the center
+  MirrorY,
+  // Mirrored across both lines, which also repeats it through the center
+  MirrorXY,
+  // Turned around the center this many times, evenly
+  Radial(u32),
+}
+
+impl Symmetry {
+  pub fn next(self) -> Self {
+    match self {
+      Symmetry::None => Symmetry::MirrorX,
+      Symmetry::MirrorX => Symmetry::MirrorY,
+      Symmetry::MirrorY => Symmetry::MirrorXY,
+      Symmetry::MirrorXY => Symmetry::Radial(4),
+      Symmetry::Radial(4) => Symmetry::Radial(6),
+      Symmetry::Radial(6) => Symmetry::Radial(8),
+      Symmetry::Radial(_) => Symmetry::None,
+    }
+  }
+
+  // Where `cell` is repeated around `center`, starting with itself. Turned cells are rounded to the
+  // nearest one.
+  pub fn repeat(self, cell: IVec2, center: IVec2) -> Vec<IVec2> {
+    let offset = cell - center;
+    let offsets = match self {
+      Symmetry::None => vec![offset],
+      Symmetry::MirrorX => vec![offset, IVec2::new(-offset.x, offset.y)],
+      Symmetry::MirrorY => vec![offset, IVec2::new(offset.x, -offset.y)],
+      Symmetry::MirrorXY => vec![offset, IVec2::new(-offset.x, offset.y), IVec2::new(offset.x, -offset.y), -offset],
+      Symmetry::Radial(count) => (0..count.max(1))
+        .map(|turn| {
+          let rotation = Mat2::from_angle(std::f32::consts::TAU * turn as f32 / count as f32);
+          (rotation * offset.as_vec2()).round().as_ivec2()
+        })
+        .collect(),
+    };
+    offsets.into_iter().map(|offset| center + offset).collect()
+  }
+}
+
+impl FromWorld for Brush {
+  fn from_world(world: &mut World) -> Self {
+    let config = world.get_resource::<Config>().cloned().unwrap_or_default();
+    let materials = world.get_resource_or_insert_with(MaterialRegistry::default);
+    Self {
+      material: config.default_material(&materials),
+      size: config.brush_size,
+      symmetry: Symmetry::default(),
+      symmetry_center: IVec2::ZERO,
+      stamp: None,
+    }
+  }
+}
+
+impl Brush {
+  // Cells covered by a brush stamp centered on `center`
+  pub fn cells(&self, center: IVec2) -> impl Iterator<Item = IVec2> {
+    let size = self.size;
+    (-size..=size)
+      .flat_map(move |y| (-size..=size).map(move |x| IVec2::new(x, y)))
+      .filter(move |offset| offset.x * offset.x + offset.y * offset.y <= size * size)
+      .map(move |offset| center + offset)
+  }
+
+  // Cells covered by a stroke centered on `center`, the stamp with its repeats from the symmetry
+  pub fn stroke(&self, center: IVec2) -> Vec<IVec2> {
+    let mut seen = HashSet::default();
+    self
+      .cells(center)
+      .flat_map(|cell| self.symmetry.repeat(cell, self.symmetry_center))
+      .filter(|cell| seen.insert(*cell))
+      .collect()
+  }
+}
+
+// Cells painted or erased by the brush in one frame. Strokes are events so they can be
+// forwarded to a host instead of being applied locally.
+#[derive(Clone, Debug)]
+pub struct BrushStroke {
+  pub cells: Vec<IVec2>,
+  // `None` erases
+  pub material: Option<MaterialId>,
+  pub stamp: Option<Stamp>,
+}
+
+// Where strokes end up, networking takes them over when the world is shared
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StrokeTarget {
+  #[default]
+  Local,
+  Network,
+}
+
+pub fn apply_stroke(
+  stroke: &BrushStroke,
+  commands: &mut Commands,
+  spatial_index: &mut SpatialIndex,
+  materials: &MaterialRegistry,
+  inventory: &mut Inventory,
+  particles: &Query<&Particle>,
+) {
+  for cell in stroke.cells.iter().copied() {
+    match stroke.material {
+      Some(material) => {
+        if !spatial_index.is_free(cell) || !inventory.spend(material) { continue }
+        let velocity = stroke.stamp.map_or(Vec2::ZERO, |stamp| stamp.velocity);
+        let entity = spawn_particle_with_velocity(commands, spatial_index, materials, cell, material, velocity);
+        if let Some(latent_heat) = stroke.stamp.and_then(|stamp| stamp.latent_heat) {
+          commands.entity(entity).insert(LatentHeat(latent_heat));
+        }
+      },
+      None => {
+        if let Some(&entity) = spatial_index.get(&cell) {
+          if let Ok(particle) = particles.get(entity) {
+            despawn_particle(commands, spatial_index, entity, particle);
+          }
+        }
+      },
+    }
+  }
+}
+
+pub(super) fn paint(
+  tool: Res<ActiveTool>,
+  brush: Res<Brush>,
+  cursor: Res<Cursor>,
+  actions: Res<Input<Action>>,
+  mut strokes: EventWriter<BrushStroke>,
+) {
+  if *tool != ActiveTool::Brush || cursor.over_ui { return }
+  let center = match cursor.cell {
+    Some(cell) => cell,
+    None => return,
+  };
+
+  let material = if actions.pressed(Action::Primary) {
+    Some(brush.material)
+  } else if actions.pressed(Action::Secondary) {
+    None
+  } else {
+    return;
+  };
+  let stamp = material.and(brush.stamp);
+  strokes.send(BrushStroke { cells: brush.stroke(center), material, stamp });
+}
+
+// `Action::PickMaterial` makes the material under the cursor the brush's, `Action::PickStamp` also
+// has the brush place particles with the picked one's velocity and latent heat
+pub(super) fn pick_material(
+  cursor: Res<Cursor>,
+  actions: Res<Input<Action>>,
+  spatial_index: Res<SpatialIndex>,
+  mut brush: ResMut<Brush>,
+  particles: Query<(&Particle, &MaterialId, Option<&LatentHeat>)>,
+) {
+  let stamp = actions.just_pressed(Action::PickStamp);
+  if (!stamp && !actions.just_pressed(Action::PickMaterial)) || cursor.over_ui { return }
+  let picked = cursor.cell.and_then(|cell| spatial_index.get(&cell)).and_then(|entity| particles.get(*entity).ok());
+  if let Some((particle, material, latent_heat)) = picked {
+    brush.material = *material;
+    brush.stamp = stamp.then(|| Stamp { velocity: particle.velocity, latent_heat: latent_heat.map(|heat| heat.0) });
+  }
+}
+
+pub(super) fn set_symmetry(cursor: Res<Cursor>, actions: Res<Input<Action>>, mut brush: ResMut<Brush>) {
+  if actions.just_pressed(Action::CycleSymmetry) {
+    brush.symmetry = brush.symmetry.next();
+    info!("Brush symmetry: {:?}", brush.symmetry);
+  }
+  if actions.just_pressed(Action::SetSymmetryCenter) {
+    if let Some(cell) = cursor.cell {
+      brush.symmetry_center = cell;
+      info!("Brush symmetry center: {}", cell);
+    }
+  }
+}
+
+pub(super) fn apply_strokes(
+  mut commands: Commands,
+  target: Res<StrokeTarget>,
+  mut strokes: EventReader<BrushStroke>,
+  materials: Res<MaterialRegistry>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut inventory: ResMut<Inventory>,
+  particles: Query<&Particle>,
+) {
+  if *target != StrokeTarget::Local { return }
+  for stroke in strokes.iter() {
+    apply_stroke(stroke, &mut commands, &mut spatial_index, &materials, &mut inventory, &particles);
+  }
+}
@@ -0,0 +1,286 @@
+use bevy::prelude::*;
+
+use crate::{
+  actions::Action,
+  cursor::Cursor,
+  despawn_particle, spawn_particle_with_velocity,
+  locale::Locale,
+  material::{MaterialId, MaterialRegistry},
+  simulation::SimulationSettings,
+  Particle, SpatialIndex,
+};
+
+use super::ActiveTool;
+
+const BUTTON_COLOR: Color = Color::rgb(0.15, 0.15, 0.15);
+const HOVERED_COLOR: Color = Color::rgb(0.35, 0.35, 0.35);
+
+// The particle picked with the inspect tool
+#[derive(Default)]
+pub(super) struct Inspected(Option<Entity>);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+  PositionX,
+  PositionY,
+  VelocityX,
+  VelocityY,
+  Mass,
+  Elasticity,
+  Material,
+  Sleeping,
+}
+
+impl Field {
+  const ALL: [Field; 8] = [
+    Field::PositionX,
+    Field::PositionY,
+    Field::VelocityX,
+    Field::VelocityY,
+    Field::Mass,
+    Field::Elasticity,
+    Field::Material,
+    Field::Sleeping,
+  ];
+
+  // How much a button press changes the field by, `None` for fields that are only shown
+  fn step(self) -> Option<f32> {
+    match self {
+      Field::PositionX | Field::PositionY | Field::Material => Some(1.),
+      Field::VelocityX | Field::VelocityY => Some(2.),
+      Field::Mass | Field::Elasticity => Some(0.1),
+      Field::Sleeping => None,
+    }
+  }
+
+  // Key of its label in the `Locale`
+  fn label(self) -> &'static str {
+    match self {
+      Field::PositionX => "inspector.x",
+      Field::PositionY => "inspector.y",
+      Field::VelocityX => "inspector.velocity_x",
+      Field::VelocityY => "inspector.velocity_y",
+      Field::Mass => "inspector.mass",
+      Field::Elasticity => "inspector.elasticity",
+      Field::Material => "inspector.material",
+      Field::Sleeping => "inspector.sleeping",
+    }
+  }
+}
+
+#[derive(Component)]
+pub(super) struct InspectorRoot;
+
+#[derive(Component)]
+pub(super) struct InspectorMarker;
+
+#[derive(Component)]
+pub(super) struct FieldValue(Field);
+
+// Changes a field by its step times the sign
+#[derive(Component)]
+pub(super) struct FieldButton(Field, f32);
+
+pub(super) fn pick_particle(
+  tool: Res<ActiveTool>,
+  cursor: Res<Cursor>,
+  actions: Res<Input<Action>>,
+  spatial_index: Res<SpatialIndex>,
+  mut inspected: ResMut<Inspected>,
+) {
+  if *tool != ActiveTool::Inspect {
+    if inspected.0.is_some() {
+      inspected.0 = None;
+    }
+    return;
+  }
+  if !actions.just_pressed(Action::Primary) || cursor.over_ui { return }
+  // Clicking an empty cell closes the panel
+  inspected.0 = cursor.cell.and_then(|cell| spatial_index.get(&cell).copied());
+}
+
+// Spawns the panel and marker once something is picked and removes them when it is gone
+pub(super) fn sync_inspector(
+  mut commands: Commands,
+  asset_server: Res<AssetServer>,
+  mut inspected: ResMut<Inspected>,
+  particles: Query<&Particle>,
+  roots: Query<Entity, With<InspectorRoot>>,
+  markers: Query<Entity, With<InspectorMarker>>,
+) {
+  if inspected.0.is_some_and(|entity| particles.get(entity).is_err()) {
+    inspected.0 = None;
+  }
+  let open = !roots.is_empty();
+  if inspected.0.is_some() == open { return }
+
+  if open {
+    for entity in roots.iter().chain(markers.iter()) {
+      commands.entity(entity).despawn_recursive();
+    }
+    return;
+  }
+
+  commands
+    .spawn_bundle(SpriteBundle {
+      sprite: Sprite {
+        color: Color::rgba(1., 1., 1., 0.4),
+        custom_size: Some(Vec2::splat(Particle::SPRITE_SIZE * 1.5)),
+        ..Default::default()
+      },
+      ..Default::default()
+    })
+    .insert(InspectorMarker);
+
+  let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+  let text_style = TextStyle { font, font_size: 16., color: Color::WHITE };
+  commands
+    .spawn_bundle(NodeBundle {
+      style: Style {
+        position_type: PositionType::Absolute,
+        position: Rect { right: Val::Px(0.), top: Val::Px(0.), ..Default::default() },
+        flex_direction: FlexDirection::ColumnReverse,
+        padding: Rect::all(Val::Px(6.)),
+        ..Default::default()
+      },
+      color: Color::rgba(0., 0., 0., 0.7).into(),
+      ..Default::default()
+    })
+    .insert(InspectorRoot)
+    // Clicks on the panel background should not pick whatever is behind it
+    .insert(Interaction::default())
+    .with_children(|parent| {
+      for field in Field::ALL {
+        parent
+          .spawn_bundle(NodeBundle {
+            style: Style {
+              size: Size::new(Val::Px(240.), Val::Px(24.)),
+              align_items: AlignItems::Center,
+              ..Default::default()
+            },
+            color: Color::NONE.into(),
+            ..Default::default()
+          })
+          .with_children(|parent| {
+            parent
+              .spawn_bundle(TextBundle {
+                style: Style { flex_grow: 1., ..Default::default() },
+                text: Text::with_section("", text_style.clone(), Default::default()),
+                ..Default::default()
+              })
+              .insert(FieldValue(field));
+            if field.step().is_none() { return }
+            for (sign, label) in [(-1., "-"), (1., "+")] {
+              parent
+                .spawn_bundle(ButtonBundle {
+                  style: Style {
+                    size: Size::new(Val::Px(22.), Val::Px(20.)),
+                    margin: Rect::all(Val::Px(1.)),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..Default::default()
+                  },
+                  color: BUTTON_COLOR.into(),
+                  ..Default::default()
+                })
+                .insert(FieldButton(field, sign))
+                .with_children(|parent| {
+                  parent.spawn_bundle(TextBundle {
+                    text: Text::with_section(label, text_style.clone(), Default::default()),
+                    ..Default::default()
+                  });
+                });
+            }
+          });
+      }
+    });
+}
+
+pub(super) fn edit_particle(
+  mut commands: Commands,
+  materials: Res<MaterialRegistry>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut inspected: ResMut<Inspected>,
+  mut buttons: Query<(&Interaction, &FieldButton, &mut UiColor), Changed<Interaction>>,
+  mut particles: Query<(&mut Particle, &mut Transform, &MaterialId)>,
+) {
+  for (interaction, button, mut color) in buttons.iter_mut() {
+    *color = if *interaction == Interaction::None { BUTTON_COLOR.into() } else { HOVERED_COLOR.into() };
+    if *interaction != Interaction::Clicked { continue }
+    let entity = match inspected.0 {
+      Some(entity) => entity,
+      None => continue,
+    };
+    let (mut particle, mut transform, material) = match particles.get_mut(entity) {
+      Ok(particle) => particle,
+      Err(_) => continue,
+    };
+
+    let FieldButton(field, sign) = *button;
+    let delta = field.step().unwrap_or_default() * sign;
+    let cell = particle.position.floor().as_ivec2();
+    match field {
+      Field::PositionX | Field::PositionY => {
+        let offset = if field == Field::PositionX { IVec2::new(delta as i32, 0) } else { IVec2::new(0, delta as i32) };
+        if !spatial_index.is_free(cell + offset) { continue }
+        spatial_index.move_entity(cell, cell + offset);
+        particle.position += offset.as_vec2();
+        transform.translation = (cell + offset).as_vec2().extend(0.) * Particle::SPRITE_SIZE;
+      },
+      Field::VelocityX => particle.velocity.x += delta,
+      Field::VelocityY => particle.velocity.y += delta,
+      Field::Mass => particle.mass = (particle.mass + delta).max(0.1),
+      Field::Elasticity => particle.elasticity = (particle.elasticity + delta).clamp(0., 1.),
+      // A new material can change whether the particle is static, so it is replaced outright
+      Field::Material => {
+        let count = materials.iter().count();
+        let next = MaterialId(if sign < 0. { (material.0 + count - 1) % count } else { (material.0 + 1) % count });
+        let velocity = particle.velocity;
+        despawn_particle(&mut commands, &mut spatial_index, entity, &particle);
+        inspected.0 = Some(spawn_particle_with_velocity(&mut commands, &mut spatial_index, &materials, cell, next, velocity));
+        continue;
+      },
+      Field::Sleeping => {},
+    }
+    // Edits should play out right away even in a sleeping chunk
+    spatial_index.wake(particle.position.floor().as_ivec2());
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(super) fn update_inspector(
+  inspected: Res<Inspected>,
+  settings: Res<SimulationSettings>,
+  materials: Res<MaterialRegistry>,
+  locale: Res<Locale>,
+  spatial_index: Res<SpatialIndex>,
+  particles: Query<(&Particle, &Transform, &MaterialId), Without<InspectorMarker>>,
+  mut values: Query<(&FieldValue, &mut Text)>,
+  mut markers: Query<&mut Transform, With<InspectorMarker>>,
+) {
+  let (particle, transform, material) = match inspected.0.and_then(|entity| particles.get(entity).ok()) {
+    Some(particle) => particle,
+    None => return,
+  };
+  for mut marker in markers.iter_mut() {
+    marker.translation = transform.translation.truncate().extend(0.5);
+  }
+
+  let cell = particle.position.floor().as_ivec2();
+  for (FieldValue(field), mut text) in values.iter_mut() {
+    let value = match field {
+      Field::PositionX => format!("{:.2}", particle.position.x),
+      Field::PositionY => format!("{:.2}", particle.position.y),
+      Field::VelocityX => format!("{:.2}", particle.velocity.x),
+      Field::VelocityY => format!("{:.2}", particle.velocity.y),
+      Field::Mass => format!("{:.2}", particle.mass),
+      Field::Elasticity => format!("{:.2}", particle.elasticity),
+      Field::Material => locale.material(&materials.get(*material).name).to_string(),
+      Field::Sleeping => {
+        let sleeping = settings.chunk_activation && spatial_index.is_sleeping(cell);
+        locale.get(if sleeping { "inspector.yes" } else { "inspector.no" }).to_string()
+      },
+    };
+    text.sections[0].value = format!("{}: {}", locale.get(field.label()), value);
+  }
+}
@@ -0,0 +1,143 @@
+use bevy::prelude::*;
+
+use crate::{
+  actions::Action,
+  clusters::spawn_cluster,
+  cursor::Cursor,
+  material::MaterialRegistry,
+  simulation::SimulationSettings,
+  spawn_particle_with_velocity, Particle, SpatialIndex,
+};
+
+use super::{ActiveTool, Brush};
+
+// Cells per second of launch speed for every cell the cursor is pulled back
+const LAUNCH_SPEED: f32 = 2.;
+// Ticks of flight the preview follows at most, one dot for each
+const PREVIEW_TICKS: usize = 40;
+const DOT_SIZE: f32 = 4.;
+
+#[derive(Component)]
+pub(super) struct TrajectoryDot;
+
+#[derive(Default)]
+pub(super) struct Launcher {
+  // Cell the projectile is fired from, while aiming
+  origin: Option<IVec2>,
+}
+
+// Works like a slingshot: press where to fire from, pull the cursor back and let go. The projectile
+// is a cluster of the brush's material as large as the brush, thrown away from the cursor. Rigid
+// materials fly as one block that tumbles when it hits something, the others come apart.
+// `Action::Secondary` puts it down without firing.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn aim_launcher(
+  mut commands: Commands,
+  tool: Res<ActiveTool>,
+  brush: Res<Brush>,
+  cursor: Res<Cursor>,
+  actions: Res<Input<Action>>,
+  settings: Res<SimulationSettings>,
+  materials: Res<MaterialRegistry>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut launcher: ResMut<Launcher>,
+) {
+  if *tool != ActiveTool::Launcher || actions.just_pressed(Action::Secondary) {
+    launcher.origin = None;
+    return;
+  }
+  if actions.just_pressed(Action::Primary) && !cursor.over_ui {
+    launcher.origin = cursor.cell;
+  }
+  if !actions.just_released(Action::Primary) { return }
+
+  let (origin, cell) = match (launcher.origin.take(), cursor.cell) {
+    (Some(origin), Some(cell)) => (origin, cell),
+    _ => return,
+  };
+  let velocity = launch_velocity(origin, cell, &settings);
+  if materials.get(brush.material).rigid {
+    spawn_cluster(&mut commands, &mut spatial_index, &materials, brush.cells(origin), brush.material, velocity, 0.);
+    return;
+  }
+  for point in brush.cells(origin) {
+    if spatial_index.is_free(point) {
+      spawn_particle_with_velocity(&mut commands, &mut spatial_index, &materials, point, brush.material, velocity);
+    }
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(super) fn draw_trajectory(
+  mut commands: Commands,
+  launcher: Res<Launcher>,
+  brush: Res<Brush>,
+  cursor: Res<Cursor>,
+  settings: Res<SimulationSettings>,
+  materials: Res<MaterialRegistry>,
+  spatial_index: Res<SpatialIndex>,
+  mut dots: Query<(Entity, &mut Transform, &mut Visibility), With<TrajectoryDot>>,
+) {
+  let (origin, cell) = match (launcher.origin, cursor.cell) {
+    (Some(origin), Some(cell)) => (origin, cell),
+    _ => {
+      for (entity, _, _) in dots.iter() {
+        commands.entity(entity).despawn();
+      }
+      return;
+    },
+  };
+
+  let velocity = launch_velocity(origin, cell, &settings);
+  let mass = materials.get(brush.material).mass;
+  let path = trajectory(origin.as_vec2(), velocity, mass, &settings, &spatial_index);
+  // Spawned hidden the first frame of an aim and placed from the next one on
+  for _ in dots.iter().count()..PREVIEW_TICKS {
+    commands
+      .spawn_bundle(SpriteBundle {
+        sprite: Sprite {
+          color: Color::rgba(1., 1., 1., 0.7),
+          custom_size: Some(Vec2::splat(DOT_SIZE)),
+          ..Default::default()
+        },
+        visibility: Visibility { is_visible: false },
+        ..Default::default()
+      })
+      .insert(TrajectoryDot);
+  }
+  for (index, (_, mut transform, mut visibility)) in dots.iter_mut().enumerate() {
+    visibility.is_visible = index < path.len();
+    if let Some(position) = path.get(index) {
+      transform.translation = (*position * Particle::SPRITE_SIZE).extend(2.);
+    }
+  }
+}
+
+// Pulled back from the origin, so the projectile flies away from the cursor
+fn launch_velocity(origin: IVec2, cursor: IVec2, settings: &SimulationSettings) -> Vec2 {
+  ((origin - cursor).as_vec2() * LAUNCH_SPEED).clamp_length_max(settings.max_velocity())
+}
+
+// Where a particle thrown from `position` ends each tick, integrating gravity and wind the way the
+// simulation does, until it would run into something or leave the world
+fn trajectory(
+  mut position: Vec2,
+  mut velocity: Vec2,
+  mass: f32,
+  settings: &SimulationSettings,
+  spatial_index: &SpatialIndex,
+) -> Vec<Vec2> {
+  let delta = settings.tick_delta();
+  let wind = settings.wind / mass.max(f32::EPSILON);
+  let start = position.floor().as_ivec2();
+  let mut path = Vec::with_capacity(PREVIEW_TICKS);
+  for _ in 0..PREVIEW_TICKS {
+    let acceleration = settings.gravity_at(position) + wind;
+    velocity += acceleration * delta;
+    position += settings.integrator.displacement(velocity, acceleration * delta) * delta;
+    let cell = position.floor().as_ivec2();
+    if spatial_index.outside(position).is_some() || (cell != start && !spatial_index.is_free(cell)) { break }
+    path.push(position);
+  }
+  path
+}
@@ -0,0 +1,102 @@
+use bevy::prelude::*;
+
+use crate::{actions::Action, AppState};
+
+pub use brush::{apply_stroke, Brush, BrushStroke, Stamp, StrokeTarget};
+pub use selection::Clipboard;
+
+mod beam;
+mod brush;
+mod inspect;
+mod launcher;
+mod preview;
+mod rope;
+mod selection;
+mod shapes;
+mod vacuum;
+
+pub struct ToolsPlugin;
+
+impl Plugin for ToolsPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<ActiveTool>()
+      .init_resource::<beam::Beam>()
+      .init_resource::<Brush>()
+      .init_resource::<Clipboard>()
+      .init_resource::<inspect::Inspected>()
+      .init_resource::<launcher::Launcher>()
+      .init_resource::<rope::Rope>()
+      .init_resource::<selection::Selection>()
+      .init_resource::<shapes::Shape>()
+      .init_resource::<StrokeTarget>()
+      .add_event::<BrushStroke>()
+      .add_system_set(SystemSet::on_update(AppState::Running)
+        .with_system(switch_tool.label("switch_tool"))
+        .with_system(brush::pick_material.label("pick_material"))
+        .with_system(brush::set_symmetry.label("set_symmetry"))
+        .with_system(brush::paint.label("paint").after("switch_tool").after("pick_material").after("set_symmetry"))
+        .with_system(brush::apply_strokes.after("paint").after("draw_shapes"))
+        .with_system(selection::select_region.after("switch_tool"))
+        .with_system(selection::paste_clipboard.after("switch_tool"))
+        .with_system(inspect::pick_particle.label("pick").after("switch_tool"))
+        .with_system(inspect::edit_particle.label("edit_particle").after("pick"))
+        .with_system(inspect::sync_inspector.label("sync_inspector").after("edit_particle"))
+        .with_system(inspect::update_inspector.after("sync_inspector"))
+        .with_system(launcher::aim_launcher.label("aim").after("switch_tool"))
+        .with_system(launcher::draw_trajectory.after("aim"))
+        .with_system(beam::fire_beam.label("fire_beam").after("switch_tool"))
+        .with_system(beam::draw_beam.after("fire_beam"))
+        .with_system(vacuum::run_vacuum.after("switch_tool"))
+        .with_system(rope::lay_rope.after("switch_tool"))
+        .with_system(shapes::draw_shapes.label("draw_shapes").after("switch_tool"))
+        .with_system(preview::draw_preview.after("switch_tool"))
+      );
+  }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ActiveTool {
+  #[default]
+  Brush,
+  Select,
+  // Shows and edits the state of a single particle
+  Inspect,
+  // Throws clusters of particles, see `launcher`
+  Launcher,
+  // Heats or cuts along a ray, see `beam`
+  Beam,
+  // Pulls particles in and stores them, see `vacuum`
+  Vacuum,
+  // Drag out shapes of the brush's material, see `shapes`
+  Line,
+  Rectangle,
+  // Fills enclosed areas with the brush's material
+  Fill,
+  // Drags out chains of particles linked by springs, see `rope`
+  Rope,
+}
+
+fn switch_tool(actions: Res<Input<Action>>, mut tool: ResMut<ActiveTool>) {
+  if actions.just_pressed(Action::BrushTool) {
+    *tool = ActiveTool::Brush;
+  } else if actions.just_pressed(Action::SelectTool) {
+    *tool = ActiveTool::Select;
+  } else if actions.just_pressed(Action::InspectTool) {
+    *tool = ActiveTool::Inspect;
+  } else if actions.just_pressed(Action::LauncherTool) {
+    *tool = ActiveTool::Launcher;
+  } else if actions.just_pressed(Action::BeamTool) {
+    *tool = ActiveTool::Beam;
+  } else if actions.just_pressed(Action::VacuumTool) {
+    *tool = ActiveTool::Vacuum;
+  } else if actions.just_pressed(Action::LineTool) {
+    *tool = ActiveTool::Line;
+  } else if actions.just_pressed(Action::RectangleTool) {
+    *tool = ActiveTool::Rectangle;
+  } else if actions.just_pressed(Action::FillTool) {
+    *tool = ActiveTool::Fill;
+  } else if actions.just_pressed(Action::RopeTool) {
+    *tool = ActiveTool::Rope;
+  }
+}
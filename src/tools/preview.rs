@@ -0,0 +1,88 @@
+use bevy::prelude::*;
+
+use crate::{
+  actions::Action,
+  cursor::Cursor,
+  material::MaterialRegistry,
+  Particle, SpatialIndex,
+};
+
+use super::{shapes::{shape_cells, Shape}, ActiveTool, Brush, Clipboard};
+
+const PREVIEW_ALPHA: f32 = 0.35;
+// Cells a stroke or paste would skip because they are taken
+const REJECTED_COLOR: Color = Color::rgba(1., 0.2, 0.2, 0.35);
+// Cells erasing would clear
+const ERASED_COLOR: Color = Color::rgba(1., 1., 1., 0.35);
+
+#[derive(Component)]
+pub(super) struct PreviewCell;
+
+// Shows translucent particles where the brush would paint under the cursor, the shape being dragged
+// out or the clipboard while `Action::Paste` is held, in red where a cell is taken and nothing would
+// be placed. Erasing shows the particles that would go instead.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn draw_preview(
+  mut commands: Commands,
+  tool: Res<ActiveTool>,
+  brush: Res<Brush>,
+  cursor: Res<Cursor>,
+  actions: Res<Input<Action>>,
+  clipboard: Res<Clipboard>,
+  shape: Res<Shape>,
+  materials: Res<MaterialRegistry>,
+  spatial_index: Res<SpatialIndex>,
+  mut cells: Query<(&mut Sprite, &mut Transform, &mut Visibility), With<PreviewCell>>,
+) {
+  let placed = |cell: IVec2, mut color: Color| {
+    if !spatial_index.is_free(cell) { return (cell, REJECTED_COLOR) }
+    color.set_a(PREVIEW_ALPHA);
+    (cell, color)
+  };
+  let preview = match (cursor.cell.filter(|_| !cursor.over_ui), shape.start) {
+    (Some(origin), _) if actions.pressed(Action::Paste) => clipboard
+      .cells
+      .iter()
+      .map(|cell| placed(origin + cell.offset, materials.get(cell.material).color))
+      .collect(),
+    (Some(end), Some(start)) => {
+      let cells = shape_cells(*tool, start, end, &brush).into_iter();
+      if shape.erase {
+        cells.filter(|cell| spatial_index.contains(cell)).map(|cell| (cell, ERASED_COLOR)).collect()
+      } else {
+        let color = materials.get(brush.material).color;
+        cells.map(|cell| placed(cell, color)).collect()
+      }
+    },
+    (Some(center), _) if *tool == ActiveTool::Brush && actions.pressed(Action::Secondary) => brush
+      .stroke(center)
+      .into_iter()
+      .filter(|cell| spatial_index.contains(cell))
+      .map(|cell| (cell, ERASED_COLOR))
+      .collect(),
+    (Some(center), _) if *tool == ActiveTool::Brush => {
+      let color = materials.get(brush.material).color;
+      brush.stroke(center).into_iter().map(|cell| placed(cell, color)).collect()
+    },
+    _ => Vec::new(),
+  };
+
+  // Spawned hidden the first frame a preview grows and placed from the next one on
+  for _ in cells.iter().count()..preview.len() {
+    commands
+      .spawn_bundle(SpriteBundle {
+        sprite: Sprite { custom_size: Some(Vec2::splat(Particle::SPRITE_SIZE)), ..Default::default() },
+        visibility: Visibility { is_visible: false },
+        ..Default::default()
+      })
+      .insert(PreviewCell);
+  }
+  for (index, (mut sprite, mut transform, mut visibility)) in cells.iter_mut().enumerate() {
+    visibility.is_visible = index < preview.len();
+    if let Some((cell, color)) = preview.get(index) {
+      sprite.color = *color;
+      // Over the particles
+      transform.translation = (cell.as_vec2() * Particle::SPRITE_SIZE).extend(2.);
+    }
+  }
+}
@@ -0,0 +1,54 @@
+use bevy::prelude::*;
+
+use crate::{
+  actions::Action,
+  cursor::Cursor,
+  material::MaterialRegistry,
+  springs::spawn_chain,
+  SpatialIndex, Static,
+};
+
+use super::{ActiveTool, Brush};
+
+// The cell a rope is being dragged out from
+#[derive(Default)]
+pub(super) struct Rope {
+  start: Option<IVec2>,
+}
+
+// Press where the rope hangs from and let go where it ends to lay a chain of the brush's material
+// between them. A rope started on a free cell is pinned there, started on a particle it hangs from
+// that particle. Let go over a particle, the rope is tied to it and pulls on it or is pulled, else
+// the end hangs free. `Action::Secondary` drops the rope without laying it.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn lay_rope(
+  mut commands: Commands,
+  tool: Res<ActiveTool>,
+  brush: Res<Brush>,
+  cursor: Res<Cursor>,
+  actions: Res<Input<Action>>,
+  materials: Res<MaterialRegistry>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut rope: ResMut<Rope>,
+) {
+  if *tool != ActiveTool::Rope || actions.just_pressed(Action::Secondary) {
+    rope.start = None;
+    return;
+  }
+  if actions.just_pressed(Action::Primary) && !cursor.over_ui {
+    rope.start = cursor.cell;
+  }
+  if !actions.just_released(Action::Primary) { return }
+
+  let (start, end) = match (rope.start.take(), cursor.cell) {
+    (Some(start), Some(end)) => (start, end),
+    _ => return,
+  };
+  let pinned = spatial_index.is_free(start);
+  let links = spawn_chain(&mut commands, &mut spatial_index, &materials, start, end, brush.material);
+  if let Some(&first) = links.first().filter(|_| pinned) {
+    // Reinserting adds the cell's collider along with the `Static` marker
+    spatial_index.insert_static(start, first);
+    commands.entity(first).insert(Static);
+  }
+}
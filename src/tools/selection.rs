@@ -0,0 +1,141 @@
+use bevy::prelude::*;
+
+use crate::{
+  actions::Action,
+  cursor::Cursor,
+  material::{MaterialId, MaterialRegistry},
+  spawn_particle_with_velocity, Particle, SpatialIndex,
+};
+
+use super::ActiveTool;
+
+#[derive(Clone, Debug)]
+pub struct ClipboardCell {
+  pub offset: IVec2,
+  pub material: MaterialId,
+  pub velocity: Vec2,
+}
+
+// Particles copied out of a selection, stored relative to the selection's bottom left cell
+#[derive(Clone, Debug, Default)]
+pub struct Clipboard {
+  pub cells: Vec<ClipboardCell>,
+}
+
+impl Clipboard {
+  pub fn capture(
+    &mut self,
+    min: IVec2,
+    max: IVec2,
+    spatial_index: &SpatialIndex,
+    particles: &Query<(&Particle, &MaterialId)>,
+  ) {
+    self.cells.clear();
+    for y in min.y..=max.y {
+      for x in min.x..=max.x {
+        let cell = IVec2::new(x, y);
+        let particle = spatial_index.get(&cell).and_then(|entity| particles.get(*entity).ok());
+        if let Some((particle, material)) = particle {
+          self.cells.push(ClipboardCell { offset: cell - min, material: *material, velocity: particle.velocity });
+        }
+      }
+    }
+  }
+
+  // Spawns the clipboard with its bottom left cell at `origin`, skipping occupied cells
+  pub fn stamp(
+    &self,
+    origin: IVec2,
+    commands: &mut Commands,
+    spatial_index: &mut SpatialIndex,
+    materials: &MaterialRegistry,
+  ) {
+    for cell in self.cells.iter() {
+      let point = origin + cell.offset;
+      if spatial_index.is_free(point) {
+        spawn_particle_with_velocity(commands, spatial_index, materials, point, cell.material, cell.velocity);
+      }
+    }
+  }
+}
+
+#[derive(Component)]
+pub(super) struct SelectionBox;
+
+#[derive(Default)]
+pub(super) struct Selection {
+  start: Option<IVec2>,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(super) fn select_region(
+  mut commands: Commands,
+  tool: Res<ActiveTool>,
+  cursor: Res<Cursor>,
+  actions: Res<Input<Action>>,
+  spatial_index: Res<SpatialIndex>,
+  mut selection: ResMut<Selection>,
+  mut clipboard: ResMut<Clipboard>,
+  particles: Query<(&Particle, &MaterialId)>,
+  mut boxes: Query<(Entity, &mut Sprite, &mut Transform), With<SelectionBox>>,
+) {
+  if *tool != ActiveTool::Select {
+    selection.start = None;
+    for (entity, _, _) in boxes.iter() {
+      commands.entity(entity).despawn();
+    }
+    return;
+  }
+
+  if actions.just_pressed(Action::Primary) && !cursor.over_ui {
+    selection.start = cursor.cell;
+    commands
+      .spawn_bundle(SpriteBundle {
+        sprite: Sprite {
+          color: Color::rgba(1., 1., 1., 0.2),
+          custom_size: Some(Vec2::ZERO),
+          ..Default::default()
+        },
+        ..Default::default()
+      })
+      .insert(SelectionBox);
+  }
+
+  let (start, end) = match (selection.start, cursor.cell) {
+    (Some(start), Some(end)) => (start, end),
+    _ => return,
+  };
+  let min = start.min(end);
+  let max = start.max(end);
+
+  if actions.just_released(Action::Primary) {
+    clipboard.capture(min, max, &spatial_index, &particles);
+    info!("Copied {} particles", clipboard.cells.len());
+    selection.start = None;
+    for (entity, _, _) in boxes.iter() {
+      commands.entity(entity).despawn();
+    }
+  } else if actions.pressed(Action::Primary) {
+    let size = (max - min + IVec2::ONE).as_vec2() * Particle::SPRITE_SIZE;
+    let center = (min + max).as_vec2() / 2. * Particle::SPRITE_SIZE;
+    for (_, mut sprite, mut transform) in boxes.iter_mut() {
+      sprite.custom_size = Some(size);
+      transform.translation = center.extend(1.);
+    }
+  }
+}
+
+pub(super) fn paste_clipboard(
+  mut commands: Commands,
+  actions: Res<Input<Action>>,
+  cursor: Res<Cursor>,
+  clipboard: Res<Clipboard>,
+  materials: Res<MaterialRegistry>,
+  mut spatial_index: ResMut<SpatialIndex>,
+) {
+  // Holding shows where it goes, see `preview`
+  if !actions.just_released(Action::Paste) { return }
+  if let Some(origin) = cursor.cell {
+    clipboard.stamp(origin, &mut commands, &mut spatial_index, &materials);
+  }
+}
@@ -0,0 +1,137 @@
+use std::collections::VecDeque;
+
+use bevy::{prelude::*, utils::HashSet};
+
+use crate::{actions::Action, cursor::Cursor, SpatialIndex};
+
+use super::{ActiveTool, Brush, BrushStroke};
+
+// Cells a fill covers at most, anything bigger is taken for an area that is not enclosed
+const MAX_FILL: usize = 20_000;
+
+// The cell a line or rectangle is being dragged out from, and whether it erases
+#[derive(Default)]
+pub(super) struct Shape {
+  pub(super) start: Option<IVec2>,
+  pub(super) erase: bool,
+}
+
+// Cells of the line or rectangle from `start` to `end`. Lines are as thick as the brush, rectangles
+// are outlines one cell wide.
+pub(super) fn shape_cells(tool: ActiveTool, start: IVec2, end: IVec2, brush: &Brush) -> Vec<IVec2> {
+  match tool {
+    ActiveTool::Line => {
+      let mut seen = HashSet::default();
+      line(start, end).into_iter().flat_map(|cell| brush.cells(cell)).filter(|cell| seen.insert(*cell)).collect()
+    },
+    ActiveTool::Rectangle => {
+      let (min, max) = (start.min(end), start.max(end));
+      let mut cells = Vec::new();
+      for x in min.x..=max.x {
+        cells.push(IVec2::new(x, min.y));
+        if max.y != min.y {
+          cells.push(IVec2::new(x, max.y));
+        }
+      }
+      for y in min.y + 1..max.y {
+        cells.push(IVec2::new(min.x, y));
+        if max.x != min.x {
+          cells.push(IVec2::new(max.x, y));
+        }
+      }
+      cells
+    },
+    _ => Vec::new(),
+  }
+}
+
+// Bresenham's line, both ends included
+fn line(start: IVec2, end: IVec2) -> Vec<IVec2> {
+  let delta = (end - start).abs();
+  let step = (end - start).signum();
+  let mut error = delta.x - delta.y;
+  let mut cell = start;
+  let mut cells = vec![cell];
+  while cell != end {
+    let doubled = 2 * error;
+    if doubled > -delta.y {
+      error -= delta.y;
+      cell.x += step.x;
+    }
+    if doubled < delta.x {
+      error += delta.x;
+      cell.y += step.y;
+    }
+    cells.push(cell);
+  }
+  cells
+}
+
+// The free cells connected to `start` through their sides, stopped by particles, colliders and the
+// edges of the world. `None` when they are more than `MAX_FILL`.
+fn flood_fill(start: IVec2, spatial_index: &SpatialIndex) -> Option<Vec<IVec2>> {
+  if !spatial_index.is_free(start) { return Some(Vec::new()) }
+  let mut seen = HashSet::default();
+  seen.insert(start);
+  let mut queue = VecDeque::from([start]);
+  let mut cells = Vec::new();
+  while let Some(cell) = queue.pop_front() {
+    cells.push(cell);
+    if cells.len() > MAX_FILL { return None }
+    for neighbor in [cell + IVec2::X, cell - IVec2::X, cell + IVec2::Y, cell - IVec2::Y] {
+      if spatial_index.is_free(neighbor) && seen.insert(neighbor) {
+        queue.push_back(neighbor);
+      }
+    }
+  }
+  Some(cells)
+}
+
+// Lines and rectangles are dragged out with `Action::Primary` to draw or `Action::Secondary` to
+// erase and placed on release. Fills take `Action::Primary` and fill the enclosed area clicked.
+// Everything goes out as `BrushStroke`s like the brush's.
+pub(super) fn draw_shapes(
+  tool: Res<ActiveTool>,
+  brush: Res<Brush>,
+  cursor: Res<Cursor>,
+  actions: Res<Input<Action>>,
+  spatial_index: Res<SpatialIndex>,
+  mut shape: ResMut<Shape>,
+  mut strokes: EventWriter<BrushStroke>,
+) {
+  if !matches!(*tool, ActiveTool::Line | ActiveTool::Rectangle | ActiveTool::Fill) {
+    *shape = Shape::default();
+    return;
+  }
+  let cell = match cursor.cell {
+    Some(cell) => cell,
+    None => return,
+  };
+
+  if *tool == ActiveTool::Fill {
+    if !actions.just_pressed(Action::Primary) || cursor.over_ui { return }
+    match flood_fill(cell, &spatial_index) {
+      Some(cells) => strokes.send(BrushStroke { cells, material: Some(brush.material), stamp: brush.stamp }),
+      None => info!("Not filling an area that is not enclosed"),
+    }
+    return;
+  }
+
+  if !cursor.over_ui && shape.start.is_none() {
+    if actions.just_pressed(Action::Primary) {
+      *shape = Shape { start: Some(cell), erase: false };
+    } else if actions.just_pressed(Action::Secondary) {
+      *shape = Shape { start: Some(cell), erase: true };
+    }
+  }
+  let start = match shape.start {
+    Some(start) => start,
+    None => return,
+  };
+  let held = if shape.erase { Action::Secondary } else { Action::Primary };
+  if !actions.just_released(held) { return }
+
+  let (material, stamp) = if shape.erase { (None, None) } else { (Some(brush.material), brush.stamp) };
+  strokes.send(BrushStroke { cells: shape_cells(*tool, start, cell, &brush), material, stamp });
+  shape.start = None;
+}
@@ -0,0 +1,75 @@
+use bevy::prelude::*;
+
+use crate::{
+  actions::Action,
+  cursor::Cursor,
+  despawn_particle,
+  inventory::Inventory,
+  material::{MaterialId, MaterialRegistry},
+  simulation::SimulationSettings,
+  spawn_particle, Particle, SpatialIndex, Static,
+};
+
+use super::{ActiveTool, Brush};
+
+// Cells around the cursor that particles are pulled in from
+const RADIUS: f32 = 8.;
+// Cells per second squared of pull, strongest close to the cursor
+const SUCTION: f32 = 80.;
+// Particles this close to the cursor are taken in
+const ABSORB_RADIUS: f32 = 1.5;
+
+// `Action::Primary` pulls loose particles around the cursor towards it and stores the ones that
+// reach it in the `Inventory`. `Action::Secondary` puts stored particles of the brush's material
+// back, in the shape of the brush.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn run_vacuum(
+  mut commands: Commands,
+  time: Res<Time>,
+  tool: Res<ActiveTool>,
+  brush: Res<Brush>,
+  cursor: Res<Cursor>,
+  actions: Res<Input<Action>>,
+  settings: Res<SimulationSettings>,
+  materials: Res<MaterialRegistry>,
+  mut spatial_index: ResMut<SpatialIndex>,
+  mut inventory: ResMut<Inventory>,
+  mut particles: Query<(&mut Particle, &MaterialId), Without<Static>>,
+) {
+  if *tool != ActiveTool::Vacuum || cursor.over_ui { return }
+  let (cell, world) = match (cursor.cell, cursor.world) {
+    (Some(cell), Some(world)) => (cell, world),
+    _ => return,
+  };
+
+  if actions.pressed(Action::Secondary) {
+    for point in brush.cells(cell) {
+      if spatial_index.is_free(point) && inventory.take(brush.material, 1) == 1 {
+        spawn_particle(&mut commands, &mut spatial_index, &materials, point, brush.material);
+      }
+    }
+    return;
+  }
+  if !actions.pressed(Action::Primary) { return }
+
+  // Particles sit at their cell's corner where sprites have their center
+  let center = world / Particle::SPRITE_SIZE + 0.5;
+  let reach = IVec2::splat(RADIUS.ceil() as i32);
+  for (point, entity) in spatial_index.query(cell - reach, cell + reach) {
+    let (mut particle, material) = match particles.get_mut(entity) {
+      Ok(particle) => particle,
+      Err(_) => continue,
+    };
+    let offset = center - particle.position;
+    let distance = offset.length();
+    if distance > RADIUS { continue }
+    if distance < ABSORB_RADIUS {
+      inventory.add(*material, 1);
+      despawn_particle(&mut commands, &mut spatial_index, entity, &particle);
+      continue;
+    }
+    let pull = offset / distance * SUCTION * (1. - distance / RADIUS) * time.delta_seconds();
+    particle.velocity = (particle.velocity + pull).clamp_length_max(settings.max_velocity());
+    spatial_index.wake(point);
+  }
+}
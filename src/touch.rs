@@ -0,0 +1,125 @@
+use bevy::{input::{touch::{ForceTouch, Touch}, InputSystem}, prelude::*, utils::HashSet};
+
+use crate::{actions::Action, camera::clamp_to_world, cursor::MainCamera, SpatialIndex};
+
+// Seconds a lone finger has to stay down before it paints, so the first finger of a pinch does not
+// leave a dot behind
+const PAINT_DELAY: f64 = 0.08;
+// Pressure past which a contact is taken for a palm, as a fraction of the most the screen reports
+const PALM_PRESSURE: f64 = 0.9;
+// Limits of the camera's projection scale, below one zooms in
+const MIN_ZOOM: f32 = 0.25;
+const MAX_ZOOM: f32 = 4.;
+
+// Multi-touch for tablets. One finger paints through `Action::Primary` at the `TouchGesture`'s
+// position, two fingers pinch to zoom and drag to pan the camera. Resting hands are left out.
+pub struct TouchPlugin;
+
+impl Plugin for TouchPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<TouchGesture>()
+      .add_system_to_stage(CoreStage::PreUpdate, read_touches.label("touch").after(InputSystem).after("actions"));
+  }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum GestureState {
+  #[default]
+  Idle,
+  // A single finger that may still turn into a pinch
+  Pending { id: u64, since: f64 },
+  Painting { id: u64 },
+  // Kept until every finger is lifted, so lifting one of two does not start painting
+  Pinching,
+  // Too many fingers to be a gesture, likely a hand resting on the screen
+  Blocked,
+}
+
+#[derive(Default)]
+pub struct TouchGesture {
+  // Screen position of the finger painting, the cursor follows it
+  pub painting: Option<Vec2>,
+  state: GestureState,
+  // Contacts taken for palms stay ignored until they are lifted, even once their pressure drops
+  palms: HashSet<u64>,
+}
+
+// Bevy only reports pressure, not contact size, but screens without a force sensor estimate the
+// pressure from how large the contact is. Where neither is known nothing is taken for a palm.
+fn is_palm(touch: &Touch) -> bool {
+  match touch.force() {
+    Some(ForceTouch::Calibrated { force, max_possible_force, .. }) => force >= max_possible_force * PALM_PRESSURE,
+    Some(ForceTouch::Normalized(force)) => force >= PALM_PRESSURE,
+    None => false,
+  }
+}
+
+fn read_touches(
+  time: Res<Time>,
+  touches: Res<Touches>,
+  windows: Res<Windows>,
+  spatial_index: Res<SpatialIndex>,
+  mut gesture: ResMut<TouchGesture>,
+  mut actions: ResMut<Input<Action>>,
+  mut cameras: Query<(&mut Transform, &mut OrthographicProjection), With<MainCamera>>,
+) {
+  for touch in touches.iter_just_pressed().filter(|touch| is_palm(touch)) {
+    gesture.palms.insert(touch.id());
+  }
+  for touch in touches.iter_just_released().chain(touches.iter_just_cancelled()) {
+    gesture.palms.remove(&touch.id());
+  }
+  let mut fingers = touches.iter().filter(|touch| !gesture.palms.contains(&touch.id())).collect::<Vec<_>>();
+  fingers.sort_unstable_by_key(|touch| touch.id());
+
+  let now = time.seconds_since_startup();
+  let was_painting = matches!(gesture.state, GestureState::Painting { .. });
+  gesture.state = match (gesture.state, fingers.as_slice()) {
+    (_, []) => GestureState::Idle,
+    (GestureState::Idle, [finger]) => GestureState::Pending { id: finger.id(), since: now },
+    (GestureState::Pending { id, since }, [finger]) if finger.id() == id && now - since >= PAINT_DELAY => {
+      GestureState::Painting { id }
+    },
+    (state, [_]) => state,
+    (_, [first, second]) => {
+      if let (Some(window), Ok(camera)) = (windows.get_primary(), cameras.get_single_mut()) {
+        pinch(first, second, window, &spatial_index, camera);
+      }
+      GestureState::Pinching
+    },
+    _ => GestureState::Blocked,
+  };
+
+  gesture.painting = match gesture.state {
+    GestureState::Painting { id } => touches.get_pressed(id).map(Touch::position),
+    _ => None,
+  };
+  if gesture.painting.is_some() && !was_painting {
+    actions.press(Action::Primary);
+  } else if gesture.painting.is_none() && was_painting {
+    actions.release(Action::Primary);
+  }
+}
+
+// Scales the view by how much the fingers spread and moves it with their middle, keeping the world
+// under the fingers where it was
+fn pinch(
+  first: &Touch,
+  second: &Touch,
+  window: &Window,
+  spatial_index: &SpatialIndex,
+  (mut transform, mut projection): (Mut<Transform>, Mut<OrthographicProjection>),
+) {
+  let previous_distance = first.previous_position().distance(second.previous_position());
+  let distance = first.position().distance(second.position());
+  if previous_distance < 1. || distance < 1. { return }
+
+  let center = Vec2::new(window.width(), window.height()) / 2.;
+  let previous_middle = (first.previous_position() + second.previous_position()) / 2.;
+  let middle = (first.position() + second.position()) / 2.;
+  let anchor = transform.translation.truncate() + (previous_middle - center) * projection.scale;
+  projection.scale = (projection.scale * previous_distance / distance).clamp(MIN_ZOOM, MAX_ZOOM);
+  let position = clamp_to_world(anchor - (middle - center) * projection.scale, spatial_index);
+  transform.translation = position.extend(transform.translation.z);
+}
@@ -0,0 +1,139 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::{
+  actions::Action,
+  material::{MaterialId, MaterialRegistry},
+  simulation::{SimulationClock, SimulationStep},
+  Particle,
+};
+
+// Fading ghost sprites behind the moving particles of materials with a trail, so flocks and
+// anything thrown are easier to follow. Toggled with `Action::ToggleTrails`.
+pub struct TrailsPlugin;
+
+impl Plugin for TrailsPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<TrailSettings>()
+      .init_resource::<GhostPool>()
+      .add_system(toggle_trails)
+      .add_system(record_trails.label("record_trails").after(SimulationStep::Commit))
+      .add_system(draw_trails.after("record_trails"));
+  }
+}
+
+pub struct TrailSettings {
+  pub enabled: bool,
+  // Ticks of positions kept behind each particle
+  pub length: usize,
+  // Opacity of the ghost right behind a particle, the ones further back fade out
+  pub alpha: f32,
+}
+
+impl Default for TrailSettings {
+  fn default() -> Self {
+    Self { enabled: true, length: 8, alpha: 0.5 }
+  }
+}
+
+// Where a particle was over the last ticks, most recent first
+#[derive(Component, Default)]
+pub struct Trail {
+  positions: VecDeque<Vec2>,
+}
+
+#[derive(Component)]
+struct Ghost;
+
+// Ghost sprites are reused between frames, the ones not needed are hidden
+#[derive(Default)]
+struct GhostPool(Vec<Entity>);
+
+fn toggle_trails(actions: Res<Input<Action>>, mut settings: ResMut<TrailSettings>, mut trails: Query<&mut Trail>) {
+  if !actions.just_pressed(Action::ToggleTrails) { return }
+  settings.enabled = !settings.enabled;
+  // Turned back on, trails start over from where the particles are
+  for mut trail in trails.iter_mut() {
+    trail.positions.clear();
+  }
+}
+
+fn record_trails(
+  mut commands: Commands,
+  clock: Res<SimulationClock>,
+  settings: Res<TrailSettings>,
+  materials: Res<MaterialRegistry>,
+  mut particles: Query<(Entity, &Particle, &MaterialId, Option<&mut Trail>)>,
+) {
+  if !settings.enabled || !clock.ticked { return }
+  for (entity, particle, material, trail) in particles.iter_mut() {
+    match trail {
+      Some(mut trail) => {
+        trail.positions.push_front(particle.position);
+        trail.positions.truncate(settings.length);
+      },
+      // Particles can change material, which only starts their trail from then on
+      None if materials.get(*material).trail => { commands.entity(entity).insert(Trail::default()); },
+      None => {},
+    }
+  }
+}
+
+fn draw_trails(
+  mut commands: Commands,
+  settings: Res<TrailSettings>,
+  materials: Res<MaterialRegistry>,
+  mut pool: ResMut<GhostPool>,
+  trails: Query<(&Particle, &MaterialId, &Trail)>,
+  mut ghosts: Query<(&mut Sprite, &mut Transform, &mut Visibility), With<Ghost>>,
+) {
+  let mut used = 0;
+  if settings.enabled {
+    for (particle, material, trail) in trails.iter() {
+      let current = particle.position.floor();
+      let color = materials.get(*material).color;
+      for (index, position) in trail.positions.iter().enumerate() {
+        // A particle standing still has nothing to leave behind
+        if position.floor() == current { continue }
+        let alpha = settings.alpha * (1. - index as f32 / settings.length as f32);
+        let translation = (position.floor() * Particle::SPRITE_SIZE).extend(-0.5);
+        match pool.0.get(used).and_then(|entity| ghosts.get_mut(*entity).ok()) {
+          Some((mut sprite, mut transform, mut visibility)) => {
+            sprite.color = *color.clone().set_a(alpha);
+            transform.translation = translation;
+            visibility.is_visible = true;
+          },
+          None => {
+            let entity = commands
+              .spawn_bundle(SpriteBundle {
+                sprite: Sprite {
+                  color: *color.clone().set_a(alpha),
+                  custom_size: Some(Vec2::splat(Particle::SPRITE_SIZE)),
+                  ..Default::default()
+                },
+                // Behind the particles but over the grid texture
+                transform: Transform::from_translation(translation),
+                ..Default::default()
+              })
+              .insert(Ghost)
+              .id();
+            if used < pool.0.len() {
+              pool.0[used] = entity;
+            } else {
+              pool.0.push(entity);
+            }
+          },
+        }
+        used += 1;
+      }
+    }
+  }
+
+  for entity in pool.0.iter().skip(used) {
+    if let Ok((_, _, mut visibility)) = ghosts.get_mut(*entity) {
+      visibility.is_visible = false;
+    }
+  }
+}
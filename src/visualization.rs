@@ -0,0 +1,106 @@
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::{material::MaterialId, profiling::ChunkStats, Particle, SpatialIndex};
+
+// How the grid is colored, cycled with `Action::CycleVisualization`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Visualization {
+  // Material colors
+  #[default]
+  Normal,
+  // Particles per chunk, over the whole chunk
+  Density,
+  // Depth of liquid above each liquid cell
+  Pressure,
+  // Kinetic energy of each particle, the simulation has no temperature of its own
+  Temperature,
+  // Time the latest tick spent on each chunk against the costliest one, over the whole chunk
+  ChunkCost,
+}
+
+impl Visualization {
+  pub fn next(self) -> Self {
+    match self {
+      Visualization::Normal => Visualization::Density,
+      Visualization::Density => Visualization::Pressure,
+      Visualization::Pressure => Visualization::Temperature,
+      Visualization::Temperature => Visualization::ChunkCost,
+      Visualization::ChunkCost => Visualization::Normal,
+    }
+  }
+}
+
+// Liquid this deep reads as the highest pressure
+const MAX_DEPTH: f32 = 16.;
+// Kinetic energy that reads as the hottest, about a sand grain falling for a few seconds
+const MAX_ENERGY: f32 = 64.;
+
+fn is_liquid(material: MaterialId) -> bool {
+  material == MaterialId::WATER
+}
+
+// Blue through green and yellow to red for values from 0 to 1
+pub fn ramp(value: f32) -> Color {
+  let value = value.clamp(0., 1.);
+  let (r, g, b) = if value < 1. / 3. {
+    let t = value * 3.;
+    (0., t, 1. - t)
+  } else if value < 2. / 3. {
+    let t = value * 3. - 1.;
+    (t, 1., 0.)
+  } else {
+    let t = value * 3. - 2.;
+    (1., 1. - t, 0.)
+  };
+  Color::rgb(r, g, b)
+}
+
+// Every cell of `chunk` with the same value
+fn chunk_cells(chunk: IVec2, value: f32) -> impl Iterator<Item = (IVec2, f32)> {
+  let size = SpatialIndex::CHUNK_SIZE;
+  (0..size * size).map(move |index| (chunk * size + IVec2::new(index % size, index / size), value))
+}
+
+// The value from 0 to 1 of every cell the visualization covers
+pub fn cell_values(
+  visualization: Visualization,
+  spatial_index: &SpatialIndex,
+  chunk_stats: &ChunkStats,
+  particles: &Query<(&Particle, &MaterialId, &Sprite)>,
+) -> Vec<(IVec2, f32)> {
+  let cell_of = |entity: &Entity| {
+    let (particle, material, _) = particles.get(*entity).ok()?;
+    Some((particle.position.floor().as_ivec2(), particle, *material))
+  };
+
+  match visualization {
+    Visualization::Normal => Vec::new(),
+    Visualization::Density => {
+      let mut counts = HashMap::<IVec2, u32>::default();
+      for cell in spatial_index.cells() {
+        *counts.entry(SpatialIndex::chunk_of(*cell)).or_default() += 1;
+      }
+      let size = SpatialIndex::CHUNK_SIZE;
+      counts.into_iter().flat_map(|(chunk, count)| chunk_cells(chunk, count as f32 / (size * size) as f32)).collect()
+    },
+    Visualization::Pressure => spatial_index
+      .values()
+      .filter_map(cell_of)
+      .filter(|(_, _, material)| is_liquid(*material))
+      .map(|(cell, _, _)| {
+        let depth = (1..).take_while(|offset| spatial_index.contains(&(cell + IVec2::new(0, *offset)))).count();
+        (cell, depth as f32 / MAX_DEPTH)
+      })
+      .collect(),
+    Visualization::Temperature => spatial_index
+      .values()
+      .filter_map(cell_of)
+      .map(|(cell, particle, _)| (cell, 0.5 * particle.mass * particle.velocity.length_squared() / MAX_ENERGY))
+      .collect(),
+    Visualization::ChunkCost => {
+      let max = chunk_stats.max_cost().as_secs_f32();
+      if max == 0. { return Vec::new() }
+      chunk_stats.iter().flat_map(|(chunk, stat)| chunk_cells(*chunk, stat.cost.as_secs_f32() / max)).collect()
+    },
+  }
+}
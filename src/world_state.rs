@@ -0,0 +1,103 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+use crate::{actions::Action, groups::Tag, material::MaterialId, simulation::SimulationClock, Particle, SpatialIndex};
+
+pub struct WorldStatePlugin;
+
+impl Plugin for WorldStatePlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<WorldChecksum>()
+      .add_system_to_stage(CoreStage::PostUpdate, update_checksum.label("world_checksum"))
+      .add_system_to_stage(CoreStage::PostUpdate, print_checksum.after("world_checksum"));
+  }
+}
+
+// Read only view of the simulated world
+#[derive(SystemParam)]
+pub struct WorldState<'w, 's> {
+  spatial_index: Res<'w, SpatialIndex>,
+  particles: Query<'w, 's, (&'static Particle, &'static MaterialId)>,
+  tags: Query<'w, 's, (Entity, &'static Tag)>,
+}
+
+impl<'w, 's> WorldState<'w, 's> {
+  // Hash of every occupied cell with its material, exact position and velocity, in cell order.
+  // Equal across builds and platforms only if the worlds are identical.
+  pub fn checksum(&self) -> u64 {
+    let mut cells: Vec<(&IVec2, &Entity)> = self.spatial_index.iter().collect();
+    cells.sort_unstable_by_key(|(cell, _)| (cell.y, cell.x));
+    checksum(cells.into_iter().filter_map(|(cell, entity)| {
+      let (particle, material) = self.particles.get(*entity).ok()?;
+      Some((*cell, *material, particle))
+    }))
+  }
+
+  // Particles in the group `tag`, in cell order
+  pub fn tagged(&self, tag: &str) -> Vec<Entity> {
+    let mut members: Vec<(IVec2, Entity)> = self.tags
+      .iter()
+      .filter(|(_, Tag(name))| name == tag)
+      .filter_map(|(entity, _)| Some((self.particles.get(entity).ok()?.0.position.floor().as_ivec2(), entity)))
+      .collect();
+    members.sort_unstable_by_key(|(cell, entity)| (cell.y, cell.x, entity.to_bits()));
+    members.into_iter().map(|(_, entity)| entity).collect()
+  }
+}
+
+// 64 bit FNV-1a over the little endian bytes of each cell's values, which unlike `DefaultHasher` stays
+// the same from one Rust release to the next
+fn checksum<'a>(cells: impl Iterator<Item = (IVec2, MaterialId, &'a Particle)>) -> u64 {
+  const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+  const PRIME: u64 = 0x0100_0000_01b3;
+  let mut hash = OFFSET;
+  let mut write = |bytes: &[u8]| {
+    for byte in bytes {
+      hash = (hash ^ *byte as u64).wrapping_mul(PRIME);
+    }
+  };
+  for (cell, material, particle) in cells {
+    write(&cell.x.to_le_bytes());
+    write(&cell.y.to_le_bytes());
+    write(&(material.0 as u64).to_le_bytes());
+    write(&particle.position.x.to_bits().to_le_bytes());
+    write(&particle.position.y.to_bits().to_le_bytes());
+    write(&particle.velocity.x.to_bits().to_le_bytes());
+    write(&particle.velocity.y.to_bits().to_le_bytes());
+  }
+  hash
+}
+
+// Checksum of the world as of the end of `tick`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WorldChecksum {
+  pub tick: u64,
+  pub value: u64,
+}
+
+// Runs after the frame's commands are applied, so spawns and despawns from the tick are included
+fn update_checksum(clock: Res<SimulationClock>, world_state: WorldState, mut checksum: ResMut<WorldChecksum>) {
+  if !clock.ticked { return }
+  *checksum = WorldChecksum { tick: clock.tick, value: world_state.checksum() };
+}
+
+fn print_checksum(actions: Option<Res<Input<Action>>>, checksum: Res<WorldChecksum>) {
+  if actions.is_some_and(|actions| actions.just_pressed(Action::PrintChecksum)) {
+    info!("Tick {} checksum {:016x}", checksum.tick, checksum.value);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn checksum_is_fixed_for_a_world() {
+    assert_eq!(checksum(std::iter::empty()), 0xcbf2_9ce4_8422_2325);
+
+    let sand = Particle { position: Vec2::new(0.5, 0.5), velocity: Vec2::new(0., -4.), ..Default::default() };
+    let water = Particle { position: Vec2::new(-3.25, 2.75), velocity: Vec2::new(1.5, 0.), ..Default::default() };
+    let cells = [(IVec2::new(0, 0), MaterialId::SAND, &sand), (IVec2::new(-4, 2), MaterialId::WATER, &water)];
+    assert_eq!(checksum(cells.into_iter()), 0x6210_f5e0_6e80_71ac);
+  }
+}
@@ -0,0 +1,155 @@
+use bevy::{
+  prelude::*,
+  app::AppLabel,
+  ecs::event::Events,
+  render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+  tasks::{AsyncComputeTaskPool, ComputeTaskPool, IoTaskPool},
+  utils::HashMap,
+};
+
+use crate::{
+  args::Args,
+  config::{Config, WorldConfig},
+  renderer::{grid_extent, set_cell},
+  scenario::{LoadScenario, Scenario},
+  simulation::{SimulationRng, SimulationSettings},
+  add_simulation, AppState, Particle, SpatialIndex,
+};
+
+// Further worlds simulated next to the main one, to compare settings side by side or keep an eye
+// on another scenario. Each is an app of its own with its own `SpatialIndex` and everything else a
+// world needs, run after the main one every frame and drawn into a part of the window given by its
+// `WorldConfig`. They run while the main world does and pause along with it.
+pub struct WorldsPlugin;
+
+impl Plugin for WorldsPlugin {
+  fn build(&self, app: &mut App) {
+    let worlds = match app.world.get_resource::<Config>() {
+      Some(Config { compare: Some(compare), .. }) => compare.worlds().to_vec(),
+      Some(config) => config.worlds.clone(),
+      None => Vec::new(),
+    };
+    let mut views = WorldViews::default();
+    for (index, config) in worlds.into_iter().enumerate() {
+      // The main world is 0
+      let id = WorldId(index as u32 + 1);
+      let image = app.world.resource_mut::<Assets<Image>>().add(Image::new_fill(
+        Extent3d { width: 1, height: 1, depth_or_array_layers: 1 },
+        TextureDimension::D2,
+        &[0, 0, 0, 0],
+        TextureFormat::Rgba8UnormSrgb,
+      ));
+      views.0.insert(id, WorldView { image: image.clone(), viewport: config.viewport, name: config.name.clone() });
+      app.add_sub_app(id, world_app(&app.world, &config), move |main, world| {
+        update_world(main, world);
+        draw_world(main, world, &image);
+      });
+    }
+    app.insert_resource(views).add_startup_system(spawn_views);
+  }
+}
+
+// Tells the worlds apart, also on the node each is shown in
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash, AppLabel)]
+pub struct WorldId(pub u32);
+
+// Image a world is drawn into and where in the window it goes
+struct WorldView {
+  image: Handle<Image>,
+  viewport: [f32; 4],
+  name: Option<String>,
+}
+
+#[derive(Default)]
+struct WorldViews(HashMap<WorldId, WorldView>);
+
+fn world_app(main: &World, config: &WorldConfig) -> App {
+  let mut app = App::new();
+  // Shares the main world's threads rather than starting its own
+  app
+    .insert_resource(main.resource::<ComputeTaskPool>().clone())
+    .insert_resource(main.resource::<AsyncComputeTaskPool>().clone())
+    .insert_resource(main.resource::<IoTaskPool>().clone())
+    .add_plugins(MinimalPlugins);
+  add_simulation(&mut app, &Args::default(), AppState::Running);
+
+  config.settings.apply(&mut app.world.resource_mut::<SimulationSettings>());
+  if let Some(seed) = config.seed {
+    app.world.resource_mut::<SimulationRng>().reseed(seed);
+  }
+  if let Some(path) = &config.scenario {
+    match Scenario::load(path) {
+      Ok(scenario) => app.world.resource_mut::<Events<LoadScenario>>().send(LoadScenario(scenario)),
+      Err(error) => error!("Could not load scenario {} into a world: {}", path, error),
+    }
+  }
+  app
+}
+
+fn update_world(main: &World, world: &mut App) {
+  let running = *main.resource::<State<AppState>>().current() == AppState::Running;
+  let mut state = world.world.resource_mut::<State<AppState>>();
+  if (*state.current() == AppState::Running) != running {
+    let result = state.overwrite_replace(if running { AppState::Running } else { AppState::Paused });
+    if let Err(error) = result {
+      error!("Could not change the state of a world: {:?}", error);
+    }
+  }
+  world.update();
+}
+
+// Like the main world's grid texture, covering the whole lookup bounds
+fn draw_world(main: &mut World, world: &mut App, image: &Handle<Image>) {
+  let (min, size) = grid_extent(world.world.resource::<SpatialIndex>());
+  let mut images = main.resource_mut::<Assets<Image>>();
+  let image = match images.get_mut(image) {
+    Some(image) => image,
+    None => return,
+  };
+  if image.texture_descriptor.size.width != size.x || image.texture_descriptor.size.height != size.y {
+    image.resize(Extent3d { width: size.x, height: size.y, depth_or_array_layers: 1 });
+  }
+  image.data.fill(0);
+  for (particle, sprite) in world.world.query::<(&Particle, &Sprite)>().iter(&world.world) {
+    set_cell(image, min, size, particle.position.floor().as_ivec2(), sprite.color);
+  }
+}
+
+fn spawn_views(mut commands: Commands, asset_server: Res<AssetServer>, views: Res<WorldViews>) {
+  let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+  for (id, view) in views.0.iter() {
+    let [left, bottom, width, height] = view.viewport;
+    commands
+      .spawn_bundle(NodeBundle {
+        style: Style {
+          position_type: PositionType::Absolute,
+          position: Rect { left: Val::Percent(left * 100.), bottom: Val::Percent(bottom * 100.), ..Default::default() },
+          size: Size::new(Val::Percent(width * 100.), Val::Percent(height * 100.)),
+          ..Default::default()
+        },
+        color: Color::rgba(0., 0., 0., 0.8).into(),
+        ..Default::default()
+      })
+      .insert(*id)
+      // Keeps the tools from reaching through to the main world underneath
+      .insert(Interaction::default())
+      .with_children(|parent| {
+        parent.spawn_bundle(ImageBundle {
+          style: Style { size: Size::new(Val::Percent(100.), Val::Percent(100.)), ..Default::default() },
+          image: view.image.clone().into(),
+          ..Default::default()
+        });
+        if let Some(name) = &view.name {
+          parent.spawn_bundle(TextBundle {
+            style: Style {
+              position_type: PositionType::Absolute,
+              position: Rect { left: Val::Px(6.), top: Val::Px(6.), ..Default::default() },
+              ..Default::default()
+            },
+            text: Text::with_section(name.clone(), TextStyle { font: font.clone(), font_size: 16., color: Color::WHITE }, Default::default()),
+            ..Default::default()
+          });
+        }
+      });
+  }
+}